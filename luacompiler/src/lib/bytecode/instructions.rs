@@ -75,22 +75,29 @@ pub fn format_instr(instr: u32) -> String {
         12 => "SetAttr",
         13 => "Closure",
         14 => "Call",
-        15 => "Push",
-        16 => "VarArg",
-        17 => "Eq",
-        18 => "MovR",
-        19 => "Ret",
-        20 => "SetTop",
-        21 => "GetUpAttr",
-        22 => "SetUpAttr",
-        23 => "Jmp",
-        24 => "JmpNE",
-        25 => "LT",
-        26 => "GT",
-        27 => "LE",
-        28 => "GE",
-        29 => "NE",
-        30 => "JmpEQ",
+        15 => "VarArg",
+        16 => "Eq",
+        17 => "Ret",
+        18 => "GetUpAttr",
+        19 => "SetUpAttr",
+        20 => "Jmp",
+        21 => "JmpNE",
+        22 => "LT",
+        23 => "GT",
+        24 => "LE",
+        25 => "GE",
+        26 => "NE",
+        27 => "JmpEQ",
+        28 => "MovUp",
+        29 => "MovUpFromUp",
+        30 => "GetUpVal",
+        31 => "SetUpVal",
+        32 => "LTJ",
+        33 => "LEJ",
+        34 => "EQJ",
+        35 => "ForPrep",
+        36 => "ForLoop",
+        37 => "TailCall",
         _ => unreachable!("No such opcode: {}", opcode(instr)),
     };
     format!(
@@ -123,34 +130,47 @@ pub enum Opcode {
     GetAttr = 11, // R(1) = R(2)[R(3)]
     SetAttr = 12, // R(1)[R(2)] = R(3)
     CLOSURE = 13, // R(1) = Closure(R(2))
-    CALL = 14,    // call R(1) with Arg(2) arguments
-    // Push R(1) to the stack; If Arg(3) is set to some value, then it is added
-    // to the number of return values of a function
-    PUSH = 15,
-    // Copy Arg(2) varargs into registers starting from R(1);
-    // If Arg(3) is set to 1, then all varargs are pushed to the stack
-    // If Arg(3) is set to 2, then the vm will do the same thing as before,
-    // but also increase the count of the return values of a function
-    VarArg = 16,
-    EQ = 17, // R(1) == R(2)
-    // Copy return value RV(2) into R(1); Arg(3) = 1 or 2 => same reasoning as above
-    MOVR = 18,
-    RET = 19,       // return to the parent frame
-    SetTop = 20,    // set R(1)'s `args_start` to the top of the stack
-    GetUpAttr = 21, // R(1) = Upvals[Arg(2)][Arg(3)]
-    SetUpAttr = 22, // Upvals[Arg(1)][Arg(2)] = R(3)
-    Jmp = 23,
-    JmpNE = 24,
-    LT = 25, // R(1) = R(2) < R(3)
-    GT = 26, // R(1) = R(2) > R(3)
-    LE = 27, // R(1) = R(2) <= R(3)
-    GE = 28, // R(1) = R(2) >= R(3)
-    NE = 29, // R(1) = R(2) != R(3)
-    JmpEQ = 30,
-    MovUp = 31,       // R(1).upvals[Arg(2)] = Reg(3)
-    MovUpFromUp = 32, // R(1).upvals[Arg(2)] = curr.upvals[Arg(3)]
-    GetUpVal = 33,    // R(1) = UpVals[Arg(2)]
-    SetUpVal = 34,    // UpVals[Arg(1)] = R(2)
+    // Call R(1) with the B - 1 arguments in R(1 + 1), R(1 + 2), ..., where B = Arg(2).
+    // If B is 0, then the arguments are all the values up to the top of the stack. The
+    // first C - 1 return values, where C = Arg(3), are stored in R(1), R(1 + 1), ...;
+    // if C is 0, then all of them are kept, and the top of the stack is set after the
+    // last one
+    CALL = 14,
+    // Copy B - 1 varargs, where B = Arg(2), into registers starting from R(1); if B is
+    // 0, then all varargs are copied, and the top of the stack is set after the last one
+    VarArg = 15,
+    EQ = 16, // R(1) == R(2)
+    // Return the B - 1 values in R(1), R(1 + 1), ..., where B = Arg(2); if B is 0, then
+    // all the values up to the top of the stack are returned
+    RET = 17,
+    GetUpAttr = 18, // R(1) = Upvals[Arg(2)][Arg(3)]
+    SetUpAttr = 19, // Upvals[Arg(1)][Arg(2)] = R(3)
+    Jmp = 20,
+    JmpNE = 21,
+    LT = 22, // R(1) = R(2) < R(3)
+    GT = 23, // R(1) = R(2) > R(3)
+    LE = 24, // R(1) = R(2) <= R(3)
+    GE = 25, // R(1) = R(2) >= R(3)
+    NE = 26, // R(1) = R(2) != R(3)
+    JmpEQ = 27,
+    MovUp = 28,       // R(1).upvals[Arg(2)] = Reg(3)
+    MovUpFromUp = 29, // R(1).upvals[Arg(2)] = curr.upvals[Arg(3)]
+    GetUpVal = 30,    // R(1) = UpVals[Arg(2)]
+    SetUpVal = 31,    // UpVals[Arg(1)] = R(2)
+    // If (R(2) < R(3)) == Arg(1), then take the jump which follows this instruction,
+    // otherwise skip it
+    LTJ = 32,
+    LEJ = 33, // same as LTJ, but for R(2) <= R(3)
+    EQJ = 34, // same as LTJ, but for R(2) == R(3)
+    // Prepare a numeric for loop whose start, end, and step are in R(1), R(1 + 1), and
+    // R(1 + 2); skip the loop by jumping Arg(2) instructions if it doesn't run at all
+    FORPREP = 35,
+    // R(1) += R(1 + 2); if R(1) hasn't reached R(1 + 1) yet, then jump Arg(2)
+    // instructions back to the body of the loop
+    FORLOOP = 36,
+    // Same as CALL, but also return the results of the call; the called function
+    // replaces the current one in its stack frame
+    TAILCALL = 37,
 }
 
 #[cfg(test)]
@@ -1,7 +1,7 @@
 pub mod instructions;
 
 use self::instructions::format_instr;
-use bincode::{deserialize, serialize};
+use bincode::{self, deserialize, serialize};
 use bytecodegen::constants_map::ConstantsMap;
 use std::{
     fmt,
@@ -66,7 +66,7 @@ impl Function {
 pub struct LuaBytecode {
     ints: Vec<i64>,
     floats: Vec<f64>,
-    strings: Vec<String>,
+    strings: Vec<Vec<u8>>,
     functions: Vec<Function>,
     main_function: usize,
 }
@@ -94,7 +94,13 @@ impl LuaBytecode {
     /// # Panics
     /// This panics if the given vector of bytes does not represent a LuaBytecode instance
     pub fn new_from_bytes(bytes: Vec<u8>) -> LuaBytecode {
-        deserialize(&bytes[..]).unwrap()
+        LuaBytecode::try_from_bytes(&bytes).unwrap()
+    }
+
+    /// Create a new bytecode structure out of the given bytes, or return the error
+    /// raised if they do not represent a LuaBytecode instance.
+    pub fn try_from_bytes(bytes: &[u8]) -> bincode::Result<LuaBytecode> {
+        deserialize(bytes)
     }
 
     pub fn get_function(&self, i: usize) -> &Function {
@@ -116,11 +122,11 @@ impl LuaBytecode {
     }
 
     /// Retrieve the string at index <i> in the constant table.
-    pub fn get_string(&self, i: u8) -> &str {
+    pub fn get_string(&self, i: u8) -> &[u8] {
         &self.strings[i as usize]
     }
 
-    pub fn strings(&self) -> &Vec<String> {
+    pub fn strings(&self) -> &Vec<Vec<u8>> {
         &self.strings
     }
 
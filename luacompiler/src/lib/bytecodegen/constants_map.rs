@@ -11,7 +11,7 @@ use std::collections::HashMap;
 pub struct ConstantsMap {
     int_map: HashMap<i64, usize>,
     float_map: HashMap<String, usize>,
-    str_map: HashMap<String, usize>,
+    str_map: HashMap<Vec<u8>, usize>,
 }
 
 impl ConstantsMap {
@@ -57,17 +57,17 @@ impl ConstantsMap {
     }
 
     /// Get the corresponding index of the given string in the constant table.
-    pub fn get_str(&mut self, string: String) -> usize {
+    pub fn get_str(&mut self, string: Vec<u8>) -> usize {
         let len = self.str_map.len();
         *self.str_map.entry(string).or_insert(len)
     }
 
     /// Get the strings constant table.
-    pub fn get_strings(&self) -> Vec<String> {
+    pub fn get_strings(&self) -> Vec<Vec<u8>> {
         let mut strings = Vec::with_capacity(self.str_map.len());
-        strings.resize(self.str_map.len(), String::from(""));
+        strings.resize(self.str_map.len(), vec![]);
         for (ref k, &v) in self.str_map.iter() {
-            strings[v as usize] = k.to_string();
+            strings[v as usize] = k.to_vec();
         }
         strings
     }
@@ -88,9 +88,9 @@ mod tests {
         for &i in &floats {
             cm.get_float(i.to_string());
         }
-        let strings = vec!["Foo", "Bar"];
-        for &i in &strings {
-            cm.get_str(i.to_string());
+        let strings = vec![b"Foo".to_vec(), b"Bar".to_vec()];
+        for i in &strings {
+            cm.get_str(i.clone());
         }
         assert_eq!(cm.get_ints(), ints);
         assert_eq!(cm.get_floats().len(), floats.len());
@@ -16,6 +16,8 @@ struct LuaIRToLuaBc<'a> {
     const_map: ConstantsMap,
     branches: Vec<(usize, usize)>,
     blocks: HashMap<usize, usize>,
+    /// How many times each register of the current function is referenced.
+    reg_uses: HashMap<usize, usize>,
 }
 
 impl<'a> LuaIRToLuaBc<'a> {
@@ -26,11 +28,13 @@ impl<'a> LuaIRToLuaBc<'a> {
             const_map: ConstantsMap::new(),
             branches: vec![],
             blocks: HashMap::new(),
+            reg_uses: HashMap::new(),
         }
     }
 
     fn compile(mut self) -> LuaBytecode {
         self.ir.substitute_phis();
+        self.ir.allocate_windows();
         let mut functions = vec![];
         for i in 0..self.ir.functions.len() {
             assert!(self.ir.functions[i].reg_count() < 255);
@@ -42,6 +46,8 @@ impl<'a> LuaIRToLuaBc<'a> {
     fn compile_function(&mut self, i: usize) -> Function {
         let reg_count = self.ir.functions[i].reg_count();
         let mut instrs = Vec::with_capacity(reg_count);
+        self.reg_uses = self.count_reg_uses(i);
+        self.coalesce_moves(i);
         for bb in 0..self.ir.functions[i].blocks().len() {
             self.blocks.insert(bb, instrs.len());
             self.compile_basic_block(i, bb, &mut instrs);
@@ -50,6 +56,8 @@ impl<'a> LuaIRToLuaBc<'a> {
             if opcode(instrs[*instr]) == Opcode::Jmp as u8
                 || opcode(instrs[*instr]) == Opcode::JmpEQ as u8
                 || opcode(instrs[*instr]) == Opcode::JmpNE as u8
+                || opcode(instrs[*instr]) == Opcode::FORPREP as u8
+                || opcode(instrs[*instr]) == Opcode::FORLOOP as u8
             {
                 let jmp: i16 = self.blocks[&bb] as i16 - *instr as i16 - 1;
                 set_extended_arg(&mut instrs[*instr], jmp);
@@ -62,9 +70,130 @@ impl<'a> LuaIRToLuaBc<'a> {
     }
 
     fn compile_basic_block(&mut self, f: usize, bb: usize, instrs: &mut Vec<u32>) {
-        for i in 0..self.ir.functions[f].get_block(bb).instrs().len() {
-            self.compile_instr(f, bb, i, instrs);
+        let len = self.ir.functions[f].get_block(bb).instrs().len();
+        let mut i = 0;
+        while i < len {
+            if i + 1 < len && self.compile_cmp_jmp(f, bb, i, instrs) {
+                i += 2;
+            } else {
+                self.compile_instr(f, bb, i, instrs);
+                i += 1;
+            }
+        }
+    }
+
+    fn count_reg_uses(&self, f: usize) -> HashMap<usize, usize> {
+        let mut uses = HashMap::new();
+        for block in self.ir.functions[f].blocks() {
+            for instr in block.instrs() {
+                let args = match *instr {
+                    Instr::ZeroArg(_) => vec![],
+                    Instr::OneArg(_, ref arg1) => vec![arg1],
+                    Instr::TwoArg(_, ref arg1, ref arg2) => vec![arg1, arg2],
+                    Instr::ThreeArg(_, ref arg1, ref arg2, ref arg3) => vec![arg1, arg2, arg3],
+                    Instr::NArg(_, ref args) => args.iter().collect(),
+                };
+                for arg in args {
+                    if let Arg::Reg(reg) = *arg {
+                        *uses.entry(reg).or_insert(0) += 1;
+                    }
+                }
+            }
         }
+        uses
+    }
+
+    /// Peephole which removes `MOV dest, tmp` if the instruction right before it computes
+    /// <tmp>, and <tmp> is not used anywhere else; the instruction stores its result in
+    /// <dest> instead. This mostly gets rid of the moves into the call window.
+    fn coalesce_moves(&mut self, f: usize) {
+        let reg_uses = &self.reg_uses;
+        for block in self.ir.functions[f].get_mut_blocks() {
+            let instrs = block.mut_instrs();
+            let mut i = 1;
+            while i < instrs.len() {
+                let (dest, tmp) = match instrs[i] {
+                    Instr::TwoArg(MOV, Arg::Reg(dest), Arg::Reg(tmp)) => (dest, tmp),
+                    _ => {
+                        i += 1;
+                        continue;
+                    }
+                };
+                if reg_uses.get(&tmp) != Some(&2) {
+                    i += 1;
+                    continue;
+                }
+                let computed = match instrs[i - 1] {
+                    Instr::TwoArg(MOV, Arg::Reg(reg), ref value) => match *value {
+                        Arg::Int(_) | Arg::Float(_) | Arg::Str(_) => reg == tmp,
+                        _ => false,
+                    },
+                    Instr::ThreeArg(op, Arg::Reg(reg), _, _) => match op {
+                        ADD | SUB | MUL | DIV | MOD | FDIV | EXP | GetAttr | GetUpAttr => {
+                            reg == tmp
+                        }
+                        _ => false,
+                    },
+                    _ => false,
+                };
+                if computed {
+                    match instrs[i - 1] {
+                        Instr::TwoArg(_, ref mut reg, _)
+                        | Instr::ThreeArg(_, ref mut reg, _, _) => *reg = Arg::Reg(dest),
+                        _ => unreachable!(),
+                    }
+                    instrs.remove(i);
+                } else {
+                    i += 1;
+                }
+            }
+        }
+    }
+
+    /// Peephole which fuses a comparison followed by a conditional jump on its result
+    /// into a compare-and-jump instruction (LTJ, LEJ, EQJ), followed by a Jmp to the
+    /// target block. The rewrite only happens if the result of the comparison is not
+    /// used anywhere else, because it is never written to a register.
+    /// Returns true if both instructions have been compiled.
+    fn compile_cmp_jmp(&mut self, f: usize, bb: usize, i: usize, instrs: &mut Vec<u32>) -> bool {
+        let (cmp, res, lhs, rhs, jmp_if, target) = {
+            let block = self.ir.functions[f].get_block(bb);
+            let (cmp, res, lhs, rhs) = match *block.get(i) {
+                Instr::ThreeArg(op, Arg::Reg(res), Arg::Reg(lhs), Arg::Reg(rhs)) => {
+                    (op, res, lhs, rhs)
+                }
+                _ => return false,
+            };
+            // JmpNE branches if the condition is false, JmpEQ if it is true
+            let (jmp_if, target) = match *block.get(i + 1) {
+                Instr::ThreeArg(JmpNE, Arg::Reg(reg), _, ref target) if reg == res => {
+                    (false, target.get_some())
+                }
+                Instr::ThreeArg(JmpEQ, Arg::Reg(reg), ref target, _) if reg == res => {
+                    (true, target.get_some())
+                }
+                _ => return false,
+            };
+            (cmp, res, lhs, rhs, jmp_if, target)
+        };
+        if self.reg_uses[&res] != 2 {
+            return false;
+        }
+        // `a > b` and `a >= b` become `b < a` and `b <= a`, and `a ~= b` is `a == b`
+        // with the opposite polarity
+        let (opcode, lhs, rhs, polarity) = match cmp {
+            LT => (Opcode::LTJ, lhs, rhs, jmp_if),
+            GT => (Opcode::LTJ, rhs, lhs, jmp_if),
+            LE => (Opcode::LEJ, lhs, rhs, jmp_if),
+            GE => (Opcode::LEJ, rhs, lhs, jmp_if),
+            EQ => (Opcode::EQJ, lhs, rhs, jmp_if),
+            NE => (Opcode::EQJ, lhs, rhs, !jmp_if),
+            _ => return false,
+        };
+        instrs.push(make_instr(opcode, polarity as u8, lhs as u8, rhs as u8));
+        self.branches.push((instrs.len(), target));
+        instrs.push(make_instr(Opcode::Jmp, 0, 0, 0));
+        true
     }
 
     fn compile_instr(&mut self, f: usize, bb: usize, i: usize, instrs: &mut Vec<u32>) {
@@ -104,36 +233,26 @@ impl<'a> LuaIRToLuaBc<'a> {
                     ))
                 }
             }
-            CALL | SetTop => {
-                if let Instr::OneArg(_, arg1) = instr {
-                    instrs.push(make_instr(opcode.to_opcode(), arg1.get_reg() as u8, 0, 0))
+            CALL | TAILCALL => {
+                if let Instr::ThreeArg(_, arg1, arg2, arg3) = instr {
+                    instrs.push(make_instr(
+                        opcode.to_opcode(),
+                        arg1.get_reg() as u8,
+                        arg2.get_some() as u8,
+                        arg3.get_some() as u8,
+                    ))
+                }
+            }
+            VarArg | RET => {
+                if let Instr::TwoArg(_, arg1, arg2) = instr {
+                    instrs.push(make_instr(
+                        opcode.to_opcode(),
+                        arg1.get_reg() as u8,
+                        arg2.get_some() as u8,
+                        0,
+                    ))
                 }
             }
-            PUSH => instrs.push(if let Instr::OneArg(_, arg1) = instr {
-                make_instr(opcode.to_opcode(), arg1.get_reg() as u8, 0, 0)
-            } else if let Instr::ThreeArg(_, arg1, arg2, arg3) = instr {
-                make_instr(
-                    opcode.to_opcode(),
-                    arg1.get_reg() as u8,
-                    arg2.get_some() as u8,
-                    arg3.get_some() as u8,
-                )
-            } else {
-                panic!("Not enough arguments for {:?}!", opcode)
-            }),
-            VarArg | MOVR => instrs.push(if let Instr::TwoArg(_, arg1, arg2) = instr {
-                make_instr(
-                    opcode.to_opcode(),
-                    arg1.get_reg() as u8,
-                    arg2.get_some() as u8,
-                    0,
-                )
-            } else if let Instr::OneArg(_, arg1) = instr {
-                make_instr(opcode.to_opcode(), 0, 0, arg1.get_some() as u8)
-            } else {
-                panic!("Not enough arguments for {:?}!", opcode)
-            }),
-            RET => instrs.push(make_instr(opcode.to_opcode(), 0, 0, 0)),
             GetUpAttr => {
                 if let Instr::ThreeArg(_, arg1, arg2, arg3) = instr {
                     let reg = arg1.get_reg() as u8;
@@ -195,6 +314,15 @@ impl<'a> LuaIRToLuaBc<'a> {
                     panic!("Not enough arguments for {:?}!", opcode)
                 })
             }
+            ForPrep | ForLoop => {
+                let len = instrs.len();
+                instrs.push(if let Instr::TwoArg(_, arg1, arg2) = instr {
+                    self.branches.push((len, arg2.get_some()));
+                    make_instr(opcode.to_opcode(), arg1.get_reg() as u8, 0, 0)
+                } else {
+                    panic!("Not enough arguments for {:?}!", opcode)
+                })
+            }
             GetAttr | SetAttr => {
                 if let Instr::ThreeArg(_, arg1, arg2, arg3) = instr {
                     instrs.push(make_instr(
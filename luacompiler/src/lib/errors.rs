@@ -1,5 +1,5 @@
 use lrpar::{LexParseError, Node, ParseError};
-use std::io;
+use std::{fmt, io};
 
 type ParseErr = (Option<Node<u8>>, Vec<ParseError<u8>>);
 
@@ -8,6 +8,9 @@ pub enum CliError {
     Io(io::Error),
     LexError(Vec<LexParseError<u8>>),
     ParseError(ParseErr),
+    /// A construct which is valid syntax but cannot be compiled, e.g. an invalid
+    /// escape sequence in a string.
+    CompileError(String),
 }
 
 impl From<Vec<LexParseError<u8>>> for CliError {
@@ -21,3 +24,49 @@ impl From<ParseErr> for CliError {
         CliError::ParseError(err)
     }
 }
+
+impl CliError {
+    /// Whether parsing <code> failed only because it ended too early, e.g. in the
+    /// middle of a block, so that it might be completed by more code.
+    pub fn is_incomplete(&self, code: &str) -> bool {
+        // the parser reports a missing token at the end of the last token
+        let end = code.trim_end().len();
+        match self {
+            CliError::Io(_) | CliError::CompileError(_) => false,
+            CliError::LexError(errs) => errs.iter().all(|err| match err {
+                LexParseError::LexError(_) => false,
+                LexParseError::ParseError(e) => e.lexeme().start() >= end,
+            }),
+            CliError::ParseError((_, errs)) => errs.iter().all(|e| e.lexeme().start() >= end),
+        }
+    }
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CliError::Io(err) => write!(f, "{}", err),
+            CliError::CompileError(msg) => write!(f, "{}", msg),
+            CliError::LexError(errs) => {
+                let errs: Vec<String> = errs
+                    .iter()
+                    .map(|err| match err {
+                        LexParseError::LexError(e) => {
+                            format!("unexpected symbol at position {}", e.idx)
+                        }
+                        LexParseError::ParseError(e) => syntax_error(e),
+                    })
+                    .collect();
+                write!(f, "{}", errs.join("; "))
+            }
+            CliError::ParseError((_, errs)) => {
+                let errs: Vec<String> = errs.iter().map(syntax_error).collect();
+                write!(f, "{}", errs.join("; "))
+            }
+        }
+    }
+}
+
+fn syntax_error(err: &ParseError<u8>) -> String {
+    format!("syntax error at position {}", err.lexeme().start())
+}
@@ -114,6 +114,10 @@ pub struct CompiledFunc<'a> {
     param_count: usize,
     basic_blocks: Vec<BasicBlock<'a>>,
     is_vararg: bool,
+    /// The number of window registers which are currently in use.
+    window_top: usize,
+    /// The number of window registers the function needs.
+    window_size: usize,
 }
 
 impl<'a> CompiledFunc<'a> {
@@ -127,6 +131,8 @@ impl<'a> CompiledFunc<'a> {
             param_count,
             basic_blocks: vec![],
             is_vararg,
+            window_top: 0,
+            window_size: 0,
         }
     }
 
@@ -173,6 +179,44 @@ impl<'a> CompiledFunc<'a> {
         self.reg_count -= 1;
     }
 
+    /// The first window register which is not in use.
+    pub fn window_top(&self) -> usize {
+        self.window_top
+    }
+
+    /// Reserve the next <n> window registers, and return the first one of them.
+    pub fn push_window(&mut self, n: usize) -> usize {
+        let start = self.window_top;
+        self.window_top += n;
+        self.reserve_window(self.window_top);
+        start
+    }
+
+    /// Release all window registers starting from <start>.
+    pub fn pop_window(&mut self, start: usize) {
+        self.window_top = start;
+    }
+
+    /// Make sure that the window has at least <size> registers, e.g. because a call
+    /// stores its return values there.
+    pub fn reserve_window(&mut self, size: usize) {
+        if size > self.window_size {
+            self.window_size = size;
+        }
+    }
+
+    /// Turn the window registers into registers which follow all the other registers of
+    /// the function.
+    pub fn allocate_window(&mut self) {
+        let first = self.reg_count;
+        for block in &mut self.basic_blocks {
+            for instr in block.mut_instrs() {
+                instr.allocate_window(first);
+            }
+        }
+        self.reg_count += self.window_size;
+    }
+
     pub fn blocks(&self) -> &Vec<BasicBlock<'a>> {
         &self.basic_blocks
     }
@@ -199,6 +243,12 @@ impl<'a> CompiledFunc<'a> {
         self.reg_count
     }
 
+    /// The number of registers of the call window, which are added to the other
+    /// registers by `allocate_window`.
+    pub fn window_size(&self) -> usize {
+        self.window_size
+    }
+
     pub fn is_vararg(&self) -> bool {
         self.is_vararg
     }
@@ -5,10 +5,15 @@ pub enum Arg {
     Nil,
     Int(i64),
     Float(f64),
-    Str(String),
+    Str(Vec<u8>),
     Reg(usize),
     Func(usize),
     Some(usize),
+    /// The i-th register of the call window of a function. Calls, returns, and varargs
+    /// expect their values in consecutive registers, so the window registers are
+    /// placed after all the other registers of the function, see
+    /// `CompiledFunc::allocate_window`.
+    Window(usize),
 }
 
 impl Arg {
@@ -28,7 +33,7 @@ impl Arg {
         }
     }
 
-    pub fn get_str(&self) -> String {
+    pub fn get_str(&self) -> Vec<u8> {
         if let Arg::Str(s) = self {
             s.clone()
         } else {
@@ -36,6 +41,13 @@ impl Arg {
         }
     }
 
+    /// Turn a window register into the register <first> + i.
+    pub fn allocate_window(&mut self, first: usize) {
+        if let Arg::Window(i) = *self {
+            *self = Arg::Reg(first + i);
+        }
+    }
+
     pub fn get_func(&self) -> usize {
         if let Arg::Func(f) = self {
             *f
@@ -65,6 +77,27 @@ impl Instr {
         }
     }
 
+    pub fn allocate_window(&mut self, first: usize) {
+        match *self {
+            Instr::ZeroArg(_) => {}
+            Instr::OneArg(_, ref mut arg) => arg.allocate_window(first),
+            Instr::TwoArg(_, ref mut arg1, ref mut arg2) => {
+                arg1.allocate_window(first);
+                arg2.allocate_window(first);
+            }
+            Instr::ThreeArg(_, ref mut arg1, ref mut arg2, ref mut arg3) => {
+                arg1.allocate_window(first);
+                arg2.allocate_window(first);
+                arg3.allocate_window(first);
+            }
+            Instr::NArg(_, ref mut args) => {
+                for arg in args {
+                    arg.allocate_window(first);
+                }
+            }
+        }
+    }
+
     pub fn replace_regs_with(&mut self, regs: &[Arg], with: &Arg) {
         match *self {
             Instr::OneArg(_, ref mut arg) => {
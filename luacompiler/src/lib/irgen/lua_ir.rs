@@ -35,6 +35,13 @@ impl<'a> LuaIR<'a> {
             }
         }
     }
+
+    /// Place the window registers of each function after its other registers.
+    pub fn allocate_windows(&mut self) {
+        for f in &mut self.functions {
+            f.allocate_window();
+        }
+    }
 }
 
 #[cfg(test)]
@@ -7,17 +7,19 @@ mod utils;
 use self::compiled_func::{BasicBlock, CompiledFunc};
 use self::instr::{Arg, Instr};
 use self::lua_ir::LuaIR;
-use self::opcodes::IROpcode::*;
-use self::utils::{find_term, get_nodes, is_nonterm, is_term};
+use self::opcodes::IROpcode::{self, *};
+use self::utils::{find_term, first_term, get_nodes, is_nonterm, is_term, unescape};
 use cfgrammar::RIdx;
+use errors::CliError;
 use lrpar::Node::{self, *};
 use lua5_3_l;
 use lua5_3_y;
+use std::cmp::min;
 use std::collections::{BTreeSet, HashMap};
 use LuaParseTree;
 
 /// Compile the given parse tree into an SSA IR.
-pub fn compile_to_ir(pt: &LuaParseTree) -> LuaIR {
+pub fn compile_to_ir(pt: &LuaParseTree) -> Result<LuaIR, CliError> {
     LuaToIR::new(pt).to_lua_ir()
 }
 
@@ -32,21 +34,11 @@ enum AssignmentType {
 }
 
 enum ResultType {
-    Local(usize),
+    Local,
     Global(usize),
     Dict(usize),
 }
 
-impl ResultType {
-    fn get_reg(&self) -> usize {
-        match *self {
-            ResultType::Local(reg) => reg,
-            ResultType::Global(reg) => reg,
-            ResultType::Dict(..) => panic!("ResultType::Dict has no register"),
-        }
-    }
-}
-
 #[derive(Clone, Copy)]
 enum VarType<'a> {
     Name(&'a str),
@@ -69,6 +61,9 @@ struct LuaToIR<'a> {
     functions: Vec<CompiledFunc<'a>>,
     curr_func: usize,
     curr_block: usize,
+    /// The first error which was found; the compilation goes on so that the tree is
+    /// fully traversed, but its result is discarded.
+    error: Option<String>,
 }
 
 impl<'a> LuaToIR<'a> {
@@ -79,14 +74,36 @@ impl<'a> LuaToIR<'a> {
             functions,
             curr_func: 0,
             curr_block: 0,
+            error: None,
         }
     }
 
     /// Compile and return the intermediate representation of the given lua parse tree.
-    pub fn to_lua_ir(mut self) -> LuaIR<'a> {
+    pub fn to_lua_ir(mut self) -> Result<LuaIR<'a>, CliError> {
         let new_block = self.curr_func().create_block();
         self.compile_block_in_basic_block(&self.pt.tree, new_block);
-        LuaIR::new(self.functions, 0)
+        // the registers of the bytecode are indexed by a single byte, and the call
+        // window is allocated after the other registers
+        if self.error.is_none()
+            && self
+                .functions
+                .iter()
+                .any(|f| f.reg_count() + f.window_size() >= 255)
+        {
+            self.error = Some("function or expression needs too many registers".to_string());
+        }
+        match self.error {
+            Some(msg) => Err(CliError::CompileError(msg)),
+            None => Ok(LuaIR::new(self.functions, 0)),
+        }
+    }
+
+    /// Record an error found at the byte <pos> of the code, unless an earlier one was
+    /// found already.
+    fn error(&mut self, msg: &str, pos: usize) {
+        if self.error.is_none() {
+            self.error = Some(format!("{} at position {}", msg, pos));
+        }
     }
 
     fn curr_func(&mut self) -> &mut CompiledFunc<'a> {
@@ -142,7 +159,7 @@ impl<'a> LuaToIR<'a> {
             self.instrs().push(Instr::ThreeArg(
                 SetUpAttr,
                 Arg::Some(0),
-                Arg::Str(name.to_string()),
+                Arg::Str(name.as_bytes().to_vec()),
                 Arg::Reg(value),
             ));
         }
@@ -205,57 +222,35 @@ impl<'a> LuaToIR<'a> {
             } if ridx == lua5_3_y::R_RETSTATOPT => {
                 if nodes.len() > 0 {
                     let exprs = self.get_underlying_exprs(&nodes[1]);
-                    // push the first n-1 return values to the stack
-                    for i in 0..(exprs.len() - 1) {
-                        let reg = self.compile_expr(exprs[i]);
-                        self.instrs().push(Instr::ThreeArg(
-                            PUSH,
-                            Arg::Reg(reg),
-                            Arg::Some(0),
-                            Arg::Some(1),
-                        ));
+                    // `return f(...)` is a tail call; the main chunk is not called by
+                    // anyone, so there is no frame which could be replaced
+                    if exprs.len() == 1 && self.curr_func != 0 {
+                        if let Some(Nonterm { ref nodes, .. }) = self.find_functioncall(exprs[0]) {
+                            self.compile_call(nodes, TAILCALL, None);
+                            return;
+                        }
+                    }
+                    // a single value can be returned straight from its register
+                    if exprs.len() == 1 && !self.is_unpackable(exprs[0]) {
+                        let reg = self.compile_expr(exprs[0]);
+                        self.instrs()
+                            .push(Instr::TwoArg(RET, Arg::Reg(reg), Arg::Some(2)));
+                        return;
                     }
-                    self.unpack_to_stack(&exprs.last().unwrap(), true);
-                    self.instrs().push(Instr::ZeroArg(RET));
+                    let window = self.curr_func().window_top();
+                    let count = self.compile_window_values(exprs);
+                    self.instrs().push(Instr::TwoArg(
+                        RET,
+                        Arg::Window(window),
+                        Arg::Some(count.map_or(0, |c| c + 1)),
+                    ));
+                    self.curr_func().pop_window(window);
                 }
             }
             _ => panic!("Expected a <retstatopt>, but got {:#?}", node),
         }
     }
 
-    fn unpack_to_stack(&mut self, last_expr: &'a Node<u8>, increment_ret_vals: bool) {
-        let reg = self.compile_expr(last_expr);
-        if self.is_unpackable(last_expr) {
-            {
-                let len = self.curr_block().instrs().len();
-                // this is either a VarArg instr, or a MOVR
-                let last_instr = self.curr_block().get_mut(len - 1);
-                debug_assert!(last_instr.opcode() == MOVR || last_instr.opcode() == VarArg);
-                // check bytecode/instructions.rs for more info on why we set the third
-                // argument to 1 or 2
-                *last_instr = Instr::OneArg(
-                    last_instr.opcode(),
-                    Arg::Some(1 + increment_ret_vals as usize),
-                );
-            }
-            // compile_expr will generate (VarArg/MOVR <new_reg> <op2> <op3>)
-            // but because we are modifying the last instruction, there is
-            // no need to keep the previously allocated register
-            self.curr_func().pop_last_reg();
-        } else {
-            if increment_ret_vals {
-                self.instrs().push(Instr::ThreeArg(
-                    PUSH,
-                    Arg::Reg(reg),
-                    Arg::Some(0),
-                    Arg::Some(1),
-                ));
-            } else {
-                self.instrs().push(Instr::OneArg(PUSH, Arg::Reg(reg)));
-            }
-        }
-    }
-
     /// Compile a <statlist> or a <statlistopt>.
     fn compile_stat_list(&mut self, node: &'a Node<u8>) {
         match *node {
@@ -334,7 +329,9 @@ impl<'a> LuaToIR<'a> {
                 Nonterm {
                     ridx: RIdx(ridx),
                     ref nodes,
-                } if ridx == lua5_3_y::R_FUNCTIONCALL => self.compile_call(&nodes[0], &nodes[1]),
+                } if ridx == lua5_3_y::R_FUNCTIONCALL => {
+                    self.compile_call(nodes, CALL, Some(0));
+                }
                 _ => {}
             }
         } else {
@@ -351,7 +348,7 @@ impl<'a> LuaToIR<'a> {
             } else if is_term(&stat_nodes[0], lua5_3_l::T_FOR) && stat_nodes.len() == 9 {
                 // stat_nodes = [<FOR>, <NAME>, <EQ>, <exp>, <COMMA>,
                 //               <explist>, <DO>, <block>, <END>]
-                let name = self.compile_var_or_name(&stat_nodes[1]);
+                let name = self.compile_var_or_name(&stat_nodes[1]).get_str();
                 self.compile_for_count(name, &stat_nodes[3], &stat_nodes[5], &stat_nodes[7]);
             }
         }
@@ -363,36 +360,23 @@ impl<'a> LuaToIR<'a> {
     fn compile_local_assignments(&mut self, names: Vec<&'a str>, exprs: Vec<&'a Node<u8>>) {
         // example: local a, b, c, d = 1, 2
         // compile local a = 1, local b = 2
-        for i in 0..exprs.len() {
+        let unpack_last = self.unpacks_into_remaining_names(&names, &exprs);
+        let assigned = if unpack_last {
+            exprs.len() - 1
+        } else {
+            min(names.len(), exprs.len())
+        };
+        for i in 0..assigned {
             // left hand-side = <namelist> and right hand-side = <explist>
             self.compile_assignment(VarType::Name(names[i]), exprs[i], AssignmentType::LocalDecl);
         }
-        // for all the remaining names (c, d), create a new empty register, because the
-        // user might access the variable later
-        if names.len() > exprs.len() {
-            let mut regs = vec![];
-            for i in exprs.len()..names.len() {
-                let new_reg = self.curr_func().get_new_reg();
-                self.curr_block().set_reg_name(new_reg, names[i], true);
-                regs.push(new_reg);
-            }
-            // check if the last expression is a vararg, so that we can emit the correct
-            // instruction
-            let mut assign_nils = false;
-            if let Some(expr) = exprs.last() {
-                if self.is_unpackable(expr) {
-                    self.unpack(&regs, expr);
-                } else {
-                    assign_nils = true;
-                }
-            } else {
-                assign_nils = true;
-            }
-            if assign_nils {
-                for reg in regs {
-                    self.instrs()
-                        .push(Instr::TwoArg(MOV, Arg::Reg(reg), Arg::Nil));
-                }
+        // for all the remaining names (c, d), create a new register, because the user
+        // might access the variable later; the registers are either set to nil, or to
+        // the values of the last expression if it is a call or a vararg
+        if names.len() > assigned {
+            let regs = self.compile_remaining_values(names.len() - assigned, &exprs, unpack_last);
+            for (reg, name) in regs.into_iter().zip(&names[assigned..]) {
+                self.curr_block().set_reg_name(reg, name, true);
             }
         } else if names.len() < exprs.len() {
             // make sure we also compile every expression on the right side
@@ -409,29 +393,38 @@ impl<'a> LuaToIR<'a> {
     fn compile_assignments(&mut self, names: Vec<&'a Node<u8>>, exprs: Vec<&'a Node<u8>>) {
         // we want to emit _ENV[<name>] = <reg> only after we assign all expressions into
         // registers. This is because of how vararg expects registers to be ordered.
-        // For instance `a, b = ...`, will generate `VarArg 3, 2, 0` meaning that the vm
+        // For instance `a, b = ...`, will generate `VarArg 3, 3` meaning that the vm
         // will copy two variable arguments into registers 3 and 4. We have to make sure
         // that a, and b point to consecutive registers, but a global assignment will
         // generate additional instructions, which we try to postpone
         let mut postponed_instrs: Vec<(VarType<'a>, usize)> = vec![];
+        let unpack_last = self.unpacks_into_remaining_names(&names, &exprs);
+        let assigned = if unpack_last {
+            exprs.len() - 1
+        } else {
+            min(names.len(), exprs.len())
+        };
         // example: x, y, z, w = 1, 2
         // compile x = 1, y = 2
-        for (name, expr) in names.iter().zip(exprs.iter()) {
+        for (name, expr) in names.iter().zip(exprs.iter()).take(assigned) {
             let var = self.compile_var_or_name(name);
             let res = self.compile_assignment(var, expr, AssignmentType::Postponed);
-            if let ResultType::Global(reg) = res {
-                postponed_instrs.push((var, reg));
+            match res {
+                ResultType::Global(reg) | ResultType::Dict(reg) => {
+                    postponed_instrs.push((var, reg))
+                }
+                ResultType::Local => {}
             }
         }
-        // for all the remaining names (z, w), create a new empty register, and update
-        // _ENV if the variable has not been declared as local in some outer scope
-        // names.len() == exprs.len() is intentionally left out because that case is
-        // handled by the loop above
-        if names.len() > exprs.len() {
-            let mut regs = vec![];
-            for i in exprs.len()..names.len() {
-                let var = self.compile_var_or_name(names[i]);
-                let reg = self.curr_func().get_new_reg();
+        // for all the remaining names (z, w), create a new register, and update _ENV if
+        // the variable has not been declared as local in some outer scope
+        if names.len() > assigned {
+            let mut vars = vec![];
+            for name in &names[assigned..] {
+                vars.push(self.compile_var_or_name(name));
+            }
+            let regs = self.compile_remaining_values(vars.len(), &exprs, unpack_last);
+            for (var, reg) in vars.into_iter().zip(regs) {
                 match var {
                     VarType::Name(name) => {
                         if !self.is_local(name) {
@@ -444,23 +437,6 @@ impl<'a> LuaToIR<'a> {
                         postponed_instrs.push((var, reg));
                     }
                 }
-                regs.push(reg);
-            }
-            let mut assign_nils = false;
-            if let Some(expr) = exprs.last() {
-                if self.is_unpackable(expr) {
-                    self.unpack(&regs, expr);
-                } else {
-                    assign_nils = true;
-                }
-            } else {
-                assign_nils = true;
-            }
-            if assign_nils {
-                for reg in regs {
-                    self.instrs()
-                        .push(Instr::TwoArg(MOV, Arg::Reg(reg), Arg::Nil));
-                }
             }
         } else if names.len() < exprs.len() {
             // make sure we also compile every expression on the right side
@@ -491,16 +467,53 @@ impl<'a> LuaToIR<'a> {
         self.is_vararg(expr) || self.is_functioncall(expr)
     }
 
-    fn unpack(&mut self, regs: &Vec<usize>, expr: &Node<u8>) {
-        // local a, b, c = f(2)
-        // we are unpacking f(2) into a, b, and c, but we have already pushed a
-        // MOVR in compile_assignemnts, thus we have to unpack the rest of the
-        // values into b, and c
-        let opcode = if self.is_vararg(expr) { VarArg } else { MOVR };
-        for (i, reg) in regs.iter().enumerate() {
+    /// Checks if there are more names than expressions in an assignment, and the last
+    /// expression is a call or a vararg, whose values are assigned to all the names
+    /// which are left.
+    fn unpacks_into_remaining_names<T>(&self, names: &[T], exprs: &[&'a Node<u8>]) -> bool {
+        names.len() > exprs.len() && exprs.last().is_some_and(|e| self.is_unpackable(e))
+    }
+
+    /// Create <count> new registers for the names of an assignment which are left
+    /// after all the other expressions have been assigned. If <unpack_last> is set,
+    /// they hold the values of the last expression, otherwise they are set to nil.
+    fn compile_remaining_values(
+        &mut self,
+        count: usize,
+        exprs: &Vec<&'a Node<u8>>,
+        unpack_last: bool,
+    ) -> Vec<usize> {
+        if unpack_last {
+            return self.compile_multi_values(exprs.last().unwrap(), count);
+        }
+        let mut regs = vec![];
+        for _ in 0..count {
+            let reg = self.curr_func().get_new_reg();
             self.instrs()
-                .push(Instr::TwoArg(opcode, Arg::Reg(*reg), Arg::Some(i + 1)));
+                .push(Instr::TwoArg(MOV, Arg::Reg(reg), Arg::Nil));
+            regs.push(reg);
+        }
+        regs
+    }
+
+    /// Compile a call or a vararg, and move its first <count> values into new
+    /// consecutive registers.
+    fn compile_multi_values(&mut self, expr: &'a Node<u8>, count: usize) -> Vec<usize> {
+        let regs: Vec<usize> = (0..count).map(|_| self.curr_func().get_new_reg()).collect();
+        if self.is_vararg(expr) {
+            self.instrs().push(Instr::TwoArg(
+                VarArg,
+                Arg::Reg(regs[0]),
+                Arg::Some(count + 1),
+            ));
+        } else if let Some(Nonterm { ref nodes, .. }) = self.find_functioncall(expr) {
+            let window = self.compile_call(nodes, CALL, Some(count));
+            for (i, reg) in regs.iter().enumerate() {
+                self.instrs()
+                    .push(Instr::TwoArg(MOV, Arg::Reg(*reg), Arg::Window(window + i)));
+            }
         }
+        regs
     }
 
     fn find_name(&mut self, name: &'a str) -> usize {
@@ -520,7 +533,7 @@ impl<'a> LuaToIR<'a> {
                         GetUpAttr,
                         Arg::Reg(reg),
                         Arg::Some(0),
-                        Arg::Str(name.to_string()),
+                        Arg::Str(name.as_bytes().to_vec()),
                     ));
                 }
                 reg
@@ -616,7 +629,7 @@ impl<'a> LuaToIR<'a> {
                         name,
                         action == AssignmentType::LocalDecl,
                     );
-                    ResultType::Local(value)
+                    ResultType::Local
                 } else {
                     if action != AssignmentType::Postponed {
                         self.set_upval(name, value);
@@ -659,7 +672,7 @@ impl<'a> LuaToIR<'a> {
                     self.instrs().push(Instr::TwoArg(
                         MOV,
                         Arg::Reg(reg),
-                        Arg::Str(string.to_string()),
+                        Arg::Str(string.as_bytes().to_vec()),
                     ));
                     VarType::Dict(prefixexp, reg)
                 }
@@ -683,10 +696,10 @@ impl<'a> LuaToIR<'a> {
                 ridx: RIdx(ridx),
                 ref nodes,
             } if ridx == lua5_3_y::R_FUNCTIONCALL => {
-                self.compile_call(&nodes[0], &nodes[1]);
+                let window = self.compile_call(nodes, CALL, Some(1));
                 let reg = self.curr_func().get_new_reg();
                 self.instrs()
-                    .push(Instr::TwoArg(MOVR, Arg::Reg(reg), Arg::Some(0)));
+                    .push(Instr::TwoArg(MOV, Arg::Reg(reg), Arg::Window(window)));
                 reg
             }
             Nonterm {
@@ -718,23 +731,36 @@ impl<'a> LuaToIR<'a> {
                 ref nodes,
             } => {
                 if nodes.len() == 1 {
-                    self.compile_expr(&nodes[0])
+                    return self.compile_expr(&nodes[0]);
+                }
+                let opcode = if nodes.len() == 3 {
+                    self.get_opcode(&nodes[1])
                 } else {
-                    debug_assert!(nodes.len() == 3);
-                    let left = self.compile_expr(&nodes[0]);
-                    let right = self.compile_expr(&nodes[2]);
-                    let new_var = self.curr_func().get_new_reg();
-                    let instr = self.get_instr(&nodes[1], new_var, left, right);
-                    self.instrs().push(instr);
-                    new_var
+                    None
+                };
+                match opcode {
+                    Some(opcode) => {
+                        let left = self.compile_expr(&nodes[0]);
+                        let right = self.compile_expr(&nodes[2]);
+                        let new_var = self.curr_func().get_new_reg();
+                        self.instrs().push(Instr::ThreeArg(
+                            opcode,
+                            Arg::Reg(new_var),
+                            Arg::Reg(left),
+                            Arg::Reg(right),
+                        ));
+                        new_var
+                    }
+                    // the operator is reported, if there is one
+                    None if nodes.len() == 3 => self.unsupported_expr(&nodes[1]),
+                    None => self.unsupported_expr(node),
                 }
             }
             Term { lexeme } => {
-                let value = self
-                    .pt
-                    .get_string(lexeme.start(), lexeme.end().unwrap_or(lexeme.start()));
+                let (start, end) = (lexeme.start(), lexeme.end().unwrap_or(lexeme.start()));
                 match lexeme.tok_id() {
                     lua5_3_l::T_NUMERAL => {
+                        let value = self.pt.get_string(start, end);
                         let new_reg = self.curr_func().get_new_reg();
                         let arg = if value.contains(".") {
                             Arg::Float(value.parse().unwrap())
@@ -747,27 +773,35 @@ impl<'a> LuaToIR<'a> {
                     }
                     lua5_3_l::T_SHORT_STR => {
                         let new_reg = self.curr_func().get_new_reg();
-                        self.instrs().push(Instr::TwoArg(
-                            MOV,
-                            Arg::Reg(new_reg),
-                            Arg::Str(value[1..(value.len() - 1)].to_string()),
-                        ));
+                        // strings are not necessarily valid UTF-8
+                        let value = self.pt.get_bytes(start, end);
+                        let string = match unescape(&value[1..(value.len() - 1)]) {
+                            Ok(string) => string,
+                            Err(msg) => {
+                                self.error(&msg, start);
+                                vec![]
+                            }
+                        };
+                        self.instrs()
+                            .push(Instr::TwoArg(MOV, Arg::Reg(new_reg), Arg::Str(string)));
                         new_reg
                     }
-                    lua5_3_l::T_NAME => self.find_name(value),
+                    lua5_3_l::T_NAME => {
+                        let name = self.pt.get_string(start, end);
+                        self.find_name(name)
+                    }
                     lua5_3_l::T_DOTDOTDOT => {
                         if self.curr_func().is_vararg() {
                             let reg = self.curr_func().get_new_reg();
                             self.instrs()
-                                .push(Instr::TwoArg(VarArg, Arg::Reg(reg), Arg::Some(0)));
+                                .push(Instr::TwoArg(VarArg, Arg::Reg(reg), Arg::Some(2)));
                             reg
                         } else {
-                            panic!("Cannot use '...' outside of a vararg function.")
+                            self.error("cannot use '...' outside a vararg function", start);
+                            self.curr_func().get_new_reg()
                         }
                     }
-                    _ => panic!(
-                        "Cannot compile terminals that are not variable names, numbers or strings."
-                    ),
+                    _ => self.unsupported_expr(node),
                 }
             }
         }
@@ -786,13 +820,16 @@ impl<'a> LuaToIR<'a> {
         new_func.set_parent_block(self.curr_block);
         self.functions.push(new_func);
         self.curr_func = new_func_id;
-        let new_basic_block = self.curr_func().create_block();
+        let old_curr_block = self.curr_block;
+        self.curr_block = self.curr_func().create_block();
         // make the first N registers point to the first N parameters
         self.compile_param_list(&nodes[1]);
+        let new_basic_block = self.curr_block;
         self.compile_block_in_basic_block(&nodes[3], new_basic_block);
         // restore the old state so that we can create a closure instruction
         // in the outer function
         self.curr_func = old_curr_func;
+        self.curr_block = old_curr_block;
         let reg = self.curr_func().get_new_reg();
         self.instrs().push(Instr::TwoArg(
             CLOSURE,
@@ -889,54 +926,83 @@ impl<'a> LuaToIR<'a> {
         merge_reg
     }
 
-    /// Compile an and short circuit in which the lhs is in <left> and the rhs block
-    /// contains the instructions <right_operand_instrs>.
-    fn compile_and_short_circuit2(
-        &mut self,
-        left: usize,
-        right_operand_instrs: Vec<Instr>,
-    ) -> usize {
-        let parent = self.curr_block;
-        let false_branch = self.create_child_block();
-        self.curr_block().mut_instrs().extend(right_operand_instrs);
-        let right =
-            if let Instr::ThreeArg(_, arg1, _, _) = self.curr_block().instrs().last().unwrap() {
-                arg1.get_reg()
-            } else {
-                panic!("Expected a three argument instruction!")
-            };
-        self.curr_block = parent;
-        let merge_branch = self.create_child_block();
-        let merge_reg = self.curr_func().get_new_reg();
-        self.instrs().push(Instr::NArg(
-            Phi,
-            vec![Arg::Reg(merge_reg), Arg::Reg(left), Arg::Reg(right)],
-        ));
-        self.get_block(parent).mut_instrs().push(Instr::ThreeArg(
-            JmpNE,
-            Arg::Reg(left),
-            Arg::Some(false_branch),
-            Arg::Some(merge_branch),
-        ));
-        merge_reg
+    /// Get the operands of <expr> if it is an `and` or an `or`, and whether it is an
+    /// `and`.
+    fn find_logical_op(&self, expr: &'a Node<u8>) -> Option<(bool, &'a Node<u8>, &'a Node<u8>)> {
+        match *expr {
+            Nonterm {
+                ridx: RIdx(ridx),
+                ref nodes,
+            } if nodes.len() == 3 && (ridx == lua5_3_y::R_EXP || ridx == lua5_3_y::R_EXP1) => {
+                Some((ridx == lua5_3_y::R_EXP1, &nodes[0], &nodes[2]))
+            }
+            // nodes = [<LBRACKET>, <exp>, <RBRACKET>]
+            Nonterm {
+                ridx: RIdx(ridx),
+                ref nodes,
+            } if nodes.len() == 3 && ridx == lua5_3_y::R_PREFIXEXP => {
+                self.find_logical_op(&nodes[1])
+            }
+            Nonterm { ref nodes, .. } if nodes.len() == 1 => self.find_logical_op(&nodes[0]),
+            _ => None,
+        }
     }
 
-    fn compile_or_short_circuit2(&mut self, left: usize, right: usize, parent: usize) -> usize {
-        let false_branch = self.curr_block;
-        self.curr_block = parent;
-        let merge_branch = self.create_child_block();
-        let merge_reg = self.curr_func().get_new_reg();
-        self.instrs().push(Instr::NArg(
-            Phi,
-            vec![Arg::Reg(merge_reg), Arg::Reg(left), Arg::Reg(right)],
-        ));
-        self.get_block(parent).mut_instrs().push(Instr::ThreeArg(
-            JmpEQ,
-            Arg::Reg(left),
-            Arg::Some(false_branch),
-            Arg::Some(merge_branch),
-        ));
-        merge_reg
+    /// Compile <expr> as the condition of a branch. The values of `and` and `or` are
+    /// never materialized: each operand ends its block with a jump instead. Returns the
+    /// blocks and the indices of the jumps which are taken if the truth of the
+    /// condition is <jump_if>; their targets are set by the caller with
+    /// `set_jmp_target`. Otherwise, the execution falls through to the next block that
+    /// is created.
+    fn compile_cond(&mut self, expr: &'a Node<u8>, jump_if: bool) -> Vec<(usize, usize)> {
+        match self.find_logical_op(expr) {
+            // `a and b` is false if either operand is, and `a or b` is true if either
+            // operand is, so both operands jump to the same target
+            Some((is_and, left, right)) if is_and != jump_if => {
+                let mut jumps = self.compile_cond(left, jump_if);
+                self.create_child_block();
+                jumps.extend(self.compile_cond(right, jump_if));
+                jumps
+            }
+            // otherwise the right operand decides, unless the left one skips it
+            Some((_, left, right)) => {
+                let skip_jumps = self.compile_cond(left, !jump_if);
+                self.create_child_block();
+                let jumps = self.compile_cond(right, jump_if);
+                let after = self.create_child_block();
+                for (bb, i) in skip_jumps {
+                    self.set_jmp_target(bb, i, after);
+                }
+                jumps
+            }
+            None => {
+                let reg = self.compile_expr(expr);
+                let next = self.curr_func().blocks().len();
+                let instr = if jump_if {
+                    Instr::ThreeArg(JmpEQ, Arg::Reg(reg), Arg::Some(0), Arg::Some(next))
+                } else {
+                    Instr::ThreeArg(JmpNE, Arg::Reg(reg), Arg::Some(next), Arg::Some(0))
+                };
+                self.instrs().push(instr);
+                vec![(self.curr_block, self.instrs().len() - 1)]
+            }
+        }
+    }
+
+    /// Set the block to which the jump at index <i> in <bb> branches, and add <bb> to
+    /// the parents of <target> if it exists already. JmpNE branches to its second
+    /// block if its register is false, and JmpEQ to its first one if it is true.
+    fn set_jmp_target(&mut self, bb: usize, i: usize, target: usize) {
+        match *self.get_block(bb).get_mut(i) {
+            Instr::ThreeArg(JmpNE, _, _, ref mut arg)
+            | Instr::ThreeArg(JmpEQ, _, ref mut arg, _) => *arg = Arg::Some(target),
+            ref instr => panic!("Expected a jump, but got {:?}", instr),
+        }
+        if target < self.curr_func().blocks().len()
+            && !self.get_block(target).parents().contains(&bb)
+        {
+            self.get_block(target).push_parent(bb);
+        }
     }
 
     /// Compile an <explist> or <explistopt> and return the roots of the expressions.
@@ -1040,28 +1106,97 @@ impl<'a> LuaToIR<'a> {
         }
     }
 
-    /// Compile a <functioncall>.
-    fn compile_call(&mut self, func: &'a Node<u8>, params: &'a Node<u8>) {
-        let func_reg = self.compile_prefix_exp(func);
-        let params = match *params {
+    /// Compile the call <nodes> of a <functioncall>, which ends with the given <opcode>
+    /// (either CALL or TAILCALL). The function and its arguments are moved to
+    /// consecutive registers of the call window, where the first <results> return
+    /// values are stored after the call; `None` keeps all of them. Returns the window
+    /// register of the function.
+    fn compile_call(
+        &mut self,
+        nodes: &'a [Node<u8>],
+        opcode: IROpcode,
+        results: Option<usize>,
+    ) -> usize {
+        let params = match *nodes.last().unwrap() {
             Nonterm {
                 ridx: RIdx(ridx),
                 ref nodes,
             } if ridx == lua5_3_y::R_ARGS => &nodes[1],
             _ => panic!("Missing node <args> from <functioncall>"),
         };
-        self.instrs()
-            .push(Instr::OneArg(SetTop, Arg::Reg(func_reg)));
+        let window = self.curr_func().push_window(1);
+        let func_reg = self.compile_prefix_exp(&nodes[0]);
+        // nodes = [<prefixexp>, <COL>, <NAME>, <args>], i.e. `obj:name(args)` is
+        // compiled to `obj.name(obj, args)`, where obj is evaluated once
+        let extra_args = if nodes.len() == 4 {
+            let string = self.get_str(&nodes[2]);
+            let attr_reg = self.curr_func().get_new_reg();
+            self.instrs().push(Instr::TwoArg(
+                MOV,
+                Arg::Reg(attr_reg),
+                Arg::Str(string.as_bytes().to_vec()),
+            ));
+            let method_reg = self.curr_func().get_new_reg();
+            self.instrs().push(Instr::ThreeArg(
+                GetAttr,
+                Arg::Reg(method_reg),
+                Arg::Reg(func_reg),
+                Arg::Reg(attr_reg),
+            ));
+            self.instrs().push(Instr::TwoArg(
+                MOV,
+                Arg::Window(window),
+                Arg::Reg(method_reg),
+            ));
+            let self_window = self.curr_func().push_window(1);
+            self.instrs().push(Instr::TwoArg(
+                MOV,
+                Arg::Window(self_window),
+                Arg::Reg(func_reg),
+            ));
+            1
+        } else {
+            self.instrs()
+                .push(Instr::TwoArg(MOV, Arg::Window(window), Arg::Reg(func_reg)));
+            0
+        };
         let exprs = self.get_underlying_exprs(params);
-        if exprs.len() > 0 {
-            // push the arguments to the function
-            for i in 0..(exprs.len() - 1) {
-                let reg = self.compile_expr(exprs[i]);
-                self.instrs().push(Instr::OneArg(PUSH, Arg::Reg(reg)));
+        let args = self.compile_window_values(exprs).map(|a| a + extra_args);
+        self.instrs().push(Instr::ThreeArg(
+            opcode,
+            Arg::Window(window),
+            Arg::Some(args.map_or(0, |a| a + 1)),
+            Arg::Some(results.map_or(0, |r| r + 1)),
+        ));
+        self.curr_func().pop_window(window);
+        if let Some(results) = results {
+            self.curr_func().reserve_window(window + results);
+        }
+        window
+    }
+
+    /// Move the values of <exprs> to the next registers of the call window, and return
+    /// how many there are. If the last expression is a call or a vararg, then all of its
+    /// values are kept, in which case the count is only known at runtime, and `None` is
+    /// returned.
+    fn compile_window_values(&mut self, exprs: Vec<&'a Node<u8>>) -> Option<usize> {
+        for (i, &expr) in exprs.iter().enumerate() {
+            if i == exprs.len() - 1 && self.is_unpackable(expr) {
+                if self.is_vararg(expr) {
+                    let window = self.curr_func().window_top();
+                    self.instrs()
+                        .push(Instr::TwoArg(VarArg, Arg::Window(window), Arg::Some(0)));
+                } else if let Some(Nonterm { ref nodes, .. }) = self.find_functioncall(expr) {
+                    self.compile_call(nodes, CALL, None);
+                }
+                return None;
             }
-            self.unpack_to_stack(&exprs.last().unwrap(), false);
+            let window = self.curr_func().push_window(1);
+            let reg = self.compile_expr(expr);
+            self.instrs()
+                .push(Instr::TwoArg(MOV, Arg::Window(window), Arg::Reg(reg)));
         }
-        self.instrs().push(Instr::OneArg(CALL, Arg::Reg(func_reg)));
+        Some(exprs.len())
     }
 
     /// Checks if exp is '...'
@@ -1073,22 +1208,28 @@ impl<'a> LuaToIR<'a> {
     }
 
     fn is_functioncall(&self, expr: &Node<u8>) -> bool {
+        self.find_functioncall(expr).is_some()
+    }
+
+    /// Get the <functioncall> node if <expr> is a function call; function calls
+    /// which are wrapped in parentheses are not included.
+    fn find_functioncall<'b>(&self, expr: &'b Node<u8>) -> Option<&'b Node<u8>> {
         if let Nonterm {
             ridx: RIdx(ridx),
             ref nodes,
         } = expr
         {
             if *ridx == lua5_3_y::R_FUNCTIONCALL {
-                return true;
-            } else {
-                return nodes.len() == 1 && self.is_functioncall(&nodes[0]);
+                return Some(expr);
+            } else if nodes.len() == 1 {
+                return self.find_functioncall(&nodes[0]);
             }
         }
-        false
+        None
     }
 
-    /// Get the appropriate instruction for a given Node::Term.
-    fn get_instr(&self, node: &'a Node<u8>, reg: usize, lreg: usize, rreg: usize) -> Instr {
+    /// Get the opcode of the binary operator <node>, or `None` if it is not supported.
+    fn get_opcode(&self, node: &'a Node<u8>) -> Option<IROpcode> {
         if let Term { lexeme } = node {
             let opcode = match lexeme.tok_id() {
                 lua5_3_l::T_PLUS => ADD,
@@ -1104,12 +1245,30 @@ impl<'a> LuaToIR<'a> {
                 lua5_3_l::T_LE => LE,
                 lua5_3_l::T_GE => GE,
                 lua5_3_l::T_NOTEQ => NE,
-                _ => unimplemented!("Instruction {:#?}", node),
+                _ => return None,
             };
-            Instr::ThreeArg(opcode, Arg::Reg(reg), Arg::Reg(lreg), Arg::Reg(rreg))
+            Some(opcode)
         } else {
-            panic!("Expected a Node::Term!");
+            None
+        }
+    }
+
+    /// Record that the expression <node> cannot be compiled, and return a register
+    /// which stands for its value.
+    fn unsupported_expr(&mut self, node: &'a Node<u8>) -> usize {
+        match first_term(node) {
+            Some(Term { lexeme }) => {
+                let start = lexeme.start();
+                let near = self.pt.get_bytes(start, lexeme.end().unwrap_or(start));
+                let msg = format!(
+                    "unsupported expression near '{}'",
+                    String::from_utf8_lossy(near)
+                );
+                self.error(&msg, start)
+            }
+            _ => self.error("unsupported expression", 0),
         }
+        self.curr_func().get_new_reg()
     }
 
     /// Compile an if-statement.
@@ -1127,21 +1286,18 @@ impl<'a> LuaToIR<'a> {
             .chain(elselist.iter())
             .map(|(e, b)| {
                 let before = self.curr_block;
-                // compile if condition
-                let expr_res = self.compile_expr(e);
+                // compile if condition, whose jumps are taken if it is false
+                let false_jumps = self.compile_cond(e, false);
                 // compile true branch as a child of the current block
-                let true_block = self.compile_block(b);
+                self.compile_block(b);
                 self.curr_block = before;
                 let last_true_block = self.curr_func().blocks().len() - 1;
                 // create a new block
                 let parent = self.curr_block;
                 let elif_block = self.curr_func().create_block_with_parents(vec![parent]);
-                self.instrs().push(Instr::ThreeArg(
-                    JmpNE,
-                    Arg::Reg(expr_res),
-                    Arg::Some(true_block),
-                    Arg::Some(elif_block),
-                ));
+                for (bb, i) in false_jumps {
+                    self.set_jmp_target(bb, i, elif_block);
+                }
                 self.curr_block = elif_block;
                 self.curr_block().push_dominator(before);
                 last_true_block
@@ -1248,39 +1404,26 @@ impl<'a> LuaToIR<'a> {
         let cond_start = self.curr_func().create_block_with_parents(vec![parent]);
         self.get_block(cond_start).push_dominator(parent);
         self.curr_block = cond_start;
-        let expr_reg = self.compile_expr(expr);
+        // the targets of the jumps are known only after the body of the loop is compiled
+        let exit_jumps = self.compile_cond(expr, false);
         let cond_end = self.curr_block;
         // compile the while loop block
-        self.compile_while_body(cond_start, cond_end, expr_reg, block, vec![], vec![]);
+        self.compile_while_body(cond_start, cond_end, exit_jumps, block);
     }
 
     fn compile_while_body(
         &mut self,
         while_cond_start: usize,
         while_cond_end: usize,
-        expr_reg: usize,
+        exit_jumps: Vec<(usize, usize)>,
         block: &'a Node<u8>,
-        additional_instrs: Vec<Instr>,
-        reg_map_updates: Vec<(usize, &'a str)>,
     ) {
-        let while_block = self.compile_block(block);
+        self.compile_block(block);
         let last_block = self.curr_func().blocks().len();
-        self.get_block(while_cond_end)
-            .mut_instrs()
-            .push(Instr::ThreeArg(
-                JmpNE,
-                Arg::Reg(expr_reg),
-                Arg::Some(while_block),
-                Arg::Some(last_block),
-            ));
         self.curr_block = last_block - 1;
         if !self.curr_block().dominators().contains(&while_cond_end) {
             self.curr_block().push_dominator(while_cond_end);
         }
-        self.instrs().extend(additional_instrs);
-        for (reg, name) in reg_map_updates {
-            self.curr_block().set_reg_name(reg, name, false);
-        }
         self.generate_phis(while_cond_end);
         self.curr_block()
             .mut_instrs()
@@ -1288,6 +1431,10 @@ impl<'a> LuaToIR<'a> {
         let after_block = self
             .curr_func()
             .create_block_with_parents(vec![while_cond_end]);
+        // <exit_jumps> are taken if the condition of the loop is false
+        for (bb, i) in exit_jumps {
+            self.set_jmp_target(bb, i, after_block);
+        }
         self.curr_block = after_block;
         self.curr_block().push_dominator(while_cond_end);
         self.generate_phis(last_block - 1);
@@ -1295,82 +1442,63 @@ impl<'a> LuaToIR<'a> {
 
     fn compile_for_count(
         &mut self,
-        name: VarType<'a>,
+        name: &'a str,
         expr: &'a Node<u8>,
         exprs: &'a Node<u8>,
         block: &'a Node<u8>,
     ) {
-        self.create_child_block();
-        let start_reg = self
-            .compile_assignment(name, expr, AssignmentType::LocalDecl)
-            .get_reg();
-        let exprs = self.get_underlying_exprs(exprs);
-        // [end_reg, step_reg]
-        let mut regs: Vec<usize> = exprs.iter().map(|e| self.compile_expr(e)).collect();
-        if regs.len() > 2 {
-            panic!("Too many expression in for-loop.");
-        } else if regs.len() == 1 {
-            let new_reg = self.curr_func().get_new_reg();
-            self.curr_block()
-                .mut_instrs()
-                .push(Instr::TwoArg(MOV, Arg::Reg(new_reg), Arg::Int(1)));
-            regs.push(new_reg);
+        let for_block = self.create_child_block();
+        let mut regs = vec![self.compile_expr(expr)];
+        // [end_reg] or [end_reg, step_reg]
+        let mut exprs = self.get_underlying_exprs(exprs);
+        if exprs.len() > 2 {
+            let pos = match first_term(exprs[2]) {
+                Some(Term { lexeme }) => lexeme.start(),
+                _ => 0,
+            };
+            self.error("too many expressions in numeric for", pos);
+            exprs.truncate(2);
+        }
+        regs.extend(exprs.iter().map(|e| self.compile_expr(e)));
+        // ForPrep and ForLoop expect the start, end, and step of the loop in
+        // consecutive registers, so they are all evaluated once before the loop
+        let base = self.curr_func().get_new_reg();
+        self.curr_func().get_new_reg();
+        self.curr_func().get_new_reg();
+        for i in 0..3 {
+            let value = if i < regs.len() {
+                Arg::Reg(regs[i])
+            } else {
+                Arg::Int(1)
+            };
+            self.instrs()
+                .push(Instr::TwoArg(MOV, Arg::Reg(base + i), value));
         }
-        // compile while loop condition
-        let while_condition_start = self.create_child_block();
-        let zero_reg = self.curr_func().get_new_reg();
+        let prep = self.instrs().len();
         self.instrs()
-            .push(Instr::TwoArg(MOV, Arg::Reg(zero_reg), Arg::Int(0)));
-
-        let left_reg =
-            self.get_operand_of_for_count_condition(zero_reg, start_reg, regs[0], regs[1], true);
-        let parent = self.curr_block;
-        self.create_child_block();
-        let right_reg =
-            self.get_operand_of_for_count_condition(zero_reg, start_reg, regs[0], regs[1], false);
-        let condition_reg = self.compile_or_short_circuit2(left_reg, right_reg, parent);
-        let new_reg = self.curr_func().get_new_reg();
-        let additional_instrs = vec![Instr::ThreeArg(
-            ADD,
-            Arg::Reg(new_reg),
-            Arg::Reg(start_reg),
-            Arg::Reg(regs[1]),
-        )];
-        let while_condition_end = self.curr_block;
-        let reg_map_updates = vec![(new_reg, name.get_str())];
-        self.compile_while_body(
-            while_condition_start,
-            while_condition_end,
-            condition_reg,
-            block,
-            additional_instrs,
-            reg_map_updates,
-        );
-    }
-
-    fn get_operand_of_for_count_condition(
-        &mut self,
-        zero_reg: usize,
-        start_reg: usize,
-        end_reg: usize,
-        step_reg: usize,
-        is_left: bool,
-    ) -> usize {
-        let zero_cmp_reg = self.curr_func().get_new_reg();
-        self.instrs().push(Instr::ThreeArg(
-            if is_left { GE } else { LT },
-            Arg::Reg(zero_cmp_reg),
-            Arg::Reg(step_reg),
-            Arg::Reg(zero_reg),
-        ));
-        let end_cmp_reg = self.curr_func().get_new_reg();
-        let right_instrs = vec![Instr::ThreeArg(
-            if is_left { LE } else { GE },
-            Arg::Reg(end_cmp_reg),
-            Arg::Reg(start_reg),
-            Arg::Reg(end_reg),
-        )];
-        self.compile_and_short_circuit2(zero_cmp_reg, right_instrs)
+            .push(Instr::TwoArg(ForPrep, Arg::Reg(base), Arg::Some(0)));
+        // each iteration gets a fresh copy of the control variable
+        let body = self.create_child_block();
+        let var_reg = self.curr_func().get_new_reg();
+        self.instrs()
+            .push(Instr::TwoArg(MOV, Arg::Reg(var_reg), Arg::Reg(base)));
+        self.curr_block().set_reg_name(var_reg, name, true);
+        self.compile_block_in_basic_block(block, body);
+        let last_block = self.curr_func().blocks().len() - 1;
+        self.curr_block = last_block;
+        if !self.curr_block().dominators().contains(&for_block) {
+            self.curr_block().push_dominator(for_block);
+        }
+        self.generate_phis(for_block);
+        self.instrs()
+            .push(Instr::TwoArg(ForLoop, Arg::Reg(base), Arg::Some(body)));
+        let after_block = self.curr_func().create_block_with_parents(vec![for_block]);
+        self.curr_block = after_block;
+        self.curr_block().push_dominator(for_block);
+        self.generate_phis(last_block);
+        if let Instr::TwoArg(ForPrep, _, ref mut arg2) = *self.get_block(for_block).get_mut(prep) {
+            *arg2 = Arg::Some(after_block);
+        }
     }
 }
 
@@ -1379,6 +1507,7 @@ mod tests {
     use super::instr::Arg::*;
     use super::instr::Instr;
     use super::*;
+    use bytecodegen::compile_to_bytecode;
     use std::fmt::Debug;
 
     fn check_eq<T: Debug + PartialEq>(output: &Vec<T>, expected: &Vec<T>) {
@@ -1416,7 +1545,7 @@ mod tests {
     #[test]
     fn simple_math() {
         let pt = &LuaParseTree::from_str(String::from("x = 1 + 2 * 3 / 2 ^ 2.0 // 1 - 2")).unwrap();
-        let ir = compile_to_ir(pt);
+        let ir = compile_to_ir(pt).unwrap();
         let expected_instrs = vec![
             Instr::TwoArg(MOV, Reg(0), Int(1)),
             Instr::TwoArg(MOV, Reg(1), Int(2)),
@@ -1431,7 +1560,7 @@ mod tests {
             Instr::ThreeArg(ADD, Reg(10), Reg(0), Reg(9)),
             Instr::TwoArg(MOV, Reg(11), Int(2)),
             Instr::ThreeArg(SUB, Reg(12), Reg(10), Reg(11)),
-            Instr::ThreeArg(SetUpAttr, Some(0), Str("x".to_string()), Reg(12)),
+            Instr::ThreeArg(SetUpAttr, Some(0), Str(b"x".to_vec()), Reg(12)),
         ];
         assert!(ir.functions.len() == 1);
         let blocks = &ir.functions[0].blocks();
@@ -1446,12 +1575,12 @@ mod tests {
              y = x",
         ))
         .unwrap();
-        let ir = compile_to_ir(pt);
+        let ir = compile_to_ir(pt).unwrap();
         let expected_instrs = vec![
             Instr::TwoArg(MOV, Reg(0), Int(1)),
-            Instr::ThreeArg(SetUpAttr, Some(0), Str("x".to_string()), Reg(0)),
-            Instr::ThreeArg(GetUpAttr, Reg(1), Some(0), Str("x".to_string())),
-            Instr::ThreeArg(SetUpAttr, Some(0), Str("y".to_string()), Reg(1)),
+            Instr::ThreeArg(SetUpAttr, Some(0), Str(b"x".to_vec()), Reg(0)),
+            Instr::ThreeArg(GetUpAttr, Reg(1), Some(0), Str(b"x".to_vec())),
+            Instr::ThreeArg(SetUpAttr, Some(0), Str(b"y".to_vec()), Reg(1)),
         ];
         assert!(ir.functions.len() == 1);
         let blocks = &ir.functions[ir.main_func].blocks();
@@ -1466,10 +1595,10 @@ mod tests {
              y = x",
         ))
         .unwrap();
-        let ir = compile_to_ir(pt);
+        let ir = compile_to_ir(pt).unwrap();
         let expected_instrs = vec![
             Instr::TwoArg(MOV, Reg(0), Int(2)),
-            Instr::ThreeArg(SetUpAttr, Some(0), Str("y".to_string()), Reg(0)),
+            Instr::ThreeArg(SetUpAttr, Some(0), Str(b"y".to_vec()), Reg(0)),
         ];
         assert!(ir.functions.len() == 1);
         let blocks = &ir.functions[0].blocks();
@@ -1485,15 +1614,15 @@ mod tests {
              end",
         ))
         .unwrap();
-        let ir = compile_to_ir(pt);
+        let ir = compile_to_ir(pt).unwrap();
         let expected_instrs = vec![
             vec![
                 Instr::TwoArg(CLOSURE, Reg(0), Func(1)),
-                Instr::ThreeArg(SetUpAttr, Some(0), Str("f".to_string()), Reg(0)),
+                Instr::ThreeArg(SetUpAttr, Some(0), Str(b"f".to_vec()), Reg(0)),
             ],
             vec![
                 Instr::TwoArg(MOV, Reg(0), Int(3)),
-                Instr::ThreeArg(SetUpAttr, Some(0), Str("x".to_string()), Reg(0)),
+                Instr::ThreeArg(SetUpAttr, Some(0), Str(b"x".to_vec()), Reg(0)),
             ],
         ];
         assert!(ir.functions.len() == 2);
@@ -1514,21 +1643,21 @@ mod tests {
              f()",
         ))
         .unwrap();
-        let ir = compile_to_ir(pt);
+        let ir = compile_to_ir(pt).unwrap();
         let expected_instrs = vec![
             vec![
                 Instr::TwoArg(CLOSURE, Reg(0), Func(1)),
-                Instr::ThreeArg(SetUpAttr, Some(0), Str("f".to_string()), Reg(0)),
-                Instr::ThreeArg(GetUpAttr, Reg(1), Some(0), Str("f".to_string())),
-                Instr::OneArg(SetTop, Reg(1)),
-                Instr::OneArg(CALL, Reg(1)),
-                Instr::ThreeArg(GetUpAttr, Reg(2), Some(0), Str("f".to_string())),
-                Instr::OneArg(SetTop, Reg(2)),
-                Instr::OneArg(CALL, Reg(2)),
+                Instr::ThreeArg(SetUpAttr, Some(0), Str(b"f".to_vec()), Reg(0)),
+                Instr::ThreeArg(GetUpAttr, Reg(1), Some(0), Str(b"f".to_vec())),
+                Instr::TwoArg(MOV, Window(0), Reg(1)),
+                Instr::ThreeArg(CALL, Window(0), Some(1), Some(1)),
+                Instr::ThreeArg(GetUpAttr, Reg(2), Some(0), Str(b"f".to_vec())),
+                Instr::TwoArg(MOV, Window(0), Reg(2)),
+                Instr::ThreeArg(CALL, Window(0), Some(1), Some(1)),
             ],
             vec![
                 Instr::TwoArg(MOV, Reg(0), Int(3)),
-                Instr::ThreeArg(SetUpAttr, Some(0), Str("x".to_string()), Reg(0)),
+                Instr::ThreeArg(SetUpAttr, Some(0), Str(b"x".to_vec()), Reg(0)),
             ],
         ];
         assert!(ir.functions.len() == 2);
@@ -1549,26 +1678,26 @@ mod tests {
              f(x)",
         ))
         .unwrap();
-        let ir = compile_to_ir(pt);
+        let ir = compile_to_ir(pt).unwrap();
         let expected_instrs = vec![
             vec![
                 Instr::TwoArg(CLOSURE, Reg(0), Func(1)),
-                Instr::ThreeArg(SetUpAttr, Some(0), Str("f".to_string()), Reg(0)),
-                Instr::ThreeArg(GetUpAttr, Reg(1), Some(0), Str("f".to_string())),
-                Instr::OneArg(SetTop, Reg(1)),
+                Instr::ThreeArg(SetUpAttr, Some(0), Str(b"f".to_vec()), Reg(0)),
+                Instr::ThreeArg(GetUpAttr, Reg(1), Some(0), Str(b"f".to_vec())),
+                Instr::TwoArg(MOV, Window(0), Reg(1)),
                 Instr::TwoArg(MOV, Reg(2), Int(2)),
-                Instr::OneArg(PUSH, Reg(2)),
-                Instr::OneArg(CALL, Reg(1)),
-                Instr::ThreeArg(GetUpAttr, Reg(3), Some(0), Str("f".to_string())),
-                Instr::OneArg(SetTop, Reg(3)),
-                Instr::ThreeArg(GetUpAttr, Reg(4), Some(0), Str("x".to_string())),
-                Instr::OneArg(PUSH, Reg(4)),
-                Instr::OneArg(CALL, Reg(3)),
+                Instr::TwoArg(MOV, Window(1), Reg(2)),
+                Instr::ThreeArg(CALL, Window(0), Some(2), Some(1)),
+                Instr::ThreeArg(GetUpAttr, Reg(3), Some(0), Str(b"f".to_vec())),
+                Instr::TwoArg(MOV, Window(0), Reg(3)),
+                Instr::ThreeArg(GetUpAttr, Reg(4), Some(0), Str(b"x".to_vec())),
+                Instr::TwoArg(MOV, Window(1), Reg(4)),
+                Instr::ThreeArg(CALL, Window(0), Some(2), Some(1)),
             ],
             vec![Instr::ThreeArg(
                 SetUpAttr,
                 Some(0),
-                Str("x".to_string()),
+                Str(b"x".to_vec()),
                 Reg(0),
             )],
         ];
@@ -1588,7 +1717,7 @@ mod tests {
              a, b = 1",
         ))
         .unwrap();
-        let ir = compile_to_ir(pt);
+        let ir = compile_to_ir(pt).unwrap();
         let expected_instrs = vec![
             Instr::TwoArg(MOV, Reg(0), Int(1)),
             Instr::TwoArg(MOV, Reg(1), Int(3)),
@@ -1600,8 +1729,8 @@ mod tests {
             Instr::TwoArg(MOV, Reg(7), Int(6)),
             Instr::TwoArg(MOV, Reg(8), Int(1)),
             Instr::TwoArg(MOV, Reg(9), Nil),
-            Instr::ThreeArg(SetUpAttr, Some(0), Str("a".to_string()), Reg(8)),
-            Instr::ThreeArg(SetUpAttr, Some(0), Str("b".to_string()), Reg(9)),
+            Instr::ThreeArg(SetUpAttr, Some(0), Str(b"a".to_vec()), Reg(8)),
+            Instr::ThreeArg(SetUpAttr, Some(0), Str(b"b".to_vec()), Reg(9)),
         ];
         assert!(ir.functions.len() == 1);
         let blocks = &ir.functions[0].blocks();
@@ -1619,31 +1748,30 @@ mod tests {
              f(1, 2, 3, 4)",
         ))
         .unwrap();
-        let ir = compile_to_ir(pt);
+        let ir = compile_to_ir(pt).unwrap();
         let expected_instrs = vec![
             vec![
                 Instr::TwoArg(CLOSURE, Reg(0), Func(1)),
-                Instr::ThreeArg(SetUpAttr, Some(0), Str("f".to_string()), Reg(0)),
-                Instr::ThreeArg(GetUpAttr, Reg(1), Some(0), Str("f".to_string())),
-                Instr::OneArg(SetTop, Reg(1)),
+                Instr::ThreeArg(SetUpAttr, Some(0), Str(b"f".to_vec()), Reg(0)),
+                Instr::ThreeArg(GetUpAttr, Reg(1), Some(0), Str(b"f".to_vec())),
+                Instr::TwoArg(MOV, Window(0), Reg(1)),
                 Instr::TwoArg(MOV, Reg(2), Int(1)),
-                Instr::OneArg(PUSH, Reg(2)),
+                Instr::TwoArg(MOV, Window(1), Reg(2)),
                 Instr::TwoArg(MOV, Reg(3), Int(2)),
-                Instr::OneArg(PUSH, Reg(3)),
+                Instr::TwoArg(MOV, Window(2), Reg(3)),
                 Instr::TwoArg(MOV, Reg(4), Int(3)),
-                Instr::OneArg(PUSH, Reg(4)),
+                Instr::TwoArg(MOV, Window(3), Reg(4)),
                 Instr::TwoArg(MOV, Reg(5), Int(4)),
-                Instr::OneArg(PUSH, Reg(5)),
-                Instr::OneArg(CALL, Reg(1)),
+                Instr::TwoArg(MOV, Window(4), Reg(5)),
+                Instr::ThreeArg(CALL, Window(0), Some(5), Some(1)),
             ],
             vec![
                 Instr::TwoArg(MOV, Reg(2), Reg(0)),
-                Instr::TwoArg(VarArg, Reg(3), Some(0)),
-                Instr::TwoArg(VarArg, Reg(4), Some(1)),
-                Instr::ThreeArg(GetUpAttr, Reg(5), Some(0), Str("f".to_string())),
-                Instr::OneArg(SetTop, Reg(5)),
-                Instr::OneArg(VarArg, Some(1)),
-                Instr::OneArg(CALL, Reg(5)),
+                Instr::TwoArg(VarArg, Reg(3), Some(3)),
+                Instr::ThreeArg(GetUpAttr, Reg(5), Some(0), Str(b"f".to_vec())),
+                Instr::TwoArg(MOV, Window(0), Reg(5)),
+                Instr::TwoArg(VarArg, Window(1), Some(0)),
+                Instr::ThreeArg(CALL, Window(0), Some(0), Some(1)),
             ],
         ];
         assert!(ir.functions.len() == 2);
@@ -1663,29 +1791,28 @@ mod tests {
              f(1, 2, 3, 4)",
         ))
         .unwrap();
-        let ir = compile_to_ir(pt);
+        let ir = compile_to_ir(pt).unwrap();
         let expected_instrs = vec![
             vec![
                 Instr::TwoArg(CLOSURE, Reg(0), Func(1)),
-                Instr::ThreeArg(SetUpAttr, Some(0), Str("f".to_string()), Reg(0)),
-                Instr::ThreeArg(GetUpAttr, Reg(1), Some(0), Str("f".to_string())),
-                Instr::OneArg(SetTop, Reg(1)),
+                Instr::ThreeArg(SetUpAttr, Some(0), Str(b"f".to_vec()), Reg(0)),
+                Instr::ThreeArg(GetUpAttr, Reg(1), Some(0), Str(b"f".to_vec())),
+                Instr::TwoArg(MOV, Window(0), Reg(1)),
                 Instr::TwoArg(MOV, Reg(2), Int(1)),
-                Instr::OneArg(PUSH, Reg(2)),
+                Instr::TwoArg(MOV, Window(1), Reg(2)),
                 Instr::TwoArg(MOV, Reg(3), Int(2)),
-                Instr::OneArg(PUSH, Reg(3)),
+                Instr::TwoArg(MOV, Window(2), Reg(3)),
                 Instr::TwoArg(MOV, Reg(4), Int(3)),
-                Instr::OneArg(PUSH, Reg(4)),
+                Instr::TwoArg(MOV, Window(3), Reg(4)),
                 Instr::TwoArg(MOV, Reg(5), Int(4)),
-                Instr::OneArg(PUSH, Reg(5)),
-                Instr::OneArg(CALL, Reg(1)),
+                Instr::TwoArg(MOV, Window(4), Reg(5)),
+                Instr::ThreeArg(CALL, Window(0), Some(5), Some(1)),
             ],
             vec![
-                Instr::TwoArg(VarArg, Reg(2), Some(0)),
-                Instr::TwoArg(VarArg, Reg(3), Some(1)),
-                Instr::ThreeArg(SetUpAttr, Some(0), Str("x".to_string()), Reg(0)),
-                Instr::ThreeArg(SetUpAttr, Some(0), Str("y".to_string()), Reg(2)),
-                Instr::ThreeArg(SetUpAttr, Some(0), Str("z".to_string()), Reg(3)),
+                Instr::TwoArg(VarArg, Reg(2), Some(3)),
+                Instr::ThreeArg(SetUpAttr, Some(0), Str(b"x".to_vec()), Reg(0)),
+                Instr::ThreeArg(SetUpAttr, Some(0), Str(b"y".to_vec()), Reg(2)),
+                Instr::ThreeArg(SetUpAttr, Some(0), Str(b"z".to_vec()), Reg(3)),
             ],
         ];
         assert!(ir.functions.len() == 2);
@@ -1705,27 +1832,62 @@ mod tests {
              f(1, f(5))",
         ))
         .unwrap();
-        let ir = compile_to_ir(pt);
+        let ir = compile_to_ir(pt).unwrap();
         let expected_instrs = vec![
             vec![
                 Instr::TwoArg(CLOSURE, Reg(0), Func(1)),
-                Instr::ThreeArg(SetUpAttr, Some(0), Str("f".to_string()), Reg(0)),
-                Instr::ThreeArg(GetUpAttr, Reg(1), Some(0), Str("f".to_string())),
-                Instr::OneArg(SetTop, Reg(1)),
+                Instr::ThreeArg(SetUpAttr, Some(0), Str(b"f".to_vec()), Reg(0)),
+                Instr::ThreeArg(GetUpAttr, Reg(1), Some(0), Str(b"f".to_vec())),
+                Instr::TwoArg(MOV, Window(0), Reg(1)),
                 Instr::TwoArg(MOV, Reg(2), Int(1)),
-                Instr::OneArg(PUSH, Reg(2)),
-                Instr::ThreeArg(GetUpAttr, Reg(3), Some(0), Str("f".to_string())),
-                Instr::OneArg(SetTop, Reg(3)),
+                Instr::TwoArg(MOV, Window(1), Reg(2)),
+                Instr::ThreeArg(GetUpAttr, Reg(3), Some(0), Str(b"f".to_vec())),
+                Instr::TwoArg(MOV, Window(2), Reg(3)),
                 Instr::TwoArg(MOV, Reg(4), Int(5)),
-                Instr::OneArg(PUSH, Reg(4)),
-                Instr::OneArg(CALL, Reg(3)),
-                Instr::OneArg(MOVR, Some(1)),
-                Instr::OneArg(CALL, Reg(1)),
+                Instr::TwoArg(MOV, Window(3), Reg(4)),
+                Instr::ThreeArg(CALL, Window(2), Some(2), Some(0)),
+                Instr::ThreeArg(CALL, Window(0), Some(0), Some(1)),
+            ],
+            vec![
+                Instr::TwoArg(MOV, Window(0), Reg(0)),
+                Instr::TwoArg(VarArg, Window(1), Some(0)),
+                Instr::TwoArg(RET, Window(0), Some(0)),
+            ],
+        ];
+        assert!(ir.functions.len() == 2);
+        for i in 0..ir.functions.len() {
+            let blocks = &ir.functions[i].blocks();
+            assert!(blocks.len() == 1);
+            check_eq(blocks[0].instrs(), &expected_instrs[i])
+        }
+    }
+
+    #[test]
+    fn generate_tail_call() {
+        let pt = &LuaParseTree::from_str(String::from(
+            "function f(a)
+                 return f(a)
+             end
+             return f(1)",
+        ))
+        .unwrap();
+        let ir = compile_to_ir(pt).unwrap();
+        let expected_instrs = vec![
+            vec![
+                Instr::TwoArg(CLOSURE, Reg(0), Func(1)),
+                Instr::ThreeArg(SetUpAttr, Some(0), Str(b"f".to_vec()), Reg(0)),
+                Instr::ThreeArg(GetUpAttr, Reg(1), Some(0), Str(b"f".to_vec())),
+                Instr::TwoArg(MOV, Window(0), Reg(1)),
+                Instr::TwoArg(MOV, Reg(2), Int(1)),
+                Instr::TwoArg(MOV, Window(1), Reg(2)),
+                Instr::ThreeArg(CALL, Window(0), Some(2), Some(0)),
+                Instr::TwoArg(RET, Window(0), Some(0)),
             ],
             vec![
-                Instr::ThreeArg(PUSH, Reg(0), Some(0), Some(1)),
-                Instr::OneArg(VarArg, Some(2)),
-                Instr::ZeroArg(RET),
+                Instr::ThreeArg(GetUpAttr, Reg(1), Some(0), Str(b"f".to_vec())),
+                Instr::TwoArg(MOV, Window(0), Reg(1)),
+                Instr::TwoArg(MOV, Window(1), Reg(0)),
+                Instr::ThreeArg(TAILCALL, Window(0), Some(2), Some(0)),
             ],
         ];
         assert!(ir.functions.len() == 2);
@@ -1747,7 +1909,7 @@ mod tests {
              end",
         ))
         .unwrap();
-        let ir = compile_to_ir(pt);
+        let ir = compile_to_ir(pt).unwrap();
         let expected_instrs = vec![
             vec![
                 Instr::TwoArg(MOV, Reg(0), Int(1)),
@@ -1783,7 +1945,7 @@ mod tests {
              end",
         ))
         .unwrap();
-        let ir = compile_to_ir(pt);
+        let ir = compile_to_ir(pt).unwrap();
         let expected_instrs = vec![
             vec![
                 Instr::TwoArg(MOV, Reg(0), Int(1)),
@@ -1821,7 +1983,7 @@ mod tests {
              end",
         ))
         .unwrap();
-        let ir = compile_to_ir(pt);
+        let ir = compile_to_ir(pt).unwrap();
         let expected_instrs = vec![
             vec![
                 Instr::TwoArg(MOV, Reg(0), Nil),
@@ -1892,7 +2054,7 @@ mod tests {
              end",
         ))
         .unwrap();
-        let ir = compile_to_ir(pt);
+        let ir = compile_to_ir(pt).unwrap();
         let expected_instrs = vec![
             vec![
                 Instr::TwoArg(MOV, Reg(0), Nil),
@@ -1933,7 +2095,7 @@ mod tests {
              end",
         ))
         .unwrap();
-        let ir = compile_to_ir(pt);
+        let ir = compile_to_ir(pt).unwrap();
         let expected_instrs = vec![
             vec![
                 Instr::TwoArg(MOV, Reg(0), Nil),
@@ -1979,7 +2141,7 @@ mod tests {
              end",
         ))
         .unwrap();
-        let ir = compile_to_ir(pt);
+        let ir = compile_to_ir(pt).unwrap();
         let expected_instrs = vec![
             vec![
                 Instr::TwoArg(MOV, Reg(0), Nil),
@@ -2024,7 +2186,7 @@ mod tests {
              end",
         ))
         .unwrap();
-        let ir = compile_to_ir(pt);
+        let ir = compile_to_ir(pt).unwrap();
         let expected_instrs = vec![
             vec![
                 Instr::TwoArg(MOV, Reg(0), Int(2)),
@@ -2051,10 +2213,49 @@ mod tests {
         );
     }
 
+    #[test]
+    fn for_loop() {
+        let pt = &LuaParseTree::from_str(String::from(
+            "local a = 0
+             for i = 1, 3 do
+                 a = a + i
+             end",
+        ))
+        .unwrap();
+        let ir = compile_to_ir(pt).unwrap();
+        let expected_instrs = vec![
+            vec![Instr::TwoArg(MOV, Reg(0), Int(0))],
+            vec![
+                Instr::TwoArg(MOV, Reg(1), Int(1)),
+                Instr::TwoArg(MOV, Reg(2), Int(3)),
+                Instr::TwoArg(MOV, Reg(3), Reg(1)),
+                Instr::TwoArg(MOV, Reg(4), Reg(2)),
+                Instr::TwoArg(MOV, Reg(5), Int(1)),
+                Instr::TwoArg(ForPrep, Reg(3), Some(3)),
+            ],
+            vec![
+                Instr::TwoArg(MOV, Reg(6), Reg(3)),
+                Instr::ThreeArg(ADD, Reg(7), Reg(0), Reg(6)),
+                Instr::NArg(Phi, vec![Reg(8), Reg(0), Reg(7)]),
+                Instr::TwoArg(ForLoop, Reg(3), Some(2)),
+            ],
+            vec![Instr::NArg(Phi, vec![Reg(9), Reg(8)])],
+        ];
+        let expected_parents = vec![vec![], vec![0], vec![1], vec![1]];
+        let expected_dominators = vec![vec![], vec![0], vec![1], vec![1]];
+        check_instrs_and_parents(
+            &ir,
+            1,
+            &expected_instrs,
+            &expected_parents,
+            &expected_dominators,
+        );
+    }
+
     #[test]
     fn or_short_circuit() {
         let pt = &LuaParseTree::from_str(String::from("local a = 0 or 1")).unwrap();
-        let ir = compile_to_ir(pt);
+        let ir = compile_to_ir(pt).unwrap();
         let expected_instrs = vec![
             vec![
                 Instr::TwoArg(MOV, Reg(0), Int(0)),
@@ -2077,7 +2278,7 @@ mod tests {
     #[test]
     fn and_short_circuit() {
         let pt = &LuaParseTree::from_str(String::from("local a = 0 and 1")).unwrap();
-        let ir = compile_to_ir(pt);
+        let ir = compile_to_ir(pt).unwrap();
         let expected_instrs = vec![
             vec![
                 Instr::TwoArg(MOV, Reg(0), Int(0)),
@@ -2097,23 +2298,50 @@ mod tests {
         );
     }
 
+    #[test]
+    fn method_call() {
+        let pt = &LuaParseTree::from_str(String::from("a:b(1)")).unwrap();
+        let ir = compile_to_ir(pt).unwrap();
+        // the object is evaluated once, and passed as the first argument
+        let expected_instrs = vec![vec![
+            Instr::ThreeArg(GetUpAttr, Reg(0), Some(0), Str(b"a".to_vec())),
+            Instr::TwoArg(MOV, Reg(1), Str(b"b".to_vec())),
+            Instr::ThreeArg(GetAttr, Reg(2), Reg(0), Reg(1)),
+            Instr::TwoArg(MOV, Window(0), Reg(2)),
+            Instr::TwoArg(MOV, Window(1), Reg(0)),
+            Instr::TwoArg(MOV, Reg(3), Int(1)),
+            Instr::TwoArg(MOV, Window(2), Reg(3)),
+            Instr::ThreeArg(CALL, Window(0), Some(3), Some(1)),
+        ]];
+        let expected_parents = vec![vec![]];
+        let expected_dominators = vec![vec![]];
+        check_instrs_and_parents(
+            &ir,
+            1,
+            &expected_instrs,
+            &expected_parents,
+            &expected_dominators,
+        );
+    }
+
     #[test]
     fn multiple_prefix_assignments() {
         let pt = &LuaParseTree::from_str(String::from("a[1][2], b, c[3].d = 5, 6")).unwrap();
-        let ir = compile_to_ir(pt);
+        let ir = compile_to_ir(pt).unwrap();
         let expected_instrs = vec![vec![
-            Instr::ThreeArg(GetUpAttr, Reg(0), Some(0), Str("a".to_string())),
+            Instr::ThreeArg(GetUpAttr, Reg(0), Some(0), Str(b"a".to_vec())),
             Instr::TwoArg(MOV, Reg(1), Int(1)),
             Instr::ThreeArg(GetAttr, Reg(2), Reg(0), Reg(1)),
             Instr::TwoArg(MOV, Reg(3), Int(2)),
             Instr::TwoArg(MOV, Reg(4), Int(5)),
             Instr::TwoArg(MOV, Reg(5), Int(6)),
-            Instr::ThreeArg(GetUpAttr, Reg(6), Some(0), Str("c".to_string())),
+            Instr::ThreeArg(GetUpAttr, Reg(6), Some(0), Str(b"c".to_vec())),
             Instr::TwoArg(MOV, Reg(7), Int(3)),
             Instr::ThreeArg(GetAttr, Reg(8), Reg(6), Reg(7)),
-            Instr::TwoArg(MOV, Reg(9), Str("d".to_string())),
+            Instr::TwoArg(MOV, Reg(9), Str(b"d".to_vec())),
             Instr::TwoArg(MOV, Reg(10), Nil),
-            Instr::ThreeArg(SetUpAttr, Some(0), Str("b".to_string()), Reg(5)),
+            Instr::ThreeArg(SetAttr, Reg(2), Reg(3), Reg(4)),
+            Instr::ThreeArg(SetUpAttr, Some(0), Str(b"b".to_vec()), Reg(5)),
             Instr::ThreeArg(SetAttr, Reg(8), Reg(9), Reg(10)),
         ]];
         let expected_parents = vec![vec![]];
@@ -2137,19 +2365,19 @@ mod tests {
              end",
         ))
         .unwrap();
-        let ir = compile_to_ir(pt);
+        let ir = compile_to_ir(pt).unwrap();
         let expected_instrs = vec![
             vec![
                 Instr::TwoArg(MOV, Reg(0), Int(2)),
                 Instr::TwoArg(CLOSURE, Reg(1), Func(1)),
                 Instr::ThreeArg(MovUp, Reg(1), Some(1), Reg(0)),
-                Instr::ThreeArg(SetUpAttr, Some(0), Str("f".to_string()), Reg(1)),
+                Instr::ThreeArg(SetUpAttr, Some(0), Str(b"f".to_vec()), Reg(1)),
             ],
             vec![
                 Instr::TwoArg(MOV, Reg(0), Int(3)),
                 Instr::TwoArg(SetUpVal, Some(1), Reg(0)),
                 Instr::TwoArg(MOV, Reg(1), Int(2)),
-                Instr::ThreeArg(SetUpAttr, Some(0), Str("x".to_string()), Reg(1)),
+                Instr::ThreeArg(SetUpAttr, Some(0), Str(b"x".to_vec()), Reg(1)),
             ],
         ];
         for (i, f) in ir.functions.iter().enumerate() {
@@ -2169,20 +2397,20 @@ mod tests {
              end",
         ))
         .unwrap();
-        let ir = compile_to_ir(pt);
+        let ir = compile_to_ir(pt).unwrap();
         let expected_instrs = vec![
             vec![
                 Instr::TwoArg(MOV, Reg(0), Int(2)),
                 Instr::TwoArg(CLOSURE, Reg(1), Func(1)),
                 Instr::ThreeArg(MovUp, Reg(1), Some(1), Reg(0)),
-                Instr::ThreeArg(SetUpAttr, Some(0), Str("f".to_string()), Reg(1)),
+                Instr::ThreeArg(SetUpAttr, Some(0), Str(b"f".to_vec()), Reg(1)),
             ],
             vec![
                 Instr::TwoArg(MOV, Reg(0), Int(3)),
                 Instr::TwoArg(CLOSURE, Reg(1), Func(2)),
                 Instr::ThreeArg(MovUpFromUp, Reg(1), Some(1), Some(1)),
                 Instr::ThreeArg(MovUp, Reg(1), Some(2), Reg(0)),
-                Instr::ThreeArg(SetUpAttr, Some(0), Str("g".to_string()), Reg(1)),
+                Instr::ThreeArg(SetUpAttr, Some(0), Str(b"g".to_vec()), Reg(1)),
             ],
             vec![
                 Instr::TwoArg(GetUpVal, Reg(0), Some(1)),
@@ -2194,4 +2422,57 @@ mod tests {
             check_eq(f.get_block(0).instrs(), &expected_instrs[i])
         }
     }
+
+    #[test]
+    fn errors_are_reported() {
+        for (code, msg) in &[
+            ("x = 'a\\qb'", "invalid escape sequence '\\q' at position 4"),
+            (
+                "function f() x = ... end",
+                "cannot use '...' outside a vararg function at position 17",
+            ),
+            (
+                "x = 1 .. 2",
+                "unsupported expression near '..' at position 6",
+            ),
+            (
+                "for i = 1, 2, 3, 4 do end",
+                "too many expressions in numeric for at position 17",
+            ),
+        ] {
+            let pt = &LuaParseTree::from_str(code.to_string()).unwrap();
+            match compile_to_ir(pt) {
+                Ok(_) => panic!("{} should not compile", code),
+                Err(err) => assert_eq!(err.to_string(), *msg),
+            }
+        }
+    }
+
+    #[test]
+    fn register_limit_includes_call_window() {
+        // around the limit, the locals and the window of the call together need too
+        // many registers, which must be reported rather than fail in bytecodegen
+        let mut errors = 0;
+        for n in 230..246 {
+            let mut code = String::new();
+            for i in 0..n {
+                code.push_str(&format!("local a{} = {}\n", i, i));
+            }
+            code.push_str("print(1, 2, 3, 4, 5, 6, 7, 8)");
+            let pt = &LuaParseTree::from_str(code).unwrap();
+            match compile_to_ir(pt) {
+                Ok(ir) => {
+                    compile_to_bytecode(ir);
+                }
+                Err(err) => {
+                    assert_eq!(
+                        err.to_string(),
+                        "function or expression needs too many registers"
+                    );
+                    errors += 1;
+                }
+            }
+        }
+        assert!(errors > 0 && errors < 16);
+    }
 }
@@ -14,12 +14,9 @@ pub enum IROpcode {
     SetAttr,
     CLOSURE,
     CALL,
-    PUSH,
     VarArg,
     EQ,
-    MOVR,
     RET,
-    SetTop,
     GetUpAttr,
     SetUpAttr,
     Jmp,
@@ -34,6 +31,9 @@ pub enum IROpcode {
     MovUpFromUp,
     GetUpVal,
     SetUpVal,
+    ForPrep,
+    ForLoop,
+    TAILCALL,
     Phi,
 }
 
@@ -52,12 +52,9 @@ impl IROpcode {
             IROpcode::SetAttr => Opcode::SetAttr,
             IROpcode::CLOSURE => Opcode::CLOSURE,
             IROpcode::CALL => Opcode::CALL,
-            IROpcode::PUSH => Opcode::PUSH,
             IROpcode::VarArg => Opcode::VarArg,
             IROpcode::EQ => Opcode::EQ,
-            IROpcode::MOVR => Opcode::MOVR,
             IROpcode::RET => Opcode::RET,
-            IROpcode::SetTop => Opcode::SetTop,
             IROpcode::GetUpAttr => Opcode::GetUpAttr,
             IROpcode::SetUpAttr => Opcode::SetUpAttr,
             IROpcode::Jmp => Opcode::Jmp,
@@ -72,6 +69,9 @@ impl IROpcode {
             IROpcode::MovUpFromUp => Opcode::MovUpFromUp,
             IROpcode::GetUpVal => Opcode::GetUpVal,
             IROpcode::SetUpVal => Opcode::SetUpVal,
+            IROpcode::ForPrep => Opcode::FORPREP,
+            IROpcode::ForLoop => Opcode::FORLOOP,
+            IROpcode::TAILCALL => Opcode::TAILCALL,
             _ => panic!("Cannot convert {:?} to opcode!", self),
         }
     }
@@ -1,5 +1,6 @@
 use cfgrammar::RIdx;
 use lrpar::Node::{self, *};
+use utf8;
 
 /// Get the children of the given node if its ridx is equal to <ridx>.
 /// # Panic
@@ -54,3 +55,168 @@ pub fn find_term(start: &Node<u8>, id: u8) -> Option<&Node<u8>> {
     }
     None
 }
+
+/// Find the leftmost Node::Term.
+pub fn first_term(start: &Node<u8>) -> Option<&Node<u8>> {
+    match start {
+        Nonterm { ridx: _, ref nodes } => nodes.iter().filter_map(first_term).next(),
+        Term { .. } => Some(start),
+    }
+}
+
+/// Decodes the escape sequences of the contents of a short string. The result is not
+/// necessarily valid UTF-8, as `\xXX` and `\ddd` can produce any byte. Returns an error
+/// if <s> contains an invalid escape sequence.
+pub fn unescape(s: &[u8]) -> Result<Vec<u8>, String> {
+    let mut res = Vec::with_capacity(s.len());
+    let mut i = 0;
+    while i < s.len() {
+        if s[i] != b'\\' {
+            res.push(s[i]);
+            i += 1;
+            continue;
+        }
+        i += 1;
+        let c = match s.get(i) {
+            Some(&c) => c,
+            None => return Err("invalid escape sequence '\\'".to_string()),
+        };
+        i += 1;
+        match c {
+            b'a' => res.push(7),
+            b'b' => res.push(8),
+            b'f' => res.push(12),
+            b'n' | b'\n' => res.push(b'\n'),
+            b'r' => res.push(b'\r'),
+            b't' => res.push(b'\t'),
+            b'v' => res.push(11),
+            b'\\' | b'"' | b'\'' => res.push(c),
+            // skips the whitespace which follows, including new lines
+            b'z' => {
+                while s.get(i).is_some_and(u8::is_ascii_whitespace) {
+                    i += 1;
+                }
+            }
+            b'x' => {
+                let digits = s
+                    .get(i..(i + 2))
+                    .filter(|d| d.iter().all(u8::is_ascii_hexdigit));
+                match digits {
+                    Some(d) => {
+                        let d = std::str::from_utf8(d).unwrap();
+                        res.push(u8::from_str_radix(d, 16).unwrap());
+                        i += 2;
+                    }
+                    None => return Err(hex_digit_expected()),
+                }
+            }
+            b'u' => {
+                if s.get(i) != Some(&b'{') {
+                    return Err("missing '{' in \\u{xxxx}".to_string());
+                }
+                i += 1;
+                let start = i;
+                let mut n: u32 = 0;
+                while let Some(d) = s.get(i).and_then(|&d| (d as char).to_digit(16)) {
+                    if n > 0x7FF_FFFF {
+                        return Err("UTF-8 value too large".to_string());
+                    }
+                    n = n * 16 + d;
+                    i += 1;
+                }
+                if i == start {
+                    return Err(hex_digit_expected());
+                }
+                if s.get(i) != Some(&b'}') {
+                    return Err("missing '}' in \\u{xxxx}".to_string());
+                }
+                i += 1;
+                utf8::encode(n, &mut res);
+            }
+            b'0'..=b'9' => {
+                // up to 3 decimal digits
+                let mut n = (c - b'0') as u32;
+                for _ in 0..2 {
+                    match s.get(i) {
+                        Some(d) if d.is_ascii_digit() => n = n * 10 + (d - b'0') as u32,
+                        _ => break,
+                    }
+                    i += 1;
+                }
+                if n > 255 {
+                    return Err("decimal escape too large".to_string());
+                }
+                res.push(n as u8);
+            }
+            _ => return Err(format!("invalid escape sequence '\\{}'", c as char)),
+        }
+    }
+    Ok(res)
+}
+
+fn hex_digit_expected() -> String {
+    "hexadecimal digit expected in escape sequence".to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unescape_works() {
+        assert_eq!(unescape(b"abc").unwrap(), b"abc");
+        assert_eq!(unescape(b"a\\tb\\nc\\\\\\\"\\'").unwrap(), b"a\tb\nc\\\"'");
+        assert_eq!(unescape(b"\\a\\b\\f\\r\\v").unwrap(), b"\x07\x08\x0c\r\x0b");
+        assert_eq!(unescape(b"\\x41\\xff\\x00").unwrap(), b"A\xff\x00");
+        assert_eq!(unescape(b"\\0\\65\\2550").unwrap(), b"\x00A\xff0");
+        assert_eq!(unescape(b"a\\z  \n  b").unwrap(), b"ab");
+        assert_eq!(unescape(b"a\\\nb").unwrap(), b"a\nb");
+    }
+
+    #[test]
+    fn unescape_utf8() {
+        assert_eq!(unescape(b"\\u{41}\\u{0}").unwrap(), b"A\x00");
+        assert_eq!(unescape(b"\\u{e9}\\u{20AC}").unwrap(), "é€".as_bytes());
+        assert_eq!(unescape(b"\\u{10FFFF}").unwrap(), "\u{10FFFF}".as_bytes());
+        assert_eq!(
+            unescape(b"\\u{7FF}\\u{800}").unwrap(),
+            b"\xdf\xbf\xe0\xa0\x80"
+        );
+        assert_eq!(
+            unescape(b"\\u{7FFFFFFF}").unwrap(),
+            b"\xfd\xbf\xbf\xbf\xbf\xbf"
+        );
+    }
+
+    #[test]
+    fn unescape_large_utf8() {
+        assert_eq!(
+            unescape(b"\\u{80000000}"),
+            Err("UTF-8 value too large".to_string())
+        );
+    }
+
+    #[test]
+    fn unescape_unclosed_utf8() {
+        assert_eq!(
+            unescape(b"\\u{41"),
+            Err("missing '}' in \\u{xxxx}".to_string())
+        );
+    }
+
+    #[test]
+    fn unescape_invalid_escape() {
+        assert_eq!(
+            unescape(b"\\q"),
+            Err("invalid escape sequence '\\q'".to_string())
+        );
+    }
+
+    #[test]
+    fn unescape_large_decimal() {
+        assert_eq!(
+            unescape(b"\\256"),
+            Err("decimal escape too large".to_string())
+        );
+    }
+}
@@ -11,18 +11,19 @@ pub mod bytecode;
 pub mod bytecodegen;
 pub mod errors;
 pub mod irgen;
+pub mod utf8;
 
 use errors::CliError;
 use lrpar::Node;
-use std::{fs::File, io::prelude::*};
+use std::{fs::File, io::prelude::*, str};
 
 lrlex_mod!(lua5_3_l); // lua lexer
 lrpar_mod!(lua5_3_y); // lua parser
 
 /// Holds the parse tree of a Lua file.
 pub struct LuaParseTree {
-    /// The original Lua code
-    pub contents: String,
+    /// The original Lua code, which is not necessarily valid UTF-8
+    pub contents: Vec<u8>,
     /// The root of the parse tree
     pub tree: Node<u8>,
 }
@@ -30,56 +31,48 @@ pub struct LuaParseTree {
 impl LuaParseTree {
     /// Create a new LuaParseTree out of the contents found in <file>.
     pub fn new(file: &str) -> Result<LuaParseTree, CliError> {
-        let mut pt = LuaParseTree {
-            contents: String::new(),
-            tree: Node::Nonterm {
-                ridx: cfgrammar::RIdx(0),
-                nodes: vec![],
-            },
-        };
         // read contents of the file
+        let mut contents = vec![];
         let mut file = File::open(file).map_err(CliError::Io)?;
-        file.read_to_string(&mut pt.contents)
-            .map_err(CliError::Io)?;
-
-        // try to parse the contents
-        {
-            let lexerdef = lua5_3_l::lexerdef();
-            let mut lexer = lexerdef.lexer(&mut pt.contents);
-            let (tree, errs) = lua5_3_y::parse(&mut lexer);
-            if let Some(tree) = tree {
-                pt.tree = tree;
-            } else {
-                return Err(CliError::from(errs));
-            }
-        }
-        Ok(pt)
+        file.read_to_end(&mut contents).map_err(CliError::Io)?;
+        LuaParseTree::from_bytes(contents)
     }
 
     /// Create a new LuaParseTree from the given string.
     pub fn from_str(code: String) -> Result<LuaParseTree, CliError> {
-        let mut pt = LuaParseTree {
-            contents: code,
-            tree: Node::Nonterm {
-                ridx: cfgrammar::RIdx(0),
-                nodes: vec![],
-            },
-        };
-        {
-            let lexerdef = lua5_3_l::lexerdef();
-            let mut lexer = lexerdef.lexer(&mut pt.contents);
-            let (tree, errs) = lua5_3_y::parse(&mut lexer);
-            if let Some(tree) = tree {
-                pt.tree = tree;
-            } else {
-                return Err(CliError::from(errs));
-            }
+        LuaParseTree::from_bytes(code.into_bytes())
+    }
+
+    /// Create a new LuaParseTree from the given bytes. Like in Lua, the bytes which are
+    /// not ASCII can only appear in strings and comments.
+    pub fn from_bytes(code: Vec<u8>) -> Result<LuaParseTree, CliError> {
+        // the lexer works on strings, so each byte which is not ASCII is replaced by a
+        // placeholder of one byte, which keeps the positions of the lexemes the same
+        let text: String = code
+            .iter()
+            .map(|&b| if b.is_ascii() { b as char } else { '\x01' })
+            .collect();
+        let lexerdef = lua5_3_l::lexerdef();
+        let mut lexer = lexerdef.lexer(&text);
+        let (tree, errs) = lua5_3_y::parse(&mut lexer);
+        // the parser might recover from errors, but the code is still invalid
+        match tree {
+            Some(tree) if errs.is_empty() => Ok(LuaParseTree {
+                contents: code,
+                tree,
+            }),
+            _ => Err(CliError::from(errs)),
         }
-        Ok(pt)
     }
 
-    /// Get a slice from the original file.
+    /// Get a slice from the original file. The slice must be ASCII, like the names and
+    /// the numbers.
     fn get_string(&self, start: usize, end: usize) -> &str {
+        str::from_utf8(self.get_bytes(start, end)).unwrap()
+    }
+
+    /// Get the bytes of a slice from the original file.
+    fn get_bytes(&self, start: usize, end: usize) -> &[u8] {
         &self.contents[start..end]
     }
 }
@@ -0,0 +1,42 @@
+/// Appends the UTF-8 encoding of <code> to <res>. Like Lua, values up to 2^31 are
+/// encoded with the original scheme of UTF-8, which allows up to 6 bytes.
+pub fn encode(code: u32, res: &mut Vec<u8>) {
+    if code < 0x80 {
+        res.push(code as u8);
+        return;
+    }
+    // the continuation bytes, from the last one
+    let mut cont = vec![];
+    let mut code = code;
+    // the largest value which fits in the first byte
+    let mut first_max = 0x3f;
+    while code > first_max {
+        cont.push(0x80 | (code & 0x3f) as u8);
+        code >>= 6;
+        first_max >>= 1;
+    }
+    // the first byte has as many leading ones as there are bytes
+    res.push(((!first_max << 1) | code) as u8);
+    res.extend(cont.iter().rev());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_works() {
+        for &(code, bytes) in &[
+            (0x41, &b"A"[..]),
+            (0xE9, b"\xC3\xA9"),
+            (0x7FF, b"\xDF\xBF"),
+            (0x800, b"\xE0\xA0\x80"),
+            (0x10_FFFF, b"\xF4\x8F\xBF\xBF"),
+            (0x7FFF_FFFF, b"\xFD\xBF\xBF\xBF\xBF\xBF"),
+        ] {
+            let mut res = vec![];
+            encode(code, &mut res);
+            assert_eq!(res, bytes);
+        }
+    }
+}
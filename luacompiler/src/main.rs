@@ -4,7 +4,7 @@ extern crate luacompiler;
 
 use clap::{App, Arg};
 use luacompiler::{bytecodegen::compile_to_bytecode, irgen::compile_to_ir, LuaParseTree};
-use std::path::PathBuf;
+use std::{path::PathBuf, process};
 
 fn main() {
     let matches = App::new("Lua compiler")
@@ -20,16 +20,17 @@ fn main() {
         .get_matches();
     // we can safely unwrap because INPUT is not an optional argument
     let file = matches.value_of("INPUT").unwrap();
-    let parse_tree = LuaParseTree::new(&file);
-    match parse_tree {
-        Ok(ref pt) => {
-            let ir = compile_to_ir(&pt);
-            let bc = compile_to_bytecode(ir);
+    let bc = LuaParseTree::new(file).and_then(|pt| compile_to_ir(&pt).map(compile_to_bytecode));
+    match bc {
+        Ok(bc) => {
             // create a luabc file next to the input file
             let mut path = PathBuf::from(file);
             path.set_extension("luabc");
             bc.serialize_to_file(path.to_str().unwrap()).unwrap();
         }
-        Err(err) => println!("{:#?}", err),
+        Err(err) => {
+            eprintln!("{}: {}", file, err);
+            process::exit(1);
+        }
     }
 }
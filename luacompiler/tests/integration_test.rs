@@ -1,7 +1,7 @@
 extern crate luacompiler;
 
 use luacompiler::{
-    bytecode::instructions::{make_instr, Opcode},
+    bytecode::instructions::{make_extended_instr, make_instr, Opcode},
     bytecodegen::compile_to_bytecode,
     irgen::compile_to_ir,
     LuaParseTree,
@@ -10,9 +10,9 @@ use luacompiler::{
 #[test]
 fn ldi_generation() {
     let pt = LuaParseTree::from_str(String::from("x = 1")).unwrap();
-    let bc = compile_to_bytecode(compile_to_ir(&pt));
+    let bc = compile_to_bytecode(compile_to_ir(&pt).unwrap());
     assert_eq!(bc.get_int(0), 1);
-    assert_eq!(bc.get_string(0), "x");
+    assert_eq!(bc.get_string(0), b"x");
     let expected_instrs = vec![
         make_instr(Opcode::LDI, 0, 0, 0),
         make_instr(Opcode::LDS, 1, 0, 0),
@@ -29,9 +29,9 @@ fn ldi_generation() {
 #[test]
 fn ldf_generation() {
     let pt = LuaParseTree::from_str(String::from("x = 2.0")).unwrap();
-    let bc = compile_to_bytecode(compile_to_ir(&pt));
+    let bc = compile_to_bytecode(compile_to_ir(&pt).unwrap());
     assert_eq!(bc.get_float(0).to_string(), "2");
-    assert_eq!(bc.get_string(0), "x");
+    assert_eq!(bc.get_string(0), b"x");
     let expected_instrs = vec![
         make_instr(Opcode::LDF, 0, 0, 0),
         make_instr(Opcode::LDS, 1, 0, 0),
@@ -48,9 +48,9 @@ fn ldf_generation() {
 #[test]
 fn lds_generation() {
     let pt = LuaParseTree::from_str(String::from("x = \"1.2\"")).unwrap();
-    let bc = compile_to_bytecode(compile_to_ir(&pt));
-    assert_eq!(bc.get_string(0), "1.2");
-    assert_eq!(bc.get_string(1), "x");
+    let bc = compile_to_bytecode(compile_to_ir(&pt).unwrap());
+    assert_eq!(bc.get_string(0), b"1.2");
+    assert_eq!(bc.get_string(1), b"x");
     let expected_instrs = vec![
         make_instr(Opcode::LDS, 0, 0, 0),
         make_instr(Opcode::LDS, 1, 1, 0),
@@ -66,10 +66,10 @@ fn lds_generation() {
 
 fn assert_bytecode(opcode: Opcode, operation: &str) {
     let pt = LuaParseTree::from_str(String::from(format!("x = 1 {} 2", operation))).unwrap();
-    let bc = compile_to_bytecode(compile_to_ir(&pt));
+    let bc = compile_to_bytecode(compile_to_ir(&pt).unwrap());
     assert_eq!(bc.get_int(0), 1);
     assert_eq!(bc.get_int(1), 2);
-    assert_eq!(bc.get_string(0), "x");
+    assert_eq!(bc.get_string(0), b"x");
     let expected_instrs = vec![
         make_instr(Opcode::LDI, 0, 0, 0),
         make_instr(Opcode::LDI, 1, 1, 0),
@@ -119,3 +119,115 @@ fn fdiv_generation() {
 fn exp_generation() {
     assert_bytecode(Opcode::EXP, "^");
 }
+
+fn assert_instrs(source: &str, expected_instrs: Vec<u32>) {
+    let pt = LuaParseTree::from_str(String::from(source)).unwrap();
+    let bc = compile_to_bytecode(compile_to_ir(&pt).unwrap());
+    let function = bc.get_function(bc.get_main_function());
+    assert_eq!(function.instrs_len(), expected_instrs.len());
+    for i in 0..expected_instrs.len() {
+        assert_eq!(function.get_instr(i), expected_instrs[i]);
+    }
+}
+
+#[test]
+fn fused_compare_and_jump() {
+    assert_instrs(
+        "local a = 1
+         if a > 2 then
+             a = 3
+         end",
+        vec![
+            make_instr(Opcode::LDI, 4, 0, 0),
+            make_instr(Opcode::LDI, 1, 1, 0),
+            // a > 2 is rewritten as 2 < a, and the jump is taken if it is false
+            make_instr(Opcode::LTJ, 0, 1, 4),
+            make_extended_instr(Opcode::Jmp, 0, 2),
+            make_instr(Opcode::LDI, 4, 2, 0),
+            make_extended_instr(Opcode::Jmp, 0, 0),
+        ],
+    );
+}
+
+#[test]
+fn compare_result_is_kept_if_used() {
+    assert_instrs(
+        "local a = 1
+         local b = a < 2
+         if b then
+             a = 3
+         end
+         x = b",
+        vec![
+            make_instr(Opcode::LDI, 4, 0, 0),
+            make_instr(Opcode::LDI, 1, 1, 0),
+            make_instr(Opcode::LT, 2, 4, 1),
+            make_extended_instr(Opcode::JmpNE, 2, 2),
+            make_instr(Opcode::LDI, 4, 2, 0),
+            make_extended_instr(Opcode::Jmp, 0, 0),
+            make_instr(Opcode::LDS, 5, 0, 0),
+            make_instr(Opcode::SetUpAttr, 0, 5, 2),
+        ],
+    );
+}
+
+#[test]
+fn call_arguments_are_computed_in_the_window() {
+    assert_instrs(
+        "local a = 1
+         local b, c = g(a + 1, 2)",
+        vec![
+            make_instr(Opcode::LDI, 0, 0, 0),
+            // the window starts after the 7 registers used by the function
+            make_instr(Opcode::LDS, 7, 0, 0),
+            make_instr(Opcode::GetUpAttr, 7, 0, 7),
+            make_instr(Opcode::LDI, 4, 0, 0),
+            make_instr(Opcode::ADD, 8, 0, 4),
+            make_instr(Opcode::LDI, 9, 1, 0),
+            // 2 arguments, and 2 return values
+            make_instr(Opcode::CALL, 7, 3, 3),
+            make_instr(Opcode::MOV, 1, 7, 0),
+            make_instr(Opcode::MOV, 2, 8, 0),
+        ],
+    );
+}
+
+#[test]
+fn incomplete_code() {
+    for code in &[
+        "if x then\n",
+        "x = ",
+        "function f()",
+        "print(1",
+        "return 1 +",
+    ] {
+        let err = LuaParseTree::from_str(code.to_string()).err().unwrap();
+        assert!(err.is_incomplete(code), "{:?} is incomplete", code);
+    }
+    for code in &["x = = 1", "s = \"abc", "return x = 1", "end"] {
+        let err = LuaParseTree::from_str(code.to_string()).err().unwrap();
+        assert!(!err.is_incomplete(code), "{:?} is invalid", code);
+    }
+}
+
+#[test]
+fn and_condition_is_not_materialized() {
+    assert_instrs(
+        "local a = 1
+         if a < 2 and a > 0 then
+             a = 3
+         end",
+        vec![
+            make_instr(Opcode::LDI, 6, 0, 0),
+            make_instr(Opcode::LDI, 1, 1, 0),
+            // each comparison jumps past the body of the if if it is false
+            make_instr(Opcode::LTJ, 0, 6, 1),
+            make_extended_instr(Opcode::Jmp, 0, 5),
+            make_instr(Opcode::LDI, 3, 2, 0),
+            make_instr(Opcode::LTJ, 0, 3, 6),
+            make_extended_instr(Opcode::Jmp, 0, 2),
+            make_instr(Opcode::LDI, 6, 3, 0),
+            make_extended_instr(Opcode::Jmp, 0, 0),
+        ],
+    );
+}
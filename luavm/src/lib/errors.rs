@@ -1,3 +1,5 @@
+use std::fmt;
+
 #[derive(PartialEq, Eq, Debug)]
 pub enum LuaError {
     /// Raised when the requested attribute is not found.
@@ -12,6 +14,52 @@ pub enum LuaError {
     StringConversionErr,
     /// Raised when the called register is not a closure.
     NotAClosure,
+    /// Raised when a call exceeds `Vm::max_call_depth`.
+    StackOverflow,
+    /// Raised when a value cannot be converted to a Rust type, see `FromLua`.
+    TypeMismatch {
+        expected: &'static str,
+        got: &'static str,
+    },
+    /// Raised when the argument at the given position (starting from 1) cannot be
+    /// converted, see `FromLuaMulti`.
+    BadArgument(usize, Box<LuaError>),
     /// A generic error.
     Error(String),
+    /// Raised by `os.exit` to end the program with the given status. It cannot be
+    /// caught, so that all the frames are unwound.
+    Exit(i32),
+}
+
+impl LuaError {
+    /// Adds the name of the function which raised a `BadArgument` to its message, e.g.
+    /// "bad argument #1 to 'format' (...)". The other errors are returned as they are.
+    pub fn in_function(self, name: &str) -> LuaError {
+        match self {
+            LuaError::BadArgument(pos, err) => {
+                LuaError::Error(format!("bad argument #{} to '{}' ({})", pos, name, err))
+            }
+            err => err,
+        }
+    }
+}
+
+impl fmt::Display for LuaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LuaError::GetAttrErr => write!(f, "attempt to index a non-table value"),
+            LuaError::SetAttrErr => write!(f, "attempt to index a non-table value"),
+            LuaError::IntConversionErr => write!(f, "value cannot be converted to an integer"),
+            LuaError::FloatConversionErr => write!(f, "value cannot be converted to a float"),
+            LuaError::StringConversionErr => write!(f, "value cannot be converted to a string"),
+            LuaError::NotAClosure => write!(f, "attempt to call a non-function value"),
+            LuaError::StackOverflow => write!(f, "stack overflow"),
+            LuaError::TypeMismatch { expected, got } => {
+                write!(f, "{} expected, got {}", expected, got)
+            }
+            LuaError::BadArgument(pos, err) => write!(f, "bad argument #{} ({})", pos, err),
+            LuaError::Error(msg) => write!(f, "{}", msg),
+            LuaError::Exit(status) => write!(f, "exit with status {}", status),
+        }
+    }
 }
@@ -1,4 +1,5 @@
 use errors::LuaError;
+use instructions::functions::call_metamethod;
 use luacompiler::bytecode::instructions::{first_arg, second_arg, third_arg};
 use Vm;
 
@@ -7,26 +8,36 @@ use Vm;
 /// or not by the vm. This macro is used to generate add, sub, etc. functions which all
 /// have the same implementation. The name of the function ($op) is also the name of
 /// the method that is called on the operands of the instruction. For example:
-/// `bin_op!(add);` generates an `add` function which extracts the arguments of the
-/// instruction (lhs, and rhs), and calls `lhs.add(rhs)`.
+/// `bin_op!(add, "__add");` generates an `add` function which extracts the arguments of
+/// the instruction (lhs, and rhs), and calls `lhs.add(rhs)`. If either of them is a
+/// userdata, its metamethod ($mm) is called instead.
 macro_rules! bin_op {
-    ($op: tt) => {
+    ($op: tt, $mm: tt) => {
         pub fn $op(vm: &mut Vm, instr: u32) -> Result<(), LuaError> {
             let res = {
-                let lhs = &vm.registers[second_arg(instr) as usize];
-                let rhs = &vm.registers[third_arg(instr) as usize];
-                lhs.$op(rhs)?
+                let lhs = &vm.stack[vm.base + second_arg(instr) as usize];
+                let rhs = &vm.stack[vm.base + third_arg(instr) as usize];
+                if lhs.is_userdata() || rhs.is_userdata() {
+                    let args = [lhs.clone(), rhs.clone()];
+                    call_metamethod(vm, $mm, &args)?.ok_or_else(|| {
+                        LuaError::Error(
+                            "attempt to perform arithmetic on a userdata value".to_string(),
+                        )
+                    })?
+                } else {
+                    lhs.$op(rhs)?
+                }
             };
-            vm.registers[first_arg(instr) as usize] = res;
+            vm.stack[vm.base + first_arg(instr) as usize] = res;
             Ok(())
         }
     };
 }
 
-bin_op!(add);
-bin_op!(sub);
-bin_op!(mul);
-bin_op!(div);
-bin_op!(modulus);
-bin_op!(fdiv);
-bin_op!(exp);
+bin_op!(add, "__add");
+bin_op!(sub, "__sub");
+bin_op!(mul, "__mul");
+bin_op!(div, "__div");
+bin_op!(modulus, "__mod");
+bin_op!(fdiv, "__idiv");
+bin_op!(exp, "__pow");
@@ -1,9 +1,10 @@
 use errors::LuaError;
+use lua_values::{convert::float_to_int, LuaVal};
 use luacompiler::bytecode::instructions::{extended_arg, first_arg};
 use Vm;
 
 pub fn jmp_eq(vm: &mut Vm, instr: u32) -> Result<(), LuaError> {
-    if vm.registers[first_arg(instr) as usize].to_bool() {
+    if vm.stack[vm.base + first_arg(instr) as usize].to_bool() {
         let jmp: isize = vm.pc as isize + extended_arg(instr) as isize;
         vm.pc = jmp as usize;
     }
@@ -11,7 +12,7 @@ pub fn jmp_eq(vm: &mut Vm, instr: u32) -> Result<(), LuaError> {
 }
 
 pub fn jmp_ne(vm: &mut Vm, instr: u32) -> Result<(), LuaError> {
-    if !vm.registers[first_arg(instr) as usize].to_bool() {
+    if !vm.stack[vm.base + first_arg(instr) as usize].to_bool() {
         let jmp: isize = vm.pc as isize + extended_arg(instr) as isize;
         vm.pc = jmp as usize;
     }
@@ -23,3 +24,115 @@ pub fn jmp(vm: &mut Vm, instr: u32) -> Result<(), LuaError> {
     vm.pc = jmp as usize;
     Ok(())
 }
+
+/// Checks whether a numeric for loop, whose control variable is `i`, should run
+/// another iteration.
+macro_rules! in_range {
+    ($i: expr, $end: expr, $step: expr, $zero: expr) => {
+        if $step > $zero {
+            $i <= $end
+        } else {
+            $i >= $end
+        }
+    };
+}
+
+fn for_number(val: &LuaVal, what: &str) -> Result<(), LuaError> {
+    if val.is_number() {
+        Ok(())
+    } else {
+        Err(LuaError::Error(format!("'for' {} must be a number", what)))
+    }
+}
+
+/// Converts the limit of an integer loop to an integer, like `forlimit` in Lua: a float
+/// is rounded towards the start of the loop, i.e. floored if <step> is positive and
+/// ceiled otherwise, and saturates at the bounds of the integers. Returns `None` if the
+/// loop doesn't run at all, because the limit is beyond the integers on the side of
+/// the start.
+fn for_limit(end: &LuaVal, step: i64) -> Result<Option<i64>, LuaError> {
+    if end.is_int() {
+        return Ok(Some(end.to_int()?));
+    }
+    let end = end.to_float()?;
+    let end = if step > 0 { end.floor() } else { end.ceil() };
+    Ok(match float_to_int(end) {
+        Some(end) => Some(end),
+        // NaN is below the integers, so that the loop doesn't run either way
+        None if end > 0.0 => Some(i64::MAX).filter(|_| step > 0),
+        None => Some(i64::MIN).filter(|_| step < 0),
+    })
+}
+
+/// R(1), R(1 + 1), R(1 + 2) = start, end, step; if start and step are integers, then
+/// the loop is an integer loop, whose end is converted by `for_limit`, otherwise all
+/// three are converted to floats. If the loop doesn't run at all, then jump Arg(2)
+/// instructions.
+pub fn for_prep(vm: &mut Vm, instr: u32) -> Result<(), LuaError> {
+    let reg = vm.base + first_arg(instr) as usize;
+    let runs = {
+        let (start, end, step) = (&vm.stack[reg], &vm.stack[reg + 1], &vm.stack[reg + 2]);
+        for_number(start, "initial value")?;
+        for_number(end, "limit")?;
+        for_number(step, "step")?;
+        if start.is_int() && step.is_int() {
+            let (start, step) = (start.to_int()?, step.to_int()?);
+            if step == 0 {
+                return Err(LuaError::Error("'for' step is zero".to_string()));
+            }
+            match for_limit(end, step)? {
+                Some(end) => {
+                    vm.stack[reg] = LuaVal::from(start);
+                    vm.stack[reg + 1] = LuaVal::from(end);
+                    vm.stack[reg + 2] = LuaVal::from(step);
+                    in_range!(start, end, step, 0)
+                }
+                None => false,
+            }
+        } else {
+            let (start, end, step) = (start.to_float()?, end.to_float()?, step.to_float()?);
+            if step == 0.0 {
+                return Err(LuaError::Error("'for' step is zero".to_string()));
+            }
+            vm.stack[reg] = LuaVal::from(start);
+            vm.stack[reg + 1] = LuaVal::from(end);
+            vm.stack[reg + 2] = LuaVal::from(step);
+            in_range!(start, end, step, 0.0)
+        }
+    };
+    if !runs {
+        vm.pc = (vm.pc as isize + extended_arg(instr) as isize) as usize;
+    }
+    Ok(())
+}
+
+/// R(1) += R(1 + 2); if R(1) is still in the range of the loop, then jump Arg(2)
+/// instructions.
+pub fn for_loop(vm: &mut Vm, instr: u32) -> Result<(), LuaError> {
+    let reg = vm.base + first_arg(instr) as usize;
+    let next = if vm.stack[reg].is_int() {
+        let i = vm.stack[reg].to_int()?;
+        let end = vm.stack[reg + 1].to_int()?;
+        let step = vm.stack[reg + 2].to_int()?;
+        // the loop stops instead of wrapping around
+        match i.checked_add(step) {
+            Some(i) if in_range!(i, end, step, 0) => Some(LuaVal::from(i)),
+            _ => None,
+        }
+    } else {
+        let i = vm.stack[reg].to_float()?;
+        let end = vm.stack[reg + 1].to_float()?;
+        let step = vm.stack[reg + 2].to_float()?;
+        let i = i + step;
+        if in_range!(i, end, step, 0.0) {
+            Some(LuaVal::from(i))
+        } else {
+            None
+        }
+    };
+    if let Some(i) = next {
+        vm.stack[reg] = i;
+        vm.pc = (vm.pc as isize + extended_arg(instr) as isize) as usize;
+    }
+    Ok(())
+}
@@ -1,195 +1,281 @@
 use errors::LuaError;
-use lua_values::{lua_closure::UserFunction, LuaVal};
-use luacompiler::bytecode::instructions::*;
-use luacompiler::bytecode::instructions::{first_arg, second_arg};
+use gc::Gc;
+use lua_std::coroutine::return_from_thread;
+use lua_values::{
+    lua_closure::{LuaClosure, UserFunction},
+    lua_thread::ThreadStatus,
+    LuaVal,
+};
+use luacompiler::bytecode::instructions::{first_arg, second_arg, third_arg};
+use std::cmp::max;
 use StackFrame;
 use Vm;
 
 // R(1) = Closure(curr_function.child(R(2)).index())
 pub fn closure(vm: &mut Vm, instr: u32) -> Result<(), LuaError> {
     // Take the index of the function which is the child of the current function
-    let func = vm.bytecode.get_function(second_arg(instr) as usize);
+    let func = vm.chunks[vm.chunk]
+        .bytecode
+        .get_function(second_arg(instr) as usize);
+    // the function shares the _ENV of the chunk
+    let env = vm.stack_frames[vm.curr_frame].closure.get_upval(0)?.clone();
     let ufunc = UserFunction::new(
+        vm.chunk,
         func.index(),
         func.reg_count(),
         func.param_count(),
-        vec![vm.env.clone()],
+        vec![env],
     );
-    vm.registers[first_arg(instr) as usize] = LuaVal::from(ufunc);
+    vm.stack[vm.base + first_arg(instr) as usize] = LuaVal::from(ufunc);
     Ok(())
 }
 
-pub fn push(vm: &mut Vm, instr: u32) -> Result<(), LuaError> {
-    let val = vm.registers[first_arg(instr) as usize].clone();
-    vm.push(val);
-    let ret_val = vm.closure().ret_vals();
-    vm.closure()
-        .set_ret_vals(ret_val + third_arg(instr) as usize);
-    Ok(())
+/// See CALL in luacompiler/bytecode/instructions.rs.
+pub fn call(vm: &mut Vm, instr: u32) -> Result<(), LuaError> {
+    let func = vm.base + first_arg(instr) as usize;
+    let args_count = arg_count(vm, func, second_arg(instr));
+    let results = match third_arg(instr) {
+        0 => None,
+        c => Some(c as usize - 1),
+    };
+    call_function(vm, func, args_count, results, false)
 }
 
-pub fn set_top(vm: &mut Vm, instr: u32) -> Result<(), LuaError> {
-    let closure = vm.registers[first_arg(instr) as usize].get_closure()?;
+/// Call the function in stack[func] with the <args_count> values after it as arguments.
+/// The caller receives <results> return values in stack[func..], or all of them if it
+/// is `None`. The frame of a <protected> call returns true before the return values,
+/// and errors raised in it are caught, see `Vm::handle_error`.
+pub fn call_function(
+    vm: &mut Vm,
+    func: usize,
+    args_count: usize,
+    results: Option<usize>,
+    protected: bool,
+) -> Result<(), LuaError> {
+    if vm.stack_frames.len() > vm.max_call_depth {
+        return Err(LuaError::StackOverflow);
+    }
+    let (closure, this) = callee(vm, func)?;
+    let args_count = args_count + this.is_some() as usize;
+    // The frame of the callee starts after all registers of the caller, since any of
+    // them might still be in use, e.g. by a loop. The arguments are copied over:
+    // func, arg1, ..., argN  <------ the registers of the caller
+    // ...
+    // extra-arg1, ...        <------ the arguments which have no parameter (varargs)
+    // arg1, ...              <------ R(0), R(1), ... of the callee
+    // The first function of a coroutine has no caller.
+    let reg_count = vm.stack_frames.last().map_or(0, |f| f.closure.reg_count());
+    let start = max(vm.base + reg_count, func + 1 + args_count);
+    if vm.stack.len() < start + args_count {
+        vm.stack.resize(start + args_count, LuaVal::new());
+    }
+    match this {
+        // a userdata with a __call metamethod is its first argument
+        Some(this) => {
+            vm.stack[start] = this;
+            for i in 1..args_count {
+                vm.stack[start + i] = vm.stack[func + i].clone();
+            }
+        }
+        None => {
+            for i in 0..args_count {
+                vm.stack[start + i] = vm.stack[func + 1 + i].clone();
+            }
+        }
+    }
     vm.stack_frames.push(StackFrame {
         closure,
-        top: vm.top,
+        base: start,
+        varargs: 0,
+        ret_frame: vm.curr_frame,
+        ret_pc: vm.pc,
+        ret_reg: func,
+        results,
+        protected,
     });
-    Ok(())
+    vm.curr_frame = vm.stack_frames.len() - 1;
+    enter_frame(vm, start, args_count)
 }
 
-const MOVR_0_0_1: u32 = make_instr(Opcode::MOVR, 0, 0, 1);
-const MOVR_0_0_2: u32 = make_instr(Opcode::MOVR, 0, 0, 2);
+/// Ends the current function by calling R(1) in its place, see TAILCALL in
+/// luacompiler/bytecode/instructions.rs. The callee takes over the frame of the current
+/// function, so tail calls don't grow the stack.
+pub fn tail_call(vm: &mut Vm, instr: u32) -> Result<(), LuaError> {
+    let func = vm.base + first_arg(instr) as usize;
+    let (closure, this) = callee(vm, func)?;
+    let args_count = arg_count(vm, func, second_arg(instr));
+    // move the arguments to the start of the current frame, after the userdata with a
+    // __call metamethod, if it is called
+    let start = vm.varargs().0;
+    let offset = this.is_some() as usize;
+    if let Some(this) = this {
+        vm.stack[start] = this;
+    }
+    for i in 0..args_count {
+        vm.stack.swap(start + offset + i, func + 1 + i);
+    }
+    vm.stack_frames[vm.curr_frame].closure = closure;
+    enter_frame(vm, start, args_count + offset)
+}
 
-pub fn call(vm: &mut Vm, _instr: u32) -> Result<(), LuaError> {
-    // The frame of a function has the following structure:
-    // arg1       <------ closure.args_start()
-    // arg2
-    // ---------- <CALL> happens here, the caller pushes the arguments for the callee
-    //            vm.top is here when <CALL> is processed
-    // saved-reg1
-    // saved-reg2
-    // ---------- callee saves the registers which it is going to clobber
-    // ret-val1
-    // ret-val2
-    // ---------- vm.top is here when the callee is ready to return
+/// The closure which is called, and the userdata which is its first argument.
+type Callee = (Gc<Box<LuaClosure>>, Option<LuaVal>);
 
-    // save the state of the caller
-    let old_pc = vm.pc;
-    // update the current frame to the last one
-    let old_curr_frame = vm.curr_frame;
-    let args_start = vm.stack_frames.last().unwrap().top;
-    let args_count = vm.top - args_start;
-    vm.curr_frame = vm.stack_frames.len() - 1;
-    // push the first `reg_count` registers to the stack, as the called function
-    // will modify these
-    for i in 0..vm.closure().reg_count() {
-        let reg = vm.registers[i].clone();
-        vm.push(reg);
-    }
-    // prepare to move arguments into registers; the callee expects the parameters in its
-    // first N registers (excluding 0 which is _ENV), where N is the number of parameters
-    let mut index_of_arg = args_start;
-    // copy arguments into registers [R(1)..R(param_count)]
-    for i in 0..vm.closure().param_count() {
-        // if the caller didn't push enough arguments, we have to set the remaining
-        // parameter registers to nil, so that we don't use some value from the old frame
-        vm.registers[i] = if i < args_count {
-            vm.stack[index_of_arg].clone()
-        } else {
-            LuaVal::new()
-        };
-        index_of_arg += 1;
-    }
-    // jump to the called function
-    vm.closure().clone().call(vm)?;
-    // the called function might have pushed some return values; the exact number is
-    // encoded by <ret_vals>
-    let ret_vals = vm.closure().ret_vals();
-    // restore the registers of the caller
-    for (reg, i) in ((args_start + args_count)..(vm.top - ret_vals)).enumerate() {
-        std::mem::swap(&mut vm.registers[reg], &mut vm.stack[i]);
-    }
-    // restore the state of the caller
-    vm.closure().set_ret_vals(0);
-    vm.stack_frames.pop();
-    vm.curr_frame = old_curr_frame;
-    vm.pc = old_pc;
-    // if we returned values, then the next few instructions might move these into
-    // registers using the MOVR instruction
-    let index = vm.closure().index();
-    let len = vm.bytecode.get_function(index).instrs_len();
-    if vm.pc + 1 < len {
-        let mut instr = vm.bytecode.get_function(index).get_instr(vm.pc + 1);
-        // special MOVR cases, see luacompiler/bytecode/instructions.rs
-        // 001 is used to push all return values to the stack as arguments to another call
-        // 002 is used to push all return values to the stack as return values
-        if instr == MOVR_0_0_1 || instr == MOVR_0_0_2 {
-            // We are going to destroy this stackframe, so we might just as well copy
-            // our return values to where we expect them to be when we call the next
-            // function
-            for (i, r) in ((vm.top - ret_vals)..vm.top).enumerate() {
-                vm.stack.swap(r, args_start + i);
-            }
-            vm.pc += 1;
-            vm.top = args_start + ret_vals;
-            // if we are returning values, then the closure's ret_vals counter should be
-            // updated as well
-            if third_arg(instr) > 1 {
-                let curr_ret_vals = vm.closure().ret_vals();
-                vm.closure().set_ret_vals(curr_ret_vals + ret_vals);
-            }
+/// The closure which is called by calling stack[func]. Userdata are called with their
+/// __call metamethod, which also gets the userdata as its first argument.
+fn callee(vm: &Vm, func: usize) -> Result<Callee, LuaError> {
+    let val = &vm.stack[func];
+    match val.get_closure() {
+        Ok(closure) => Ok((closure, None)),
+        Err(err) => match val.metamethod("__call") {
+            Some(mm) => Ok((mm.get_closure()?, Some(val.clone()))),
+            None => Err(err),
+        },
+    }
+}
+
+/// Calls the metamethod <name> of the first value in <args> which has it, and returns
+/// its first result. Returns `None` if none of the values has the metamethod.
+pub fn call_metamethod(
+    vm: &mut Vm,
+    name: &str,
+    args: &[LuaVal],
+) -> Result<Option<LuaVal>, LuaError> {
+    let mm = match args
+        .iter()
+        .filter_map(|arg| vm.metamethod(arg, name))
+        .next()
+    {
+        Some(mm) => mm,
+        None => return Ok(None),
+    };
+    let res = vm.call(&mm, args)?;
+    Ok(Some(res.into_iter().next().unwrap_or_default()))
+}
+
+/// The number of arguments of a call to the function in stack[func], based on the B
+/// argument of the instruction.
+fn arg_count(vm: &Vm, func: usize, b: u8) -> usize {
+    match b {
+        0 => vm.top - func - 1,
+        b => b as usize - 1,
+    }
+}
+
+/// Set up the current frame for its closure, which is called with the <args_count>
+/// arguments in stack[start..], and start executing it.
+fn enter_frame(vm: &mut Vm, start: usize, args_count: usize) -> Result<(), LuaError> {
+    let closure = vm.closure().clone();
+    let param_count = closure.param_count();
+    // the arguments without a parameter are moved in front of the others
+    let varargs = args_count.saturating_sub(param_count);
+    if varargs > 0 {
+        vm.stack[start..(start + args_count)].rotate_left(param_count);
+    }
+    let base = start + varargs;
+    let end = base + max(closure.reg_count(), param_count);
+    if vm.stack.len() < end {
+        vm.stack.resize(end, LuaVal::new());
+    }
+    // the parameters which didn't get an argument are nil
+    for i in (args_count - varargs)..param_count {
+        vm.stack[base + i] = LuaVal::new();
+    }
+    {
+        let frame = &mut vm.stack_frames[vm.curr_frame];
+        frame.base = base;
+        frame.varargs = varargs;
+    }
+    vm.base = base;
+    vm.top = base;
+    closure.call(vm)
+}
+
+/// Leave the current frame, and give the <count> values in stack[first..] to the
+/// caller, which continues from the instruction after the call.
+pub fn return_to_caller(vm: &mut Vm, first: usize, count: usize) -> Result<(), LuaError> {
+    let frame = vm.stack_frames.pop().unwrap();
+    if frame.protected {
+        // the function was called by pcall, which returns true and the return values
+        // of the function
+        vm.stack[frame.ret_reg] = LuaVal::from(true);
+        for i in 0..count {
+            vm.stack.swap(frame.ret_reg + 1 + i, first + i);
+        }
+        vm.curr_frame = frame.ret_frame;
+        return return_to_caller(vm, frame.ret_reg, count + 1);
+    }
+    if vm.stack_frames.is_empty() {
+        // the first function of a coroutine returned
+        let vals = vm.stack[first..(first + count)].to_vec();
+        return return_from_thread(vm, vals, ThreadStatus::Dead);
+    }
+    let results = frame.results.unwrap_or(count);
+    // the return values are moved down, so the regions can overlap
+    for i in 0..results {
+        if i < count {
+            vm.stack.swap(frame.ret_reg + i, first + i);
         } else {
-            while opcode(instr) == Opcode::MOVR as u8 {
-                let from = second_arg(instr) as usize;
-                // if we don't have enough return values to unpack, we return nils
-                vm.registers[first_arg(instr) as usize] = if from < ret_vals {
-                    vm.stack[vm.top - (ret_vals - from)].clone()
-                } else {
-                    LuaVal::new()
-                };
-                vm.pc += 1;
-                if vm.pc + 1 < len {
-                    instr = vm.bytecode.get_function(index).get_instr(vm.pc + 1);
-                } else {
-                    break;
-                }
-            }
-            // "destroy" our stack frame
-            vm.top = args_start;
+            vm.stack[frame.ret_reg + i] = LuaVal::new();
         }
-    } else {
-        vm.top = args_start;
     }
+    vm.top = frame.ret_reg + results;
+    vm.curr_frame = frame.ret_frame;
+    vm.base = vm.stack_frames[vm.curr_frame].base;
+    vm.pc = frame.ret_pc;
     Ok(())
 }
 
+/// Leave the current frame, and give <vals> to the caller.
+pub fn return_values(vm: &mut Vm, vals: Vec<LuaVal>) -> Result<(), LuaError> {
+    let count = vals.len();
+    for val in vals {
+        vm.push(val);
+    }
+    let first = vm.top - count;
+    return_to_caller(vm, first, count)
+}
+
+/// See VarArg in luacompiler/bytecode/instructions.rs.
 pub fn vararg(vm: &mut Vm, instr: u32) -> Result<(), LuaError> {
-    if third_arg(instr) > 0 {
-        let (args_start, args_count, param_count) = {
-            let curr_frame = &vm.stack_frames[vm.curr_frame];
-            let start = curr_frame.top;
-            (
-                start,
-                vm.top - start - curr_frame.closure.reg_count(),
-                curr_frame.closure.param_count(),
-            )
-        };
-        // make sure to skip the arguments which are the actual parameters
-        for i in (args_start + param_count)..(args_start + args_count) {
-            let val = vm.stack[i].clone();
-            vm.push(val);
-        }
-        if third_arg(instr) > 1 {
-            let curr_frame = &mut vm.stack_frames[vm.curr_frame];
-            let ret_val = curr_frame.closure.ret_vals();
-            curr_frame
-                .closure
-                .set_ret_vals(ret_val + args_count - param_count);
-        }
-    } else {
-        let curr_frame = &vm.stack_frames[vm.curr_frame];
-        let args_start = curr_frame.top;
-        let var_args_start = args_start + curr_frame.closure.param_count();
-        let from = second_arg(instr) as usize;
-        let args_count = vm.top - args_start - curr_frame.closure.reg_count();
-        // if we don't have enough varargs to unpack, we return nils
-        vm.registers[first_arg(instr) as usize] = if var_args_start + from < args_start + args_count
-        {
-            vm.stack[var_args_start + from].clone()
+    let dest = vm.base + first_arg(instr) as usize;
+    let (start, varargs) = vm.varargs();
+    let count = match second_arg(instr) {
+        0 => varargs,
+        b => b as usize - 1,
+    };
+    if vm.stack.len() < dest + count {
+        vm.stack.resize(dest + count, LuaVal::new());
+    }
+    // if we don't have enough varargs to unpack, we return nils
+    for i in 0..count {
+        vm.stack[dest + i] = if i < varargs {
+            vm.stack[start + i].clone()
         } else {
             LuaVal::new()
         };
     }
+    if second_arg(instr) == 0 {
+        vm.top = dest + count;
+    }
     Ok(())
 }
 
-pub fn movr(_vm: &mut Vm, _instr: u32) -> Result<(), LuaError> {
-    panic!("This should be handled by <call>.")
-}
-
-pub fn ret(vm: &mut Vm, _instr: u32) -> Result<(), LuaError> {
-    let index = vm.closure().index();
-    let len = vm.bytecode.get_function(index).instrs_len();
-    vm.pc = len;
-    Ok(())
+/// See RET in luacompiler/bytecode/instructions.rs.
+pub fn ret(vm: &mut Vm, instr: u32) -> Result<(), LuaError> {
+    // the main function has no caller, so its return values are dropped
+    if vm.is_main_frame() {
+        let index = vm.closure().index();
+        vm.pc = vm.chunks[vm.chunk]
+            .bytecode
+            .get_function(index)
+            .instrs_len();
+        return Ok(());
+    }
+    let first = vm.base + first_arg(instr) as usize;
+    let count = match second_arg(instr) {
+        0 => vm.top - first,
+        b => b as usize - 1,
+    };
+    return_to_caller(vm, first, count)
 }
@@ -6,27 +6,27 @@ use Vm;
 pub fn mov(vm: &mut Vm, instr: u32) -> Result<(), LuaError> {
     let i = first_arg(instr) as usize;
     let j = second_arg(instr) as usize;
-    vm.registers[i] = vm.registers[j].clone();
+    vm.stack[vm.base + i] = vm.stack[vm.base + j].clone();
     Ok(())
 }
 
 pub fn ldi(vm: &mut Vm, instr: u32) -> Result<(), LuaError> {
-    let val = vm.bytecode.get_int(second_arg(instr));
-    vm.registers[first_arg(instr) as usize] = LuaVal::from(val);
+    let val = vm.chunks[vm.chunk].bytecode.get_int(second_arg(instr));
+    vm.stack[vm.base + first_arg(instr) as usize] = LuaVal::from(val);
     Ok(())
 }
 
 pub fn ldf(vm: &mut Vm, instr: u32) -> Result<(), LuaError> {
-    let val = vm.bytecode.get_float(second_arg(instr));
-    vm.registers[first_arg(instr) as usize] = LuaVal::from(val);
+    let val = vm.chunks[vm.chunk].bytecode.get_float(second_arg(instr));
+    vm.stack[vm.base + first_arg(instr) as usize] = LuaVal::from(val);
     Ok(())
 }
 
 pub fn lds(vm: &mut Vm, instr: u32) -> Result<(), LuaError> {
     let arg2 = second_arg(instr);
-    let val = vm.bytecode.get_string(arg2);
-    // we also want to save the index of the string in the constant table in order to
-    // speed up lookups in _ENV
-    vm.registers[first_arg(instr) as usize] = LuaVal::from((val.to_string(), arg2 as usize));
+    // the string also holds its index in the string table of the interpreter in order
+    // to speed up lookups in _ENV
+    let val = vm.chunks[vm.chunk].strings[arg2 as usize].clone();
+    vm.stack[vm.base + first_arg(instr) as usize] = val;
     Ok(())
 }
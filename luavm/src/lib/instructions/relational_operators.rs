@@ -1,17 +1,79 @@
 use errors::LuaError;
+use instructions::functions::call_metamethod;
 use lua_values::LuaVal;
-use luacompiler::bytecode::instructions::{first_arg, second_arg, third_arg};
+use luacompiler::bytecode::instructions::{extended_arg, first_arg, second_arg, third_arg};
+use std::cmp::Ordering;
 use Vm;
 
+/// Compares the values in R(2) and R(3) with `$op`, or with the metamethods of userdata.
+macro_rules! compare {
+    ($vm: ident, $instr: ident, $op: tt) => {{
+        let lhs = &$vm.stack[$vm.base + second_arg($instr) as usize];
+        let rhs = &$vm.stack[$vm.base + third_arg($instr) as usize];
+        if lhs.is_userdata() || rhs.is_userdata() {
+            let (lhs, rhs) = (lhs.clone(), rhs.clone());
+            compare_userdata($vm, stringify!($op), lhs, rhs)?
+        } else {
+            lhs $op rhs
+        }
+    }};
+}
+
+/// Compares <lhs> and <rhs>, one of which is a userdata, with the `__eq`, `__lt` or
+/// `__le` metamethod which corresponds to <op>.
+fn compare_userdata(vm: &mut Vm, op: &str, lhs: LuaVal, rhs: LuaVal) -> Result<bool, LuaError> {
+    let (mm, args, negate) = match op {
+        "==" => ("__eq", [lhs, rhs], false),
+        "!=" => ("__eq", [lhs, rhs], true),
+        "<" => ("__lt", [lhs, rhs], false),
+        ">" => ("__lt", [rhs, lhs], false),
+        "<=" => ("__le", [lhs, rhs], false),
+        ">=" => ("__le", [rhs, lhs], false),
+        _ => unreachable!(),
+    };
+    if mm == "__eq" {
+        // __eq is only used for two different userdata
+        if args[0] == args[1] || !args[0].is_userdata() || !args[1].is_userdata() {
+            return Ok((args[0] == args[1]) != negate);
+        }
+        let eq = call_metamethod(vm, mm, &args)?.map_or(false, |res| res.to_bool());
+        return Ok(eq != negate);
+    }
+    match call_metamethod(vm, mm, &args)? {
+        Some(res) => Ok(res.to_bool()),
+        None => Err(LuaError::Error(format!(
+            "attempt to compare {} with {}",
+            args[0].type_name(),
+            args[1].type_name()
+        ))),
+    }
+}
+
+/// Checks whether <lhs> < <rhs>, in the same way as the `<` operator, except that the
+/// values which cannot be ordered raise an error instead of being unordered.
+pub fn less_than(vm: &mut Vm, lhs: &LuaVal, rhs: &LuaVal) -> Result<bool, LuaError> {
+    if lhs.is_userdata() || rhs.is_userdata() {
+        return compare_userdata(vm, "<", lhs.clone(), rhs.clone());
+    }
+    match lhs.partial_cmp(rhs) {
+        Some(ord) => Ok(ord == Ordering::Less),
+        None if lhs.type_name() == rhs.type_name() => Err(LuaError::Error(format!(
+            "attempt to compare two {} values",
+            lhs.type_name()
+        ))),
+        None => Err(LuaError::Error(format!(
+            "attempt to compare {} with {}",
+            lhs.type_name(),
+            rhs.type_name()
+        ))),
+    }
+}
+
 macro_rules! rel_op {
     ($name: tt, $op: tt) => {
         pub fn $name(vm: &mut Vm, instr: u32) -> Result<(), LuaError> {
-            let res = {
-                let lhs = &vm.registers[second_arg(instr) as usize];
-                let rhs = &vm.registers[third_arg(instr) as usize];
-                LuaVal::from(lhs $op rhs)
-            };
-            vm.registers[first_arg(instr) as usize] = res;
+            let res = LuaVal::from(compare!(vm, instr, $op));
+            vm.stack[vm.base + first_arg(instr) as usize] = res;
             Ok(())
         }
     };
@@ -23,3 +85,29 @@ rel_op!(gt, >);
 rel_op!(le, <=);
 rel_op!(ge, >=);
 rel_op!(ne, !=);
+
+/// Generates a compare-and-jump handler: if `R(2) $op R(3)` is equal to the polarity
+/// in Arg(1), then the jump which follows the instruction is taken, otherwise it is
+/// skipped.
+macro_rules! rel_jmp {
+    ($name: tt, $op: tt) => {
+        pub fn $name(vm: &mut Vm, instr: u32) -> Result<(), LuaError> {
+            let res = compare!(vm, instr, $op);
+            if res == (first_arg(instr) != 0) {
+                let index = vm.closure().index();
+                let jmp = vm.chunks[vm.chunk]
+                    .bytecode
+                    .get_function(index)
+                    .get_instr(vm.pc);
+                vm.pc = (vm.pc as isize + 1 + extended_arg(jmp) as isize) as usize;
+            } else {
+                vm.pc += 1;
+            }
+            Ok(())
+        }
+    };
+}
+
+rel_jmp!(lt_jmp, <);
+rel_jmp!(le_jmp, <=);
+rel_jmp!(eq_jmp, ==);
@@ -1,4 +1,6 @@
 use errors::LuaError;
+use instructions::functions::call_metamethod;
+use lua_values::LuaVal;
 use luacompiler::bytecode::instructions::{first_arg, second_arg, third_arg};
 use Vm;
 
@@ -6,18 +8,51 @@ use Vm;
 pub fn get_attr(vm: &mut Vm, instr: u32) -> Result<(), LuaError> {
     let val = {
         let arg2 = second_arg(instr) as usize;
-        let from = &vm.registers[arg2];
-        let attr = &vm.registers[third_arg(instr) as usize];
-        from.get_attr(attr)?
+        let from = &vm.stack[vm.base + arg2];
+        let attr = &vm.stack[vm.base + third_arg(instr) as usize];
+        // strings are indexed with the __index of their metatable, i.e. `string`
+        let val = if from.is_string() {
+            LuaVal::new()
+        } else {
+            from.get_attr(attr)?
+        };
+        // the missing fields of userdata are looked up with __index
+        if val.is_nil() && (from.is_userdata() || from.is_string()) {
+            let (from, attr) = (from.clone(), attr.clone());
+            index(vm, from, attr)?
+        } else {
+            val
+        }
     };
-    vm.registers[first_arg(instr) as usize] = val;
+    vm.stack[vm.base + first_arg(instr) as usize] = val;
     Ok(())
 }
 
+/// Looks up <attr> with the __index metamethod of <from>, which is either a function
+/// or a table.
+fn index(vm: &mut Vm, from: LuaVal, attr: LuaVal) -> Result<LuaVal, LuaError> {
+    match vm.metamethod(&from, "__index") {
+        Some(ref mm) if mm.is_table() => mm.get_attr(&attr),
+        Some(_) => Ok(call_metamethod(vm, "__index", &[from, attr])?.unwrap()),
+        None => Ok(LuaVal::new()),
+    }
+}
+
 /// R(1)[R(2)] = R(3)
 pub fn set_attr(vm: &mut Vm, instr: u32) -> Result<(), LuaError> {
-    let attr = vm.registers[second_arg(instr) as usize].clone();
-    let val = vm.registers[third_arg(instr) as usize].clone();
+    let attr = vm.stack[vm.base + second_arg(instr) as usize].clone();
+    let val = vm.stack[vm.base + third_arg(instr) as usize].clone();
     let arg1 = first_arg(instr) as usize;
-    vm.registers[arg1].set_attr(attr, val)
+    let to = &vm.stack[vm.base + arg1];
+    if let Some(userdata) = to.get_userdata() {
+        // the fields which can't be set are handled by __newindex
+        if !userdata.set_field(&attr, &val)? {
+            let to = to.clone();
+            return call_metamethod(vm, "__newindex", &[to, attr, val])?
+                .map(|_| ())
+                .ok_or(LuaError::SetAttrErr);
+        }
+        return Ok(());
+    }
+    to.set_attr(attr, val)
 }
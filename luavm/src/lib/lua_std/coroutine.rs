@@ -0,0 +1,190 @@
+use crate::Vm;
+use errors::LuaError;
+use gc::Gc;
+use instructions::functions::{call_function, return_values};
+use lua_values::{
+    lua_closure::ControlFunction,
+    lua_table::UserTable,
+    lua_thread::{LuaThread, ThreadStatus},
+    LuaVal,
+};
+use std::collections::HashMap;
+use stdlib::StdFunction;
+
+pub fn get_coroutine_module() -> (String, LuaVal) {
+    let coroutine = LuaVal::from(UserTable::new(HashMap::new()));
+    for func in &[
+        ("create", lua_create as fn(&mut Vm) -> Result<(), LuaError>),
+        ("status", lua_status),
+        ("wrap", lua_wrap),
+        ("isyieldable", lua_isyieldable),
+        ("running", lua_running),
+    ] {
+        let std_func = StdFunction {
+            name: func.0,
+            handler: func.1,
+        };
+        coroutine
+            .set_attr(LuaVal::from(func.0.to_string()), LuaVal::from(&std_func))
+            .unwrap();
+    }
+    for func in &[
+        ("resume", lua_resume as fn(&mut Vm) -> Result<(), LuaError>),
+        ("yield", lua_yield),
+    ] {
+        coroutine
+            .set_attr(
+                LuaVal::from(func.0.to_string()),
+                LuaVal::from(ControlFunction::new(func.1, vec![]).with_name(func.0)),
+            )
+            .unwrap();
+    }
+    ("coroutine".to_string(), coroutine)
+}
+
+/// Gets the thread which is the first argument of <name>.
+fn thread_arg(vm: &Vm, name: &str) -> Result<Gc<LuaThread>, LuaError> {
+    let (args_start, args_count) = vm.varargs();
+    if args_count > 0 {
+        if let Some(thread) = vm.stack[args_start].get_thread() {
+            return Ok(thread);
+        }
+    }
+    Err(LuaError::Error(format!(
+        "bad argument #1 to '{}' (coroutine expected)",
+        name
+    )))
+}
+
+/// Creates a thread which calls the first argument of <name>.
+fn new_thread(vm: &Vm, name: &str) -> Result<Gc<LuaThread>, LuaError> {
+    let (args_start, args_count) = vm.varargs();
+    if args_count == 0 || vm.stack[args_start].get_closure().is_err() {
+        return Err(LuaError::Error(format!(
+            "bad argument #1 to '{}' (function expected)",
+            name
+        )));
+    }
+    Ok(Gc::new(LuaThread::new(vm.stack[args_start].clone())))
+}
+
+/// Leave the running coroutine, and give <vals> to the function which resumed it.
+pub fn return_from_thread(
+    vm: &mut Vm,
+    mut vals: Vec<LuaVal>,
+    status: ThreadStatus,
+) -> Result<(), LuaError> {
+    let thread = vm.leave_thread(status);
+    if !thread.wrapped() {
+        vals.insert(0, LuaVal::from(true));
+    }
+    return_values(vm, vals)
+}
+
+/// Continue the execution in <thread>, which receives <args>.
+fn resume(
+    vm: &mut Vm,
+    thread: Gc<LuaThread>,
+    args: Vec<LuaVal>,
+    wrapped: bool,
+) -> Result<(), LuaError> {
+    let msg = match thread.status() {
+        ThreadStatus::Suspended => None,
+        ThreadStatus::Dead => Some("cannot resume dead coroutine"),
+        _ => Some("cannot resume non-suspended coroutine"),
+    };
+    if let Some(msg) = msg {
+        if wrapped {
+            return Err(LuaError::Error(msg.to_string()));
+        }
+        return return_values(vm, vec![LuaVal::from(false), LuaVal::from(msg.to_string())]);
+    }
+    thread.set_wrapped(wrapped);
+    vm.enter_thread(thread);
+    if vm.stack_frames.is_empty() {
+        // the thread starts by calling its function, which is at the bottom of its
+        // stack
+        let args_count = args.len();
+        for arg in args {
+            vm.push(arg);
+        }
+        call_function(vm, 0, args_count, None, false)
+    } else {
+        // the thread continues by returning from coroutine.yield
+        return_values(vm, args)
+    }
+}
+
+pub fn lua_create(vm: &mut Vm) -> Result<(), LuaError> {
+    let thread = new_thread(vm, "create")?;
+    vm.push(LuaVal::from(thread));
+    vm.closure().set_ret_vals(1);
+    Ok(())
+}
+
+pub fn lua_resume(vm: &mut Vm) -> Result<(), LuaError> {
+    let thread = thread_arg(vm, "resume")?;
+    let (args_start, args_count) = vm.varargs();
+    let args = vm.stack[(args_start + 1)..(args_start + args_count)].to_vec();
+    resume(vm, thread, args, false)
+}
+
+pub fn lua_yield(vm: &mut Vm) -> Result<(), LuaError> {
+    if vm.threads.len() == 1 {
+        return Err(LuaError::Error(
+            "attempt to yield from outside a coroutine".to_string(),
+        ));
+    }
+    // the Rust code which called into Lua cannot be suspended
+    if vm.host_calls > 0 {
+        return Err(LuaError::Error(
+            "attempt to yield across a C-call boundary".to_string(),
+        ));
+    }
+    let (args_start, args_count) = vm.varargs();
+    let vals = vm.stack[args_start..(args_start + args_count)].to_vec();
+    return_from_thread(vm, vals, ThreadStatus::Suspended)
+}
+
+pub fn lua_status(vm: &mut Vm) -> Result<(), LuaError> {
+    let thread = thread_arg(vm, "status")?;
+    vm.push(LuaVal::from(thread.status().name().to_string()));
+    vm.closure().set_ret_vals(1);
+    Ok(())
+}
+
+/// Creates a function which resumes a new thread. Unlike coroutine.resume, the
+/// function only returns the values passed to coroutine.yield, and it propagates
+/// errors.
+pub fn lua_wrap(vm: &mut Vm) -> Result<(), LuaError> {
+    let thread = new_thread(vm, "wrap")?;
+    let upvals = vec![Gc::new(LuaVal::from(thread))];
+    vm.push(LuaVal::from(ControlFunction::new(resume_wrapped, upvals)));
+    vm.closure().set_ret_vals(1);
+    Ok(())
+}
+
+/// The handler of the functions created by coroutine.wrap.
+fn resume_wrapped(vm: &mut Vm) -> Result<(), LuaError> {
+    let thread = vm.closure().get_upval(0)?.get_thread().unwrap();
+    let (args_start, args_count) = vm.varargs();
+    let args = vm.stack[args_start..(args_start + args_count)].to_vec();
+    resume(vm, thread, args, true)
+}
+
+pub fn lua_isyieldable(vm: &mut Vm) -> Result<(), LuaError> {
+    let yieldable = vm.threads.len() > 1 && vm.host_calls == 0;
+    vm.push(LuaVal::from(yieldable));
+    vm.closure().set_ret_vals(1);
+    Ok(())
+}
+
+/// Returns the running thread, and whether it is the main thread.
+pub fn lua_running(vm: &mut Vm) -> Result<(), LuaError> {
+    let thread = vm.threads.last().unwrap().clone();
+    let is_main = vm.threads.len() == 1;
+    vm.push(LuaVal::from(thread));
+    vm.push(LuaVal::from(is_main));
+    vm.closure().set_ret_vals(2);
+    Ok(())
+}
@@ -0,0 +1,339 @@
+//! The directives of `string.format`, which follow the ones of C's `printf`.
+use errors::LuaError;
+use lua_values::LuaVal;
+
+/// The flags which can precede the width of a directive.
+const FLAGS: &[u8] = b"-+ #0";
+
+/// A directive such as `%-5.2f`.
+#[derive(Default)]
+pub struct Spec {
+    /// The value is aligned to the left of the width ('-').
+    left: bool,
+    /// Positive numbers are preceded by '+'.
+    plus: bool,
+    /// Positive numbers are preceded by a space, unless `plus` is set.
+    space: bool,
+    /// The alternate form ('#'), e.g. `0x` before hexadecimal numbers.
+    alt: bool,
+    /// Numbers are padded with zeros instead of spaces.
+    zero: bool,
+    width: usize,
+    precision: Option<usize>,
+    /// The conversion, e.g. 'f', or 0 if the format string ends before it.
+    pub conv: u8,
+}
+
+/// Parses the directive which starts at <fmt>[i], right after its '%'. Returns the
+/// directive and the index of the byte which follows it.
+pub fn parse_spec(fmt: &[u8], mut i: usize) -> Result<(Spec, usize), LuaError> {
+    let mut spec = Spec::default();
+    let start = i;
+    while let Some(c) = fmt.get(i).filter(|c| FLAGS.contains(c)) {
+        match c {
+            b'-' => spec.left = true,
+            b'+' => spec.plus = true,
+            b' ' => spec.space = true,
+            b'#' => spec.alt = true,
+            _ => spec.zero = true,
+        }
+        i += 1;
+    }
+    if i - start > FLAGS.len() {
+        return Err(LuaError::Error(
+            "invalid format (repeated flags)".to_string(),
+        ));
+    }
+    // the width and the precision have 2 digits at most
+    let digits = |i: &mut usize| {
+        let mut n = 0;
+        for _ in 0..2 {
+            match fmt.get(*i) {
+                Some(c) if c.is_ascii_digit() => n = n * 10 + (c - b'0') as usize,
+                _ => break,
+            }
+            *i += 1;
+        }
+        n
+    };
+    spec.width = digits(&mut i);
+    if fmt.get(i) == Some(&b'.') {
+        i += 1;
+        spec.precision = Some(digits(&mut i));
+    }
+    if fmt.get(i).is_some_and(u8::is_ascii_digit) {
+        return Err(LuaError::Error(
+            "invalid format (width or precision too long)".to_string(),
+        ));
+    }
+    spec.conv = fmt.get(i).cloned().unwrap_or(0);
+    Ok((spec, i + 1))
+}
+
+/// The sign of a number, which is omitted for positive numbers unless the directive has
+/// the '+' or the ' ' flag.
+fn sign(spec: &Spec, negative: bool) -> &'static str {
+    if negative {
+        "-"
+    } else if spec.plus {
+        "+"
+    } else if spec.space {
+        " "
+    } else {
+        ""
+    }
+}
+
+/// Appends <prefix> and <body> to <out>, padded to the width of <spec>. Zeros are put
+/// between the prefix (a sign or `0x`) and the body if <zero> is true, and spaces around
+/// both otherwise.
+fn pad(spec: &Spec, prefix: &str, body: &[u8], zero: bool, out: &mut Vec<u8>) {
+    let fill = spec.width.saturating_sub(prefix.len() + body.len());
+    if !spec.left && !zero {
+        out.extend(std::iter::repeat_n(b' ', fill));
+    }
+    out.extend_from_slice(prefix.as_bytes());
+    if !spec.left && zero {
+        out.extend(std::iter::repeat_n(b'0', fill));
+    }
+    out.extend_from_slice(body);
+    if spec.left {
+        out.extend(std::iter::repeat_n(b' ', fill));
+    }
+}
+
+/// Formats the integer <val> with the conversion 'd', 'i', 'u', 'o', 'x' or 'X'. The
+/// last four treat negative numbers as unsigned.
+pub fn format_int(spec: &Spec, val: i64, out: &mut Vec<u8>) {
+    let (prefix, mut digits) = match spec.conv {
+        b'd' | b'i' => (sign(spec, val < 0), val.unsigned_abs().to_string()),
+        b'u' => ("", (val as u64).to_string()),
+        b'o' => ("", format!("{:o}", val as u64)),
+        b'x' => (
+            if spec.alt && val != 0 { "0x" } else { "" },
+            format!("{:x}", val),
+        ),
+        _ => (
+            if spec.alt && val != 0 { "0X" } else { "" },
+            format!("{:X}", val),
+        ),
+    };
+    // the precision is the minimum number of digits
+    if let Some(precision) = spec.precision {
+        if precision == 0 && val == 0 {
+            digits.clear();
+        } else if digits.len() < precision {
+            digits = format!("{:0>1$}", digits, precision);
+        }
+    }
+    if spec.conv == b'o' && spec.alt && !digits.starts_with('0') {
+        digits.insert(0, '0');
+    }
+    let zero = spec.zero && spec.precision.is_none();
+    pad(spec, prefix, digits.as_bytes(), zero, out);
+}
+
+/// Formats the byte <c>.
+pub fn format_char(spec: &Spec, c: u8, out: &mut Vec<u8>) {
+    pad(spec, "", &[c], false, out);
+}
+
+/// Formats the string <s>, which is cut at the precision.
+pub fn format_str(spec: &Spec, s: &[u8], out: &mut Vec<u8>) {
+    let len = spec.precision.map_or(s.len(), |p| p.min(s.len()));
+    pad(spec, "", &s[..len], false, out);
+}
+
+/// Formats the float <val> with the conversion 'f', 'F', 'e', 'E', 'g', 'G', 'a' or 'A'.
+pub fn format_float(spec: &Spec, val: f64, out: &mut Vec<u8>) {
+    let upper = spec.conv.is_ascii_uppercase();
+    let prefix = sign(spec, val.is_sign_negative());
+    let abs = val.abs();
+    if !val.is_finite() {
+        let body = if val.is_nan() { "nan" } else { "inf" };
+        let body = if upper {
+            body.to_uppercase()
+        } else {
+            body.to_string()
+        };
+        return pad(spec, prefix, body.as_bytes(), false, out);
+    }
+    let precision = spec.precision.unwrap_or(6);
+    let (prefix, body) = match spec.conv.to_ascii_lowercase() {
+        b'f' => (prefix.to_string(), fixed(abs, precision, spec.alt)),
+        b'e' => (
+            prefix.to_string(),
+            exponent(abs, precision, spec.alt, upper),
+        ),
+        b'g' => (prefix.to_string(), general(abs, precision, spec.alt, upper)),
+        _ => {
+            let body = hex_float(abs, spec.precision, spec.alt);
+            if upper {
+                (format!("{}0X", prefix), body.to_uppercase())
+            } else {
+                (format!("{}0x", prefix), body)
+            }
+        }
+    };
+    pad(spec, &prefix, body.as_bytes(), spec.zero, out);
+}
+
+/// Formats <val> with <precision> decimals, i.e. `%f`.
+fn fixed(val: f64, precision: usize, alt: bool) -> String {
+    let mut s = format!("{:.*}", precision, val);
+    if alt && precision == 0 {
+        s.push('.');
+    }
+    s
+}
+
+/// Formats <val> as a digit, <precision> decimals and an exponent of at least two
+/// digits, i.e. `%e`.
+fn exponent(val: f64, precision: usize, alt: bool, upper: bool) -> String {
+    let s = format!("{:.*e}", precision, val);
+    let (mantissa, exp) = s.split_at(s.find('e').unwrap());
+    let exp: i32 = exp[1..].parse().unwrap();
+    format!(
+        "{}{}{}{}{:02}",
+        mantissa,
+        if alt && precision == 0 { "." } else { "" },
+        if upper { 'E' } else { 'e' },
+        if exp < 0 { '-' } else { '+' },
+        exp.abs()
+    )
+}
+
+/// Formats <val> with <precision> significant digits, i.e. `%g`: `%e` is used for very
+/// small or large numbers, and `%f` otherwise. The trailing zeros of the decimals are
+/// removed, unless <alt> is true.
+fn general(val: f64, precision: usize, alt: bool, upper: bool) -> String {
+    let precision = precision.max(1);
+    // the exponent of the number after it is rounded
+    let exp: i32 = if val == 0.0 {
+        0
+    } else {
+        let s = format!("{:.*e}", precision - 1, val);
+        s[(s.find('e').unwrap() + 1)..].parse().unwrap()
+    };
+    let s = if exp < precision as i32 && exp >= -4 {
+        fixed(val, (precision as i32 - 1 - exp) as usize, alt)
+    } else {
+        exponent(val, precision - 1, alt, upper)
+    };
+    if alt {
+        return s;
+    }
+    let (mantissa, exp) = s.split_at(s.find(['e', 'E']).unwrap_or(s.len()));
+    let mantissa = if mantissa.contains('.') {
+        mantissa.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        mantissa
+    };
+    format!("{}{}", mantissa, exp)
+}
+
+/// Formats <val> as a hexadecimal float without its `0x` prefix, i.e. `%a`. Without a
+/// precision, all the digits which are needed to represent it exactly are shown.
+fn hex_float(val: f64, precision: Option<usize>, alt: bool) -> String {
+    // the number of hexadecimal digits of the fraction of a f64
+    const DIGITS: usize = 13;
+    let bits = val.to_bits();
+    let biased_exp = (bits >> 52) & 0x7ff;
+    let mut frac = bits & ((1 << 52) - 1);
+    let (mut lead, exp) = if val == 0.0 {
+        (0, 0)
+    } else if biased_exp == 0 {
+        // subnormal numbers
+        (0, -1022)
+    } else {
+        (1, biased_exp as i64 - 1023)
+    };
+    let mut digits = match precision {
+        Some(p) if p < DIGITS => {
+            // round half to even to the given number of digits
+            let shift = 4 * (DIGITS - p);
+            let rest = frac & ((1 << shift) - 1);
+            let half = 1 << (shift - 1);
+            frac >>= shift;
+            // the last digit which is kept is the leading one if there are no decimals
+            let odd = if p == 0 { lead & 1 == 1 } else { frac & 1 == 1 };
+            if rest > half || (rest == half && odd) {
+                frac += 1;
+                if frac >> (4 * p) != 0 {
+                    frac = 0;
+                    lead += 1;
+                }
+            }
+            if p == 0 {
+                String::new()
+            } else {
+                format!("{:01$x}", frac, p)
+            }
+        }
+        _ => format!("{:013x}", frac),
+    };
+    match precision {
+        Some(p) if p > DIGITS => digits.extend(std::iter::repeat_n('0', p - DIGITS)),
+        None => digits.truncate(digits.trim_end_matches('0').len()),
+        _ => {}
+    }
+    let point = if digits.is_empty() && !alt { "" } else { "." };
+    format!("{}{}{}p{:+}", lead, point, digits, exp)
+}
+
+/// Appends <val> in a form which can be read back by Lua, i.e. `%q`. Returns an error
+/// if the value has no literal form.
+pub fn quote(val: &LuaVal, out: &mut Vec<u8>) -> Result<(), LuaError> {
+    if val.is_string() {
+        quote_string(&val.to_bytes()?, out);
+    } else if let Some(int) = val.as_int() {
+        // the minimum integer cannot be written in decimal, as it would be read as a
+        // float
+        if int == i64::MIN {
+            out.extend_from_slice(format!("0x{:x}", int).as_bytes());
+        } else {
+            out.extend_from_slice(int.to_string().as_bytes());
+        }
+    } else if val.is_number() {
+        let float = val.to_float()?;
+        let s = if float.is_nan() {
+            "(0/0)".to_string()
+        } else if float.is_infinite() {
+            if float > 0.0 { "1e9999" } else { "-1e9999" }.to_string()
+        } else {
+            let sign = if float.is_sign_negative() { "-" } else { "" };
+            format!("{}0x{}", sign, hex_float(float.abs(), None, false))
+        };
+        out.extend_from_slice(s.as_bytes());
+    } else if val.is_nil() || val.is_bool() {
+        out.extend_from_slice(val.to_string()?.as_bytes());
+    } else {
+        return Err(LuaError::Error("value has no literal form".to_string()));
+    }
+    Ok(())
+}
+
+/// Appends <s> in quotes, where the quotes, backslashes, new lines and control
+/// characters are escaped.
+fn quote_string(s: &[u8], out: &mut Vec<u8>) {
+    out.push(b'"');
+    for (i, &c) in s.iter().enumerate() {
+        match c {
+            b'"' | b'\\' | b'\n' => {
+                out.push(b'\\');
+                out.push(c);
+            }
+            c if c.is_ascii_control() => {
+                // a digit which follows the escape would become part of it
+                let escape = if s.get(i + 1).is_some_and(u8::is_ascii_digit) {
+                    format!("\\{:03}", c)
+                } else {
+                    format!("\\{}", c)
+                };
+                out.extend_from_slice(escape.as_bytes());
+            }
+            c => out.push(c),
+        }
+    }
+    out.push(b'"');
+}
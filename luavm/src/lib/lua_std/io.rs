@@ -1,31 +1,789 @@
 use crate::Vm;
 use errors::LuaError;
-use lua_values::{lua_table::UserTable, LuaVal};
-use std::collections::HashMap;
-use std::fmt::Write as FmtWrite;
-use stdlib::StdFunction;
+use gc::Gc;
+use instructions::functions::return_values;
+use lua_values::{
+    convert::{FromLua, FromLuaMulti, LuaBytes, Variadic},
+    lua_closure::ControlFunction,
+    lua_table::UserTable,
+    lua_userdata::{UserData, UserDataMethods},
+    LuaVal,
+};
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    str,
+};
+use stdlib::{args, push_values, StdFunction};
 
-pub fn get_io_module() -> (String, LuaVal) {
+/// The number of bytes which are read ahead from a file.
+const BUFFER_SIZE: usize = 8192;
+/// The maximum length of a numeral read with the "n" format.
+const MAX_NUMERAL_LEN: usize = 200;
+
+pub fn get_io_module(vm: &mut Vm) -> (String, LuaVal) {
     let io = LuaVal::from(UserTable::new(HashMap::new()));
-    for func in &[("write", lua_write)] {
+    for func in &[
+        ("open", lua_open as fn(&mut Vm) -> Result<(), LuaError>),
+        ("type", lua_type),
+    ] {
         let std_func = StdFunction {
             name: func.0,
             handler: func.1,
         };
-        let std_val = LuaVal::from(&std_func);
-        io.set_attr(LuaVal::from(func.0.to_string()), std_val)
+        io.set_attr(LuaVal::from(func.0), LuaVal::from(&std_func))
+            .unwrap();
+    }
+    let stdin = vm.create_userdata(LuaFile::new(Stream::Stdin));
+    let stdout = vm.create_userdata(LuaFile::new(Stream::Stdout));
+    let stderr = vm.create_userdata(LuaFile::new(Stream::Stderr));
+    // the default input and output files are shared by the functions which use them
+    let defaults = LuaVal::new_table();
+    defaults
+        .set_attr(LuaVal::from("input"), stdin.clone())
+        .unwrap();
+    defaults
+        .set_attr(LuaVal::from("output"), stdout.clone())
+        .unwrap();
+    let defaults = Gc::new(defaults);
+    for func in &[
+        ("close", lua_close as fn(&mut Vm) -> Result<(), LuaError>),
+        ("read", lua_read),
+        ("write", lua_write),
+        ("lines", lua_lines),
+        ("input", lua_input),
+        ("output", lua_output),
+    ] {
+        let control_func = ControlFunction::new(func.1, vec![defaults.clone()]).with_name(func.0);
+        io.set_attr(LuaVal::from(func.0), LuaVal::from(control_func))
             .unwrap();
     }
+    for (name, file) in [("stdin", stdin), ("stdout", stdout), ("stderr", stderr)] {
+        io.set_attr(LuaVal::from(name), file).unwrap();
+    }
     ("io".to_string(), io)
 }
 
+/// The underlying stream of a file.
+enum Stream {
+    Stdin,
+    Stdout,
+    Stderr,
+    File(File),
+}
+
+impl Stream {
+    fn is_standard(&self) -> bool {
+        !matches!(self, Stream::File(_))
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Stdin => io::stdin().read(buf),
+            Stream::File(file) => file.read(buf),
+            _ => Err(bad_descriptor()),
+        }
+    }
+
+    fn write_all(&mut self, bytes: &[u8]) -> io::Result<()> {
+        match self {
+            Stream::Stdout => io::stdout().write_all(bytes),
+            Stream::Stderr => io::stderr().write_all(bytes),
+            Stream::File(file) => file.write_all(bytes),
+            Stream::Stdin => Err(bad_descriptor()),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Stdout => io::stdout().flush(),
+            Stream::Stderr => io::stderr().flush(),
+            Stream::File(file) => file.flush(),
+            Stream::Stdin => Ok(()),
+        }
+    }
+
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            Stream::File(file) => file.seek(pos),
+            _ => Err(io::Error::other("Illegal seek")),
+        }
+    }
+}
+
+fn bad_descriptor() -> io::Error {
+    io::Error::other("Bad file descriptor")
+}
+
+/// The buffering mode of a file, which is set with `setvbuf`. A file is flushed after
+/// each write in the `No` mode, and after each write which contains a newline in the
+/// `Line` mode. Writes to files are not buffered otherwise, so that nothing is lost when
+/// a file is not closed, but the standard output is still flushed at the end of lines.
+#[derive(Clone, Copy, PartialEq)]
+enum BufMode {
+    No,
+    Full,
+    Line,
+}
+
+/// A format of `read`.
+enum Format {
+    Number,
+    Line { keep_newline: bool },
+    All,
+    Count(usize),
+}
+
+/// A file handle, which is given to Lua as a userdata whose methods are the ones of
+/// the files of the reference implementation.
+#[derive(Trace, Finalize)]
+pub struct LuaFile {
+    /// The stream of the file, which is `None` once the file is closed.
+    #[unsafe_ignore_trace]
+    stream: Option<Stream>,
+    /// The bytes which were read ahead from the stream; the ones before `pos` have
+    /// already been consumed.
+    #[unsafe_ignore_trace]
+    buf: Vec<u8>,
+    #[unsafe_ignore_trace]
+    pos: usize,
+    #[unsafe_ignore_trace]
+    mode: BufMode,
+}
+
+impl UserData for LuaFile {
+    fn add_methods(methods: &mut UserDataMethods<Self>) {
+        methods.add_method_mut("read", |_, f: &mut LuaFile, formats: Variadic<LuaVal>| {
+            f.read(&formats, 2)
+        });
+        methods.add_function("write", |_, args: Variadic<LuaVal>| {
+            let file = args.first().cloned().unwrap_or_default();
+            write(&file, &args[1.min(args.len())..], 2)
+        });
+        methods.add_function("lines", |_, args: Variadic<LuaVal>| {
+            let file = args.first().cloned().unwrap_or_default();
+            with_file(&file, |_| Ok(()))?;
+            Ok(lines_iterator(file, false, &args[1.min(args.len())..]))
+        });
+        methods.add_method_mut(
+            "seek",
+            |_, f: &mut LuaFile, (whence, offset): (Option<String>, Option<i64>)| {
+                let offset = offset.unwrap_or(0);
+                let pos = match whence.as_ref().map_or("cur", String::as_str) {
+                    "set" if offset < 0 => {
+                        let err = io::Error::new(io::ErrorKind::InvalidInput, "Invalid argument");
+                        return Ok(error_result(&err, None));
+                    }
+                    "set" => SeekFrom::Start(offset as u64),
+                    "cur" => SeekFrom::Current(offset),
+                    "end" => SeekFrom::End(offset),
+                    opt => return Err(invalid_option(2, opt)),
+                };
+                Ok(match f.seek(pos)? {
+                    Ok(pos) => Variadic(vec![LuaVal::from(pos as i64)]),
+                    Err(err) => error_result(&err, None),
+                })
+            },
+        );
+        methods.add_method_mut(
+            "setvbuf",
+            |_, f: &mut LuaFile, (mode, _): (String, Option<i64>)| {
+                f.stream()?;
+                f.mode = match mode.as_str() {
+                    "no" => BufMode::No,
+                    "full" => BufMode::Full,
+                    "line" => BufMode::Line,
+                    opt => return Err(invalid_option(2, opt)),
+                };
+                Ok(true)
+            },
+        );
+        methods.add_method_mut("flush", |_, f: &mut LuaFile, ()| {
+            Ok(match f.stream()?.flush() {
+                Ok(()) => Variadic(vec![LuaVal::from(true)]),
+                Err(err) => error_result(&err, None),
+            })
+        });
+        methods.add_method_mut("close", |_, f: &mut LuaFile, ()| f.close());
+        methods.add_meta_method("__tostring", |_, f: &LuaFile, ()| {
+            Ok(match f.stream {
+                Some(_) => format!("file ({:p})", f),
+                None => "file (closed)".to_string(),
+            })
+        });
+    }
+}
+
+impl LuaFile {
+    fn new(stream: Stream) -> LuaFile {
+        LuaFile {
+            stream: Some(stream),
+            buf: vec![],
+            pos: 0,
+            mode: BufMode::Full,
+        }
+    }
+
+    /// Opens the file called <name>, with a mode of `fopen`.
+    fn open(name: &str, mode: &str) -> Result<io::Result<LuaFile>, LuaError> {
+        let mut options = OpenOptions::new();
+        let mut chars = mode.chars();
+        match chars.next() {
+            Some('r') => options.read(true),
+            Some('w') => options.write(true).create(true).truncate(true),
+            Some('a') => options.append(true).create(true),
+            _ => return Err(invalid_mode()),
+        };
+        let rest = chars.as_str();
+        let binary = match rest.strip_prefix('+') {
+            Some(binary) => {
+                options.read(true).write(true);
+                binary
+            }
+            None => rest,
+        };
+        if binary.chars().any(|c| c != 'b') {
+            return Err(invalid_mode());
+        }
+        Ok(options
+            .open(name)
+            .map(|file| LuaFile::new(Stream::File(file))))
+    }
+
+    fn stream(&mut self) -> Result<&mut Stream, LuaError> {
+        self.stream
+            .as_mut()
+            .ok_or_else(|| LuaError::Error("attempt to use a closed file".to_string()))
+    }
+
+    /// Gets the bytes which were read ahead and not consumed yet, reading more from the
+    /// stream if there are none. They are empty at the end of the file.
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos == self.buf.len() {
+            self.buf.resize(BUFFER_SIZE, 0);
+            let res = match self.stream {
+                Some(ref mut stream) => stream.read(&mut self.buf),
+                None => Ok(0),
+            };
+            self.buf.truncate(*res.as_ref().unwrap_or(&0));
+            self.pos = 0;
+            res?;
+        }
+        Ok(&self.buf[self.pos..])
+    }
+
+    fn peek(&mut self) -> io::Result<Option<u8>> {
+        Ok(self.fill_buf()?.first().cloned())
+    }
+
+    fn consume(&mut self, n: usize) {
+        self.pos += n;
+    }
+
+    /// Gives the bytes which were read ahead back to the stream, so that the stream is
+    /// where the next read would start.
+    fn discard_read_ahead(&mut self) -> io::Result<()> {
+        let ahead = (self.buf.len() - self.pos) as i64;
+        if ahead > 0 {
+            if let Some(ref mut stream) = self.stream {
+                stream.seek(SeekFrom::Current(-ahead))?;
+            }
+        }
+        self.buf.clear();
+        self.pos = 0;
+        Ok(())
+    }
+
+    /// Reads the values given by <formats>, which are the arguments of the function from
+    /// position <first_arg>. Reading stops at the first format which fails, which gives
+    /// nil.
+    fn read(&mut self, formats: &[LuaVal], first_arg: usize) -> Result<Variadic<LuaVal>, LuaError> {
+        self.stream()?;
+        let formats = if formats.is_empty() {
+            vec![Format::Line {
+                keep_newline: false,
+            }]
+        } else {
+            formats
+                .iter()
+                .enumerate()
+                .map(|(i, fmt)| parse_format(fmt, first_arg + i))
+                .collect::<Result<_, _>>()?
+        };
+        let mut res = vec![];
+        for fmt in formats {
+            let val = match self.read_format(&fmt) {
+                Ok(val) => val,
+                Err(err) => return Ok(error_result(&err, None)),
+            };
+            let failed = val.is_none();
+            res.push(val.unwrap_or_default());
+            if failed {
+                break;
+            }
+        }
+        Ok(Variadic(res))
+    }
+
+    fn read_format(&mut self, fmt: &Format) -> io::Result<Option<LuaVal>> {
+        match *fmt {
+            Format::Number => self.read_number(),
+            Format::Line { keep_newline } => Ok(self.read_line(keep_newline)?.map(LuaVal::from)),
+            Format::All => Ok(Some(LuaVal::from(self.read_count(usize::MAX)?))),
+            // 0 tests whether the end of the file is reached
+            Format::Count(0) => Ok(self.peek()?.map(|_| LuaVal::from(""))),
+            Format::Count(n) => {
+                let bytes = self.read_count(n)?;
+                Ok(if bytes.is_empty() {
+                    None
+                } else {
+                    Some(LuaVal::from(bytes))
+                })
+            }
+        }
+    }
+
+    /// Reads a line, which is `None` at the end of the file.
+    fn read_line(&mut self, keep_newline: bool) -> io::Result<Option<Vec<u8>>> {
+        let mut line = vec![];
+        loop {
+            let avail = self.fill_buf()?;
+            if avail.is_empty() {
+                return Ok(if line.is_empty() { None } else { Some(line) });
+            }
+            match avail.iter().position(|&c| c == b'\n') {
+                Some(i) => {
+                    let end = if keep_newline { i + 1 } else { i };
+                    line.extend_from_slice(&avail[..end]);
+                    self.consume(i + 1);
+                    return Ok(Some(line));
+                }
+                None => {
+                    let n = avail.len();
+                    line.extend_from_slice(avail);
+                    self.consume(n);
+                }
+            }
+        }
+    }
+
+    /// Reads at most <n> bytes.
+    fn read_count(&mut self, n: usize) -> io::Result<Vec<u8>> {
+        let mut bytes = vec![];
+        while bytes.len() < n {
+            let avail = self.fill_buf()?;
+            if avail.is_empty() {
+                break;
+            }
+            let count = avail.len().min(n - bytes.len());
+            bytes.extend_from_slice(&avail[..count]);
+            self.consume(count);
+        }
+        Ok(bytes)
+    }
+
+    /// Consumes the next byte and adds it to <numeral> if it is in <set>.
+    fn accept(&mut self, numeral: &mut Vec<u8>, set: &[u8]) -> io::Result<bool> {
+        match self.peek()? {
+            Some(c) if set.contains(&c) => {
+                numeral.push(c);
+                self.consume(1);
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// Reads digits, or hexadecimal digits if <hex> is true, and returns their number.
+    fn accept_digits(&mut self, numeral: &mut Vec<u8>, hex: bool) -> io::Result<usize> {
+        let mut count = 0;
+        while let Some(c) = self.peek()? {
+            if !(c.is_ascii_digit() || (hex && c.is_ascii_hexdigit())) {
+                break;
+            }
+            numeral.push(c);
+            self.consume(1);
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Reads the longest prefix of a numeral, like the reference implementation, and
+    /// converts it to a number. Gives `None` if the prefix is not a valid numeral.
+    fn read_number(&mut self) -> io::Result<Option<LuaVal>> {
+        while self.peek()?.is_some_and(|c| c.is_ascii_whitespace()) {
+            self.consume(1);
+        }
+        let mut numeral = vec![];
+        let mut count = 0;
+        let mut hex = false;
+        self.accept(&mut numeral, b"-+")?;
+        if self.accept(&mut numeral, b"0")? {
+            if self.accept(&mut numeral, b"xX")? {
+                hex = true;
+            } else {
+                count = 1;
+            }
+        }
+        count += self.accept_digits(&mut numeral, hex)?;
+        if self.accept(&mut numeral, b".")? {
+            count += self.accept_digits(&mut numeral, hex)?;
+        }
+        let exponent: &[u8] = if hex { b"pP" } else { b"eE" };
+        if count > 0 && self.accept(&mut numeral, exponent)? {
+            self.accept(&mut numeral, b"-+")?;
+            self.accept_digits(&mut numeral, false)?;
+        }
+        if numeral.len() > MAX_NUMERAL_LEN {
+            return Ok(None);
+        }
+        Ok(str::from_utf8(&numeral).ok().and_then(str_to_number))
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<io::Result<()>, LuaError> {
+        self.stream()?;
+        if let Err(err) = self.discard_read_ahead() {
+            return Ok(Err(err));
+        }
+        let mode = self.mode;
+        let stream = self.stream()?;
+        Ok(stream.write_all(bytes).and_then(|_| {
+            if mode == BufMode::No || (mode == BufMode::Line && bytes.contains(&b'\n')) {
+                stream.flush()
+            } else {
+                Ok(())
+            }
+        }))
+    }
+
+    fn seek(&mut self, pos: SeekFrom) -> Result<io::Result<u64>, LuaError> {
+        let ahead = (self.buf.len() - self.pos) as i64;
+        let pos = match pos {
+            SeekFrom::Current(offset) => SeekFrom::Current(offset - ahead),
+            pos => pos,
+        };
+        let res = self.stream()?.seek(pos);
+        if res.is_ok() {
+            self.buf.clear();
+            self.pos = 0;
+        }
+        Ok(res)
+    }
+
+    /// Closes the file, unless it is a standard file.
+    fn close(&mut self) -> Result<Variadic<LuaVal>, LuaError> {
+        if self.stream()?.is_standard() {
+            return Ok(Variadic(vec![
+                LuaVal::new(),
+                LuaVal::from("cannot close standard file"),
+            ]));
+        }
+        let res = self.stream()?.flush();
+        self.stream = None;
+        self.buf = vec![];
+        self.pos = 0;
+        Ok(match res {
+            Ok(()) => Variadic(vec![LuaVal::from(true)]),
+            Err(err) => error_result(&err, None),
+        })
+    }
+}
+
+/// Converts a numeral read by the "n" format to a number. Hexadecimal integers wrap
+/// around, like in the reference implementation.
+fn str_to_number(numeral: &str) -> Option<LuaVal> {
+    let (negative, unsigned) = match numeral.as_bytes().first() {
+        Some(b'-') => (true, &numeral[1..]),
+        Some(b'+') => (false, &numeral[1..]),
+        _ => (false, numeral),
+    };
+    if unsigned.len() > 2 && unsigned[..2].eq_ignore_ascii_case("0x") {
+        let digits = &unsigned[2..];
+        if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        let n = digits.chars().fold(0i64, |n, c| {
+            n.wrapping_mul(16)
+                .wrapping_add(i64::from(c.to_digit(16).unwrap()))
+        });
+        return Some(LuaVal::from(if negative { n.wrapping_neg() } else { n }));
+    }
+    if let Ok(int) = numeral.parse::<i64>() {
+        Some(LuaVal::from(int))
+    } else {
+        numeral.parse::<f64>().ok().map(LuaVal::from)
+    }
+}
+
+fn parse_format(fmt: &LuaVal, pos: usize) -> Result<Format, LuaError> {
+    if fmt.is_number() {
+        let n = i64::from_lua(fmt).map_err(|err| LuaError::BadArgument(pos, Box::new(err)))?;
+        // like in the reference implementation, a negative count reads everything
+        return Ok(Format::Count(n as usize));
+    }
+    let fmt = LuaBytes::from_lua(fmt).map_err(|err| LuaError::BadArgument(pos, Box::new(err)))?;
+    // the formats of Lua 5.2 start with '*'
+    let fmt = if fmt.first() == Some(&b'*') {
+        &fmt[1..]
+    } else {
+        &fmt[..]
+    };
+    match fmt.first() {
+        Some(b'n') => Ok(Format::Number),
+        Some(b'l') => Ok(Format::Line {
+            keep_newline: false,
+        }),
+        Some(b'L') => Ok(Format::Line { keep_newline: true }),
+        Some(b'a') => Ok(Format::All),
+        _ => Err(LuaError::BadArgument(
+            pos,
+            Box::new(LuaError::Error("invalid format".to_string())),
+        )),
+    }
+}
+
+fn invalid_option(pos: usize, opt: &str) -> LuaError {
+    LuaError::BadArgument(
+        pos,
+        Box::new(LuaError::Error(format!("invalid option '{}'", opt))),
+    )
+}
+
+fn invalid_mode() -> LuaError {
+    LuaError::BadArgument(2, Box::new(LuaError::Error("invalid mode".to_string())))
+}
+
+/// The results of a function which failed because of <err>: nil, the message, which
+/// starts with <name> if it is given, and the error number.
+pub fn error_result(err: &io::Error, name: Option<&str>) -> Variadic<LuaVal> {
+    let msg = error_message(err);
+    let msg = match name {
+        Some(name) => format!("{}: {}", name, msg),
+        None => msg,
+    };
+    let errno = err.raw_os_error().unwrap_or(0);
+    Variadic(vec![
+        LuaVal::new(),
+        LuaVal::from(msg),
+        LuaVal::from(i64::from(errno)),
+    ])
+}
+
+/// The message of <err>, without the error number which Rust adds to it.
+fn error_message(err: &io::Error) -> String {
+    let msg = err.to_string();
+    match err.raw_os_error() {
+        Some(errno) => msg
+            .trim_end_matches(&format!(" (os error {})", errno))
+            .to_string(),
+        None => msg,
+    }
+}
+
+/// Calls <f> with the file <file>, which must be open.
+fn with_file<R, F>(file: &LuaVal, f: F) -> Result<R, LuaError>
+where
+    F: FnOnce(&mut LuaFile) -> Result<R, LuaError>,
+{
+    file.with_userdata_mut(|file: &mut LuaFile| {
+        file.stream()?;
+        f(file)
+    })
+    .map_err(|err| match err {
+        LuaError::TypeMismatch { got, .. } => LuaError::BadArgument(
+            1,
+            Box::new(LuaError::TypeMismatch {
+                expected: "FILE*",
+                got,
+            }),
+        ),
+        err => err,
+    })?
+}
+
+/// Writes the strings and numbers <vals> to <file>, which are the arguments of the
+/// function from position <first_arg>, and returns the file.
+fn write(file: &LuaVal, vals: &[LuaVal], first_arg: usize) -> Result<Variadic<LuaVal>, LuaError> {
+    let mut bytes = vec![];
+    for (i, val) in vals.iter().enumerate() {
+        let LuaBytes(val) = LuaBytes::from_lua(val)
+            .map_err(|err| LuaError::BadArgument(first_arg + i, Box::new(err)))?;
+        bytes.extend(val);
+    }
+    Ok(match with_file(file, |f| f.write(&bytes))? {
+        Ok(()) => Variadic(vec![file.clone()]),
+        Err(err) => error_result(&err, None),
+    })
+}
+
+/// Creates the function returned by `lines`, which reads <file> with <formats> each time
+/// it is called, and closes it at the end if <close> is true.
+fn lines_iterator(file: LuaVal, close: bool, formats: &[LuaVal]) -> LuaVal {
+    let mut upvals = vec![Gc::new(file), Gc::new(LuaVal::from(close))];
+    upvals.extend(formats.iter().cloned().map(Gc::new));
+    LuaVal::from(ControlFunction::new(lua_lines_iter, upvals))
+}
+
+fn lua_lines_iter(vm: &mut Vm) -> Result<(), LuaError> {
+    let file = (*vm.closure().get_upval(0)?).clone();
+    let close = vm.closure().get_upval(1)?.to_bool();
+    let mut formats = vec![];
+    while let Ok(fmt) = vm.closure().get_upval(formats.len() + 2) {
+        formats.push((**fmt).clone());
+    }
+    let res = file.with_userdata_mut(|f: &mut LuaFile| {
+        if f.stream.is_none() {
+            return Err(LuaError::Error("file is already closed".to_string()));
+        }
+        let res = f.read(&formats, 1)?;
+        if res.first().is_some_and(|val| !val.is_nil()) {
+            return Ok(res);
+        }
+        // the second result is the message of an error
+        if let Some(msg) = res.get(1) {
+            return Err(LuaError::Error(msg.to_string()?));
+        }
+        if close {
+            f.close()?;
+        }
+        Ok(Variadic(vec![LuaVal::new()]))
+    })??;
+    return_values(vm, res.0)
+}
+
+/// Gets the file <name> ("input" or "output") of the default files.
+fn default_file(vm: &mut Vm, name: &str) -> Result<LuaVal, LuaError> {
+    let file = vm.closure().get_upval(0)?.get_attr(&LuaVal::from(name))?;
+    let closed = file.with_userdata(|f: &LuaFile| f.stream.is_none())?;
+    if closed {
+        return Err(LuaError::Error(format!("standard {} file is closed", name)));
+    }
+    Ok(file)
+}
+
+/// Implements `io.input` and `io.output`: sets the default file <name> to a file, or
+/// to the file whose name is given, opened with <mode>. Returns the default file.
+fn set_default_file(vm: &mut Vm, name: &str, mode: &str) -> Result<(), LuaError> {
+    let (args_start, args_count) = vm.varargs();
+    let arg = vm.stack[args_start..(args_start + args_count)]
+        .first()
+        .cloned()
+        .unwrap_or_default();
+    let defaults = (*vm.closure().get_upval(0)?).clone();
+    if !arg.is_nil() {
+        let file = if arg.is_string() || arg.is_number() {
+            let path = arg.to_string()?;
+            match LuaFile::open(&path, mode)? {
+                Ok(file) => vm.create_userdata(file),
+                Err(err) => {
+                    return Err(LuaError::Error(format!(
+                        "cannot open file '{}' ({})",
+                        path,
+                        error_message(&err)
+                    )));
+                }
+            }
+        } else {
+            with_file(&arg, |_| Ok(()))?;
+            arg
+        };
+        defaults.set_attr(LuaVal::from(name), file)?;
+    }
+    let file = defaults.get_attr(&LuaVal::from(name))?;
+    return_values(vm, vec![file])
+}
+
+/// Opens a file with a mode of `fopen`, which is "r" by default. Returns the file, or
+/// nil, an error message and an error number if it cannot be opened.
+pub fn lua_open(vm: &mut Vm) -> Result<(), LuaError> {
+    let (name, mode): (String, Option<String>) = args(vm)?;
+    let res = match LuaFile::open(&name, mode.as_ref().map_or("r", String::as_str))? {
+        Ok(file) => vec![vm.create_userdata(file)],
+        Err(err) => error_result(&err, Some(&name)).0,
+    };
+    push_values(vm, res)
+}
+
+/// Returns "file" if the argument is an open file, "closed file" if it is a closed one,
+/// and nil otherwise.
+pub fn lua_type(vm: &mut Vm) -> Result<(), LuaError> {
+    let (args_start, args_count) = vm.varargs();
+    if args_count == 0 {
+        return Err(LuaError::BadArgument(
+            1,
+            Box::new(LuaError::Error("value expected".to_string())),
+        ));
+    }
+    let res = match vm.stack[args_start].with_userdata(|f: &LuaFile| f.stream.is_some()) {
+        Ok(true) => LuaVal::from("file"),
+        Ok(false) => LuaVal::from("closed file"),
+        Err(_) => LuaVal::new(),
+    };
+    push_values(vm, vec![res])
+}
+
+/// Closes the given file, or the default output file.
+pub fn lua_close(vm: &mut Vm) -> Result<(), LuaError> {
+    let (args_start, args_count) = vm.varargs();
+    let file = match vm.stack[args_start..(args_start + args_count)].first() {
+        Some(file) if !file.is_nil() => file.clone(),
+        _ => default_file(vm, "output")?,
+    };
+    let res = with_file(&file, LuaFile::close)?;
+    return_values(vm, res.0)
+}
+
+/// Reads from the default input file, see the `read` method of files.
+pub fn lua_read(vm: &mut Vm) -> Result<(), LuaError> {
+    let (args_start, args_count) = vm.varargs();
+    let formats = vm.stack[args_start..(args_start + args_count)].to_vec();
+    let file = default_file(vm, "input")?;
+    let res = with_file(&file, |f| f.read(&formats, 1))?;
+    return_values(vm, res.0)
+}
+
+/// Writes its arguments to the default output file, without separators, and returns
+/// the file.
 pub fn lua_write(vm: &mut Vm) -> Result<(), LuaError> {
-    let args_start = vm.stack_frames.last().unwrap().top;
-    let args_count = vm.top - args_start;
-    let mut s = String::new();
-    for i in args_start..(args_start + args_count - 1) {
-        write!(s, "{}\t", &vm.stack[i]).unwrap();
-    }
-    println!("{}{}", s, &vm.stack[args_start + args_count - 1]);
-    Ok(())
+    let (args_start, args_count) = vm.varargs();
+    let vals = vm.stack[args_start..(args_start + args_count)].to_vec();
+    let file = default_file(vm, "output")?;
+    let res = write(&file, &vals, 1)?;
+    return_values(vm, res.0)
+}
+
+/// Returns a function which reads the file whose name is given with the given formats
+/// each time it is called, and closes the file at its end. Without a file name, the
+/// function reads the default input file and doesn't close it.
+pub fn lua_lines(vm: &mut Vm) -> Result<(), LuaError> {
+    let (args_start, args_count) = vm.varargs();
+    let args = vm.stack[args_start..(args_start + args_count)].to_vec();
+    let name = args.first().cloned().unwrap_or_default();
+    let formats = &args[1.min(args.len())..];
+    let iter = if name.is_nil() {
+        lines_iterator(default_file(vm, "input")?, false, formats)
+    } else {
+        let name = String::from_lua_multi(&args)?;
+        let file = match LuaFile::open(&name, "r")? {
+            Ok(file) => vm.create_userdata(file),
+            Err(err) => {
+                return Err(LuaError::Error(format!(
+                    "{}: {}",
+                    name,
+                    error_message(&err)
+                )));
+            }
+        };
+        lines_iterator(file, true, formats)
+    };
+    return_values(vm, vec![iter])
+}
+
+pub fn lua_input(vm: &mut Vm) -> Result<(), LuaError> {
+    set_default_file(vm, "input", "r")
+}
+
+pub fn lua_output(vm: &mut Vm) -> Result<(), LuaError> {
+    set_default_file(vm, "output", "w")
 }
@@ -0,0 +1,412 @@
+use crate::Vm;
+use errors::LuaError;
+use instructions::relational_operators::less_than;
+use lua_values::{
+    convert::{float_to_int, FromLua, FromLuaMulti},
+    lua_closure::HostFunction,
+    lua_table::UserTable,
+    LuaVal,
+};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    f64::consts::PI,
+    rc::Rc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use stdlib::{args, push_values, StdFunction};
+
+pub fn get_math_module() -> (String, LuaVal) {
+    let math = LuaVal::from(UserTable::new(HashMap::new()));
+    for func in &[
+        ("abs", lua_abs as fn(&mut Vm) -> Result<(), LuaError>),
+        ("ceil", lua_ceil),
+        ("floor", lua_floor),
+        ("sqrt", lua_sqrt),
+        ("sin", lua_sin),
+        ("cos", lua_cos),
+        ("tan", lua_tan),
+        ("asin", lua_asin),
+        ("acos", lua_acos),
+        ("atan", lua_atan),
+        ("exp", lua_exp),
+        ("log", lua_log),
+        ("fmod", lua_fmod),
+        ("modf", lua_modf),
+        ("tointeger", lua_tointeger),
+        ("type", lua_type),
+        ("ult", lua_ult),
+        ("max", lua_max),
+        ("min", lua_min),
+    ] {
+        let std_func = StdFunction {
+            name: func.0,
+            handler: func.1,
+        };
+        math.set_attr(LuaVal::from(func.0), LuaVal::from(&std_func))
+            .unwrap();
+    }
+    // the generator is shared by `random` and `randomseed`
+    let rng = Rc::new(RefCell::new(Xoshiro::from_time()));
+    let random_rng = rng.clone();
+    let random = HostFunction::from_handler(Box::new(move |_, args| {
+        lua_random(&mut random_rng.borrow_mut(), args)
+    }))
+    .with_name("random");
+    let randomseed = HostFunction::from_handler(Box::new(move |_, args| {
+        lua_randomseed(&mut rng.borrow_mut(), args)
+    }))
+    .with_name("randomseed");
+    for (name, val) in [
+        ("random", LuaVal::from(random)),
+        ("randomseed", LuaVal::from(randomseed)),
+        ("huge", LuaVal::from(f64::INFINITY)),
+        ("pi", LuaVal::from(PI)),
+        ("maxinteger", LuaVal::from(i64::MAX)),
+        ("mininteger", LuaVal::from(i64::MIN)),
+    ] {
+        math.set_attr(LuaVal::from(name), val).unwrap();
+    }
+    ("math".to_string(), math)
+}
+
+/// Checks that the running builtin has at least one argument.
+fn check_any(vm: &Vm) -> Result<(), LuaError> {
+    if vm.varargs().1 == 0 {
+        Err(LuaError::BadArgument(
+            1,
+            Box::new(LuaError::Error("value expected".to_string())),
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Gets an integer if the float has an integer representation, and the float otherwise.
+fn int_or_float(f: f64) -> LuaVal {
+    match float_to_int(f) {
+        Some(int) => LuaVal::from(int),
+        None => LuaVal::from(f),
+    }
+}
+
+/// Applies <f> to the first argument, converted to a float, and returns its result.
+fn float_func(vm: &mut Vm, f: fn(f64) -> f64) -> Result<(), LuaError> {
+    let x: f64 = args(vm)?;
+    push_values(vm, vec![LuaVal::from(f(x))])
+}
+
+/// Returns the absolute value of a number, which is an integer if the number is one.
+pub fn lua_abs(vm: &mut Vm) -> Result<(), LuaError> {
+    let x: LuaVal = args(vm)?;
+    let res = match x.as_int() {
+        // like in the reference implementation, the absolute value of mininteger is
+        // itself
+        Some(int) => LuaVal::from(int.wrapping_abs()),
+        None => LuaVal::from(args::<f64>(vm)?.abs()),
+    };
+    push_values(vm, vec![res])
+}
+
+/// Returns the smallest integral value greater than or equal to a number, which is a
+/// float only if it cannot be represented by an integer.
+pub fn lua_ceil(vm: &mut Vm) -> Result<(), LuaError> {
+    let x: LuaVal = args(vm)?;
+    let res = match x.as_int() {
+        Some(_) => x,
+        None => int_or_float(args::<f64>(vm)?.ceil()),
+    };
+    push_values(vm, vec![res])
+}
+
+/// Returns the largest integral value smaller than or equal to a number, which is a
+/// float only if it cannot be represented by an integer.
+pub fn lua_floor(vm: &mut Vm) -> Result<(), LuaError> {
+    let x: LuaVal = args(vm)?;
+    let res = match x.as_int() {
+        Some(_) => x,
+        None => int_or_float(args::<f64>(vm)?.floor()),
+    };
+    push_values(vm, vec![res])
+}
+
+pub fn lua_sqrt(vm: &mut Vm) -> Result<(), LuaError> {
+    float_func(vm, f64::sqrt)
+}
+
+pub fn lua_sin(vm: &mut Vm) -> Result<(), LuaError> {
+    float_func(vm, f64::sin)
+}
+
+pub fn lua_cos(vm: &mut Vm) -> Result<(), LuaError> {
+    float_func(vm, f64::cos)
+}
+
+pub fn lua_tan(vm: &mut Vm) -> Result<(), LuaError> {
+    float_func(vm, f64::tan)
+}
+
+pub fn lua_asin(vm: &mut Vm) -> Result<(), LuaError> {
+    float_func(vm, f64::asin)
+}
+
+pub fn lua_acos(vm: &mut Vm) -> Result<(), LuaError> {
+    float_func(vm, f64::acos)
+}
+
+pub fn lua_exp(vm: &mut Vm) -> Result<(), LuaError> {
+    float_func(vm, f64::exp)
+}
+
+/// Returns the arc tangent of y/x, using the signs of both to find the quadrant of the
+/// result. x defaults to 1.
+pub fn lua_atan(vm: &mut Vm) -> Result<(), LuaError> {
+    let (y, x): (f64, Option<f64>) = args(vm)?;
+    push_values(vm, vec![LuaVal::from(y.atan2(x.unwrap_or(1.0)))])
+}
+
+/// Returns the logarithm of x in the given base, which defaults to e.
+pub fn lua_log(vm: &mut Vm) -> Result<(), LuaError> {
+    let (x, base): (f64, Option<f64>) = args(vm)?;
+    let res = match base {
+        None => x.ln(),
+        Some(2.0) => x.log2(),
+        Some(10.0) => x.log10(),
+        Some(base) => x.ln() / base.ln(),
+    };
+    push_values(vm, vec![LuaVal::from(res)])
+}
+
+/// Returns the remainder of the division of x by y which rounds the quotient towards
+/// zero. The remainder of two integers is an integer.
+pub fn lua_fmod(vm: &mut Vm) -> Result<(), LuaError> {
+    let (x, y): (LuaVal, LuaVal) = args(vm)?;
+    let res = match (x.as_int(), y.as_int()) {
+        (Some(_), Some(0)) => {
+            return Err(LuaError::BadArgument(
+                2,
+                Box::new(LuaError::Error("zero".to_string())),
+            ));
+        }
+        // mininteger % -1 overflows
+        (Some(_), Some(-1)) => LuaVal::from(0i64),
+        (Some(x), Some(y)) => LuaVal::from(x % y),
+        _ => {
+            let (x, y): (f64, f64) = args(vm)?;
+            LuaVal::from(x % y)
+        }
+    };
+    push_values(vm, vec![res])
+}
+
+/// Returns the integral part of a number, rounded towards zero, and its fractional
+/// part. The fractional part is always a float.
+pub fn lua_modf(vm: &mut Vm) -> Result<(), LuaError> {
+    let x: LuaVal = args(vm)?;
+    if x.as_int().is_some() {
+        return push_values(vm, vec![x, LuaVal::from(0.0)]);
+    }
+    let x: f64 = args(vm)?;
+    let int = x.trunc();
+    // the fractional part of an infinite number is 0
+    let frac = if x == int { 0.0 } else { x - int };
+    push_values(vm, vec![LuaVal::from(int), LuaVal::from(frac)])
+}
+
+/// Converts a value to an integer if it is convertible to one, and returns nil
+/// otherwise.
+pub fn lua_tointeger(vm: &mut Vm) -> Result<(), LuaError> {
+    check_any(vm)?;
+    let x: LuaVal = args(vm)?;
+    let res = match i64::from_lua(&x) {
+        Ok(int) => LuaVal::from(int),
+        Err(_) => LuaVal::new(),
+    };
+    push_values(vm, vec![res])
+}
+
+/// Returns "integer" or "float" depending on the subtype of a number, and nil if the
+/// value is not a number.
+pub fn lua_type(vm: &mut Vm) -> Result<(), LuaError> {
+    check_any(vm)?;
+    let x: LuaVal = args(vm)?;
+    let res = if !x.is_number() {
+        LuaVal::new()
+    } else if x.is_int() {
+        LuaVal::from("integer")
+    } else {
+        LuaVal::from("float")
+    };
+    push_values(vm, vec![res])
+}
+
+/// Checks whether m is smaller than n when they are compared as unsigned integers.
+pub fn lua_ult(vm: &mut Vm) -> Result<(), LuaError> {
+    let (m, n): (i64, i64) = args(vm)?;
+    push_values(vm, vec![LuaVal::from((m as u64) < (n as u64))])
+}
+
+pub fn lua_max(vm: &mut Vm) -> Result<(), LuaError> {
+    min_max(vm, true)
+}
+
+pub fn lua_min(vm: &mut Vm) -> Result<(), LuaError> {
+    min_max(vm, false)
+}
+
+/// Returns the maximum of the arguments if <max> is true, and their minimum otherwise.
+/// The arguments must be numbers, and the result keeps its subtype.
+fn min_max(vm: &mut Vm, max: bool) -> Result<(), LuaError> {
+    let (args_start, args_count) = vm.varargs();
+    let vals = vm.stack[args_start..(args_start + args_count)].to_vec();
+    if vals.is_empty() {
+        return Err(LuaError::BadArgument(
+            1,
+            Box::new(LuaError::TypeMismatch {
+                expected: "number",
+                got: "no value",
+            }),
+        ));
+    }
+    let mut best = 0;
+    for (i, val) in vals.iter().enumerate() {
+        f64::from_lua(val).map_err(|err| LuaError::BadArgument(i + 1, Box::new(err)))?;
+        let better = if max {
+            less_than(vm, &vals[best], val)?
+        } else {
+            less_than(vm, val, &vals[best])?
+        };
+        if better {
+            best = i;
+        }
+    }
+    push_values(vm, vec![vals[best].clone()])
+}
+
+/// Without arguments, returns a float in [0, 1). With an integer m, returns an integer
+/// in [1, m]. With two integers m and n, returns an integer in [m, n].
+fn lua_random(rng: &mut Xoshiro, args: &[LuaVal]) -> Result<Vec<LuaVal>, LuaError> {
+    let rv = rng.next();
+    let (low, up) = match args.len() {
+        0 => return Ok(vec![LuaVal::from(Xoshiro::to_float(rv))]),
+        1 => (1, i64::from_lua_multi(args)?),
+        2 => <(i64, i64)>::from_lua_multi(args)?,
+        _ => return Err(LuaError::Error("wrong number of arguments".to_string())),
+    };
+    if low > up {
+        return Err(LuaError::BadArgument(
+            1,
+            Box::new(LuaError::Error("interval is empty".to_string())),
+        ));
+    }
+    let n = rng.project(rv, (up as u64).wrapping_sub(low as u64));
+    Ok(vec![LuaVal::from(n.wrapping_add(low as u64) as i64)])
+}
+
+/// Seeds the generator with one or two numbers, or with the current time if there are
+/// none. The sequence of numbers given by `random` only depends on the seed.
+fn lua_randomseed(rng: &mut Xoshiro, args: &[LuaVal]) -> Result<Vec<LuaVal>, LuaError> {
+    if args.is_empty() {
+        *rng = Xoshiro::from_time();
+        return Ok(vec![]);
+    }
+    // floats are truncated, so that any number can be a seed
+    let seed = |i: usize| -> Result<u64, LuaError> {
+        let val = args.get(i).cloned().unwrap_or_else(LuaVal::new);
+        if i > 0 && val.is_nil() {
+            return Ok(0);
+        }
+        match val.as_int() {
+            Some(int) => Ok(int as u64),
+            None => f64::from_lua(&val)
+                .map(|f| f as i64 as u64)
+                .map_err(|err| LuaError::BadArgument(i + 1, Box::new(err))),
+        }
+    };
+    *rng = Xoshiro::new(seed(0)?, seed(1)?);
+    Ok(vec![])
+}
+
+/// The xoshiro256** generator, which is the one of the reference implementation since
+/// Lua 5.4.
+struct Xoshiro([u64; 4]);
+
+impl Xoshiro {
+    fn new(n1: u64, n2: u64) -> Xoshiro {
+        // 0xff avoids a state which is only made of zeros
+        let mut rng = Xoshiro([n1, 0xff, n2, 0]);
+        // the first values are discarded to spread the seed
+        for _ in 0..16 {
+            rng.next();
+        }
+        rng
+    }
+
+    fn from_time() -> Xoshiro {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        Xoshiro::new(time.as_secs(), u64::from(time.subsec_nanos()))
+    }
+
+    fn next(&mut self) -> u64 {
+        let s = &mut self.0;
+        let res = s[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = s[1] << 17;
+        s[2] ^= s[0];
+        s[3] ^= s[1];
+        s[1] ^= s[2];
+        s[0] ^= s[3];
+        s[2] ^= t;
+        s[3] = s[3].rotate_left(45);
+        res
+    }
+
+    /// Converts a random integer to a float in [0, 1) using its 53 higher bits.
+    fn to_float(rv: u64) -> f64 {
+        (rv >> 11) as f64 * 2f64.powi(-53)
+    }
+
+    /// Projects a random integer into [0, n] without bias, by drawing new integers
+    /// until one of them falls into the interval once its higher bits are cleared.
+    fn project(&mut self, mut rv: u64, n: u64) -> u64 {
+        // n + 1 is a power of 2
+        if n & n.wrapping_add(1) == 0 {
+            return rv & n;
+        }
+        // the smallest 2^b - 1 which is not smaller than n
+        let lim = u64::MAX >> n.leading_zeros();
+        loop {
+            rv &= lim;
+            if rv <= n {
+                return rv;
+            }
+            rv = self.next();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn xoshiro_is_deterministic() {
+        let (mut a, mut b) = (Xoshiro::new(42, 0), Xoshiro::new(42, 0));
+        let mut c = Xoshiro::new(43, 0);
+        let first: Vec<u64> = (0..8).map(|_| a.next()).collect();
+        assert_eq!(first, (0..8).map(|_| b.next()).collect::<Vec<_>>());
+        assert_ne!(first, (0..8).map(|_| c.next()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn project_stays_in_interval() {
+        let mut rng = Xoshiro::new(1, 2);
+        for n in &[0, 1, 5, 6, 7, 100, u64::MAX] {
+            for _ in 0..100 {
+                let rv = rng.next();
+                assert!(rng.project(rv, *n) <= *n);
+            }
+        }
+    }
+}
@@ -1 +1,11 @@
+pub mod coroutine;
+pub mod format;
 pub mod io;
+pub mod math;
+pub mod os;
+pub mod pack;
+pub mod package;
+pub mod pattern;
+pub mod string;
+pub mod table;
+pub mod utf8;
@@ -0,0 +1,348 @@
+use crate::Vm;
+use errors::LuaError;
+use libc;
+use lua_std::io::error_result;
+use lua_values::{
+    convert::{FromLua, LuaBytes},
+    lua_table::UserTable,
+    LuaVal,
+};
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    env,
+    ffi::{CString, OsStr},
+    fs::{self, OpenOptions},
+    io, mem,
+    os::unix::ffi::{OsStrExt, OsStringExt},
+    path::Path,
+    process,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use stdlib::{args, push_values, StdFunction};
+
+/// The maximum absolute value of the fields of the tables given to `os.time`, so that
+/// they still fit in the fields of a `tm` once they are shifted (e.g. years start at
+/// 1900).
+const MAX_DATE_FIELD: i64 = i32::MAX as i64 / 2;
+/// The size of the buffer which receives the result of a conversion of `os.date`.
+const MAX_CONVERSION_LEN: usize = 250;
+/// The conversion specifiers of C99 which are accepted by `os.date`.
+const CONVERSIONS: &[u8] = b"aAbBcCdDeFgGhHIjmMnprRStTuUVwWxXyYzZ%";
+/// The conversion specifiers which can have the modifier 'E'.
+const E_CONVERSIONS: &[u8] = b"cCxXyY";
+/// The conversion specifiers which can have the modifier 'O'.
+const O_CONVERSIONS: &[u8] = b"deHImMSuUVwWy";
+/// The number of names which `os.tmpname` tries before giving up.
+const TMPNAME_ATTEMPTS: usize = 100;
+
+pub fn get_os_module() -> (String, LuaVal) {
+    let os = LuaVal::from(UserTable::new(HashMap::new()));
+    for func in &[
+        ("clock", lua_clock as fn(&mut Vm) -> Result<(), LuaError>),
+        ("date", lua_date),
+        ("difftime", lua_difftime),
+        ("exit", lua_exit),
+        ("getenv", lua_getenv),
+        ("remove", lua_remove),
+        ("rename", lua_rename),
+        ("time", lua_time),
+        ("tmpname", lua_tmpname),
+    ] {
+        let std_func = StdFunction {
+            name: func.0,
+            handler: func.1,
+        };
+        os.set_attr(LuaVal::from(func.0), LuaVal::from(&std_func))
+            .unwrap();
+    }
+    ("os".to_string(), os)
+}
+
+fn unrepresentable_time() -> LuaError {
+    LuaError::Error("time result cannot be represented in this installation".to_string())
+}
+
+/// The number of seconds since the epoch.
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64)
+}
+
+/// Splits <time> into its fields, either in UTC if <utc> is true, or in the local
+/// timezone.
+fn to_tm(time: i64, utc: bool) -> Option<libc::tm> {
+    let time = time as libc::time_t;
+    let mut tm: libc::tm = unsafe { mem::zeroed() };
+    let res = unsafe {
+        if utc {
+            libc::gmtime_r(&time, &mut tm)
+        } else {
+            libc::localtime_r(&time, &mut tm)
+        }
+    };
+    if res.is_null() {
+        None
+    } else {
+        Some(tm)
+    }
+}
+
+/// Sets the fields of the table <date> to the fields of <tm>, like `os.date("*t")`.
+fn set_date_fields(date: &LuaVal, tm: &libc::tm) -> Result<(), LuaError> {
+    for &(name, val) in &[
+        ("year", i64::from(tm.tm_year) + 1900),
+        ("month", i64::from(tm.tm_mon) + 1),
+        ("day", i64::from(tm.tm_mday)),
+        ("hour", i64::from(tm.tm_hour)),
+        ("min", i64::from(tm.tm_min)),
+        ("sec", i64::from(tm.tm_sec)),
+        ("yday", i64::from(tm.tm_yday) + 1),
+        ("wday", i64::from(tm.tm_wday) + 1),
+    ] {
+        date.set_attr(LuaVal::from(name), LuaVal::from(val))?;
+    }
+    // a negative value means that the information is not available
+    if tm.tm_isdst >= 0 {
+        date.set_attr(LuaVal::from("isdst"), LuaVal::from(tm.tm_isdst > 0))?;
+    }
+    Ok(())
+}
+
+/// Gets the field <name> of the table given to `os.time`, which is <default> if it is
+/// absent, and removes <delta> from it.
+fn date_field(
+    date: &LuaVal,
+    name: &str,
+    default: Option<i32>,
+    delta: i64,
+) -> Result<i32, LuaError> {
+    let val = date.get_attr(&LuaVal::from(name))?;
+    if val.is_nil() {
+        return default
+            .ok_or_else(|| LuaError::Error(format!("field '{}' missing in date table", name)));
+    }
+    let int = i64::from_lua(&val)
+        .map_err(|_| LuaError::Error(format!("field '{}' is not an integer", name)))?;
+    if !(-MAX_DATE_FIELD..=MAX_DATE_FIELD).contains(&int) {
+        return Err(LuaError::Error(format!("field '{}' is out-of-bound", name)));
+    }
+    Ok((int - delta) as i32)
+}
+
+/// Returns the current time, or the time given by a table with the fields `year`,
+/// `month`, `day`, and optionally `hour` (12 by default), `min`, `sec` and `isdst`.
+/// The fields don't need to be in their usual ranges: they are normalized, and the
+/// table is updated with the normalized fields.
+pub fn lua_time(vm: &mut Vm) -> Result<(), LuaError> {
+    let date: LuaVal = args(vm)?;
+    if date.is_nil() {
+        return push_values(vm, vec![LuaVal::from(now())]);
+    }
+    if !date.is_table() {
+        return Err(LuaError::BadArgument(
+            1,
+            Box::new(LuaError::TypeMismatch {
+                expected: "table",
+                got: date.type_name(),
+            }),
+        ));
+    }
+    let mut tm: libc::tm = unsafe { mem::zeroed() };
+    tm.tm_sec = date_field(&date, "sec", Some(0), 0)?;
+    tm.tm_min = date_field(&date, "min", Some(0), 0)?;
+    tm.tm_hour = date_field(&date, "hour", Some(12), 0)?;
+    tm.tm_mday = date_field(&date, "day", None, 0)?;
+    tm.tm_mon = date_field(&date, "month", None, 1)?;
+    tm.tm_year = date_field(&date, "year", None, 1900)?;
+    let isdst = date.get_attr(&LuaVal::from("isdst"))?;
+    // mktime finds out whether daylight saving time is in effect
+    tm.tm_isdst = if isdst.is_nil() {
+        -1
+    } else {
+        i32::from(isdst.to_bool())
+    };
+    let time = unsafe { libc::mktime(&mut tm) };
+    set_date_fields(&date, &tm)?;
+    if time == -1 {
+        return Err(unrepresentable_time());
+    }
+    push_values(vm, vec![LuaVal::from(time as i64)])
+}
+
+/// Gets the length of the conversion specifier at the start of <spec>, which follows a
+/// '%', or `None` if it is not valid.
+fn conversion_len(spec: &[u8]) -> Option<usize> {
+    match spec {
+        [b'E', c, ..] if E_CONVERSIONS.contains(c) => Some(2),
+        [b'O', c, ..] if O_CONVERSIONS.contains(c) => Some(2),
+        [c, ..] if CONVERSIONS.contains(c) => Some(1),
+        _ => None,
+    }
+}
+
+/// Formats <tm> with the format <fmt> of `strftime`. Each conversion is given to
+/// `strftime` on its own, after it is checked.
+fn format_date(fmt: &[u8], tm: &libc::tm) -> Result<Vec<u8>, LuaError> {
+    let mut res = vec![];
+    let mut i = 0;
+    while i < fmt.len() {
+        if fmt[i] != b'%' {
+            res.push(fmt[i]);
+            i += 1;
+            continue;
+        }
+        let len = conversion_len(&fmt[(i + 1)..]).ok_or_else(|| {
+            LuaError::BadArgument(
+                1,
+                Box::new(LuaError::Error(format!(
+                    "invalid conversion specifier '{}'",
+                    String::from_utf8_lossy(&fmt[i..])
+                ))),
+            )
+        })?;
+        let spec = CString::new(&fmt[i..(i + 1 + len)]).unwrap();
+        let mut buf = [0u8; MAX_CONVERSION_LEN];
+        let n = unsafe {
+            libc::strftime(
+                buf.as_mut_ptr() as *mut libc::c_char,
+                buf.len(),
+                spec.as_ptr(),
+                tm,
+            )
+        };
+        res.extend_from_slice(&buf[..n]);
+        i += 1 + len;
+    }
+    Ok(res)
+}
+
+/// Formats a time, which is the current time by default, like `strftime`. The format
+/// is "%c" by default; it is in UTC if it starts with '!'. The format "*t" gives a
+/// table with the fields of the time instead, see `os.time`.
+pub fn lua_date(vm: &mut Vm) -> Result<(), LuaError> {
+    let (fmt, time): (Option<LuaBytes>, Option<i64>) = args(vm)?;
+    let fmt = fmt.map_or(b"%c".to_vec(), |fmt| fmt.0);
+    let time = time.unwrap_or_else(now);
+    let (utc, fmt) = match fmt.strip_prefix(b"!") {
+        Some(fmt) => (true, fmt),
+        None => (false, &fmt[..]),
+    };
+    let tm = to_tm(time, utc).ok_or_else(unrepresentable_time)?;
+    let res = if fmt == b"*t" {
+        let date = LuaVal::new_table();
+        set_date_fields(&date, &tm)?;
+        date
+    } else {
+        LuaVal::from(format_date(fmt, &tm)?)
+    };
+    push_values(vm, vec![res])
+}
+
+/// Returns the processor time used by the program, in seconds.
+pub fn lua_clock(vm: &mut Vm) -> Result<(), LuaError> {
+    let mut ts: libc::timespec = unsafe { mem::zeroed() };
+    unsafe { libc::clock_gettime(libc::CLOCK_PROCESS_CPUTIME_ID, &mut ts) };
+    let secs = ts.tv_sec as f64 + ts.tv_nsec as f64 / 1e9;
+    push_values(vm, vec![LuaVal::from(secs)])
+}
+
+/// Returns the number of seconds from the time t1 to the time t2.
+pub fn lua_difftime(vm: &mut Vm) -> Result<(), LuaError> {
+    let (t2, t1): (i64, i64) = args(vm)?;
+    push_values(vm, vec![LuaVal::from(t2 as f64 - t1 as f64)])
+}
+
+/// Ends the program with a status, which is a number, true for success (the default)
+/// or false for failure. A number which doesn't fit in an `i32` is a failure, rather
+/// than being truncated to a status which could mean success. The frames are unwound by
+/// the error `LuaError::Exit`, which the interpreter turns into the exit of its process.
+pub fn lua_exit(vm: &mut Vm) -> Result<(), LuaError> {
+    let status: LuaVal = args(vm)?;
+    let status = if status.is_nil() {
+        0
+    } else if let Some(success) = status.as_bool() {
+        if success {
+            0
+        } else {
+            1
+        }
+    } else {
+        let status =
+            i64::from_lua(&status).map_err(|err| LuaError::BadArgument(1, Box::new(err)))?;
+        i32::try_from(status).unwrap_or(1)
+    };
+    Err(LuaError::Exit(status))
+}
+
+/// Returns the value of an environment variable, or nil if it is not defined.
+pub fn lua_getenv(vm: &mut Vm) -> Result<(), LuaError> {
+    let name: LuaBytes = args(vm)?;
+    // such names cannot be defined, and the standard library rejects them
+    let valid = !name.is_empty() && !name.contains(&b'=') && !name.contains(&0);
+    let val = if valid {
+        env::var_os(OsStr::from_bytes(&name))
+    } else {
+        None
+    };
+    let res = match val {
+        Some(val) => LuaVal::from(val.into_vec()),
+        None => LuaVal::new(),
+    };
+    push_values(vm, vec![res])
+}
+
+/// Returns true if <res> is a success, and nil, an error message which starts with
+/// <name> and an error number otherwise.
+fn file_result(res: io::Result<()>, name: &str) -> Vec<LuaVal> {
+    match res {
+        Ok(()) => vec![LuaVal::from(true)],
+        Err(err) => error_result(&err, Some(name)).0,
+    }
+}
+
+/// Removes a file or an empty directory.
+pub fn lua_remove(vm: &mut Vm) -> Result<(), LuaError> {
+    let name: String = args(vm)?;
+    let res = if Path::new(&name).is_dir() {
+        fs::remove_dir(&name)
+    } else {
+        fs::remove_file(&name)
+    };
+    push_values(vm, file_result(res, &name))
+}
+
+/// Renames a file or a directory.
+pub fn lua_rename(vm: &mut Vm) -> Result<(), LuaError> {
+    let (old, new): (String, String) = args(vm)?;
+    let res = fs::rename(&old, &new);
+    push_values(vm, file_result(res, &old))
+}
+
+/// Creates an empty file with a new name in the temporary directory, like `mkstemp`,
+/// and returns its name.
+pub fn lua_tmpname(vm: &mut Vm) -> Result<(), LuaError> {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.subsec_nanos());
+    let mut seed = (u64::from(process::id()) << 32) ^ u64::from(nanos);
+    for _ in 0..TMPNAME_ATTEMPTS {
+        // the names are generated by a linear congruential generator
+        seed = seed
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(1_442_695_040_888_963_407);
+        let path = env::temp_dir().join(format!("lua_{:06x}", seed >> 40));
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(_) => {
+                let name = path.into_os_string().into_vec();
+                return push_values(vm, vec![LuaVal::from(name)]);
+            }
+            Err(ref err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(_) => break,
+        }
+    }
+    Err(LuaError::Error(
+        "unable to generate a unique filename".to_string(),
+    ))
+}
@@ -0,0 +1,368 @@
+//! The format language of `string.pack`, `string.unpack` and `string.packsize`, which
+//! convert between Lua values and their binary representation.
+use errors::LuaError;
+use lua_values::{
+    convert::{FromLua, LuaBytes},
+    LuaVal,
+};
+
+/// The size of a Lua integer.
+const INT_SIZE: usize = 8;
+/// The maximum size of an integer in a format.
+const MAX_INT_SIZE: usize = 16;
+/// The alignment which `!` sets if it isn't given a size, i.e. the one of the largest
+/// native type.
+const NATIVE_ALIGN: usize = 8;
+/// The maximum size of a packed string.
+const MAX_SIZE: usize = i32::MAX as usize;
+
+/// The kinds of options of a format.
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Int,
+    Uint,
+    Float,
+    /// A string of a fixed size, i.e. `cn`.
+    Char,
+    /// A string preceded by its length, i.e. `sn`.
+    String,
+    /// A zero-terminated string, i.e. `z`.
+    ZStr,
+    /// A padding byte, i.e. `x`.
+    Padding,
+    /// An alignment to the next option, i.e. `X`.
+    PadAlign,
+    /// An option which has no data, e.g. the endianness.
+    Nop,
+}
+
+fn error<T>(msg: String) -> Result<T, LuaError> {
+    Err(LuaError::Error(msg))
+}
+
+/// An error of the argument <arg> of a function.
+fn arg_error(arg: usize, msg: &str) -> LuaError {
+    LuaError::BadArgument(arg, Box::new(LuaError::Error(msg.to_string())))
+}
+
+/// Reads the options of a format, and keeps track of the endianness and the maximum
+/// alignment which they set.
+struct Format<'a> {
+    fmt: &'a [u8],
+    pos: usize,
+    little: bool,
+    max_align: usize,
+}
+
+impl<'a> Format<'a> {
+    fn new(fmt: &'a [u8]) -> Format<'a> {
+        Format {
+            fmt,
+            pos: 0,
+            little: cfg!(target_endian = "little"),
+            max_align: 1,
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        self.pos >= self.fmt.len()
+    }
+
+    /// Reads the size which follows an option, or returns <default> if there is none.
+    fn read_num(&mut self, default: usize) -> usize {
+        let digit = |f: &Self| f.fmt.get(f.pos).is_some_and(u8::is_ascii_digit);
+        if !digit(self) {
+            return default;
+        }
+        let mut n = 0;
+        loop {
+            n = n * 10 + (self.fmt[self.pos] - b'0') as usize;
+            self.pos += 1;
+            if !digit(self) || n > (MAX_SIZE - 9) / 10 {
+                return n;
+            }
+        }
+    }
+
+    /// Reads the size of an integer option.
+    fn read_int_size(&mut self, default: usize) -> Result<usize, LuaError> {
+        let size = self.read_num(default);
+        if size > MAX_INT_SIZE || size == 0 {
+            return error(format!(
+                "integral size ({}) out of limits [1,{}]",
+                size, MAX_INT_SIZE
+            ));
+        }
+        Ok(size)
+    }
+
+    /// Reads the next option, and returns its kind and its size.
+    fn option(&mut self) -> Result<(Kind, usize), LuaError> {
+        let opt = self.fmt[self.pos];
+        self.pos += 1;
+        Ok(match opt {
+            b'b' => (Kind::Int, 1),
+            b'B' => (Kind::Uint, 1),
+            b'h' => (Kind::Int, 2),
+            b'H' => (Kind::Uint, 2),
+            b'l' | b'j' => (Kind::Int, 8),
+            b'L' | b'J' | b'T' => (Kind::Uint, 8),
+            b'f' => (Kind::Float, 4),
+            b'd' | b'n' => (Kind::Float, 8),
+            b'i' => (Kind::Int, self.read_int_size(4)?),
+            b'I' => (Kind::Uint, self.read_int_size(4)?),
+            b's' => (Kind::String, self.read_int_size(8)?),
+            b'c' => match self.read_num(usize::MAX) {
+                usize::MAX => return error("missing size for format option 'c'".to_string()),
+                size => (Kind::Char, size),
+            },
+            b'z' => (Kind::ZStr, 0),
+            b'x' => (Kind::Padding, 1),
+            b'X' => (Kind::PadAlign, 0),
+            b' ' => (Kind::Nop, 0),
+            b'<' => {
+                self.little = true;
+                (Kind::Nop, 0)
+            }
+            b'>' => {
+                self.little = false;
+                (Kind::Nop, 0)
+            }
+            b'=' => {
+                self.little = cfg!(target_endian = "little");
+                (Kind::Nop, 0)
+            }
+            b'!' => {
+                self.max_align = self.read_int_size(NATIVE_ALIGN)?;
+                (Kind::Nop, 0)
+            }
+            c => return error(format!("invalid format option '{}'", c as char)),
+        })
+    }
+
+    /// Reads the next option, and returns its kind, its size, and the number of padding
+    /// bytes which align it, where <total> is the size of the data so far.
+    fn details(&mut self, total: usize) -> Result<(Kind, usize, usize), LuaError> {
+        let (kind, size) = self.option()?;
+        let mut align = size;
+        // `X` takes the alignment of the next option, which is otherwise ignored
+        if kind == Kind::PadAlign {
+            let next = if self.is_done() {
+                None
+            } else {
+                Some(self.option()?)
+            };
+            match next {
+                Some((next, size)) if next != Kind::Char && size != 0 => align = size,
+                _ => return Err(arg_error(1, "invalid next option for option 'X'")),
+            }
+        }
+        if align <= 1 || kind == Kind::Char {
+            return Ok((kind, size, 0));
+        }
+        let align = align.min(self.max_align);
+        if !align.is_power_of_two() {
+            return Err(arg_error(1, "format asks for alignment not power of 2"));
+        }
+        Ok((kind, size, (align - (total & (align - 1))) & (align - 1)))
+    }
+}
+
+/// Appends the <size> bytes of the integer <n>. The bytes beyond the size of a Lua
+/// integer extend its sign.
+fn pack_int(out: &mut Vec<u8>, n: u64, little: bool, size: usize, negative: bool) {
+    let mut bytes: Vec<u8> = (0..size)
+        .map(|i| match i {
+            i if i < INT_SIZE => (n >> (8 * i)) as u8,
+            _ if negative => 0xff,
+            _ => 0,
+        })
+        .collect();
+    if !little {
+        bytes.reverse();
+    }
+    out.extend_from_slice(&bytes);
+}
+
+/// Reads an integer of <size> bytes. Integers which are larger than a Lua integer must
+/// have an extended sign.
+fn unpack_int(data: &[u8], little: bool, size: usize, signed: bool) -> Result<i64, LuaError> {
+    let byte = |i: usize| if little { data[i] } else { data[size - 1 - i] };
+    let limit = size.min(INT_SIZE);
+    let mut res: u64 = 0;
+    for i in (0..limit).rev() {
+        res = (res << 8) | byte(i) as u64;
+    }
+    if size < INT_SIZE {
+        if signed {
+            let mask = 1u64 << (size * 8 - 1);
+            res = (res ^ mask).wrapping_sub(mask);
+        }
+    } else if size > INT_SIZE {
+        let ext = if signed && (res as i64) < 0 { 0xff } else { 0 };
+        if (limit..size).any(|i| byte(i) != ext) {
+            return error(format!(
+                "{}-byte integer does not fit into Lua Integer",
+                size
+            ));
+        }
+    }
+    Ok(res as i64)
+}
+
+/// Converts the argument <arg> of <args>, where the format is argument 1.
+fn arg<T: FromLua>(args: &[LuaVal], arg: usize) -> Result<T, LuaError> {
+    let val = args.get(arg - 1).cloned().unwrap_or_default();
+    T::from_lua(&val).map_err(|err| LuaError::BadArgument(arg, Box::new(err)))
+}
+
+/// Packs <args>, which follow the format <fmt>.
+pub fn pack(fmt: &[u8], args: &[LuaVal]) -> Result<Vec<u8>, LuaError> {
+    let mut fmt = Format::new(fmt);
+    let mut out = vec![];
+    let mut i = 1;
+    while !fmt.is_done() {
+        let (kind, size, padding) = fmt.details(out.len())?;
+        out.resize(out.len() + padding, 0);
+        i += 1;
+        match kind {
+            Kind::Int => {
+                let n: i64 = arg(args, i)?;
+                if size < INT_SIZE {
+                    let lim = 1i64 << (size * 8 - 1);
+                    if n < -lim || n >= lim {
+                        return Err(arg_error(i, "integer overflow"));
+                    }
+                }
+                pack_int(&mut out, n as u64, fmt.little, size, n < 0);
+            }
+            Kind::Uint => {
+                let n: i64 = arg(args, i)?;
+                if size < INT_SIZE && (n as u64) >= 1u64 << (size * 8) {
+                    return Err(arg_error(i, "unsigned overflow"));
+                }
+                pack_int(&mut out, n as u64, fmt.little, size, false);
+            }
+            Kind::Float => {
+                let n: f64 = arg(args, i)?;
+                let mut bytes = if size == 4 {
+                    (n as f32).to_le_bytes().to_vec()
+                } else {
+                    n.to_le_bytes().to_vec()
+                };
+                if !fmt.little {
+                    bytes.reverse();
+                }
+                out.extend_from_slice(&bytes);
+            }
+            Kind::Char => {
+                let s: LuaBytes = arg(args, i)?;
+                if s.len() > size {
+                    return Err(arg_error(i, "string longer than given size"));
+                }
+                out.extend_from_slice(&s);
+                out.resize(out.len() + size - s.len(), 0);
+            }
+            Kind::String => {
+                let s: LuaBytes = arg(args, i)?;
+                if size < INT_SIZE && s.len() as u64 >= 1u64 << (size * 8) {
+                    return Err(arg_error(i, "string length does not fit in given size"));
+                }
+                pack_int(&mut out, s.len() as u64, fmt.little, size, false);
+                out.extend_from_slice(&s);
+            }
+            Kind::ZStr => {
+                let s: LuaBytes = arg(args, i)?;
+                if s.contains(&0) {
+                    return Err(arg_error(i, "string contains zeros"));
+                }
+                out.extend_from_slice(&s);
+                out.push(0);
+            }
+            Kind::Padding => {
+                out.push(0);
+                i -= 1;
+            }
+            Kind::PadAlign | Kind::Nop => i -= 1,
+        }
+    }
+    Ok(out)
+}
+
+/// Gets the size of the data packed with <fmt>, which cannot have options of variable
+/// size.
+pub fn packsize(fmt: &[u8]) -> Result<usize, LuaError> {
+    let mut fmt = Format::new(fmt);
+    let mut total = 0;
+    while !fmt.is_done() {
+        let (kind, size, padding) = fmt.details(total)?;
+        let size = size + padding;
+        if total > MAX_SIZE - size {
+            return Err(arg_error(1, "format result too large"));
+        }
+        total += size;
+        if kind == Kind::String || kind == Kind::ZStr {
+            return Err(arg_error(1, "variable-length format"));
+        }
+    }
+    Ok(total)
+}
+
+/// Unpacks the values of <data> which follow the format <fmt>, starting at index <pos>.
+/// Returns the values followed by the position after them, counted from 1.
+pub fn unpack(fmt: &[u8], data: &[u8], mut pos: usize) -> Result<Vec<LuaVal>, LuaError> {
+    let mut fmt = Format::new(fmt);
+    let mut vals = vec![];
+    while !fmt.is_done() {
+        let (kind, size, padding) = fmt.details(pos)?;
+        if pos + padding + size > data.len() {
+            return Err(arg_error(2, "data string too short"));
+        }
+        pos += padding;
+        let bytes = &data[pos..(pos + size)];
+        match kind {
+            Kind::Int | Kind::Uint => {
+                let n = unpack_int(bytes, fmt.little, size, kind == Kind::Int)?;
+                vals.push(LuaVal::from(n));
+            }
+            Kind::Float => {
+                let mut bytes = bytes.to_vec();
+                if !fmt.little {
+                    bytes.reverse();
+                }
+                let n = if size == 4 {
+                    let mut b = [0; 4];
+                    b.copy_from_slice(&bytes);
+                    f32::from_le_bytes(b) as f64
+                } else {
+                    let mut b = [0; 8];
+                    b.copy_from_slice(&bytes);
+                    f64::from_le_bytes(b)
+                };
+                vals.push(LuaVal::from(n));
+            }
+            Kind::Char => vals.push(LuaVal::from(bytes)),
+            Kind::String => {
+                let len = unpack_int(bytes, fmt.little, size, false)? as u64;
+                if len > (data.len() - pos - size) as u64 {
+                    return Err(arg_error(2, "data string too short"));
+                }
+                let start = pos + size;
+                vals.push(LuaVal::from(&data[start..(start + len as usize)]));
+                pos += len as usize;
+            }
+            Kind::ZStr => {
+                let len = match data[pos..].iter().position(|b| *b == 0) {
+                    Some(len) => len,
+                    None => return Err(arg_error(2, "unfinished string for format 'z'")),
+                };
+                vals.push(LuaVal::from(&data[pos..(pos + len)]));
+                pos += len + 1;
+            }
+            Kind::Padding | Kind::PadAlign | Kind::Nop => {}
+        }
+        pos += size;
+    }
+    vals.push(LuaVal::from(pos as i64 + 1));
+    Ok(vals)
+}
@@ -0,0 +1,217 @@
+use crate::Vm;
+use errors::LuaError;
+use gc::Gc;
+use instructions::functions::return_values;
+use lua_values::{lua_closure::ControlFunction, LuaVal};
+use luacompiler::{bytecode::LuaBytecode, LuaParseTree};
+use std::{env, fs};
+use stdlib::{args, compile, push_values, StdFunction};
+
+/// The value of `package.path` if neither LUA_PATH_5_3 nor LUA_PATH are set. A ";;" in
+/// the variables is replaced by it as well.
+pub const DEFAULT_PATH: &str = "./?.lua;./?/init.lua;./?.luabc";
+
+pub fn get_package_module() -> (String, LuaVal) {
+    let package = LuaVal::new_table();
+    let searchers = LuaVal::new_table();
+    for (i, searcher) in [
+        search_preload as fn(&mut Vm) -> Result<(), LuaError>,
+        search_path,
+    ]
+    .iter()
+    .enumerate()
+    {
+        let searcher = ControlFunction::new(*searcher, vec![Gc::new(package.clone())]);
+        searchers
+            .set_attr(LuaVal::from(i as i64 + 1), LuaVal::from(searcher))
+            .unwrap();
+    }
+    let searchpath = StdFunction {
+        name: "searchpath",
+        handler: lua_searchpath,
+    };
+    for field in &[
+        ("loaded", LuaVal::new_table()),
+        ("preload", LuaVal::new_table()),
+        ("path", LuaVal::from(lua_path())),
+        ("searchers", searchers),
+        ("searchpath", LuaVal::from(&searchpath)),
+    ] {
+        package
+            .set_attr(LuaVal::from(field.0), field.1.clone())
+            .unwrap();
+    }
+    ("package".to_string(), package)
+}
+
+/// Creates the `require` function, which loads the modules with the searchers of
+/// <package>.
+pub fn get_require(package: &LuaVal) -> LuaVal {
+    // the second upvalue holds the names of the modules which are being loaded
+    LuaVal::from(
+        ControlFunction::new(
+            lua_require,
+            vec![Gc::new(package.clone()), Gc::new(LuaVal::new_table())],
+        )
+        .with_name("require"),
+    )
+}
+
+/// The initial value of `package.path`.
+fn lua_path() -> String {
+    match env::var("LUA_PATH_5_3").or_else(|_| env::var("LUA_PATH")) {
+        Ok(path) => path
+            .replace(";;", &format!(";{};", DEFAULT_PATH))
+            .trim_matches(';')
+            .to_string(),
+        Err(_) => DEFAULT_PATH.to_string(),
+    }
+}
+
+/// Gets the field <name> of <package>, which must have the type <expected>.
+fn package_field(package: &LuaVal, name: &str, expected: &str) -> Result<LuaVal, LuaError> {
+    let val = package.get_attr(&LuaVal::from(name))?;
+    if val.type_name() != expected {
+        return Err(LuaError::Error(format!(
+            "'package.{}' must be a {}",
+            name, expected
+        )));
+    }
+    Ok(val)
+}
+
+/// Loads the module given as argument, unless `package.loaded` already contains it.
+/// The loader of the module is the first one found by `package.searchers`, and it is
+/// called with the name of the module and the extra value returned by the searcher. The
+/// value returned by the loader is stored in `package.loaded`, or true if it returned
+/// nothing.
+fn lua_require(vm: &mut Vm) -> Result<(), LuaError> {
+    let name: String = args(vm)?;
+    let package = (*vm.closure().get_upval(0)?).clone();
+    let loading = (*vm.closure().get_upval(1)?).clone();
+    let key = LuaVal::from(name.as_str());
+    let loaded = package_field(&package, "loaded", "table")?;
+    let module = loaded.get_attr(&key)?;
+    if module.to_bool() {
+        return return_values(vm, vec![module]);
+    }
+    if loading.get_attr(&key)?.to_bool() {
+        return Err(LuaError::Error(format!(
+            "circular require of module '{}'",
+            name
+        )));
+    }
+    let (loader, extra) = find_loader(vm, &package, &name)?;
+    loading.set_attr(key.clone(), LuaVal::from(true))?;
+    let res = vm.call(&loader, &[key.clone(), extra]);
+    loading.set_attr(key.clone(), LuaVal::new())?;
+    let module = res?.into_iter().next().unwrap_or_default();
+    if !module.is_nil() {
+        loaded.set_attr(key.clone(), module)?;
+    }
+    // the loader might have set the entry itself
+    if loaded.get_attr(&key)?.is_nil() {
+        loaded.set_attr(key.clone(), LuaVal::from(true))?;
+    }
+    let module = loaded.get_attr(&key)?;
+    return_values(vm, vec![module])
+}
+
+/// Calls the searchers of <package> until one of them finds the module <name>, and
+/// returns its loader and the extra value for the loader.
+fn find_loader(vm: &mut Vm, package: &LuaVal, name: &str) -> Result<(LuaVal, LuaVal), LuaError> {
+    let searchers = package_field(package, "searchers", "table")?;
+    let mut msg = String::new();
+    for i in 1.. {
+        let searcher = searchers.get_attr(&LuaVal::from(i))?;
+        if searcher.is_nil() {
+            break;
+        }
+        let mut res = vm.call(&searcher, &[LuaVal::from(name)])?.into_iter();
+        let loader = res.next().unwrap_or_default();
+        if loader.is_function() {
+            return Ok((loader, res.next().unwrap_or_default()));
+        } else if loader.is_string() {
+            msg.push_str(&loader.to_string()?);
+        }
+    }
+    Err(LuaError::Error(format!(
+        "module '{}' not found:{}",
+        name, msg
+    )))
+}
+
+/// Finds the loader of a module in `package.preload`.
+fn search_preload(vm: &mut Vm) -> Result<(), LuaError> {
+    let name: String = args(vm)?;
+    let package = (*vm.closure().get_upval(0)?).clone();
+    let preload = package_field(&package, "preload", "table")?;
+    let loader = preload.get_attr(&LuaVal::from(name.as_str()))?;
+    let res = if loader.is_nil() {
+        LuaVal::from(format!("\n\tno field package.preload['{}']", name))
+    } else {
+        loader
+    };
+    return_values(vm, vec![res, LuaVal::from(":preload:")])
+}
+
+/// Finds a module in the files of `package.path`. The files whose extension is
+/// ".luabc" hold the serialized bytecode of the module, the others its source code.
+fn search_path(vm: &mut Vm) -> Result<(), LuaError> {
+    let name: String = args(vm)?;
+    let package = (*vm.closure().get_upval(0)?).clone();
+    let path = package_field(&package, "path", "string")?.to_string()?;
+    let file = match search(&name, &path, ".", "/") {
+        Ok(file) => file,
+        Err(msg) => return return_values(vm, vec![LuaVal::from(msg)]),
+    };
+    let bytecode = if file.ends_with(".luabc") {
+        fs::read(&file)
+            .map_err(|err| err.to_string())
+            .and_then(|bytes| LuaBytecode::try_from_bytes(&bytes).map_err(|err| err.to_string()))
+    } else {
+        compile(LuaParseTree::new(&file), &format!("@{}", file))
+    };
+    match bytecode {
+        Ok(bytecode) => {
+            let loader = vm.load_chunk(bytecode, None);
+            return_values(vm, vec![loader, LuaVal::from(file)])
+        }
+        Err(msg) => Err(LuaError::Error(format!(
+            "error loading module '{}' from file '{}':\n\t{}",
+            name, file, msg
+        ))),
+    }
+}
+
+/// Looks for <name> in <path>, a list of templates separated by ";", in which "?" is
+/// replaced by <name>. Every <sep> in <name> is replaced by <rep> first. Returns the
+/// first file which can be read, or a message which lists the files that were tried.
+fn search(name: &str, path: &str, sep: &str, rep: &str) -> Result<String, String> {
+    let name = if sep.is_empty() {
+        name.to_string()
+    } else {
+        name.replace(sep, rep)
+    };
+    let mut msg = String::new();
+    for template in path.split(';').filter(|t| !t.is_empty()) {
+        let file = template.replace('?', &name);
+        if fs::File::open(&file).is_ok() {
+            return Ok(file);
+        }
+        msg.push_str(&format!("\n\tno file '{}'", file));
+    }
+    Err(msg)
+}
+
+/// Returns the first file of the path given as the second argument in which the module
+/// given as the first argument is found, or nil and the files which were tried.
+fn lua_searchpath(vm: &mut Vm) -> Result<(), LuaError> {
+    let (name, path, sep, rep): (String, String, Option<String>, Option<String>) = args(vm)?;
+    let sep = sep.unwrap_or_else(|| ".".to_string());
+    let rep = rep.unwrap_or_else(|| "/".to_string());
+    match search(&name, &path, &sep, &rep) {
+        Ok(file) => push_values(vm, vec![LuaVal::from(file)]),
+        Err(msg) => push_values(vm, vec![LuaVal::new(), LuaVal::from(msg)]),
+    }
+}
@@ -0,0 +1,411 @@
+//! The matcher of Lua patterns, which works on the bytes of strings in the same way as
+//! the one of the reference implementation.
+use errors::LuaError;
+
+/// The escape character of patterns.
+const ESC: u8 = b'%';
+/// The characters which make a pattern more than a plain string.
+const SPECIALS: &[u8] = b"^$*+?.([%-";
+/// The maximum number of captures of a pattern.
+const MAX_CAPTURES: usize = 32;
+/// The maximum depth of the recursion of the matcher, which limits how complex
+/// patterns can get.
+const MAX_MATCH_DEPTH: usize = 200;
+
+/// The length of a capture while the pattern is being matched.
+#[derive(Clone, Copy, PartialEq)]
+enum CaptureLen {
+    /// A capture whose closing parenthesis was not matched yet.
+    Unfinished,
+    /// A position capture, i.e. `()`.
+    Position,
+    Len(usize),
+}
+
+/// A capture of a successful match.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Capture {
+    /// The bytes of the subject from the first index up to the second one.
+    Slice(usize, usize),
+    /// The position of a `()` capture, counted from 1.
+    Position(usize),
+}
+
+fn error<T>(msg: &str) -> Result<T, LuaError> {
+    Err(LuaError::Error(msg.to_string()))
+}
+
+/// Checks if <pat> has no special characters, in which case it can be found as it is.
+pub fn is_plain(pat: &[u8]) -> bool {
+    !pat.iter().any(|c| SPECIALS.contains(c))
+}
+
+/// Finds the first occurrence of <pat> in <src> at or after <start>.
+pub fn find_plain(src: &[u8], pat: &[u8], start: usize) -> Option<usize> {
+    if pat.is_empty() {
+        return Some(start);
+    }
+    src[start..]
+        .windows(pat.len())
+        .position(|w| w == pat)
+        .map(|i| start + i)
+}
+
+/// Checks if <c> is part of the class `%<class>`, e.g. `%d`. The upper case classes
+/// are the complements of the lower case ones; any other character is matched as it is.
+fn match_class(c: u8, class: u8) -> bool {
+    let res = match class.to_ascii_lowercase() {
+        b'a' => c.is_ascii_alphabetic(),
+        b'c' => c.is_ascii_control(),
+        b'd' => c.is_ascii_digit(),
+        b'g' => c.is_ascii_graphic(),
+        b'l' => c.is_ascii_lowercase(),
+        b'p' => c.is_ascii_punctuation(),
+        // unlike `is_ascii_whitespace`, this includes the vertical tab
+        b's' => c == b' ' || (b'\t'..=b'\r').contains(&c),
+        b'u' => c.is_ascii_uppercase(),
+        b'w' => c.is_ascii_alphanumeric(),
+        b'x' => c.is_ascii_hexdigit(),
+        _ => return class == c,
+    };
+    if class.is_ascii_uppercase() {
+        !res
+    } else {
+        res
+    }
+}
+
+/// Matches a pattern against a subject. The state of the captures is kept between
+/// calls of `match_at`, so that they can be retrieved after a successful match.
+pub struct Matcher<'a> {
+    src: &'a [u8],
+    pat: &'a [u8],
+    /// The start of each capture in `src`, and its length.
+    captures: Vec<(usize, CaptureLen)>,
+    /// How many more times `do_match` can recurse.
+    depth: usize,
+}
+
+impl<'a> Matcher<'a> {
+    pub fn new(src: &'a [u8], pat: &'a [u8]) -> Matcher<'a> {
+        Matcher {
+            src,
+            pat,
+            captures: vec![],
+            depth: MAX_MATCH_DEPTH,
+        }
+    }
+
+    /// Matches the pattern, starting from index <p> of the pattern, with the subject
+    /// starting at index <s>. Returns the end of the match if there is one.
+    pub fn match_at(&mut self, s: usize, p: usize) -> Result<Option<usize>, LuaError> {
+        self.captures.clear();
+        self.depth = MAX_MATCH_DEPTH;
+        self.do_match(s, p)
+    }
+
+    /// Gets the captures of the last match, which spans <start>..<end>. A pattern
+    /// without captures captures the whole match if <whole> is true.
+    pub fn captures(
+        &self,
+        start: usize,
+        end: usize,
+        whole: bool,
+    ) -> Result<Vec<Capture>, LuaError> {
+        let count = if self.captures.is_empty() && whole {
+            1
+        } else {
+            self.captures.len()
+        };
+        (0..count).map(|i| self.capture(i, start, end)).collect()
+    }
+
+    /// Gets capture <i> of the last match, which spans <start>..<end>. Capture 0 is
+    /// the whole match if the pattern has no captures.
+    pub fn capture(&self, i: usize, start: usize, end: usize) -> Result<Capture, LuaError> {
+        match self.captures.get(i) {
+            None if i == 0 => Ok(Capture::Slice(start, end)),
+            None => Err(LuaError::Error(format!("invalid capture index %{}", i + 1))),
+            Some(&(_, CaptureLen::Unfinished)) => error("unfinished capture"),
+            Some(&(start, CaptureLen::Position)) => Ok(Capture::Position(start + 1)),
+            Some(&(start, CaptureLen::Len(len))) => Ok(Capture::Slice(start, start + len)),
+        }
+    }
+
+    /// The byte of the pattern at <p>, or 0 at its end.
+    fn pat_at(&self, p: usize) -> u8 {
+        self.pat.get(p).cloned().unwrap_or(0)
+    }
+
+    fn do_match(&mut self, mut s: usize, mut p: usize) -> Result<Option<usize>, LuaError> {
+        if self.depth == 0 {
+            return error("pattern too complex");
+        }
+        self.depth -= 1;
+        let res = loop {
+            if p == self.pat.len() {
+                break Some(s);
+            }
+            match self.pat[p] {
+                b'(' => {
+                    break if self.pat_at(p + 1) == b')' {
+                        self.start_capture(s, p + 2, CaptureLen::Position)?
+                    } else {
+                        self.start_capture(s, p + 1, CaptureLen::Unfinished)?
+                    };
+                }
+                b')' => break self.end_capture(s, p + 1)?,
+                b'$' if p + 1 == self.pat.len() => {
+                    break if s == self.src.len() { Some(s) } else { None };
+                }
+                ESC if self.pat_at(p + 1) == b'b' => match self.match_balance(s, p + 2)? {
+                    Some(end) => {
+                        s = end;
+                        p += 4;
+                    }
+                    None => break None,
+                },
+                ESC if self.pat_at(p + 1) == b'f' => {
+                    p += 2;
+                    if self.pat_at(p) != b'[' {
+                        return error("missing '[' after '%f' in pattern");
+                    }
+                    let ep = self.class_end(p)?;
+                    let prev = if s == 0 { 0 } else { self.src[s - 1] };
+                    let curr = self.src.get(s).cloned().unwrap_or(0);
+                    if !self.match_bracket_class(prev, p, ep - 1)
+                        && self.match_bracket_class(curr, p, ep - 1)
+                    {
+                        p = ep;
+                    } else {
+                        break None;
+                    }
+                }
+                ESC if self.pat_at(p + 1).is_ascii_digit() => {
+                    match self.match_capture(s, self.pat[p + 1])? {
+                        Some(end) => {
+                            s = end;
+                            p += 2;
+                        }
+                        None => break None,
+                    }
+                }
+                _ => {
+                    let ep = self.class_end(p)?;
+                    let suffix = self.pat_at(ep);
+                    if !self.single_match(s, p, ep) {
+                        // the item can match zero times
+                        if suffix == b'*' || suffix == b'?' || suffix == b'-' {
+                            p = ep + 1;
+                            continue;
+                        }
+                        break None;
+                    }
+                    match suffix {
+                        b'?' => match self.do_match(s + 1, ep + 1)? {
+                            Some(end) => break Some(end),
+                            None => p = ep + 1,
+                        },
+                        b'+' => break self.max_expand(s + 1, p, ep)?,
+                        b'*' => break self.max_expand(s, p, ep)?,
+                        b'-' => break self.min_expand(s, p, ep)?,
+                        _ => {
+                            s += 1;
+                            p = ep;
+                        }
+                    }
+                }
+            }
+        };
+        self.depth += 1;
+        Ok(res)
+    }
+
+    /// Gets the end of the single character class which starts at <p>.
+    fn class_end(&self, mut p: usize) -> Result<usize, LuaError> {
+        let c = self.pat[p];
+        p += 1;
+        if c == ESC {
+            if p >= self.pat.len() {
+                return error("malformed pattern (ends with '%')");
+            }
+            return Ok(p + 1);
+        }
+        if c == b'[' {
+            if self.pat_at(p) == b'^' {
+                p += 1;
+            }
+            // the first character of a set is part of it, even if it is a ']'
+            loop {
+                if p >= self.pat.len() {
+                    return error("malformed pattern (missing ']')");
+                }
+                let c = self.pat[p];
+                p += 1;
+                if c == ESC && p < self.pat.len() {
+                    p += 1;
+                }
+                if self.pat_at(p) == b']' {
+                    break;
+                }
+            }
+            return Ok(p + 1);
+        }
+        Ok(p)
+    }
+
+    /// Checks if <c> is part of the set which starts at <p> with a '[', and ends at
+    /// <end> with a ']'.
+    fn match_bracket_class(&self, c: u8, mut p: usize, end: usize) -> bool {
+        let mut found = true;
+        if self.pat_at(p + 1) == b'^' {
+            found = false;
+            p += 1;
+        }
+        p += 1;
+        while p < end {
+            if self.pat[p] == ESC {
+                p += 1;
+                if match_class(c, self.pat[p]) {
+                    return found;
+                }
+            } else if self.pat_at(p + 1) == b'-' && p + 2 < end {
+                if self.pat[p] <= c && c <= self.pat[p + 2] {
+                    return found;
+                }
+                p += 2;
+            } else if self.pat[p] == c {
+                return found;
+            }
+            p += 1;
+        }
+        !found
+    }
+
+    /// Checks if the byte of the subject at <s> matches the single character class
+    /// which spans <p>..<ep> of the pattern.
+    fn single_match(&self, s: usize, p: usize, ep: usize) -> bool {
+        let c = match self.src.get(s) {
+            Some(c) => *c,
+            None => return false,
+        };
+        match self.pat[p] {
+            b'.' => true,
+            ESC => match_class(c, self.pat[p + 1]),
+            b'[' => self.match_bracket_class(c, p, ep - 1),
+            pc => pc == c,
+        }
+    }
+
+    /// Matches as many repetitions of the class at <p>..<ep> as possible, and then
+    /// backtracks until the rest of the pattern matches.
+    fn max_expand(&mut self, s: usize, p: usize, ep: usize) -> Result<Option<usize>, LuaError> {
+        let mut count = 0;
+        while self.single_match(s + count, p, ep) {
+            count += 1;
+        }
+        loop {
+            if let Some(end) = self.do_match(s + count, ep + 1)? {
+                return Ok(Some(end));
+            }
+            if count == 0 {
+                return Ok(None);
+            }
+            count -= 1;
+        }
+    }
+
+    /// Matches as few repetitions of the class at <p>..<ep> as possible, such that the
+    /// rest of the pattern matches.
+    fn min_expand(&mut self, mut s: usize, p: usize, ep: usize) -> Result<Option<usize>, LuaError> {
+        loop {
+            if let Some(end) = self.do_match(s, ep + 1)? {
+                return Ok(Some(end));
+            } else if self.single_match(s, p, ep) {
+                s += 1;
+            } else {
+                return Ok(None);
+            }
+        }
+    }
+
+    fn start_capture(
+        &mut self,
+        s: usize,
+        p: usize,
+        len: CaptureLen,
+    ) -> Result<Option<usize>, LuaError> {
+        if self.captures.len() >= MAX_CAPTURES {
+            return error("too many captures");
+        }
+        self.captures.push((s, len));
+        let res = self.do_match(s, p)?;
+        if res.is_none() {
+            self.captures.pop();
+        }
+        Ok(res)
+    }
+
+    fn end_capture(&mut self, s: usize, p: usize) -> Result<Option<usize>, LuaError> {
+        let i = match self
+            .captures
+            .iter()
+            .rposition(|&(_, len)| len == CaptureLen::Unfinished)
+        {
+            Some(i) => i,
+            None => return error("invalid pattern capture"),
+        };
+        self.captures[i].1 = CaptureLen::Len(s - self.captures[i].0);
+        let res = self.do_match(s, p)?;
+        if res.is_none() {
+            self.captures[i].1 = CaptureLen::Unfinished;
+        }
+        Ok(res)
+    }
+
+    /// Matches `%bxy`, where x and y are at <p>, i.e. a string which starts with x, ends
+    /// with y, and where x and y are balanced.
+    fn match_balance(&self, s: usize, p: usize) -> Result<Option<usize>, LuaError> {
+        if p + 1 >= self.pat.len() {
+            return error("malformed pattern (missing arguments to '%b')");
+        }
+        let (open, close) = (self.pat[p], self.pat[p + 1]);
+        if self.src.get(s) != Some(&open) {
+            return Ok(None);
+        }
+        let mut depth = 1;
+        for (i, c) in self.src.iter().enumerate().skip(s + 1) {
+            if *c == close {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(Some(i + 1));
+                }
+            } else if *c == open {
+                depth += 1;
+            }
+        }
+        Ok(None)
+    }
+
+    /// Matches the back-reference `%<digit>`, i.e. the same bytes as that capture.
+    fn match_capture(&self, s: usize, digit: u8) -> Result<Option<usize>, LuaError> {
+        let i = (digit as usize).wrapping_sub(b'1' as usize);
+        let (start, len) = match self.captures.get(i) {
+            Some(&(start, CaptureLen::Len(len))) => (start, len),
+            // a position capture never matches
+            Some(&(_, CaptureLen::Position)) => return Ok(None),
+            _ => {
+                return Err(LuaError::Error(format!(
+                    "invalid capture index %{}",
+                    i.wrapping_add(1)
+                )))
+            }
+        };
+        let end = s + len;
+        if end <= self.src.len() && self.src[start..(start + len)] == self.src[s..end] {
+            Ok(Some(end))
+        } else {
+            Ok(None)
+        }
+    }
+}
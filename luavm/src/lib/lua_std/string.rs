@@ -0,0 +1,447 @@
+use crate::Vm;
+use errors::LuaError;
+use lua_std::format::{format_char, format_float, format_int, format_str, parse_spec, quote};
+use lua_std::pack::{pack, packsize, unpack};
+use lua_std::pattern::{find_plain, is_plain, Capture, Matcher};
+use lua_values::{
+    convert::{FromLua, FromLuaMulti, LuaBytes},
+    lua_closure::HostFunction,
+    lua_table::UserTable,
+    LuaVal,
+};
+use std::{cell::Cell, collections::HashMap};
+use stdlib::{abs_pos, args, push_values, tostring, StdFunction};
+
+pub fn get_string_module() -> (String, LuaVal) {
+    let string = LuaVal::from(UserTable::new(HashMap::new()));
+    for func in &[
+        ("len", lua_len as fn(&mut Vm) -> Result<(), LuaError>),
+        ("sub", lua_sub),
+        ("upper", lua_upper),
+        ("lower", lua_lower),
+        ("rep", lua_rep),
+        ("reverse", lua_reverse),
+        ("byte", lua_byte),
+        ("char", lua_char),
+        ("find", lua_find),
+        ("match", lua_match),
+        ("gmatch", lua_gmatch),
+        ("gsub", lua_gsub),
+        ("format", lua_format),
+        ("pack", lua_pack),
+        ("unpack", lua_unpack),
+        ("packsize", lua_packsize),
+    ] {
+        let std_func = StdFunction {
+            name: func.0,
+            handler: func.1,
+        };
+        string
+            .set_attr(LuaVal::from(func.0.to_string()), LuaVal::from(&std_func))
+            .unwrap();
+    }
+    ("string".to_string(), string)
+}
+
+/// Creates the metatable which is shared by all strings, whose __index is the `string`
+/// module, so that `s:upper()` calls `string.upper(s)`.
+pub fn get_string_metatable(string: &LuaVal) -> LuaVal {
+    let meta = LuaVal::from(UserTable::new(HashMap::new()));
+    meta.set_attr(LuaVal::from("__index"), string.clone())
+        .unwrap();
+    meta
+}
+
+/// Clamps the positions <i> and <j> of a string of length <len> to the string, and
+/// returns the range of the bytes between them, which is empty if i > j.
+fn byte_range(i: i64, j: i64, len: usize) -> (usize, usize) {
+    let start = abs_pos(i, len).max(1) as usize;
+    let end = abs_pos(j, len).min(len as i64);
+    if end < start as i64 {
+        (0, 0)
+    } else {
+        (start - 1, end as usize)
+    }
+}
+
+pub fn lua_len(vm: &mut Vm) -> Result<(), LuaError> {
+    let s: LuaBytes = args(vm)?;
+    push_values(vm, vec![LuaVal::from(s.len() as i64)])
+}
+
+/// Returns the substring from <i> to <j>, where <j> is -1 if it is missing.
+pub fn lua_sub(vm: &mut Vm) -> Result<(), LuaError> {
+    let (s, i, j): (LuaBytes, Option<i64>, Option<i64>) = args(vm)?;
+    let (start, end) = byte_range(i.unwrap_or(1), j.unwrap_or(-1), s.len());
+    push_values(vm, vec![LuaVal::from(&s[start..end])])
+}
+
+pub fn lua_upper(vm: &mut Vm) -> Result<(), LuaError> {
+    let s: LuaBytes = args(vm)?;
+    push_values(vm, vec![LuaVal::from(s.to_ascii_uppercase())])
+}
+
+pub fn lua_lower(vm: &mut Vm) -> Result<(), LuaError> {
+    let s: LuaBytes = args(vm)?;
+    push_values(vm, vec![LuaVal::from(s.to_ascii_lowercase())])
+}
+
+/// Returns <n> copies of the first argument, separated by the optional third one.
+pub fn lua_rep(vm: &mut Vm) -> Result<(), LuaError> {
+    let (s, n, sep): (LuaBytes, i64, Option<LuaBytes>) = args(vm)?;
+    let sep = sep.unwrap_or_default();
+    if n <= 0 || s.len() + sep.len() == 0 {
+        return push_values(vm, vec![LuaVal::from("")]);
+    }
+    let len = (s.len() + sep.len())
+        .checked_mul(n as usize)
+        .filter(|len| *len < isize::MAX as usize)
+        .ok_or_else(|| LuaError::Error("resulting string too large".to_string()))?;
+    let mut res = Vec::with_capacity(len);
+    for i in 0..n {
+        if i > 0 {
+            res.extend_from_slice(&sep);
+        }
+        res.extend_from_slice(&s);
+    }
+    push_values(vm, vec![LuaVal::from(res)])
+}
+
+pub fn lua_reverse(vm: &mut Vm) -> Result<(), LuaError> {
+    let s: LuaBytes = args(vm)?;
+    let mut bytes = s.0;
+    bytes.reverse();
+    push_values(vm, vec![LuaVal::from(bytes)])
+}
+
+/// Returns the bytes from <i> to <j>, where <i> is 1 and <j> is <i> if they are
+/// missing.
+pub fn lua_byte(vm: &mut Vm) -> Result<(), LuaError> {
+    let (s, i, j): (LuaBytes, Option<i64>, Option<i64>) = args(vm)?;
+    let i = i.unwrap_or(1);
+    let (start, end) = byte_range(i, j.unwrap_or(i), s.len());
+    let bytes = s[start..end]
+        .iter()
+        .map(|b| LuaVal::from(*b as i64))
+        .collect();
+    push_values(vm, bytes)
+}
+
+/// Returns the string whose bytes are the arguments.
+pub fn lua_char(vm: &mut Vm) -> Result<(), LuaError> {
+    let (args_start, args_count) = vm.varargs();
+    let mut bytes = Vec::with_capacity(args_count);
+    for (i, arg) in vm.stack[args_start..(args_start + args_count)]
+        .iter()
+        .enumerate()
+    {
+        let byte = i64::from_lua(arg).map_err(|err| LuaError::BadArgument(i + 1, Box::new(err)))?;
+        if !(0..=255).contains(&byte) {
+            return Err(LuaError::BadArgument(
+                i + 1,
+                Box::new(LuaError::Error("value out of range".to_string())),
+            ));
+        }
+        bytes.push(byte as u8);
+    }
+    push_values(vm, vec![LuaVal::from(bytes)])
+}
+
+/// Converts a capture of a match in <src> to a value.
+fn capture_val(src: &[u8], cap: Capture) -> LuaVal {
+    match cap {
+        Capture::Slice(start, end) => LuaVal::from(&src[start..end]),
+        Capture::Position(pos) => LuaVal::from(pos as i64),
+    }
+}
+
+fn capture_vals(src: &[u8], caps: Vec<Capture>) -> Vec<LuaVal> {
+    caps.into_iter().map(|cap| capture_val(src, cap)).collect()
+}
+
+/// Splits an anchor ('^') off <pat>, and returns whether there was one.
+fn split_anchor(pat: &[u8]) -> (bool, usize) {
+    if pat.first() == Some(&b'^') {
+        (true, 1)
+    } else {
+        (false, 0)
+    }
+}
+
+/// Looks for the first match of a pattern, starting at the position given by the third
+/// argument. `string.find` returns the position of the match followed by its captures,
+/// and `string.match` only returns the captures.
+fn find_aux(vm: &mut Vm, find: bool) -> Result<(), LuaError> {
+    let (s, pat, init, plain): (LuaBytes, LuaBytes, Option<i64>, bool) = args(vm)?;
+    let (src, pat) = (&s[..], &pat[..]);
+    let init = abs_pos(init.unwrap_or(1), src.len()).max(1) as usize;
+    if init > src.len() + 1 {
+        return push_values(vm, vec![LuaVal::new()]);
+    }
+    if find && (plain || is_plain(pat)) {
+        let vals = match find_plain(src, pat, init - 1) {
+            Some(i) => vec![
+                LuaVal::from(i as i64 + 1),
+                LuaVal::from((i + pat.len()) as i64),
+            ],
+            None => vec![LuaVal::new()],
+        };
+        return push_values(vm, vals);
+    }
+    let (anchor, p) = split_anchor(pat);
+    let mut matcher = Matcher::new(src, pat);
+    for start in (init - 1)..=src.len() {
+        if let Some(end) = matcher.match_at(start, p)? {
+            let mut vals = vec![];
+            if find {
+                vals.push(LuaVal::from(start as i64 + 1));
+                vals.push(LuaVal::from(end as i64));
+            }
+            vals.extend(capture_vals(src, matcher.captures(start, end, !find)?));
+            return push_values(vm, vals);
+        }
+        if anchor {
+            break;
+        }
+    }
+    push_values(vm, vec![LuaVal::new()])
+}
+
+/// Returns the start and end of the first match of a pattern, and its captures. The
+/// pattern is found as a plain string if the fourth argument is true.
+pub fn lua_find(vm: &mut Vm) -> Result<(), LuaError> {
+    find_aux(vm, true)
+}
+
+/// Returns the captures of the first match of a pattern, or the whole match if there
+/// are none.
+pub fn lua_match(vm: &mut Vm) -> Result<(), LuaError> {
+    find_aux(vm, false)
+}
+
+/// Returns a function which returns the captures of the next match of a pattern each
+/// time it is called, and nothing after the last one.
+pub fn lua_gmatch(vm: &mut Vm) -> Result<(), LuaError> {
+    let (s, pat): (LuaBytes, LuaBytes) = args(vm)?;
+    let pos = Cell::new(0);
+    // an empty match right after the previous match is skipped
+    let last_end = Cell::new(None);
+    let iter = HostFunction::from_handler(Box::new(move |_, _| {
+        let (src, pat) = (&s[..], &pat[..]);
+        let mut matcher = Matcher::new(src, pat);
+        for start in pos.get()..=src.len() {
+            if let Some(end) = matcher.match_at(start, 0)? {
+                if last_end.get() != Some(end) {
+                    pos.set(end);
+                    last_end.set(Some(end));
+                    return Ok(capture_vals(src, matcher.captures(start, end, true)?));
+                }
+            }
+        }
+        pos.set(src.len() + 1);
+        Ok(vec![])
+    }));
+    push_values(vm, vec![LuaVal::from(iter)])
+}
+
+/// Replaces all matches of a pattern, or only the first n if the fourth argument is
+/// given. The replacement is either a string, where `%1`..`%9` are the captures and
+/// `%0` is the whole match, a table indexed by the first capture, or a function called
+/// with all captures. The match is kept if the table or the function gives false or
+/// nil. Returns the new string and the number of matches.
+pub fn lua_gsub(vm: &mut Vm) -> Result<(), LuaError> {
+    let (s, pat, repl, max_n): (LuaBytes, LuaBytes, LuaVal, Option<i64>) = args(vm)?;
+    if !(repl.is_string() || repl.is_number() || repl.is_table() || repl.is_function()) {
+        return Err(LuaError::BadArgument(
+            3,
+            Box::new(LuaError::TypeMismatch {
+                expected: "string/function/table",
+                got: repl.type_name(),
+            }),
+        ));
+    }
+    let (src, pat) = (&s[..], &pat[..]);
+    let max_n = max_n.unwrap_or(src.len() as i64 + 1);
+    let (anchor, p) = split_anchor(pat);
+    let mut matcher = Matcher::new(src, pat);
+    let mut res = vec![];
+    let (mut start, mut n, mut last_end) = (0, 0, None);
+    while n < max_n {
+        match matcher.match_at(start, p)? {
+            Some(end) if last_end != Some(end) => {
+                n += 1;
+                add_value(vm, &matcher, src, (start, end), &repl, &mut res)?;
+                start = end;
+                last_end = Some(end);
+            }
+            _ if start < src.len() => {
+                res.push(src[start]);
+                start += 1;
+            }
+            _ => break,
+        }
+        if anchor {
+            break;
+        }
+    }
+    res.extend_from_slice(&src[start..]);
+    push_values(vm, vec![LuaVal::from(res), LuaVal::from(n)])
+}
+
+/// Appends the replacement of the match <m> of <src> to <res>, see `lua_gsub`.
+fn add_value(
+    vm: &mut Vm,
+    matcher: &Matcher,
+    src: &[u8],
+    m: (usize, usize),
+    repl: &LuaVal,
+    res: &mut Vec<u8>,
+) -> Result<(), LuaError> {
+    let val = if repl.is_function() {
+        let caps = capture_vals(src, matcher.captures(m.0, m.1, true)?);
+        vm.call(repl, &caps)?.into_iter().next().unwrap_or_default()
+    } else if repl.is_table() {
+        repl.get_attr(&capture_val(src, matcher.capture(0, m.0, m.1)?))?
+    } else {
+        return add_string(matcher, src, m, &repl.to_bytes()?, res);
+    };
+    if !val.to_bool() {
+        res.extend_from_slice(&src[m.0..m.1]);
+    } else if val.is_string() || val.is_number() {
+        res.extend_from_slice(&val.to_bytes()?);
+    } else {
+        return Err(LuaError::Error(format!(
+            "invalid replacement value (a {})",
+            val.type_name()
+        )));
+    }
+    Ok(())
+}
+
+/// Appends the replacement string <repl> of the match <m> of <src> to <res>, where
+/// `%d` is replaced by capture d.
+fn add_string(
+    matcher: &Matcher,
+    src: &[u8],
+    m: (usize, usize),
+    repl: &[u8],
+    res: &mut Vec<u8>,
+) -> Result<(), LuaError> {
+    let mut bytes = repl.iter();
+    while let Some(&c) = bytes.next() {
+        if c != b'%' {
+            res.push(c);
+            continue;
+        }
+        match bytes.next() {
+            Some(&b'%') => res.push(b'%'),
+            Some(&d) if d.is_ascii_digit() => {
+                let cap = if d == b'0' {
+                    Capture::Slice(m.0, m.1)
+                } else {
+                    matcher.capture((d - b'1') as usize, m.0, m.1)?
+                };
+                match cap {
+                    Capture::Slice(start, end) => res.extend_from_slice(&src[start..end]),
+                    Capture::Position(pos) => res.extend_from_slice(pos.to_string().as_bytes()),
+                }
+            }
+            _ => {
+                return Err(LuaError::Error(
+                    "invalid use of '%' in replacement string".to_string(),
+                ))
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Formats its arguments according to the first one, which contains `printf`-like
+/// directives, e.g. `%5.2f`. `%q` writes a value in a form which can be read back by
+/// Lua, and `%s` converts any value with `tostring`.
+pub fn lua_format(vm: &mut Vm) -> Result<(), LuaError> {
+    let (args_start, args_count) = vm.varargs();
+    let args = vm.stack[args_start..(args_start + args_count)].to_vec();
+    let fmt = LuaBytes::from_lua_multi(&args)?;
+    let fmt = &fmt[..];
+    let mut out = vec![];
+    let (mut i, mut arg) = (0, 0);
+    while i < fmt.len() {
+        if fmt[i] != b'%' {
+            out.push(fmt[i]);
+            i += 1;
+            continue;
+        } else if fmt.get(i + 1) == Some(&b'%') {
+            out.push(b'%');
+            i += 2;
+            continue;
+        }
+        arg += 1;
+        let val = match args.get(arg) {
+            Some(val) => val.clone(),
+            None => {
+                return Err(LuaError::BadArgument(
+                    arg + 1,
+                    Box::new(LuaError::Error("no value".to_string())),
+                ))
+            }
+        };
+        let bad_arg = |err| LuaError::BadArgument(arg + 1, Box::new(err));
+        let (spec, next) = parse_spec(fmt, i + 1)?;
+        i = next;
+        match spec.conv {
+            b'c' => format_char(&spec, i64::from_lua(&val).map_err(bad_arg)? as u8, &mut out),
+            b'd' | b'i' | b'o' | b'u' | b'x' | b'X' => {
+                format_int(&spec, i64::from_lua(&val).map_err(bad_arg)?, &mut out)
+            }
+            b'a' | b'A' | b'e' | b'E' | b'f' | b'F' | b'g' | b'G' => {
+                format_float(&spec, f64::from_lua(&val).map_err(bad_arg)?, &mut out)
+            }
+            b'q' => quote(&val, &mut out).map_err(bad_arg)?,
+            b's' => {
+                let s = tostring(vm, val)?;
+                format_str(&spec, &s, &mut out);
+            }
+            c => {
+                return Err(LuaError::Error(format!(
+                    "invalid option '%{}' to 'format'",
+                    c as char
+                )))
+            }
+        }
+    }
+    push_values(vm, vec![LuaVal::from(out)])
+}
+
+/// Packs the arguments which follow the format into a binary string.
+pub fn lua_pack(vm: &mut Vm) -> Result<(), LuaError> {
+    let (args_start, args_count) = vm.varargs();
+    let args = vm.stack[args_start..(args_start + args_count)].to_vec();
+    let fmt = LuaBytes::from_lua_multi(&args)?;
+    let packed = pack(&fmt, &args)?;
+    push_values(vm, vec![LuaVal::from(packed)])
+}
+
+/// Returns the values packed in the second argument with the format, starting at the
+/// position given by the third one, followed by the position after them.
+pub fn lua_unpack(vm: &mut Vm) -> Result<(), LuaError> {
+    let (fmt, data, pos): (LuaBytes, LuaBytes, Option<i64>) = args(vm)?;
+    let pos = abs_pos(pos.unwrap_or(1), data.len()) - 1;
+    if pos < 0 || pos as usize > data.len() {
+        return Err(LuaError::BadArgument(
+            3,
+            Box::new(LuaError::Error(
+                "initial position out of string".to_string(),
+            )),
+        ));
+    }
+    let vals = unpack(&fmt, &data, pos as usize)?;
+    push_values(vm, vals)
+}
+
+/// Returns the size of the data packed with the format.
+pub fn lua_packsize(vm: &mut Vm) -> Result<(), LuaError> {
+    let fmt: LuaBytes = args(vm)?;
+    let size = packsize(&fmt)?;
+    push_values(vm, vec![LuaVal::from(size as i64)])
+}
@@ -0,0 +1,365 @@
+use crate::Vm;
+use errors::LuaError;
+use instructions::relational_operators::less_than;
+use lua_values::{
+    convert::{FromLua, LuaBytes},
+    lua_table::UserTable,
+    LuaVal,
+};
+use std::{
+    collections::HashMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use stdlib::{args, push_values, StdFunction};
+
+/// The maximum number of values which `table.unpack` returns, i.e. the maximum size of
+/// the stack in the reference implementation.
+const MAX_UNPACK: u64 = 1_000_000;
+/// The size of the intervals of `table.sort` above which the pivot can be chosen at
+/// random.
+const RANLIMIT: i64 = 100;
+
+pub fn get_table_module() -> (String, LuaVal) {
+    let table = LuaVal::from(UserTable::new(HashMap::new()));
+    for func in &[
+        ("insert", lua_insert as fn(&mut Vm) -> Result<(), LuaError>),
+        ("remove", lua_remove),
+        ("concat", lua_concat),
+        ("pack", lua_pack),
+        ("unpack", lua_unpack),
+        ("move", lua_move),
+        ("sort", lua_sort),
+    ] {
+        let std_func = StdFunction {
+            name: func.0,
+            handler: func.1,
+        };
+        table
+            .set_attr(LuaVal::from(func.0), LuaVal::from(&std_func))
+            .unwrap();
+    }
+    ("table".to_string(), table)
+}
+
+/// Checks that the argument at position <pos> is a table.
+fn check_table(val: &LuaVal, pos: usize) -> Result<(), LuaError> {
+    if val.is_table() {
+        Ok(())
+    } else {
+        Err(LuaError::BadArgument(
+            pos,
+            Box::new(LuaError::TypeMismatch {
+                expected: "table",
+                got: val.type_name(),
+            }),
+        ))
+    }
+}
+
+fn out_of_bounds(pos: usize) -> LuaError {
+    LuaError::BadArgument(
+        pos,
+        Box::new(LuaError::Error("position out of bounds".to_string())),
+    )
+}
+
+fn get(t: &LuaVal, i: i64) -> Result<LuaVal, LuaError> {
+    t.get_attr(&LuaVal::from(i))
+}
+
+fn set(t: &LuaVal, i: i64, val: LuaVal) -> Result<(), LuaError> {
+    t.set_attr(LuaVal::from(i), val)
+}
+
+/// Inserts a value at the given position, which is after the last element if it is
+/// missing, and shifts the elements which follow it.
+pub fn lua_insert(vm: &mut Vm) -> Result<(), LuaError> {
+    let (args_start, args_count) = vm.varargs();
+    let args = vm.stack[args_start..(args_start + args_count)].to_vec();
+    let t = args.first().cloned().unwrap_or_default();
+    check_table(&t, 1)?;
+    let end = t.length()? + 1;
+    match args_count {
+        2 => set(&t, end, args[1].clone())?,
+        3 => {
+            let pos =
+                i64::from_lua(&args[1]).map_err(|err| LuaError::BadArgument(2, Box::new(err)))?;
+            // the position can be right after the last element
+            if (pos as u64).wrapping_sub(1) >= end as u64 {
+                return Err(out_of_bounds(2));
+            }
+            for i in ((pos + 1)..=end).rev() {
+                set(&t, i, get(&t, i - 1)?)?;
+            }
+            set(&t, pos, args[2].clone())?;
+        }
+        _ => {
+            return Err(LuaError::Error(
+                "wrong number of arguments to 'insert'".to_string(),
+            ))
+        }
+    }
+    push_values(vm, vec![])
+}
+
+/// Removes the element at the given position, which is the last element if it is
+/// missing, shifts the elements which follow it, and returns it.
+pub fn lua_remove(vm: &mut Vm) -> Result<(), LuaError> {
+    let (t, pos): (LuaVal, Option<i64>) = args(vm)?;
+    check_table(&t, 1)?;
+    let size = t.length()?;
+    let mut pos = pos.unwrap_or(size);
+    // the position can be right after the last element
+    if pos != size && (pos as u64).wrapping_sub(1) > size as u64 {
+        return Err(out_of_bounds(2));
+    }
+    let val = get(&t, pos)?;
+    while pos < size {
+        set(&t, pos, get(&t, pos + 1)?)?;
+        pos += 1;
+    }
+    set(&t, pos, LuaVal::new())?;
+    push_values(vm, vec![val])
+}
+
+/// Concatenates the strings or numbers from <i> to <j>, separated by <sep>, where <i>
+/// is 1, <j> is the length of the table, and <sep> is empty if they are missing.
+pub fn lua_concat(vm: &mut Vm) -> Result<(), LuaError> {
+    let (t, sep, i, j): (LuaVal, Option<LuaBytes>, Option<i64>, Option<i64>) = args(vm)?;
+    check_table(&t, 1)?;
+    let sep = sep.unwrap_or_default();
+    let (i, j) = match j {
+        Some(j) => (i.unwrap_or(1), j),
+        None => (i.unwrap_or(1), t.length()?),
+    };
+    let mut res = vec![];
+    let mut k = i;
+    while k <= j {
+        let val = get(&t, k)?;
+        if !val.is_string() && !val.is_number() {
+            return Err(LuaError::Error(format!(
+                "invalid value (at index {}) in table for 'concat'",
+                k
+            )));
+        }
+        res.extend_from_slice(&val.to_bytes()?);
+        if k == j {
+            break;
+        }
+        res.extend_from_slice(&sep);
+        k += 1;
+    }
+    push_values(vm, vec![LuaVal::from(res)])
+}
+
+/// Returns a table whose elements are the arguments, and whose field `n` is their
+/// number.
+pub fn lua_pack(vm: &mut Vm) -> Result<(), LuaError> {
+    let (args_start, args_count) = vm.varargs();
+    let t = LuaVal::new_table();
+    for i in 0..args_count {
+        set(&t, i as i64 + 1, vm.stack[args_start + i].clone())?;
+    }
+    t.set_attr(LuaVal::from("n"), LuaVal::from(args_count as i64))?;
+    push_values(vm, vec![t])
+}
+
+/// Returns the elements from <i> to <j>, where <i> is 1 and <j> is the length of the
+/// table if they are missing.
+pub fn lua_unpack(vm: &mut Vm) -> Result<(), LuaError> {
+    let (t, i, j): (LuaVal, Option<i64>, Option<i64>) = args(vm)?;
+    let i = i.unwrap_or(1);
+    let j = match j {
+        Some(j) => j,
+        None => t.length()?,
+    };
+    if i > j {
+        return push_values(vm, vec![]);
+    }
+    let n = (j as u64).wrapping_sub(i as u64);
+    if n >= MAX_UNPACK {
+        return Err(LuaError::Error("too many results to unpack".to_string()));
+    }
+    // the elements are pushed as they are read, as there can be many of them
+    for k in 0..=n {
+        let val = get(&t, i + k as i64)?;
+        vm.push(val);
+    }
+    vm.closure().set_ret_vals(n as usize + 1);
+    Ok(())
+}
+
+/// Copies the elements from <f> to <e> of the first table to the position <t> of the
+/// fifth argument, which is the first table if it is missing, and returns the latter.
+/// The ranges can overlap.
+pub fn lua_move(vm: &mut Vm) -> Result<(), LuaError> {
+    let (a1, f, e, t, a2): (LuaVal, i64, i64, i64, Option<LuaVal>) = args(vm)?;
+    let a2 = a2.unwrap_or_else(|| a1.clone());
+    check_table(&a1, 1)?;
+    check_table(&a2, 5)?;
+    if e >= f {
+        if f <= 0 && e >= i64::MAX + f {
+            return Err(LuaError::BadArgument(
+                3,
+                Box::new(LuaError::Error("too many elements to move".to_string())),
+            ));
+        }
+        let n = e - f + 1;
+        if t > i64::MAX - n + 1 {
+            return Err(LuaError::BadArgument(
+                4,
+                Box::new(LuaError::Error("destination wrap around".to_string())),
+            ));
+        }
+        // the elements are copied backwards if the destination overlaps the end of
+        // the source
+        if t > e || t <= f || a1 != a2 {
+            for i in 0..n {
+                set(&a2, t + i, get(&a1, f + i)?)?;
+            }
+        } else {
+            for i in (0..n).rev() {
+                set(&a2, t + i, get(&a1, f + i)?)?;
+            }
+        }
+    }
+    push_values(vm, vec![a2])
+}
+
+/// Sorts the elements of the table in place, with `<` or the comparison function which
+/// is the second argument. The sort is not stable.
+pub fn lua_sort(vm: &mut Vm) -> Result<(), LuaError> {
+    let (t, comp): (LuaVal, LuaVal) = args(vm)?;
+    check_table(&t, 1)?;
+    let n = t.length()?;
+    if n > 1 {
+        if n >= i32::MAX as i64 {
+            return Err(LuaError::BadArgument(
+                1,
+                Box::new(LuaError::Error("array too big".to_string())),
+            ));
+        }
+        if !comp.is_nil() && !comp.is_function() {
+            return Err(LuaError::BadArgument(
+                2,
+                Box::new(LuaError::TypeMismatch {
+                    expected: "function",
+                    got: comp.type_name(),
+                }),
+            ));
+        }
+        let comp = if comp.is_nil() { None } else { Some(&comp) };
+        Sort { vm, t: &t, comp }.sort(1, n, 0)?;
+    }
+    push_values(vm, vec![])
+}
+
+/// The quicksort of `table.sort`, which works on the table itself, in the same way as
+/// the one of the reference implementation.
+struct Sort<'a> {
+    vm: &'a mut Vm,
+    t: &'a LuaVal,
+    comp: Option<&'a LuaVal>,
+}
+
+impl<'a> Sort<'a> {
+    fn less_than(&mut self, a: &LuaVal, b: &LuaVal) -> Result<bool, LuaError> {
+        match self.comp {
+            Some(comp) => {
+                let res = self.vm.call(comp, &[a.clone(), b.clone()])?;
+                Ok(res.first().is_some_and(LuaVal::to_bool))
+            }
+            None => less_than(self.vm, a, b),
+        }
+    }
+
+    fn swap(&self, i: i64, j: i64) -> Result<(), LuaError> {
+        let (a, b) = (get(self.t, i)?, get(self.t, j)?);
+        set(self.t, i, b)?;
+        set(self.t, j, a)
+    }
+
+    /// Sorts the elements from <lo> to <up>. The pivot is picked at random with <rnd>
+    /// if it isn't 0.
+    fn sort(&mut self, mut lo: i64, mut up: i64, mut rnd: u32) -> Result<(), LuaError> {
+        while lo < up {
+            // sort the elements at lo, p and up
+            if self.less_than(&get(self.t, up)?, &get(self.t, lo)?)? {
+                self.swap(lo, up)?;
+            }
+            if up - lo == 1 {
+                break;
+            }
+            let p = if up - lo < RANLIMIT || rnd == 0 {
+                (lo + up) / 2
+            } else {
+                // a random pivot in the middle half of the interval
+                let r4 = (up - lo) / 4;
+                rnd as i64 % (r4 * 2) + (lo + r4)
+            };
+            if self.less_than(&get(self.t, p)?, &get(self.t, lo)?)? {
+                self.swap(p, lo)?;
+            } else if self.less_than(&get(self.t, up)?, &get(self.t, p)?)? {
+                self.swap(p, up)?;
+            }
+            if up - lo == 2 {
+                break;
+            }
+            // the pivot is kept at up - 1 during the partition
+            let pivot = get(self.t, p)?;
+            self.swap(p, up - 1)?;
+            let p = self.partition(lo, up, &pivot)?;
+            // the smaller interval is sorted recursively, and the larger one in the loop
+            let n;
+            if p - lo < up - p {
+                self.sort(lo, p - 1, rnd)?;
+                n = p - lo;
+                lo = p + 1;
+            } else {
+                self.sort(p + 1, up, rnd)?;
+                n = up - p;
+                up = p - 1;
+            }
+            if (up - lo) / 128 > n {
+                rnd = random_pivot();
+            }
+        }
+        Ok(())
+    }
+
+    /// Moves the elements from <lo> to <up> which are smaller than the pivot, which is
+    /// at up - 1, before the ones which are greater, and returns its new position.
+    /// Raises an error if the comparison is not a strict order.
+    fn partition(&mut self, lo: i64, up: i64, pivot: &LuaVal) -> Result<i64, LuaError> {
+        let invalid_order = || LuaError::Error("invalid order function for sorting".to_string());
+        let (mut i, mut j) = (lo, up - 1);
+        loop {
+            i += 1;
+            while self.less_than(&get(self.t, i)?, pivot)? {
+                if i == up - 1 {
+                    return Err(invalid_order());
+                }
+                i += 1;
+            }
+            j -= 1;
+            while self.less_than(pivot, &get(self.t, j)?)? {
+                if j < i {
+                    return Err(invalid_order());
+                }
+                j -= 1;
+            }
+            if j < i {
+                self.swap(up - 1, i)?;
+                return Ok(i);
+            }
+            self.swap(i, j)?;
+        }
+    }
+}
+
+/// A new seed for the pivots of `table.sort`, used when the partitions are unbalanced.
+fn random_pivot() -> u32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.subsec_nanos() ^ d.as_secs() as u32)
+}
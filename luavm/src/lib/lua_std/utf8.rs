@@ -0,0 +1,269 @@
+use crate::Vm;
+use errors::LuaError;
+use lua_values::{
+    convert::{FromLua, LuaBytes},
+    lua_table::UserTable,
+    LuaVal,
+};
+use luacompiler::utf8::encode;
+use std::collections::HashMap;
+use stdlib::{abs_pos, args, push_values, StdFunction};
+
+/// The largest code point which is accepted by the functions of the library.
+const MAX_UNICODE: u32 = 0x10_FFFF;
+/// The pattern which matches exactly one UTF-8 byte sequence, assuming that the subject
+/// is valid UTF-8.
+const CHARPATTERN: &[u8] = b"[\x00-\x7F\xC2-\xF4][\x80-\xBF]*";
+
+pub fn get_utf8_module() -> (String, LuaVal) {
+    let utf8 = LuaVal::from(UserTable::new(HashMap::new()));
+    for func in &[
+        ("char", lua_char as fn(&mut Vm) -> Result<(), LuaError>),
+        ("codepoint", lua_codepoint),
+        ("len", lua_len),
+        ("offset", lua_offset),
+        ("codes", lua_codes),
+    ] {
+        let std_func = StdFunction {
+            name: func.0,
+            handler: func.1,
+        };
+        utf8.set_attr(LuaVal::from(func.0), LuaVal::from(&std_func))
+            .unwrap();
+    }
+    utf8.set_attr(LuaVal::from("charpattern"), LuaVal::from(CHARPATTERN))
+        .unwrap();
+    ("utf8".to_string(), utf8)
+}
+
+fn bad_argument(pos: usize, msg: &str) -> LuaError {
+    LuaError::BadArgument(pos, Box::new(LuaError::Error(msg.to_string())))
+}
+
+fn invalid_code() -> LuaError {
+    LuaError::Error("invalid UTF-8 code".to_string())
+}
+
+/// Whether the byte at <i> in <s> is a continuation byte. The end of the string isn't
+/// one.
+fn is_cont(s: &[u8], i: usize) -> bool {
+    s.get(i).is_some_and(|b| b & 0xC0 == 0x80)
+}
+
+/// Decodes the UTF-8 sequence at the start of <s>, and returns the code point and the
+/// length of the sequence, or `None` if the sequence is invalid. Like Lua, surrogates
+/// are accepted, but overlong encodings and code points after `MAX_UNICODE` are not.
+fn decode(s: &[u8]) -> Option<(u32, usize)> {
+    // the largest code point which needs the number of continuation bytes
+    const LIMITS: [u32; 4] = [0xFF, 0x7F, 0x7FF, 0xFFFF];
+    let mut c = u32::from(*s.first()?);
+    if c < 0x80 {
+        return Some((c, 1));
+    }
+    let mut res = 0;
+    let mut count = 0;
+    // each leading one of the first byte is a continuation byte
+    while c & 0x40 != 0 {
+        count += 1;
+        // a first byte with more leading ones is never valid, and would overflow <res>
+        if count > 3 {
+            return None;
+        }
+        let cc = u32::from(*s.get(count)?);
+        if cc & 0xC0 != 0x80 {
+            return None;
+        }
+        res = (res << 6) | (cc & 0x3F);
+        c <<= 1;
+    }
+    res |= (c & 0x7F) << (count * 5);
+    if res > MAX_UNICODE || res <= LIMITS[count] {
+        return None;
+    }
+    Some((res, count + 1))
+}
+
+/// Returns the string made of the UTF-8 encodings of the arguments.
+pub fn lua_char(vm: &mut Vm) -> Result<(), LuaError> {
+    let (args_start, args_count) = vm.varargs();
+    let mut bytes = Vec::with_capacity(args_count);
+    for (i, arg) in vm.stack[args_start..(args_start + args_count)]
+        .iter()
+        .enumerate()
+    {
+        let code = i64::from_lua(arg).map_err(|err| LuaError::BadArgument(i + 1, Box::new(err)))?;
+        // negative values are out of range as well, like in Lua
+        if code as u64 > u64::from(MAX_UNICODE) {
+            return Err(bad_argument(i + 1, "value out of range"));
+        }
+        encode(code as u32, &mut bytes);
+    }
+    push_values(vm, vec![LuaVal::from(bytes)])
+}
+
+/// Returns the code points of the characters which start between the positions <i>
+/// and <j>, where <i> is 1 and <j> is <i> if they are missing.
+pub fn lua_codepoint(vm: &mut Vm) -> Result<(), LuaError> {
+    let (s, i, j): (LuaBytes, Option<i64>, Option<i64>) = args(vm)?;
+    let start = abs_pos(i.unwrap_or(1), s.len());
+    let end = abs_pos(j.unwrap_or(start), s.len());
+    if start < 1 {
+        return Err(bad_argument(2, "out of range"));
+    }
+    if end > s.len() as i64 {
+        return Err(bad_argument(3, "out of range"));
+    }
+    let mut codes = vec![];
+    let mut pos = start as usize - 1;
+    while pos < end as usize {
+        let (code, len) = decode(&s[pos..]).ok_or_else(invalid_code)?;
+        codes.push(LuaVal::from(i64::from(code)));
+        pos += len;
+    }
+    push_values(vm, codes)
+}
+
+/// Returns the number of characters which start between the positions <i> and <j>,
+/// which are 1 and -1 if they are missing. If the string is not valid UTF-8, returns
+/// nil and the position of the first invalid byte.
+pub fn lua_len(vm: &mut Vm) -> Result<(), LuaError> {
+    let (s, i, j): (LuaBytes, Option<i64>, Option<i64>) = args(vm)?;
+    let start = abs_pos(i.unwrap_or(1), s.len());
+    let end = abs_pos(j.unwrap_or(-1), s.len());
+    if start < 1 || start - 1 > s.len() as i64 {
+        return Err(bad_argument(2, "initial position out of string"));
+    }
+    if end > s.len() as i64 {
+        return Err(bad_argument(3, "final position out of string"));
+    }
+    let mut n = 0;
+    let mut pos = start - 1;
+    while pos < end {
+        match decode(&s[(pos as usize)..]) {
+            Some((_, len)) => pos += len as i64,
+            None => return push_values(vm, vec![LuaVal::new(), LuaVal::from(pos + 1)]),
+        }
+        n += 1;
+    }
+    push_values(vm, vec![LuaVal::from(n)])
+}
+
+/// Returns the position of the n-th character after the position <i>, or before it if
+/// n is negative. <i> is 1 by default, or the end of the string if n is negative. If n
+/// is 0, returns the start of the character which contains the position <i>. Returns
+/// nil if there is no such character.
+pub fn lua_offset(vm: &mut Vm) -> Result<(), LuaError> {
+    let (s, mut n, i): (LuaBytes, i64, Option<i64>) = args(vm)?;
+    let len = s.len();
+    let default = if n >= 0 { 1 } else { len as i64 + 1 };
+    let pos = abs_pos(i.unwrap_or(default), len);
+    if pos < 1 || pos - 1 > len as i64 {
+        return Err(bad_argument(3, "position out of range"));
+    }
+    let mut pos = pos as usize - 1;
+    if n == 0 {
+        while pos > 0 && is_cont(&s, pos) {
+            pos -= 1;
+        }
+    } else {
+        if is_cont(&s, pos) {
+            return Err(LuaError::Error(
+                "initial position is a continuation byte".to_string(),
+            ));
+        }
+        if n < 0 {
+            while n < 0 && pos > 0 {
+                pos -= 1;
+                while pos > 0 && is_cont(&s, pos) {
+                    pos -= 1;
+                }
+                n += 1;
+            }
+        } else {
+            n -= 1;
+            while n > 0 && pos < len {
+                pos += 1;
+                while is_cont(&s, pos) {
+                    pos += 1;
+                }
+                n -= 1;
+            }
+        }
+    }
+    let res = if n == 0 {
+        LuaVal::from(pos as i64 + 1)
+    } else {
+        LuaVal::new()
+    };
+    push_values(vm, vec![res])
+}
+
+/// The iterator of `utf8.codes`: returns the position and the code point of the
+/// character after the one at the given position, or nothing at the end of the string.
+fn codes_next(vm: &mut Vm) -> Result<(), LuaError> {
+    let (s, pos): (LuaBytes, i64) = args(vm)?;
+    let len = s.len() as i64;
+    let mut pos = pos - 1;
+    if pos < 0 {
+        pos = 0;
+    } else if pos < len {
+        // skips the current character
+        pos += 1;
+        while is_cont(&s, pos as usize) {
+            pos += 1;
+        }
+    }
+    if pos >= len {
+        return push_values(vm, vec![]);
+    }
+    let pos = pos as usize;
+    match decode(&s[pos..]) {
+        Some((code, n)) if !is_cont(&s, pos + n) => push_values(
+            vm,
+            vec![LuaVal::from(pos as i64 + 1), LuaVal::from(i64::from(code))],
+        ),
+        _ => Err(invalid_code()),
+    }
+}
+
+/// Returns an iterator over the positions and the code points of the characters of a
+/// string, with the string and the initial position, like `ipairs`.
+pub fn lua_codes(vm: &mut Vm) -> Result<(), LuaError> {
+    let s: LuaBytes = args(vm)?;
+    let iter = StdFunction {
+        name: "codes",
+        handler: codes_next,
+    };
+    push_values(
+        vm,
+        vec![LuaVal::from(&iter), LuaVal::from(s.0), LuaVal::from(0i64)],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_and_decode() {
+        for &(code, bytes) in &[
+            (0x41, &b"A"[..]),
+            (0xE9, b"\xC3\xA9"),
+            (0x7FF, b"\xDF\xBF"),
+            (0x800, b"\xE0\xA0\x80"),
+            (0xD800, b"\xED\xA0\x80"),
+            (0x10_FFFF, b"\xF4\x8F\xBF\xBF"),
+        ] {
+            let mut res = vec![];
+            encode(code, &mut res);
+            assert_eq!(res, bytes);
+            assert_eq!(decode(bytes), Some((code, bytes.len())));
+        }
+        // overlong, truncated, and too large
+        assert_eq!(decode(b"\xC0\x80"), None);
+        assert_eq!(decode(b"\xE0\x80"), None);
+        assert_eq!(decode(b"\xF4\x90\x80\x80"), None);
+        assert_eq!(decode(b"\x80"), None);
+        assert_eq!(decode(b"\xFF\x80\x80\x80\x80\x80\x80\x80"), None);
+    }
+}
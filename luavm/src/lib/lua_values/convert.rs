@@ -0,0 +1,453 @@
+//! Conversions between Rust types and Lua values.
+use errors::LuaError;
+use lua_values::LuaVal;
+use std::{collections::HashMap, convert::TryFrom, hash::Hash, ops::Deref};
+
+/// A type which can be converted to a Lua value.
+pub trait IntoLua {
+    fn into_lua(self) -> LuaVal;
+}
+
+/// A type which can be created from a Lua value.
+pub trait FromLua: Sized {
+    fn from_lua(val: &LuaVal) -> Result<Self, LuaError>;
+}
+
+/// A type which can be converted to multiple Lua values, e.g. the return values of a
+/// function. Tuples are converted to one value per element.
+pub trait IntoLuaMulti {
+    fn into_lua_multi(self) -> Vec<LuaVal>;
+}
+
+/// A type which can be created from multiple Lua values, e.g. the arguments of a
+/// function. Each element of a tuple is created from one value; the missing values are
+/// nil.
+pub trait FromLuaMulti: Sized {
+    fn from_lua_multi(vals: &[LuaVal]) -> Result<Self, LuaError>;
+}
+
+fn type_mismatch(expected: &'static str, val: &LuaVal) -> LuaError {
+    LuaError::TypeMismatch {
+        expected,
+        got: val.type_name(),
+    }
+}
+
+impl IntoLua for LuaVal {
+    fn into_lua(self) -> LuaVal {
+        self
+    }
+}
+
+impl FromLua for LuaVal {
+    fn from_lua(val: &LuaVal) -> Result<Self, LuaError> {
+        Ok(val.clone())
+    }
+}
+
+/// Converts a value to an integer in the same way as the arguments of the functions of
+/// the standard library: floats with an exact integer representation, and strings which
+/// represent an integer are converted as well.
+fn to_integer(val: &LuaVal) -> Result<i64, LuaError> {
+    if let Some(int) = val.as_int() {
+        return Ok(int);
+    }
+    if !val.is_number() && !val.is_string() {
+        return Err(type_mismatch("number", val));
+    }
+    if let Ok(int) = val.to_int() {
+        return Ok(int);
+    }
+    let float = val.to_float().map_err(|_| type_mismatch("number", val))?;
+    float_to_int(float)
+        .ok_or_else(|| LuaError::Error("number has no integer representation".to_string()))
+}
+
+/// Converts a float to an integer if it has an exact integer representation.
+pub fn float_to_int(f: f64) -> Option<i64> {
+    // -2^63 is exactly representable, unlike 2^63 - 1
+    if f.fract() == 0.0 && f >= -(2f64.powi(63)) && f < 2f64.powi(63) {
+        Some(f as i64)
+    } else {
+        None
+    }
+}
+
+macro_rules! impl_int {
+    ($($ty: ty),*) => {
+        $(
+            impl IntoLua for $ty {
+                fn into_lua(self) -> LuaVal {
+                    LuaVal::from(self as i64)
+                }
+            }
+
+            impl FromLua for $ty {
+                fn from_lua(val: &LuaVal) -> Result<Self, LuaError> {
+                    let int = to_integer(val)?;
+                    <$ty>::try_from(int).map_err(|_| {
+                        LuaError::Error(format!("{} is out of range for {}", int, stringify!($ty)))
+                    })
+                }
+            }
+        )*
+    };
+}
+
+impl_int!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+macro_rules! impl_float {
+    ($($ty: ty),*) => {
+        $(
+            impl IntoLua for $ty {
+                fn into_lua(self) -> LuaVal {
+                    LuaVal::from(self as f64)
+                }
+            }
+
+            impl FromLua for $ty {
+                fn from_lua(val: &LuaVal) -> Result<Self, LuaError> {
+                    if val.is_number() || val.is_string() {
+                        if let Ok(float) = val.to_float() {
+                            return Ok(float as $ty);
+                        }
+                    }
+                    Err(type_mismatch("number", val))
+                }
+            }
+        )*
+    };
+}
+
+impl_float!(f32, f64);
+
+impl IntoLua for bool {
+    fn into_lua(self) -> LuaVal {
+        LuaVal::from(self)
+    }
+}
+
+impl FromLua for bool {
+    /// Any value can be converted to a bool; only nil and false are false.
+    fn from_lua(val: &LuaVal) -> Result<Self, LuaError> {
+        Ok(val.to_bool())
+    }
+}
+
+impl IntoLua for String {
+    fn into_lua(self) -> LuaVal {
+        LuaVal::from(self)
+    }
+}
+
+impl IntoLua for &str {
+    fn into_lua(self) -> LuaVal {
+        LuaVal::from(self)
+    }
+}
+
+impl FromLua for String {
+    /// Strings and numbers can be converted to a String.
+    fn from_lua(val: &LuaVal) -> Result<Self, LuaError> {
+        if val.is_string() || val.is_number() {
+            val.to_string()
+        } else {
+            Err(type_mismatch("string", val))
+        }
+    }
+}
+
+/// The bytes of a Lua string, which unlike a `String` are not necessarily valid UTF-8.
+/// Numbers are converted in the same way as for a `String`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct LuaBytes(pub Vec<u8>);
+
+impl Deref for LuaBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl IntoLua for LuaBytes {
+    fn into_lua(self) -> LuaVal {
+        LuaVal::from(self.0)
+    }
+}
+
+impl FromLua for LuaBytes {
+    fn from_lua(val: &LuaVal) -> Result<Self, LuaError> {
+        if val.is_string() || val.is_number() {
+            val.to_bytes().map(LuaBytes)
+        } else {
+            Err(type_mismatch("string", val))
+        }
+    }
+}
+
+impl<T: IntoLua> IntoLua for Option<T> {
+    fn into_lua(self) -> LuaVal {
+        match self {
+            Some(v) => v.into_lua(),
+            None => LuaVal::new(),
+        }
+    }
+}
+
+impl<T: FromLua> FromLua for Option<T> {
+    fn from_lua(val: &LuaVal) -> Result<Self, LuaError> {
+        if val.is_nil() {
+            Ok(None)
+        } else {
+            T::from_lua(val).map(Some)
+        }
+    }
+}
+
+impl<T: IntoLua> IntoLua for Vec<T> {
+    /// Creates a table whose keys are 1, 2, ..., n.
+    fn into_lua(self) -> LuaVal {
+        let table = LuaVal::new_table();
+        for (i, v) in self.into_iter().enumerate() {
+            table
+                .set_attr(LuaVal::from(i as i64 + 1), v.into_lua())
+                .unwrap();
+        }
+        table
+    }
+}
+
+impl<T: FromLua> FromLua for Vec<T> {
+    /// Converts the values of the keys 1, 2, ... of a table, up to the first nil value.
+    fn from_lua(val: &LuaVal) -> Result<Self, LuaError> {
+        if !val.is_table() {
+            return Err(type_mismatch("table", val));
+        }
+        let mut vec = vec![];
+        loop {
+            let v = val.get_attr(&LuaVal::from(vec.len() as i64 + 1))?;
+            if v.is_nil() {
+                return Ok(vec);
+            }
+            vec.push(T::from_lua(&v)?);
+        }
+    }
+}
+
+impl<K: IntoLua + Eq + Hash, V: IntoLua> IntoLua for HashMap<K, V> {
+    fn into_lua(self) -> LuaVal {
+        let table = LuaVal::new_table();
+        for (k, v) in self {
+            table.set_attr(k.into_lua(), v.into_lua()).unwrap();
+        }
+        table
+    }
+}
+
+impl<K: FromLua + Eq + Hash, V: FromLua> FromLua for HashMap<K, V> {
+    fn from_lua(val: &LuaVal) -> Result<Self, LuaError> {
+        if !val.is_table() {
+            return Err(type_mismatch("table", val));
+        }
+        let mut hm = HashMap::new();
+        for (k, v) in val.entries()? {
+            hm.insert(K::from_lua(&k)?, V::from_lua(&v)?);
+        }
+        Ok(hm)
+    }
+}
+
+impl<T: IntoLua> IntoLuaMulti for T {
+    fn into_lua_multi(self) -> Vec<LuaVal> {
+        vec![self.into_lua()]
+    }
+}
+
+impl<T: FromLua> FromLuaMulti for T {
+    fn from_lua_multi(vals: &[LuaVal]) -> Result<Self, LuaError> {
+        from_arg(vals, 0)
+    }
+}
+
+/// Converts the value at index <i> of <vals>, which is nil if it is missing.
+fn from_arg<T: FromLua>(vals: &[LuaVal], i: usize) -> Result<T, LuaError> {
+    let res = match vals.get(i) {
+        Some(val) => T::from_lua(val),
+        None => T::from_lua(&LuaVal::new()),
+    };
+    res.map_err(|err| LuaError::BadArgument(i + 1, Box::new(err)))
+}
+
+/// Any number of values, e.g. the variable arguments of a function, which are all
+/// converted to <T>.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Variadic<T>(pub Vec<T>);
+
+impl<T> Deref for Variadic<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        &self.0
+    }
+}
+
+impl<T: IntoLua> IntoLuaMulti for Variadic<T> {
+    fn into_lua_multi(self) -> Vec<LuaVal> {
+        self.0.into_iter().map(IntoLua::into_lua).collect()
+    }
+}
+
+impl<T: FromLua> FromLuaMulti for Variadic<T> {
+    fn from_lua_multi(vals: &[LuaVal]) -> Result<Self, LuaError> {
+        (0..vals.len())
+            .map(|i| from_arg(vals, i))
+            .collect::<Result<_, _>>()
+            .map(Variadic)
+    }
+}
+
+impl IntoLuaMulti for () {
+    fn into_lua_multi(self) -> Vec<LuaVal> {
+        vec![]
+    }
+}
+
+impl FromLuaMulti for () {
+    fn from_lua_multi(_: &[LuaVal]) -> Result<Self, LuaError> {
+        Ok(())
+    }
+}
+
+macro_rules! impl_tuple {
+    ($(($($name: ident $i: tt),+))*) => {
+        $(
+            impl<$($name: IntoLua),+> IntoLuaMulti for ($($name,)+) {
+                fn into_lua_multi(self) -> Vec<LuaVal> {
+                    vec![$(self.$i.into_lua()),+]
+                }
+            }
+
+            impl<$($name: FromLua),+> FromLuaMulti for ($($name,)+) {
+                fn from_lua_multi(vals: &[LuaVal]) -> Result<Self, LuaError> {
+                    Ok(($(from_arg::<$name>(vals, $i)?,)+))
+                }
+            }
+        )*
+    };
+}
+
+impl_tuple! {
+    (A 0)
+    (A 0, B 1)
+    (A 0, B 1, C 2)
+    (A 0, B 1, C 2, D 3)
+    (A 0, B 1, C 2, D 3, E 4)
+    (A 0, B 1, C 2, D 3, E 4, F 5)
+    (A 0, B 1, C 2, D 3, E 4, F 5, G 6)
+    (A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbers() {
+        assert_eq!(i32::from_lua(&3.into_lua()), Ok(3));
+        assert_eq!(u8::from_lua(&LuaVal::from(2.0)), Ok(2));
+        assert_eq!(i64::from_lua(&LuaVal::from("12")), Ok(12));
+        assert!(u8::from_lua(&LuaVal::from(300)).is_err());
+        assert!(u8::from_lua(&LuaVal::from(-1)).is_err());
+        assert_eq!(
+            i64::from_lua(&LuaVal::from(2.5)),
+            Err(LuaError::Error(
+                "number has no integer representation".to_string()
+            ))
+        );
+        // 2^63 saturates when it is cast to an integer
+        assert!(i64::from_lua(&LuaVal::from(2f64.powi(63))).is_err());
+        assert_eq!(i64::from_lua(&LuaVal::from(-(2f64.powi(63)))), Ok(i64::MIN));
+        assert_eq!(f64::from_lua(&LuaVal::from(2)), Ok(2.0));
+        assert_eq!(f32::from_lua(&LuaVal::from("0.5")), Ok(0.5));
+        assert_eq!(
+            f64::from_lua(&LuaVal::new()),
+            Err(LuaError::TypeMismatch {
+                expected: "number",
+                got: "nil"
+            })
+        );
+    }
+
+    #[test]
+    fn strings_bools_and_options() {
+        assert_eq!(String::from_lua(&"foo".into_lua()), Ok("foo".to_string()));
+        assert_eq!(String::from_lua(&LuaVal::from(1)), Ok("1".to_string()));
+        assert!(String::from_lua(&LuaVal::from(true)).is_err());
+        assert_eq!(bool::from_lua(&LuaVal::new()), Ok(false));
+        assert_eq!(bool::from_lua(&LuaVal::from(0)), Ok(true));
+        assert_eq!(Option::<i64>::from_lua(&LuaVal::new()), Ok(None));
+        assert_eq!(Option::<i64>::from_lua(&LuaVal::from(1)), Ok(Some(1)));
+        assert!(Some(1).into_lua().is_number());
+        assert!(None::<i64>.into_lua().is_nil());
+    }
+
+    #[test]
+    fn bytes() {
+        let val = LuaBytes(vec![0, 0xff]).into_lua();
+        assert!(val.is_string());
+        assert_eq!(val.as_bytes(), Some(&[0, 0xff][..]));
+        assert_eq!(val.as_str(), None);
+        assert_eq!(val.to_string(), Ok("\u{0}\u{fffd}".to_string()));
+        assert_eq!(LuaBytes::from_lua(&val), Ok(LuaBytes(vec![0, 0xff])));
+        assert_eq!(
+            LuaBytes::from_lua(&LuaVal::from(12)),
+            Ok(LuaBytes(b"12".to_vec()))
+        );
+        assert!(LuaBytes::from_lua(&LuaVal::new()).is_err());
+    }
+
+    #[test]
+    fn tables() {
+        let table = vec![1, 2, 3].into_lua();
+        assert!(table.is_table());
+        assert_eq!(table.get_attr(&LuaVal::from(3)).unwrap(), LuaVal::from(3));
+        assert_eq!(Vec::<i64>::from_lua(&table), Ok(vec![1, 2, 3]));
+        let mut hm = HashMap::new();
+        hm.insert("a".to_string(), 1.5);
+        hm.insert("b".to_string(), 2.5);
+        let table = hm.clone().into_lua();
+        assert_eq!(HashMap::<String, f64>::from_lua(&table), Ok(hm));
+        assert_eq!(
+            Vec::<i64>::from_lua(&LuaVal::from(1)),
+            Err(LuaError::TypeMismatch {
+                expected: "table",
+                got: "number"
+            })
+        );
+    }
+
+    #[test]
+    fn multiple_values() {
+        let vals = (1, "two", Some(3.0)).into_lua_multi();
+        assert_eq!(vals.len(), 3);
+        let (a, b, c, d): (i64, String, f64, Option<bool>) =
+            FromLuaMulti::from_lua_multi(&vals).unwrap();
+        assert_eq!((a, b.as_str(), c, d), (1, "two", 3.0, None));
+        assert_eq!(i64::from_lua_multi(&vals), Ok(1));
+        let err = <(i64, i64)>::from_lua_multi(&vals).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "bad argument #2 (number expected, got string)"
+        );
+        assert!(().into_lua_multi().is_empty());
+        let Variadic(ints) = Variadic::<i64>::from_lua_multi(&vals[..1]).unwrap();
+        assert_eq!(ints, vec![1]);
+        let err = Variadic::<i64>::from_lua_multi(&vals).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "bad argument #2 (number expected, got string)"
+        );
+        assert_eq!(Variadic(vec!["a", "b"]).into_lua_multi().len(), 2);
+    }
+}
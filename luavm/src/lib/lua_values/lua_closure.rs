@@ -1,4 +1,13 @@
-use crate::{errors::LuaError, lua_values::LuaVal, stdlib::StdFunction, Vm};
+use crate::{
+    errors::LuaError,
+    instructions::functions::{return_to_caller, return_values},
+    lua_values::{
+        convert::{FromLuaMulti, IntoLuaMulti},
+        LuaVal,
+    },
+    stdlib::StdFunction,
+    Vm,
+};
 use gc::{Finalize, Gc, Trace};
 use luacompiler::bytecode::Function;
 use std::cell::Cell;
@@ -26,6 +35,8 @@ unsafe impl Trace for Box<LuaClosure> {
 /// Represents a closure in Lua.
 #[derive(Trace, Finalize)]
 pub struct UserFunction {
+    /// The index of the chunk which defines the function, see `Vm::chunks`.
+    chunk: usize,
     index: usize,
     reg_count: usize,
     param_count: usize,
@@ -36,12 +47,14 @@ pub struct UserFunction {
 
 impl UserFunction {
     pub fn new(
+        chunk: usize,
         index: usize,
         reg_count: usize,
         param_count: usize,
         upvals: Vec<Gc<LuaVal>>,
     ) -> UserFunction {
         UserFunction {
+            chunk,
             index,
             reg_count,
             param_count,
@@ -52,6 +65,10 @@ impl UserFunction {
 }
 
 impl LuaClosure for UserFunction {
+    fn chunk(&self) -> usize {
+        self.chunk
+    }
+
     fn index(&self) -> usize {
         self.index
     }
@@ -65,7 +82,9 @@ impl LuaClosure for UserFunction {
     }
 
     fn call(&self, vm: &mut Vm) -> Result<(), LuaError> {
-        vm.eval()
+        // the interpreter loop continues with the first instruction of the function
+        vm.pc = 0;
+        Ok(())
     }
 
     fn ret_vals(&self) -> usize {
@@ -92,6 +111,8 @@ impl LuaClosure for UserFunction {
 
 #[derive(Trace, Finalize)]
 pub struct BuiltinFunction {
+    #[unsafe_ignore_trace]
+    name: &'static str,
     #[unsafe_ignore_trace]
     handler: fn(&mut Vm) -> Result<(), LuaError>,
     #[unsafe_ignore_trace]
@@ -112,7 +133,11 @@ impl LuaClosure for BuiltinFunction {
     }
 
     fn call(&self, vm: &mut Vm) -> Result<(), LuaError> {
-        (self.handler)(vm)
+        (self.handler)(vm).map_err(|err| err.in_function(self.name))?;
+        // the return values are the last <ret_vals> values on the stack
+        let count = self.ret_vals.replace(0);
+        let first = vm.top - count;
+        return_to_caller(vm, first, count)
     }
 
     fn ret_vals(&self) -> usize {
@@ -136,8 +161,165 @@ impl LuaClosure for BuiltinFunction {
     }
 }
 
+/// A builtin function which continues the execution in another frame, like `pcall`,
+/// which calls its argument. Unlike a `BuiltinFunction`, the handler returns the values
+/// to the caller itself, see `return_values`.
+#[derive(Trace, Finalize)]
+pub struct ControlFunction {
+    #[unsafe_ignore_trace]
+    name: Option<&'static str>,
+    #[unsafe_ignore_trace]
+    handler: fn(&mut Vm) -> Result<(), LuaError>,
+    upvals: Vec<Gc<LuaVal>>,
+}
+
+impl ControlFunction {
+    pub fn new(
+        handler: fn(&mut Vm) -> Result<(), LuaError>,
+        upvals: Vec<Gc<LuaVal>>,
+    ) -> ControlFunction {
+        ControlFunction {
+            name: None,
+            handler,
+            upvals,
+        }
+    }
+
+    /// Sets the name which is shown in the errors about the arguments of the function.
+    pub fn with_name(mut self, name: &'static str) -> ControlFunction {
+        self.name = Some(name);
+        self
+    }
+}
+
+impl LuaClosure for ControlFunction {
+    fn index(&self) -> usize {
+        0
+    }
+
+    fn reg_count(&self) -> usize {
+        0
+    }
+
+    fn param_count(&self) -> usize {
+        0
+    }
+
+    fn call(&self, vm: &mut Vm) -> Result<(), LuaError> {
+        match self.name {
+            Some(name) => (self.handler)(vm).map_err(|err| err.in_function(name)),
+            None => (self.handler)(vm),
+        }
+    }
+
+    fn ret_vals(&self) -> usize {
+        0
+    }
+
+    fn set_ret_vals(&self, _: usize) {}
+
+    fn get_upval(&self, i: usize) -> Result<&Gc<LuaVal>, LuaError> {
+        self.upvals.get(i).ok_or(LuaError::Error(format!(
+            "Upvalue with index {} doesn't exist!",
+            i
+        )))
+    }
+
+    fn set_upval(&self, _: usize, _: LuaVal) -> Result<(), LuaError> {
+        Err(LuaError::Error(
+            "SetUpVal doesn't work on ControlFunctions.".to_string(),
+        ))
+    }
+}
+
+/// The handler of a `HostFunction`, which gets the arguments of the call and returns its
+/// results.
+pub type HostHandler = Box<dyn Fn(&mut Vm, &[LuaVal]) -> Result<Vec<LuaVal>, LuaError>>;
+
+/// A builtin function implemented by a Rust closure, which can capture state. Its
+/// arguments and results are converted with `FromLuaMulti` and `IntoLuaMulti`, so the
+/// closure doesn't need to access the stack of the vm.
+#[derive(Trace, Finalize)]
+pub struct HostFunction {
+    #[unsafe_ignore_trace]
+    name: Option<String>,
+    #[unsafe_ignore_trace]
+    handler: HostHandler,
+}
+
+impl HostFunction {
+    pub fn new<A, R, F>(func: F) -> HostFunction
+    where
+        A: FromLuaMulti,
+        R: IntoLuaMulti,
+        F: Fn(&mut Vm, A) -> Result<R, LuaError> + 'static,
+    {
+        HostFunction::from_handler(Box::new(move |vm, args| {
+            let args = A::from_lua_multi(args)?;
+            func(vm, args).map(IntoLuaMulti::into_lua_multi)
+        }))
+    }
+
+    /// Creates a function which gets its arguments as they are.
+    pub fn from_handler(handler: HostHandler) -> HostFunction {
+        HostFunction {
+            name: None,
+            handler,
+        }
+    }
+
+    /// Sets the name which is shown in the errors about the arguments of the function.
+    pub fn with_name(mut self, name: &str) -> HostFunction {
+        self.name = Some(name.to_string());
+        self
+    }
+}
+
+impl LuaClosure for HostFunction {
+    fn index(&self) -> usize {
+        0
+    }
+
+    fn reg_count(&self) -> usize {
+        0
+    }
+
+    fn param_count(&self) -> usize {
+        0
+    }
+
+    fn call(&self, vm: &mut Vm) -> Result<(), LuaError> {
+        let (args_start, args_count) = vm.varargs();
+        let args = vm.stack[args_start..(args_start + args_count)].to_vec();
+        let rets = (self.handler)(vm, &args).map_err(|err| match self.name {
+            Some(ref name) => err.in_function(name),
+            None => err,
+        })?;
+        return_values(vm, rets)
+    }
+
+    fn ret_vals(&self) -> usize {
+        0
+    }
+
+    fn set_ret_vals(&self, _: usize) {}
+
+    fn get_upval(&self, _: usize) -> Result<&Gc<LuaVal>, LuaError> {
+        Err(LuaError::Error(
+            "GetUpVal doesn't work on HostFunctions.".to_string(),
+        ))
+    }
+
+    fn set_upval(&self, _: usize, _: LuaVal) -> Result<(), LuaError> {
+        Err(LuaError::Error(
+            "SetUpVal doesn't work on HostFunctions.".to_string(),
+        ))
+    }
+}
+
 pub fn from_stdfunction(func: &StdFunction) -> Gc<Box<LuaClosure>> {
     Gc::new(Box::new(BuiltinFunction {
+        name: func.name,
         handler: func.handler(),
         ret_vals: Cell::new(0),
     }))
@@ -145,6 +327,7 @@ pub fn from_stdfunction(func: &StdFunction) -> Gc<Box<LuaClosure>> {
 
 pub fn from_function(func: &Function) -> Gc<Box<LuaClosure>> {
     Gc::new(Box::new(UserFunction {
+        chunk: 0,
         index: func.index(),
         reg_count: func.reg_count(),
         param_count: func.param_count(),
@@ -154,6 +337,10 @@ pub fn from_function(func: &Function) -> Gc<Box<LuaClosure>> {
 }
 
 pub trait LuaClosure: Trace + Finalize {
+    /// The chunk which contains the bytecode of the function.
+    fn chunk(&self) -> usize {
+        0
+    }
     fn index(&self) -> usize;
     fn reg_count(&self) -> usize;
     fn param_count(&self) -> usize;
@@ -1,4 +1,6 @@
 use errors::LuaError;
+use lua_values::lua_string::LuaStr;
+use std::str;
 
 /// Represents a super type for all primitives that don't fit in 61 bits.
 pub trait LuaObj {
@@ -19,7 +21,7 @@ pub trait LuaObj {
     /// Converts the underlying type to a string.
     fn to_string(&self) -> Result<String, LuaError>;
     /// Gets a reference to the underlying string.
-    fn get_string_ref(&self) -> Option<&str> {
+    fn get_string_ref(&self) -> Option<&LuaStr> {
         None
     }
     /// If the underlying type is a String, then this method returns the String's index
@@ -105,7 +107,7 @@ impl LuaObj for LuaFloat {
 }
 
 pub struct LuaString {
-    pub v: String,
+    pub v: LuaStr,
     /// The index of the string in the constant table.
     pub const_index: Option<usize>,
 }
@@ -131,18 +133,25 @@ impl LuaObj for LuaString {
     }
 
     fn to_int(&self) -> Result<i64, LuaError> {
-        self.v.parse().map_err(|_| LuaError::IntConversionErr)
+        str::from_utf8(self.v.as_bytes())
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .ok_or(LuaError::IntConversionErr)
     }
 
     fn to_float(&self) -> Result<f64, LuaError> {
-        self.v.parse().map_err(|_| LuaError::FloatConversionErr)
+        str::from_utf8(self.v.as_bytes())
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .ok_or(LuaError::FloatConversionErr)
     }
 
+    /// The bytes which are not valid UTF-8 are replaced by U+FFFD.
     fn to_string(&self) -> Result<String, LuaError> {
-        Ok(self.v.clone())
+        Ok(String::from_utf8_lossy(self.v.as_bytes()).into_owned())
     }
 
-    fn get_string_ref(&self) -> Option<&str> {
+    fn get_string_ref(&self) -> Option<&LuaStr> {
         Some(&self.v)
     }
 
@@ -0,0 +1,150 @@
+use std::{
+    cell::RefCell,
+    cmp::Ordering,
+    collections::HashSet,
+    hash::{Hash, Hasher},
+    rc::Rc,
+};
+
+/// The maximum length of the strings which are interned.
+pub const MAX_SHORT_LEN: usize = 40;
+/// The number of interned strings above which the unused ones are removed.
+const MIN_PURGE_LEN: usize = 1024;
+
+/// The short strings which are alive. The table belongs to the thread rather than to a
+/// `Vm`: strings are created without a vm, e.g. by `LuaVal::from` and `IntoLua`, and
+/// short strings are compared by address, so a string given by one interpreter to
+/// another must have been interned in the same table. Since strings are reference
+/// counted with `Rc`, they never leave their thread, which makes the thread the
+/// largest scope in which they can be shared.
+struct Interned {
+    strings: HashSet<Rc<[u8]>>,
+    /// The number of strings above which the strings which are only referenced by the
+    /// table are removed.
+    purge_len: usize,
+}
+
+thread_local! {
+    static INTERNED: RefCell<Interned> = RefCell::new(Interned {
+        strings: HashSet::new(),
+        purge_len: MIN_PURGE_LEN,
+    });
+}
+
+/// Gets the interned string whose bytes are <bytes>, and interns it if there is none.
+fn intern(bytes: &[u8]) -> Rc<[u8]> {
+    INTERNED.with(|interned| {
+        let mut interned = interned.borrow_mut();
+        if let Some(s) = interned.strings.get(bytes) {
+            return s.clone();
+        }
+        if interned.strings.len() >= interned.purge_len {
+            interned.strings.retain(|s| Rc::strong_count(s) > 1);
+            interned.purge_len = (interned.strings.len() * 2).max(MIN_PURGE_LEN);
+        }
+        let s: Rc<[u8]> = Rc::from(bytes);
+        interned.strings.insert(s.clone());
+        s
+    })
+}
+
+/// An immutable string, whose bytes are shared by all its clones. Short strings are
+/// interned, so that two short strings are equal only if they share their bytes, and
+/// they are hashed by address. Long strings are compared and hashed by content.
+#[derive(Clone, Debug)]
+pub struct LuaStr(Rc<[u8]>);
+
+impl LuaStr {
+    pub fn new(bytes: &[u8]) -> LuaStr {
+        if bytes.len() <= MAX_SHORT_LEN {
+            LuaStr(intern(bytes))
+        } else {
+            LuaStr(Rc::from(bytes))
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    fn is_short(&self) -> bool {
+        self.0.len() <= MAX_SHORT_LEN
+    }
+}
+
+impl From<Vec<u8>> for LuaStr {
+    fn from(bytes: Vec<u8>) -> Self {
+        if bytes.len() <= MAX_SHORT_LEN {
+            LuaStr(intern(&bytes))
+        } else {
+            LuaStr(Rc::from(bytes))
+        }
+    }
+}
+
+impl PartialEq for LuaStr {
+    fn eq(&self, other: &LuaStr) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+            || (!self.is_short() && !other.is_short() && self.0 == other.0)
+    }
+}
+
+impl Eq for LuaStr {}
+
+impl Hash for LuaStr {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        if self.is_short() {
+            (self.0.as_ptr() as usize).hash(state)
+        } else {
+            self.0.hash(state)
+        }
+    }
+}
+
+impl PartialOrd for LuaStr {
+    fn partial_cmp(&self, other: &LuaStr) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for LuaStr {
+    /// Strings are ordered by their bytes.
+    fn cmp(&self, other: &LuaStr) -> Ordering {
+        self.as_bytes().cmp(other.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_strings_are_interned() {
+        let a = LuaStr::new(b"foo");
+        let b = LuaStr::from(b"foo".to_vec());
+        assert!(Rc::ptr_eq(&a.0, &b.0));
+        assert_eq!(a, b);
+        assert_ne!(a, LuaStr::new(b"bar"));
+    }
+
+    #[test]
+    fn long_strings_are_compared_by_content() {
+        let long = [b'x'; MAX_SHORT_LEN + 1];
+        let a = LuaStr::new(&long);
+        let b = LuaStr::new(&long);
+        assert!(!Rc::ptr_eq(&a.0, &b.0));
+        assert_eq!(a, b);
+        assert_ne!(a, LuaStr::new(&long[1..]));
+        assert!(LuaStr::new(&long[1..]) < a);
+    }
+
+    #[test]
+    fn unused_strings_are_purged() {
+        let kept = LuaStr::new(b"kept");
+        for i in 0..(MIN_PURGE_LEN * 3) {
+            LuaStr::new(i.to_string().as_bytes());
+        }
+        INTERNED.with(|interned| assert!(interned.borrow().strings.len() <= MIN_PURGE_LEN * 2));
+        assert!(Rc::ptr_eq(&kept.0, &LuaStr::new(b"kept").0));
+    }
+}
@@ -25,6 +25,16 @@ unsafe impl Trace for Box<LuaTable> {
 pub trait LuaTable: Trace + Finalize {
     fn get_attr(&self, attr: &LuaVal) -> LuaVal;
     fn set_attr(&self, attr: LuaVal, val: LuaVal);
+    /// Returns the keys of the table together with their values, which are not nil.
+    fn entries(&self) -> Vec<(LuaVal, LuaVal)>;
+}
+
+/// Clones the entries of <hm> whose value is not nil.
+fn non_nil_entries(hm: &HashMap<LuaVal, LuaVal>) -> Vec<(LuaVal, LuaVal)> {
+    hm.iter()
+        .filter(|(_, v)| !v.is_nil())
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect()
 }
 
 /// Represents a table in Lua.
@@ -51,18 +61,25 @@ impl LuaTable for UserTable {
             None => LuaVal::new(),
         }
     }
+
+    fn entries(&self) -> Vec<(LuaVal, LuaVal)> {
+        non_nil_entries(&self.v.borrow())
+    }
 }
 
 #[derive(Trace, Finalize)]
 pub struct CachingTable {
-    str_attrs: GcCell<Vec<LuaVal>>,
+    /// The attributes whose key is a string of the string table of the interpreter,
+    /// stored together with their key at the index of the string. The vector grows as
+    /// new chunks add strings to the table.
+    str_attrs: GcCell<Vec<(LuaVal, LuaVal)>>,
     attrs: GcCell<HashMap<LuaVal, LuaVal>>,
 }
 
 impl CachingTable {
     pub fn new(hash_map: HashMap<LuaVal, LuaVal>, num: usize) -> CachingTable {
         let mut str_attrs = Vec::new();
-        str_attrs.resize(num, LuaVal::new());
+        str_attrs.resize(num, (LuaVal::new(), LuaVal::new()));
         CachingTable {
             str_attrs: GcCell::new(str_attrs),
             attrs: GcCell::new(hash_map),
@@ -74,7 +91,11 @@ impl LuaTable for CachingTable {
     fn set_attr(&self, attr: LuaVal, val: LuaVal) {
         match attr.get_constant_index() {
             Some(i) => {
-                self.str_attrs.borrow_mut()[i] = val;
+                let mut str_attrs = self.str_attrs.borrow_mut();
+                if i >= str_attrs.len() {
+                    str_attrs.resize(i + 1, (LuaVal::new(), LuaVal::new()));
+                }
+                str_attrs[i] = (attr, val);
             }
             None => {
                 self.attrs.borrow_mut().insert(attr, val);
@@ -84,11 +105,24 @@ impl LuaTable for CachingTable {
 
     fn get_attr(&self, attr: &LuaVal) -> LuaVal {
         match attr.get_constant_index() {
-            Some(i) => self.str_attrs.borrow()[i].clone(),
+            Some(i) => match self.str_attrs.borrow().get(i) {
+                Some((_, val)) => val.clone(),
+                None => LuaVal::new(),
+            },
             None => match self.attrs.borrow().get(attr) {
                 Some(val) => val.clone(),
                 None => LuaVal::new(),
             },
         }
     }
+
+    fn entries(&self) -> Vec<(LuaVal, LuaVal)> {
+        let mut entries = non_nil_entries(&self.attrs.borrow());
+        for (k, v) in self.str_attrs.borrow().iter() {
+            if !v.is_nil() {
+                entries.push((k.clone(), v.clone()));
+            }
+        }
+        entries
+    }
 }
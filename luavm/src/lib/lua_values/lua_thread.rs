@@ -0,0 +1,113 @@
+use gc::GcCell;
+use lua_values::LuaVal;
+use std::{cell::Cell, mem::swap};
+use StackFrame;
+use Vm;
+
+/// The state of a coroutine, as returned by `coroutine.status`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ThreadStatus {
+    /// The thread hasn't started yet, or it yielded.
+    Suspended,
+    Running,
+    /// The thread resumed another coroutine, and waits for it to yield or to finish.
+    Normal,
+    /// The function of the thread returned, or raised an error.
+    Dead,
+}
+
+impl ThreadStatus {
+    pub fn name(self) -> &'static str {
+        match self {
+            ThreadStatus::Suspended => "suspended",
+            ThreadStatus::Running => "running",
+            ThreadStatus::Normal => "normal",
+            ThreadStatus::Dead => "dead",
+        }
+    }
+}
+
+/// The execution state of a thread which is not running. The state of the running
+/// thread is kept in the fields of the same name of `Vm`.
+#[derive(Trace, Finalize)]
+struct ThreadState {
+    stack: Vec<LuaVal>,
+    base: usize,
+    top: usize,
+    stack_frames: Vec<StackFrame>,
+    curr_frame: usize,
+    pc: usize,
+    host_calls: usize,
+}
+
+/// Represents a coroutine in Lua.
+#[derive(Trace, Finalize)]
+pub struct LuaThread {
+    state: GcCell<ThreadState>,
+    #[unsafe_ignore_trace]
+    status: Cell<ThreadStatus>,
+    /// Whether the thread was last resumed by a function created with `coroutine.wrap`,
+    /// which doesn't return the status of the resume, and propagates errors.
+    #[unsafe_ignore_trace]
+    wrapped: Cell<bool>,
+}
+
+impl LuaThread {
+    /// Creates a suspended thread which calls <func> when it is first resumed.
+    pub fn new(func: LuaVal) -> LuaThread {
+        LuaThread {
+            state: GcCell::new(ThreadState {
+                stack: vec![func],
+                base: 0,
+                top: 1,
+                stack_frames: vec![],
+                curr_frame: 0,
+                pc: 0,
+                host_calls: 0,
+            }),
+            status: Cell::new(ThreadStatus::Suspended),
+            wrapped: Cell::new(false),
+        }
+    }
+
+    /// Creates the thread which runs the main chunk.
+    pub fn main() -> LuaThread {
+        let thread = LuaThread::new(LuaVal::new());
+        thread.status.set(ThreadStatus::Running);
+        thread
+    }
+
+    pub fn status(&self) -> ThreadStatus {
+        self.status.get()
+    }
+
+    pub fn set_status(&self, status: ThreadStatus) {
+        self.status.set(status);
+        // a dead thread cannot be resumed, so its stack is no longer needed
+        if status == ThreadStatus::Dead {
+            let mut state = self.state.borrow_mut();
+            state.stack.clear();
+            state.stack_frames.clear();
+        }
+    }
+
+    pub fn wrapped(&self) -> bool {
+        self.wrapped.get()
+    }
+
+    pub fn set_wrapped(&self, wrapped: bool) {
+        self.wrapped.set(wrapped);
+    }
+
+    /// Exchanges the state of the thread with the state of the running thread of <vm>.
+    pub fn swap_state(&self, vm: &mut Vm) {
+        let mut state = self.state.borrow_mut();
+        swap(&mut state.stack, &mut vm.stack);
+        swap(&mut state.base, &mut vm.base);
+        swap(&mut state.top, &mut vm.top);
+        swap(&mut state.stack_frames, &mut vm.stack_frames);
+        swap(&mut state.curr_frame, &mut vm.curr_frame);
+        swap(&mut state.pc, &mut vm.pc);
+        swap(&mut state.host_calls, &mut vm.host_calls);
+    }
+}
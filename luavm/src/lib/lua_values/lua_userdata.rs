@@ -0,0 +1,370 @@
+use crate::{
+    errors::LuaError,
+    lua_values::{
+        convert::{FromLua, FromLuaMulti, IntoLua, IntoLuaMulti},
+        lua_closure::HostFunction,
+        LuaVal,
+    },
+    Vm,
+};
+use gc::{Finalize, Gc, GcCell, Trace};
+use std::{
+    any::{type_name, Any},
+    cell::Cell,
+    collections::HashMap,
+    marker::PhantomData,
+};
+
+/// A Rust type which can be given to Lua as a userdata, see `Vm::create_userdata`.
+pub trait UserData: Any + Trace + Finalize + Sized {
+    /// Declares the methods, fields and metamethods of the type.
+    fn add_methods(_methods: &mut UserDataMethods<Self>) {}
+}
+
+/// The part of `UserData` which can be used as a trait object, and which allows
+/// downcasting the value back to its Rust type.
+pub trait AnyUserData: Trace + Finalize {
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<T: UserData> AnyUserData for T {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+impl Finalize for Box<dyn AnyUserData> {}
+unsafe impl Trace for Box<dyn AnyUserData> {
+    unsafe fn trace(&self) {
+        (**self).trace();
+    }
+
+    unsafe fn root(&self) {
+        (**self).root();
+    }
+
+    unsafe fn unroot(&self) {
+        (**self).unroot();
+    }
+
+    fn finalize_glue(&self) {
+        (**self).finalize();
+        (**self).finalize_glue();
+    }
+}
+
+type Getter = Box<dyn Fn(&dyn AnyUserData) -> Result<LuaVal, LuaError>>;
+type Setter = Box<dyn Fn(&mut dyn AnyUserData, &LuaVal) -> Result<(), LuaError>>;
+
+/// The methods, fields and metamethods which are shared by the userdata of a Rust type.
+#[derive(Trace, Finalize)]
+pub struct UserDataType {
+    #[unsafe_ignore_trace]
+    name: &'static str,
+    methods: HashMap<String, LuaVal>,
+    #[unsafe_ignore_trace]
+    getters: HashMap<String, Getter>,
+    #[unsafe_ignore_trace]
+    setters: HashMap<String, Setter>,
+    metamethods: HashMap<String, LuaVal>,
+}
+
+impl UserDataType {
+    pub fn new<T: UserData>() -> UserDataType {
+        let mut methods = UserDataMethods {
+            ty: UserDataType {
+                name: type_name::<T>(),
+                methods: HashMap::new(),
+                getters: HashMap::new(),
+                setters: HashMap::new(),
+                metamethods: HashMap::new(),
+            },
+            marker: PhantomData,
+        };
+        T::add_methods(&mut methods);
+        methods.ty
+    }
+}
+
+/// Collects the methods, fields and metamethods of <T>, see `UserData::add_methods`.
+pub struct UserDataMethods<T> {
+    ty: UserDataType,
+    marker: PhantomData<T>,
+}
+
+impl<T: UserData> UserDataMethods<T> {
+    /// Adds a method, which gets the userdata as its first argument, e.g.
+    /// `obj.method(obj, arg1, ...)`.
+    pub fn add_method<A, R, F>(&mut self, name: &str, method: F)
+    where
+        A: FromLuaMulti,
+        R: IntoLuaMulti,
+        F: Fn(&mut Vm, &T, A) -> Result<R, LuaError> + 'static,
+    {
+        let func = method_function(move |vm, this: &LuaVal, args| {
+            this.with_userdata(|t: &T| method(vm, t, args))
+                .map_err(|err| LuaError::BadArgument(1, Box::new(err)))?
+        });
+        let func = LuaVal::from(func.with_name(name));
+        self.ty.methods.insert(name.to_string(), func);
+    }
+
+    /// Adds a method which can modify the userdata.
+    pub fn add_method_mut<A, R, F>(&mut self, name: &str, method: F)
+    where
+        A: FromLuaMulti,
+        R: IntoLuaMulti,
+        F: Fn(&mut Vm, &mut T, A) -> Result<R, LuaError> + 'static,
+    {
+        let func = method_function(move |vm, this: &LuaVal, args| {
+            this.with_userdata_mut(|t: &mut T| method(vm, t, args))
+                .map_err(|err| LuaError::BadArgument(1, Box::new(err)))?
+        });
+        let func = LuaVal::from(func.with_name(name));
+        self.ty.methods.insert(name.to_string(), func);
+    }
+
+    /// Adds a method which gets all of its arguments converted to <A>, including the
+    /// userdata, e.g. to return it.
+    pub fn add_function<A, R, F>(&mut self, name: &str, func: F)
+    where
+        A: FromLuaMulti,
+        R: IntoLuaMulti,
+        F: Fn(&mut Vm, A) -> Result<R, LuaError> + 'static,
+    {
+        let func = LuaVal::from(HostFunction::new(func).with_name(name));
+        self.ty.methods.insert(name.to_string(), func);
+    }
+
+    /// Adds a field which can be read, e.g. `obj.field`.
+    pub fn add_field_getter<R, F>(&mut self, name: &str, getter: F)
+    where
+        R: IntoLua,
+        F: Fn(&T) -> Result<R, LuaError> + 'static,
+    {
+        let getter = move |data: &dyn AnyUserData| {
+            let t = data.as_any().downcast_ref::<T>().unwrap();
+            getter(t).map(IntoLua::into_lua)
+        };
+        self.ty.getters.insert(name.to_string(), Box::new(getter));
+    }
+
+    /// Adds a field which can be assigned, e.g. `obj.field = val`.
+    pub fn add_field_setter<A, F>(&mut self, name: &str, setter: F)
+    where
+        A: FromLua,
+        F: Fn(&mut T, A) -> Result<(), LuaError> + 'static,
+    {
+        let setter = move |data: &mut dyn AnyUserData, val: &LuaVal| {
+            let t = data.as_any_mut().downcast_mut::<T>().unwrap();
+            setter(t, A::from_lua(val)?)
+        };
+        self.ty.setters.insert(name.to_string(), Box::new(setter));
+    }
+
+    /// Adds a metamethod which gets the userdata as its first argument, like `__call`.
+    pub fn add_meta_method<A, R, F>(&mut self, name: &str, method: F)
+    where
+        A: FromLuaMulti,
+        R: IntoLuaMulti,
+        F: Fn(&mut Vm, &T, A) -> Result<R, LuaError> + 'static,
+    {
+        let func = method_function(move |vm, this: &LuaVal, args| {
+            this.with_userdata(|t: &T| method(vm, t, args))
+                .map_err(|err| LuaError::BadArgument(1, Box::new(err)))?
+        });
+        self.ty
+            .metamethods
+            .insert(name.to_string(), LuaVal::from(func));
+    }
+
+    /// Adds a metamethod which gets all of its arguments converted to <A>. This is
+    /// needed for the metamethods of binary operators, since the userdata might be the
+    /// second operand, like in `1 + obj`.
+    pub fn add_meta_function<A, R, F>(&mut self, name: &str, func: F)
+    where
+        A: FromLuaMulti,
+        R: IntoLuaMulti,
+        F: Fn(&mut Vm, A) -> Result<R, LuaError> + 'static,
+    {
+        let func = LuaVal::from(HostFunction::new(func));
+        self.ty.metamethods.insert(name.to_string(), func);
+    }
+}
+
+/// Creates a function which calls <method> with its first argument, and the rest of
+/// its arguments converted to <A>.
+fn method_function<A, R, F>(method: F) -> HostFunction
+where
+    A: FromLuaMulti,
+    R: IntoLuaMulti,
+    F: Fn(&mut Vm, &LuaVal, A) -> Result<R, LuaError> + 'static,
+{
+    let handler = move |vm: &mut Vm, args: &[LuaVal]| {
+        let this = args.first().cloned().unwrap_or_default();
+        let rest = A::from_lua_multi(args.get(1..).unwrap_or(&[])).map_err(|err| match err {
+            // the first argument is the userdata
+            LuaError::BadArgument(i, err) => LuaError::BadArgument(i + 1, err),
+            err => err,
+        })?;
+        method(vm, &this, rest).map(IntoLuaMulti::into_lua_multi)
+    };
+    HostFunction::from_handler(Box::new(handler))
+}
+
+/// Represents a userdata in Lua, which owns a value of a Rust type.
+#[derive(Trace, Finalize)]
+pub struct LuaUserData {
+    data: GcCell<Box<dyn AnyUserData>>,
+    /// The number of active borrows of `data`, or -1 if it is borrowed mutably. It is
+    /// checked before borrowing `data`, so that Lua code which uses a userdata while it
+    /// is borrowed gets an error instead of a panic.
+    #[unsafe_ignore_trace]
+    borrows: Cell<isize>,
+    ty: Gc<UserDataType>,
+}
+
+impl LuaUserData {
+    pub fn new<T: UserData>(data: T, ty: Gc<UserDataType>) -> LuaUserData {
+        LuaUserData {
+            data: GcCell::new(Box::new(data)),
+            borrows: Cell::new(0),
+            ty,
+        }
+    }
+
+    /// The name of the Rust type of the userdata.
+    pub fn type_name(&self) -> &'static str {
+        self.ty.name
+    }
+
+    fn mismatch<T: Any>(&self) -> LuaError {
+        LuaError::TypeMismatch {
+            expected: type_name::<T>(),
+            got: self.ty.name,
+        }
+    }
+
+    fn already_borrowed() -> LuaError {
+        LuaError::Error("userdata is already borrowed".to_string())
+    }
+
+    /// Calls <f> with the value of the userdata, if it is a <T>.
+    pub fn borrow<T: Any, R, F: FnOnce(&T) -> R>(&self, f: F) -> Result<R, LuaError> {
+        let borrows = self.borrows.get();
+        if borrows < 0 {
+            return Err(LuaUserData::already_borrowed());
+        }
+        self.borrows.set(borrows + 1);
+        let res = {
+            let data = self.data.borrow();
+            data.as_any().downcast_ref::<T>().map(f)
+        };
+        self.borrows.set(borrows);
+        res.ok_or_else(|| self.mismatch::<T>())
+    }
+
+    /// Calls <f> with a mutable reference to the value of the userdata, if it is a <T>.
+    pub fn borrow_mut<T: Any, R, F: FnOnce(&mut T) -> R>(&self, f: F) -> Result<R, LuaError> {
+        if self.borrows.get() != 0 {
+            return Err(LuaUserData::already_borrowed());
+        }
+        self.borrows.set(-1);
+        let res = {
+            let mut data = self.data.borrow_mut();
+            data.as_any_mut().downcast_mut::<T>().map(f)
+        };
+        self.borrows.set(0);
+        res.ok_or_else(|| self.mismatch::<T>())
+    }
+
+    /// Gets the value of the field, or the method called <attr>.
+    pub fn get_field(&self, attr: &LuaVal) -> Result<Option<LuaVal>, LuaError> {
+        let name = match attr.as_str() {
+            Some(name) => name,
+            None => return Ok(None),
+        };
+        if let Some(getter) = self.ty.getters.get(name) {
+            if self.borrows.get() < 0 {
+                return Err(LuaUserData::already_borrowed());
+            }
+            return getter(&**self.data.borrow()).map(Some);
+        }
+        Ok(self.ty.methods.get(name).cloned())
+    }
+
+    /// Sets the field <attr> to <val>, and returns whether the field exists.
+    pub fn set_field(&self, attr: &LuaVal, val: &LuaVal) -> Result<bool, LuaError> {
+        let setter = match attr.as_str().and_then(|n| self.ty.setters.get(n)) {
+            Some(setter) => setter,
+            None => return Ok(false),
+        };
+        if self.borrows.get() != 0 {
+            return Err(LuaUserData::already_borrowed());
+        }
+        setter(&mut **self.data.borrow_mut(), val)?;
+        Ok(true)
+    }
+
+    pub fn metamethod(&self, name: &str) -> Option<LuaVal> {
+        self.ty.metamethods.get(name).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Trace, Finalize)]
+    struct Counter {
+        n: i64,
+    }
+
+    impl UserData for Counter {
+        fn add_methods(methods: &mut UserDataMethods<Self>) {
+            methods.add_field_getter("n", |c: &Counter| Ok(c.n));
+            methods.add_field_setter("n", |c: &mut Counter, n: i64| {
+                c.n = n;
+                Ok(())
+            });
+        }
+    }
+
+    #[test]
+    fn borrows_and_fields() {
+        let ud = LuaUserData::new(Counter { n: 1 }, Gc::new(UserDataType::new::<Counter>()));
+        let n = LuaVal::from("n");
+        assert_eq!(ud.get_field(&n), Ok(Some(LuaVal::from(1))));
+        assert_eq!(ud.set_field(&n, &LuaVal::from(5)), Ok(true));
+        assert_eq!(
+            ud.set_field(&LuaVal::from("m"), &LuaVal::from(5)),
+            Ok(false)
+        );
+        assert_eq!(ud.get_field(&LuaVal::from("m")), Ok(None));
+        assert_eq!(ud.borrow(|c: &Counter| c.n), Ok(5));
+        assert_eq!(
+            ud.borrow(|s: &String| s.len()),
+            Err(LuaError::TypeMismatch {
+                expected: "alloc::string::String",
+                got: ud.type_name()
+            })
+        );
+        // the userdata cannot be used while it is modified
+        let res = ud.borrow_mut(|c: &mut Counter| {
+            c.n += 1;
+            ud.get_field(&n)
+        });
+        assert_eq!(
+            res,
+            Ok(Err(LuaError::Error(
+                "userdata is already borrowed".to_string()
+            )))
+        );
+        assert_eq!(ud.get_field(&n), Ok(Some(LuaVal::from(6))));
+    }
+}
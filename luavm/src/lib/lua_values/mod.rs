@@ -1,12 +1,19 @@
+pub mod convert;
 pub mod lua_closure;
 mod lua_obj;
+pub mod lua_string;
 pub mod lua_table;
+pub mod lua_thread;
+pub mod lua_userdata;
 mod tagging;
 
 use self::{
     lua_closure::*,
     lua_obj::*,
+    lua_string::LuaStr,
     lua_table::{CachingTable, UserTable},
+    lua_thread::LuaThread,
+    lua_userdata::LuaUserData,
     tagging::*,
 };
 use crate::stdlib::StdFunction;
@@ -16,11 +23,14 @@ use ieee754::Ieee754;
 use lua_values::lua_table::LuaTable;
 use luacompiler::bytecode::Function;
 use std::{
+    any::{type_name, Any},
     cmp::Ordering,
+    collections::HashMap,
     fmt,
     fmt::{Display, Formatter},
     hash::{Hash, Hasher},
     mem::transmute,
+    str,
 };
 
 /// Represents a value in Lua.
@@ -35,6 +45,8 @@ unsafe impl Trace for LuaVal {
         match self.kind() {
             LuaValKind::TABLE => (*table_ptr(self.val)).trace(),
             LuaValKind::CLOSURE => (*closure_ptr(self.val)).trace(),
+            LuaValKind::THREAD => (*thread_ptr(self.val)).trace(),
+            LuaValKind::USERDATA => (*userdata_ptr(self.val)).trace(),
             _ => {}
         }
     }
@@ -43,6 +55,8 @@ unsafe impl Trace for LuaVal {
         match self.kind() {
             LuaValKind::TABLE => (*table_ptr(self.val)).root(),
             LuaValKind::CLOSURE => (*closure_ptr(self.val)).root(),
+            LuaValKind::THREAD => (*thread_ptr(self.val)).root(),
+            LuaValKind::USERDATA => (*userdata_ptr(self.val)).root(),
             _ => {}
         }
     }
@@ -51,6 +65,8 @@ unsafe impl Trace for LuaVal {
         match self.kind() {
             LuaValKind::TABLE => (*table_ptr(self.val)).unroot(),
             LuaValKind::CLOSURE => (*closure_ptr(self.val)).unroot(),
+            LuaValKind::THREAD => (*thread_ptr(self.val)).unroot(),
+            LuaValKind::USERDATA => (*userdata_ptr(self.val)).unroot(),
             _ => {}
         }
     }
@@ -65,6 +81,14 @@ unsafe impl Trace for LuaVal {
                 (*closure_ptr(self.val)).finalize();
                 (*closure_ptr(self.val)).finalize_glue();
             },
+            LuaValKind::THREAD => unsafe {
+                (*thread_ptr(self.val)).finalize();
+                (*thread_ptr(self.val)).finalize_glue();
+            },
+            LuaValKind::USERDATA => unsafe {
+                (*userdata_ptr(self.val)).finalize();
+                (*userdata_ptr(self.val)).finalize_glue();
+            },
             _ => {}
         }
     }
@@ -76,6 +100,31 @@ impl LuaVal {
         LuaVal { val: 0 }
     }
 
+    /// Create an empty table.
+    pub fn new_table() -> LuaVal {
+        LuaVal::from(UserTable::new(HashMap::new()))
+    }
+
+    /// The name of the type of the value, as returned by `type` in Lua.
+    pub fn type_name(&self) -> &'static str {
+        match self.kind() {
+            LuaValKind::NIL => "nil",
+            LuaValKind::BOOL => "boolean",
+            LuaValKind::INT | LuaValKind::FLOAT => "number",
+            LuaValKind::TABLE => "table",
+            LuaValKind::CLOSURE => "function",
+            LuaValKind::THREAD => "thread",
+            LuaValKind::USERDATA => "userdata",
+            LuaValKind::BOXED => {
+                if self.is_string() {
+                    "string"
+                } else {
+                    "number"
+                }
+            }
+        }
+    }
+
     /// Returns the type of the value store in the pointer.
     fn kind(&self) -> LuaValKind {
         LuaValKind::from(self.val)
@@ -94,6 +143,12 @@ impl LuaVal {
         }
     }
 
+    /// Checks whether the value is an integer; strings which represent integers are
+    /// not considered to be integers.
+    pub fn is_int(&self) -> bool {
+        self.is_number() && !self.is_aop_float()
+    }
+
     pub fn is_string(&self) -> bool {
         match self.kind() {
             LuaValKind::BOXED => unsafe { (*self.as_boxed()).is_string() },
@@ -101,6 +156,68 @@ impl LuaVal {
         }
     }
 
+    pub fn is_nil(&self) -> bool {
+        self.kind() == LuaValKind::NIL
+    }
+
+    pub fn is_bool(&self) -> bool {
+        self.kind() == LuaValKind::BOOL
+    }
+
+    pub fn is_table(&self) -> bool {
+        self.kind() == LuaValKind::TABLE
+    }
+
+    pub fn is_function(&self) -> bool {
+        self.kind() == LuaValKind::CLOSURE
+    }
+
+    pub fn is_userdata(&self) -> bool {
+        self.kind() == LuaValKind::USERDATA
+    }
+
+    pub fn is_thread(&self) -> bool {
+        self.kind() == LuaValKind::THREAD
+    }
+
+    /// Gets the value of an integer. Unlike `to_int`, strings and floats are not
+    /// converted.
+    pub fn as_int(&self) -> Option<i64> {
+        if self.is_int() {
+            self.to_int().ok()
+        } else {
+            None
+        }
+    }
+
+    /// Gets the value of a number. Unlike `to_float`, strings are not converted.
+    pub fn as_float(&self) -> Option<f64> {
+        if self.is_number() {
+            self.to_float().ok()
+        } else {
+            None
+        }
+    }
+
+    /// Gets the underlying string of a string value, if it is valid UTF-8.
+    pub fn as_str(&self) -> Option<&str> {
+        self.get_string_ref()
+            .and_then(|s| str::from_utf8(s.as_bytes()).ok())
+    }
+
+    /// Gets the bytes of a string value.
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        self.get_string_ref().map(LuaStr::as_bytes)
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        if self.is_bool() {
+            Some(self.to_bool())
+        } else {
+            None
+        }
+    }
+
     /// Gets the index of the underlying string in the constant table.
     pub fn get_constant_index(&self) -> Option<usize> {
         match self.kind() {
@@ -146,7 +263,8 @@ impl LuaVal {
         }
     }
 
-    /// Attempts to convert this value to a string.
+    /// Attempts to convert this value to a string. The bytes of a string which are not
+    /// valid UTF-8 are replaced by U+FFFD, see `as_bytes` for the exact contents.
     pub fn to_string(&self) -> Result<String, LuaError> {
         match self.kind() {
             LuaValKind::BOOL => Ok(((self.val >> tagging::TAG_SHIFT) != 0).to_string()),
@@ -159,6 +277,15 @@ impl LuaVal {
         }
     }
 
+    /// Attempts to convert this value to the bytes of a string. Unlike `to_string`,
+    /// the bytes of a string are kept as they are.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, LuaError> {
+        match self.get_string_ref() {
+            Some(s) => Ok(s.as_bytes().to_vec()),
+            None => self.to_string().map(String::into_bytes),
+        }
+    }
+
     pub fn to_bool(&self) -> bool {
         match self.kind() {
             LuaValKind::NIL => false,
@@ -167,7 +294,7 @@ impl LuaVal {
         }
     }
 
-    fn get_string_ref(&self) -> Option<&str> {
+    fn get_string_ref(&self) -> Option<&LuaStr> {
         match self.kind() {
             LuaValKind::BOXED => unsafe { (*self.as_boxed()).get_string_ref() },
             _ => None,
@@ -176,24 +303,85 @@ impl LuaVal {
 
     /// Sets the given attribute to a given value.
     pub fn set_attr(&self, attr: LuaVal, val: LuaVal) -> Result<(), LuaError> {
-        if let LuaValKind::TABLE = self.kind() {
-            Ok(unsafe {
+        match self.kind() {
+            LuaValKind::TABLE => Ok(unsafe {
                 (*table_ptr(self.val)).set_attr(attr, val);
-            })
-        } else {
-            Err(LuaError::SetAttrErr)
+            }),
+            LuaValKind::USERDATA => {
+                if unsafe { (*userdata_ptr(self.val)).set_field(&attr, &val)? } {
+                    Ok(())
+                } else {
+                    Err(LuaError::SetAttrErr)
+                }
+            }
+            _ => Err(LuaError::SetAttrErr),
         }
     }
 
     /// Gets the value of the given attribute.
     pub fn get_attr(&self, attr: &LuaVal) -> Result<LuaVal, LuaError> {
+        match self.kind() {
+            LuaValKind::TABLE => Ok(unsafe { (*table_ptr(self.val)).get_attr(attr) }),
+            // the fields and methods of userdata which don't exist are nil
+            LuaValKind::USERDATA => unsafe {
+                (*userdata_ptr(self.val))
+                    .get_field(attr)
+                    .map(Option::unwrap_or_default)
+            },
+            _ => Err(LuaError::GetAttrErr),
+        }
+    }
+
+    /// Gets the attributes of a table, and their values.
+    pub fn entries(&self) -> Result<Vec<(LuaVal, LuaVal)>, LuaError> {
         if let LuaValKind::TABLE = self.kind() {
-            Ok(unsafe { (*table_ptr(self.val)).get_attr(attr) })
+            Ok(unsafe { (*table_ptr(self.val)).entries() })
         } else {
             Err(LuaError::GetAttrErr)
         }
     }
 
+    /// The length of the value, i.e. `#` in Lua: the number of bytes of a string, or
+    /// a border of a table, which is an index whose value is not nil and is followed
+    /// by nil, or 0 if the value at 1 is nil. A table with nils has several borders,
+    /// any of which can be returned.
+    pub fn length(&self) -> Result<i64, LuaError> {
+        if let Some(s) = self.as_bytes() {
+            return Ok(s.len() as i64);
+        }
+        if !self.is_table() {
+            return Err(LuaError::Error(format!(
+                "attempt to get length of a {} value",
+                self.type_name()
+            )));
+        }
+        let is_nil = |i: i64| self.get_attr(&LuaVal::from(i)).map(|v| v.is_nil());
+        // look for an index whose value is nil, by doubling a non nil one
+        let (mut i, mut j) = (0, 1);
+        while !is_nil(j)? {
+            i = j;
+            if j > i64::MAX / 2 {
+                // the index would overflow, so the first border is searched linearly
+                let mut k = 1;
+                while !is_nil(k)? {
+                    k += 1;
+                }
+                return Ok(k - 1);
+            }
+            j *= 2;
+        }
+        // a border is between i, which is 0 or not nil, and j, which is nil
+        while j - i > 1 {
+            let m = i + (j - i) / 2;
+            if is_nil(m)? {
+                j = m;
+            } else {
+                i = m;
+            }
+        }
+        Ok(i)
+    }
+
     pub fn add(&self, other: &LuaVal) -> Result<LuaVal, LuaError> {
         Ok(if self.is_aop_float() || other.is_aop_float() {
             LuaVal::from(self.to_float()? + other.to_float()?)
@@ -252,6 +440,61 @@ impl LuaVal {
         }
         Err(LuaError::NotAClosure)
     }
+
+    pub fn get_thread(&self) -> Option<Gc<LuaThread>> {
+        if let LuaValKind::THREAD = self.kind() {
+            return Some(unsafe { (*thread_ptr(self.val)).clone() });
+        }
+        None
+    }
+
+    pub fn get_userdata(&self) -> Option<Gc<LuaUserData>> {
+        if let LuaValKind::USERDATA = self.kind() {
+            return Some(unsafe { (*userdata_ptr(self.val)).clone() });
+        }
+        None
+    }
+
+    /// Calls <f> with the Rust value of the userdata, if it is a <T>.
+    pub fn with_userdata<T: Any, R, F: FnOnce(&T) -> R>(&self, f: F) -> Result<R, LuaError> {
+        match self.kind() {
+            LuaValKind::USERDATA => unsafe { (*userdata_ptr(self.val)).borrow(f) },
+            _ => Err(LuaError::TypeMismatch {
+                expected: type_name::<T>(),
+                got: self.type_name(),
+            }),
+        }
+    }
+
+    /// Calls <f> with a mutable reference to the Rust value of the userdata, if it is a
+    /// <T>.
+    pub fn with_userdata_mut<T: Any, R, F: FnOnce(&mut T) -> R>(
+        &self,
+        f: F,
+    ) -> Result<R, LuaError> {
+        match self.kind() {
+            LuaValKind::USERDATA => unsafe { (*userdata_ptr(self.val)).borrow_mut(f) },
+            _ => Err(LuaError::TypeMismatch {
+                expected: type_name::<T>(),
+                got: self.type_name(),
+            }),
+        }
+    }
+
+    /// Gets the metamethod <name> of the value, e.g. `__add`. Only userdata have
+    /// metamethods.
+    pub fn metamethod(&self, name: &str) -> Option<LuaVal> {
+        match self.kind() {
+            LuaValKind::USERDATA => unsafe { (*userdata_ptr(self.val)).metamethod(name) },
+            _ => None,
+        }
+    }
+}
+
+impl Default for LuaVal {
+    fn default() -> Self {
+        LuaVal::new()
+    }
 }
 
 impl PartialEq for LuaVal {
@@ -267,6 +510,10 @@ impl PartialEq for LuaVal {
                 return unsafe { (*table_ptr(self.val)).same_ptr(&*table_ptr(other.val)) };
             } else if self.kind() == LuaValKind::CLOSURE {
                 return unsafe { (*closure_ptr(self.val)).same_ptr(&*closure_ptr(other.val)) };
+            } else if self.kind() == LuaValKind::THREAD {
+                return unsafe { (*thread_ptr(self.val)).same_ptr(&*thread_ptr(other.val)) };
+            } else if self.kind() == LuaValKind::USERDATA {
+                return unsafe { (*userdata_ptr(self.val)).same_ptr(&*userdata_ptr(other.val)) };
             } else if self.kind() == LuaValKind::BOOL {
                 return self.val == other.val;
             }
@@ -295,6 +542,12 @@ impl Hash for LuaVal {
                     val.to_int().unwrap().hash(state)
                 }
             }
+            // each clone of a reference has its own pointer to the Gc, so the address of
+            // the value is hashed instead, like `eq` compares them
+            LuaValKind::TABLE => unsafe { (*table_ptr(self.val)).addr() }.hash(state),
+            LuaValKind::CLOSURE => unsafe { (*closure_ptr(self.val)).addr() }.hash(state),
+            LuaValKind::THREAD => unsafe { (*thread_ptr(self.val)).addr() }.hash(state),
+            LuaValKind::USERDATA => unsafe { (*userdata_ptr(self.val)).addr() }.hash(state),
             _ => self.val.hash(state),
         }
     }
@@ -330,26 +583,53 @@ impl From<f64> for LuaVal {
     }
 }
 
-impl From<String> for LuaVal {
-    /// Create a float LuaVal.
-    fn from(string: String) -> Self {
+impl From<Vec<u8>> for LuaVal {
+    /// Create a string LuaVal out of any bytes.
+    fn from(bytes: Vec<u8>) -> Self {
         LuaVal {
             val: LuaValKind::BOXED
                 ^ to_boxed(Box::new(LuaString {
-                    v: string,
+                    v: LuaStr::from(bytes),
                     const_index: None,
                 })),
         }
     }
 }
 
-impl From<(String, usize)> for LuaVal {
-    /// Create a float LuaVal.
-    fn from(string: (String, usize)) -> Self {
+impl<'a> From<&'a [u8]> for LuaVal {
+    /// Create a string LuaVal out of any bytes.
+    fn from(bytes: &'a [u8]) -> Self {
         LuaVal {
             val: LuaValKind::BOXED
                 ^ to_boxed(Box::new(LuaString {
-                    v: string.0,
+                    v: LuaStr::new(bytes),
+                    const_index: None,
+                })),
+        }
+    }
+}
+
+impl From<String> for LuaVal {
+    /// Create a string LuaVal.
+    fn from(string: String) -> Self {
+        LuaVal::from(string.into_bytes())
+    }
+}
+
+impl<'a> From<&'a str> for LuaVal {
+    /// Create a string LuaVal.
+    fn from(string: &'a str) -> Self {
+        LuaVal::from(string.as_bytes())
+    }
+}
+
+impl From<(Vec<u8>, usize)> for LuaVal {
+    /// Create a string LuaVal which is at the given index of the string table.
+    fn from(string: (Vec<u8>, usize)) -> Self {
+        LuaVal {
+            val: LuaValKind::BOXED
+                ^ to_boxed(Box::new(LuaString {
+                    v: LuaStr::from(string.0),
                     const_index: Some(string.1),
                 })),
         }
@@ -384,6 +664,44 @@ impl From<UserFunction> for LuaVal {
     }
 }
 
+impl From<ControlFunction> for LuaVal {
+    /// Create a closure LuaVal
+    fn from(func: ControlFunction) -> Self {
+        let control_func: Box<LuaClosure> = Box::new(func);
+        LuaVal {
+            val: LuaValKind::CLOSURE ^ to_raw_ptr(Gc::new(control_func)),
+        }
+    }
+}
+
+impl From<HostFunction> for LuaVal {
+    /// Create a closure LuaVal
+    fn from(func: HostFunction) -> Self {
+        let host_func: Box<LuaClosure> = Box::new(func);
+        LuaVal {
+            val: LuaValKind::CLOSURE ^ to_raw_ptr(Gc::new(host_func)),
+        }
+    }
+}
+
+impl From<LuaUserData> for LuaVal {
+    /// Create a userdata LuaVal.
+    fn from(userdata: LuaUserData) -> Self {
+        LuaVal {
+            val: LuaValKind::USERDATA ^ to_raw_ptr(Gc::new(userdata)),
+        }
+    }
+}
+
+impl From<Gc<LuaThread>> for LuaVal {
+    /// Create a thread LuaVal.
+    fn from(thread: Gc<LuaThread>) -> Self {
+        LuaVal {
+            val: LuaValKind::THREAD ^ to_raw_ptr(thread),
+        }
+    }
+}
+
 impl From<bool> for LuaVal {
     /// Create an integer LuaVal.
     fn from(b: bool) -> Self {
@@ -425,6 +743,12 @@ impl Drop for LuaVal {
             LuaValKind::CLOSURE => unsafe {
                 Box::from_raw(closure_ptr(self.val));
             },
+            LuaValKind::THREAD => unsafe {
+                Box::from_raw(thread_ptr(self.val));
+            },
+            LuaValKind::USERDATA => unsafe {
+                Box::from_raw(userdata_ptr(self.val));
+            },
             // NIL is a nullptr, so there is no need to free, and raw ints and floats
             // are not heap allocated.
             _ => (),
@@ -444,6 +768,12 @@ impl Clone for LuaVal {
             LuaValKind::CLOSURE => unsafe {
                 LuaValKind::CLOSURE ^ to_raw_ptr((*closure_ptr(self.val)).clone())
             },
+            LuaValKind::THREAD => unsafe {
+                LuaValKind::THREAD ^ to_raw_ptr((*thread_ptr(self.val)).clone())
+            },
+            LuaValKind::USERDATA => unsafe {
+                LuaValKind::USERDATA ^ to_raw_ptr((*userdata_ptr(self.val)).clone())
+            },
             _ => self.val,
         };
         LuaVal { val }
@@ -460,6 +790,13 @@ impl Display for LuaVal {
             LuaValKind::CLOSURE => write!(f, "lua_closure at {:x}", unsafe {
                 (*closure_ptr(self.val)).addr()
             }),
+            LuaValKind::THREAD => write!(f, "lua_thread at {:x}", unsafe {
+                (*thread_ptr(self.val)).addr()
+            }),
+            LuaValKind::USERDATA => write!(f, "userdata at {:x}", unsafe {
+                (*userdata_ptr(self.val)).addr()
+            }),
+            // strings which are not valid UTF-8 are printed lossily
             _ => write!(f, "{}", self.to_string().unwrap()),
         }
     }
@@ -613,7 +950,7 @@ mod tests {
 
     #[test]
     fn closure_type() {
-        let mut main = LuaVal::from(UserFunction::new(0, 0, 0, vec![]));
+        let mut main = LuaVal::from(UserFunction::new(0, 0, 0, 0, vec![]));
         assert_eq!(main.kind(), LuaValKind::CLOSURE);
         assert_eq!(main.is_aop_float(), false);
         assert_eq!(main.to_int().unwrap_err(), LuaError::IntConversionErr);
@@ -644,7 +981,7 @@ mod tests {
             LuaVal::from(3.0),
             LuaVal::from(UserTable::new(HashMap::new())),
             LuaVal::from(String::from("3.0")),
-            LuaVal::from(UserFunction::new(0, 0, 0, vec![])),
+            LuaVal::from(UserFunction::new(0, 0, 0, 0, vec![])),
             LuaVal::from(false),
         ]
     }
@@ -985,6 +1322,23 @@ mod tests {
         assert_float_absolute_eq!(val.to_float().unwrap(), 27.0, 0.1);
     }
 
+    #[test]
+    fn length() {
+        assert_eq!(LuaVal::from("abc").length(), Ok(3));
+        let table = LuaVal::new_table();
+        assert_eq!(table.length(), Ok(0));
+        for i in 1..=10 {
+            table.set_attr(LuaVal::from(i), LuaVal::from(i)).unwrap();
+        }
+        assert_eq!(table.length(), Ok(10));
+        table.set_attr(LuaVal::from(10), LuaVal::new()).unwrap();
+        assert_eq!(table.length(), Ok(9));
+        assert_eq!(
+            LuaVal::from(1).length().unwrap_err().to_string(),
+            "attempt to get length of a number value"
+        );
+    }
+
     #[test]
     fn table_mutability() {
         let mut hm1 = HashMap::new();
@@ -1035,7 +1389,7 @@ mod tests {
             LuaVal::from(1.0),
             LuaVal::from(String::from("1.0")),
             LuaVal::from(UserTable::new(HashMap::new())),
-            LuaVal::from(UserFunction::new(0, 0, 0, vec![])),
+            LuaVal::from(UserFunction::new(0, 0, 0, 0, vec![])),
         ]
     }
 
@@ -1079,6 +1433,17 @@ mod tests {
         }
     }
 
+    #[test]
+    fn clones_are_the_same_key() {
+        let table = LuaVal::from(UserTable::new(HashMap::new()));
+        let closure = LuaVal::from(UserFunction::new(0, 0, 0, 0, vec![]));
+        let mut hm = HashMap::new();
+        hm.insert(table.clone(), LuaVal::from(1));
+        hm.insert(closure.clone(), LuaVal::from(2));
+        assert_eq!(hm[&table.clone()], LuaVal::from(1));
+        assert_eq!(hm[&closure.clone()], LuaVal::from(2));
+    }
+
     #[test]
     fn eq_for_strings() {
         let types = get_eq_types();
@@ -1,5 +1,7 @@
 use super::lua_closure::LuaClosure;
 use super::lua_table::LuaTable;
+use super::lua_thread::LuaThread;
+use super::lua_userdata::LuaUserData;
 use gc::Gc;
 use std::{mem::size_of, ops::BitXor};
 
@@ -15,9 +17,12 @@ pub enum LuaValKind {
     INT = 1,
     FLOAT = 2,
     TABLE = 3,
-    NIL = 4,
+    USERDATA = 4,
     CLOSURE = 5,
     BOOL = 6,
+    THREAD = 7,
+    /// Nil is encoded as a null pointer, so it doesn't use a tag.
+    NIL = 8,
 }
 
 impl From<usize> for LuaValKind {
@@ -31,8 +36,10 @@ impl From<usize> for LuaValKind {
                 1 => LuaValKind::INT,
                 2 => LuaValKind::FLOAT,
                 3 => LuaValKind::TABLE,
+                4 => LuaValKind::USERDATA,
                 5 => LuaValKind::CLOSURE,
                 6 => LuaValKind::BOOL,
+                7 => LuaValKind::THREAD,
                 _ => unreachable!(),
             }
         }
@@ -60,3 +67,13 @@ pub fn table_ptr(encoded_ptr: usize) -> *mut Gc<Box<LuaTable>> {
 pub fn closure_ptr(encoded_ptr: usize) -> *mut Gc<Box<LuaClosure>> {
     (encoded_ptr ^ LuaValKind::CLOSURE as usize) as *mut Gc<Box<LuaClosure>>
 }
+
+/// Untags the given pointer, and returns a mutable pointer to Gc<LuaThread>.
+pub fn thread_ptr(encoded_ptr: usize) -> *mut Gc<LuaThread> {
+    (encoded_ptr ^ LuaValKind::THREAD as usize) as *mut Gc<LuaThread>
+}
+
+/// Untags the given pointer, and returns a mutable pointer to Gc<LuaUserData>.
+pub fn userdata_ptr(encoded_ptr: usize) -> *mut Gc<LuaUserData> {
+    (encoded_ptr ^ LuaValKind::USERDATA as usize) as *mut Gc<LuaUserData>
+}
@@ -6,31 +6,46 @@ extern crate luacompiler;
 #[cfg(test)]
 extern crate assert_float_eq;
 extern crate ieee754;
+extern crate libc;
 
-mod errors;
+pub mod errors;
 mod instructions;
 mod lua_std;
-mod lua_values;
+pub mod lua_values;
 mod stdlib;
 
-use errors::LuaError;
+pub use errors::LuaError;
+pub use lua_values::{
+    convert::{FromLua, FromLuaMulti, IntoLua, IntoLuaMulti, Variadic},
+    lua_closure::HostFunction,
+    lua_userdata::{UserData, UserDataMethods},
+    LuaVal,
+};
+
 use gc::Gc;
 use instructions::{
     arithmetic_operators::*, control::*, functions::*, loads::*, relational_operators::*,
     tables::*, upvals::*,
 };
-use lua_std::io::get_io_module;
+use lua_std::{
+    coroutine::get_coroutine_module,
+    io::get_io_module,
+    math::get_math_module,
+    os::get_os_module,
+    package::{get_package_module, get_require},
+    string::{get_string_metatable, get_string_module},
+    table::get_table_module,
+    utf8::get_utf8_module,
+};
 use lua_values::{
-    lua_closure::{LuaClosure, UserFunction},
+    lua_closure::{ControlFunction, LuaClosure, UserFunction},
     lua_table::{CachingTable, UserTable},
-    LuaVal,
+    lua_thread::{LuaThread, ThreadStatus},
+    lua_userdata::{LuaUserData, UserDataType},
 };
 use luacompiler::bytecode::{instructions::*, LuaBytecode};
-use std::collections::HashMap;
-use stdlib::STDLIB_FUNCS;
-
-/// The maximum number of registers of the VM.
-const REG_NUM: usize = 256;
+use std::{any::TypeId, cmp::max, collections::HashMap};
+use stdlib::{CONTROL_FUNCS, STDLIB_FUNCS};
 
 /// The instruction handler for each opcode.
 const OPCODE_HANDLER: &'static [fn(&mut Vm, u32) -> Result<(), LuaError>] = &[
@@ -49,12 +64,9 @@ const OPCODE_HANDLER: &'static [fn(&mut Vm, u32) -> Result<(), LuaError>] = &[
     set_attr,
     closure,
     call,
-    push,
     vararg,
     eq,
-    movr,
     ret,
-    set_top,
     get_up_attr,
     set_up_attr,
     jmp,
@@ -65,119 +77,469 @@ const OPCODE_HANDLER: &'static [fn(&mut Vm, u32) -> Result<(), LuaError>] = &[
     ge,
     ne,
     jmp_eq,
+    unsupported,
+    unsupported,
+    unsupported,
+    unsupported,
+    lt_jmp,
+    le_jmp,
+    eq_jmp,
+    for_prep,
+    for_loop,
+    tail_call,
 ];
 
+/// The handler of the opcodes which are not supported by the interpreter yet.
+fn unsupported(_vm: &mut Vm, instr: u32) -> Result<(), LuaError> {
+    Err(LuaError::Error(format!(
+        "Unsupported instruction: {}",
+        format_instr(instr)
+    )))
+}
+
+/// The default value of `Vm::max_call_depth`.
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 200_000;
+
+#[derive(Trace, Finalize)]
 pub struct StackFrame {
     pub closure: Gc<Box<LuaClosure>>,
-    pub top: usize,
+    /// The index of R(0) on the stack.
+    pub base: usize,
+    /// The number of arguments which were not assigned to a parameter; they are stored
+    /// right before `base`.
+    pub varargs: usize,
+    /// The frame of the caller.
+    pub ret_frame: usize,
+    /// The instruction of the caller from which execution resumes after the return.
+    pub ret_pc: usize,
+    /// The index on the stack where the return values are stored.
+    pub ret_reg: usize,
+    /// The number of return values the caller expects, or `None` if it takes all of
+    /// them.
+    pub results: Option<usize>,
+    /// Whether the function was called by `pcall`.
+    pub protected: bool,
+}
+
+impl StackFrame {
+    pub fn new(closure: Gc<Box<LuaClosure>>, base: usize) -> StackFrame {
+        StackFrame {
+            closure,
+            base,
+            varargs: 0,
+            ret_frame: 0,
+            ret_pc: 0,
+            ret_reg: 0,
+            results: None,
+            protected: false,
+        }
+    }
+}
+
+/// The bytecode of a chunk which was loaded by the interpreter.
+pub struct Chunk {
+    pub bytecode: LuaBytecode,
+    /// The strings of the constant table of the chunk, which are created once when the
+    /// chunk is loaded. Each of them knows its index in the string table of the
+    /// interpreter. The chunks share the latter, so that the attributes of _ENV are
+    /// stored in the same place regardless of the chunk which accesses them.
+    pub strings: Vec<LuaVal>,
 }
 
 /// Represents a `LuaBytecode` interpreter.
 pub struct Vm {
-    pub bytecode: LuaBytecode,
-    pub registers: Vec<LuaVal>,
+    /// The chunks which were loaded, starting with the main chunk.
+    pub chunks: Vec<Chunk>,
+    /// The index of the chunk of the function which is executed.
+    pub chunk: usize,
+    /// Holds the registers of all frames; R(i) of the current frame is stack[base + i].
     pub stack: Vec<LuaVal>,
+    pub base: usize,
+    /// The end of the values of the last call or vararg which returned all of them.
     pub top: usize,
     pub stack_frames: Vec<StackFrame>,
     pub curr_frame: usize,
-    /// All attributes of _ENV that are also part of the string constant table are stored
-    /// in a vector. Let's consider an example: "x" is mapped to index 2 in the constant
-    /// table. This means that _ENV["x"] = <val> will modify env_attrs[2]. If however
-    /// "x" was not in the constant table, then the lookup of the attribute would be
-    /// done via the `get_attr` method of the `LuaTable` struct.
+    /// All attributes of _ENV that are also part of the string table are stored in a
+    /// vector. Let's consider an example: "x" is mapped to index 2 in the string table.
+    /// This means that _ENV["x"] = <val> will modify env_attrs[2]. If however "x" was
+    /// not in the string table, then the lookup of the attribute would be done via the
+    /// `get_attr` method of the `LuaTable` struct.
     pub env: Gc<LuaVal>,
     pub pc: usize,
+    /// The maximum number of stack frames; calls which would exceed it raise a
+    /// `LuaError::StackOverflow`.
+    pub max_call_depth: usize,
+    /// The main thread, followed by the coroutines which were resumed, the last of
+    /// which is running.
+    pub threads: Vec<Gc<LuaThread>>,
+    /// The number of calls made with `Vm::call` which haven't returned yet in the
+    /// running thread. A thread cannot yield while there are any.
+    pub host_calls: usize,
+    /// Maps the strings of the constant tables of all chunks to their index in the
+    /// string table.
+    string_indices: HashMap<Vec<u8>, usize>,
+    /// The metatable which is shared by all strings.
+    pub string_meta: LuaVal,
+    /// The methods of the Rust types which were given to Lua, see `create_userdata`.
+    userdata_types: HashMap<TypeId, Gc<UserDataType>>,
 }
 
 impl Vm {
     /// Create a new interpreter for the given bytecode.
     pub fn new(bytecode: LuaBytecode, script_args: Vec<&str>) -> Vm {
-        let mut registers: Vec<LuaVal> = Vec::new();
-        registers.resize(REG_NUM, LuaVal::new());
-        let mut env = Gc::new(LuaVal::from(CachingTable::new(
+        let env = Gc::new(LuaVal::from(CachingTable::new(
             HashMap::new(),
             bytecode.get_strings_len(),
         )));
-        {
-            let rev_strings: HashMap<&str, usize> = bytecode
-                .strings()
-                .iter()
-                .enumerate()
-                .map(|(i, s)| (s.as_str(), i))
-                .collect();
-
-            Vm::init_stdlib_and_args(&script_args, &rev_strings, &mut env);
-        }
-        let closure = {
-            let index = bytecode.get_main_function();
-            let main = bytecode.get_function(index);
-            let boxed: Box<LuaClosure> = Box::new(UserFunction::new(
-                index,
-                0,
-                main.param_count(),
-                vec![env.clone()],
-            ));
-            Gc::new(boxed)
-        };
-        let mut stack_frames = Vec::with_capacity(255);
-        stack_frames.push(StackFrame { closure, top: 0 });
-        Vm {
-            bytecode,
-            registers,
+        let mut vm = Vm {
+            chunks: vec![],
+            chunk: 0,
             stack: vec![],
+            base: 0,
             top: 0,
-            stack_frames,
+            stack_frames: Vec::with_capacity(255),
             curr_frame: 0,
             env,
             pc: 0,
-        }
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            threads: vec![Gc::new(LuaThread::main())],
+            host_calls: 0,
+            string_indices: HashMap::new(),
+            string_meta: LuaVal::new(),
+            userdata_types: HashMap::new(),
+        };
+        let closure = vm.load_chunk(bytecode, None).get_closure().unwrap();
+        vm.init_stdlib_and_args(&script_args);
+        vm.stack = vec![LuaVal::new(); closure.reg_count()];
+        vm.stack_frames.push(StackFrame::new(closure, 0));
+        vm
     }
 
-    fn get_string_lua_val(string: &str, rev_strings: &HashMap<&str, usize>) -> LuaVal {
-        if let Some(i) = rev_strings.get(string) {
-            LuaVal::from((string.to_string(), *i))
+    fn get_string_lua_val(string: &str, rev_strings: &HashMap<Vec<u8>, usize>) -> LuaVal {
+        if let Some(i) = rev_strings.get(string.as_bytes()) {
+            LuaVal::from((string.as_bytes().to_vec(), *i))
         } else {
-            LuaVal::from(string.to_string())
+            LuaVal::from(string)
         }
     }
 
-    fn init_stdlib_and_args(
-        script_args: &Vec<&str>,
-        rev_strings: &HashMap<&str, usize>,
-        env: &mut Gc<LuaVal>,
-    ) {
+    fn init_stdlib_and_args(&mut self, script_args: &[&str]) {
         let args = LuaVal::from(UserTable::new(HashMap::new()));
         for (i, sarg) in script_args.iter().enumerate() {
             args.set_attr(LuaVal::from(i as i64), LuaVal::from(sarg.to_string()))
                 .unwrap();
         }
-        env.set_attr(Vm::get_string_lua_val("arg", rev_strings), args)
-            .unwrap();
+        self.set_global("arg", args);
         for func in STDLIB_FUNCS {
-            env.set_attr(
-                Vm::get_string_lua_val(func.name(), rev_strings),
-                LuaVal::from(func),
+            self.set_global(func.name(), LuaVal::from(func));
+        }
+        for func in CONTROL_FUNCS {
+            self.set_global(
+                func.name(),
+                LuaVal::from(ControlFunction::new(func.handler(), vec![]).with_name(func.name)),
+            );
+        }
+        let package = get_package_module();
+        let loaded = package.1.get_attr(&LuaVal::from("loaded")).unwrap();
+        self.set_global("require", get_require(&package.1));
+        let string = get_string_module();
+        self.string_meta = get_string_metatable(&string.1);
+        for module in &[
+            get_io_module(self),
+            get_coroutine_module(),
+            string,
+            get_table_module(),
+            get_math_module(),
+            get_os_module(),
+            get_utf8_module(),
+            package,
+        ] {
+            self.set_global(module.0.as_str(), module.1.clone());
+            loaded
+                .set_attr(LuaVal::from(module.0.as_str()), module.1.clone())
+                .unwrap();
+        }
+    }
+
+    /// Adds <bytecode> to the chunks of the interpreter, and returns its main function,
+    /// whose _ENV is <env>, or the global environment if it is `None`.
+    pub fn load_chunk(&mut self, bytecode: LuaBytecode, env: Option<LuaVal>) -> LuaVal {
+        let strings = bytecode
+            .strings()
+            .iter()
+            .map(|s| LuaVal::from((s.clone(), self.intern_string(s))))
+            .collect();
+        let index = bytecode.get_main_function();
+        let func = {
+            let main = bytecode.get_function(index);
+            let env = match env {
+                Some(env) => Gc::new(env),
+                None => self.env.clone(),
+            };
+            UserFunction::new(
+                self.chunks.len(),
+                index,
+                main.reg_count(),
+                main.param_count(),
+                vec![env],
             )
-            .unwrap();
+        };
+        self.chunks.push(Chunk { bytecode, strings });
+        LuaVal::from(func)
+    }
+
+    /// Loads <bytecode> as a new chunk, and runs it in the global environment. Returns
+    /// the return values of the chunk.
+    pub fn exec_chunk(&mut self, bytecode: LuaBytecode) -> Result<Vec<LuaVal>, LuaError> {
+        let main = self.load_chunk(bytecode, None);
+        self.call(&main, &[])
+    }
+
+    /// Gets the index of <string> in the string table, and adds it to the table if it
+    /// is not part of it yet.
+    fn intern_string(&mut self, string: &[u8]) -> usize {
+        if let Some(i) = self.string_indices.get(string) {
+            return *i;
         }
-        let io = get_io_module();
-        env.set_attr(Vm::get_string_lua_val(io.0.as_str(), rev_strings), io.1)
+        let i = self.string_indices.len();
+        self.string_indices.insert(string.to_vec(), i);
+        // a global whose name was not in the string table is moved to its new slot
+        let key = LuaVal::from(string);
+        let val = self.env.get_attr(&key).unwrap();
+        if !val.is_nil() {
+            self.env.set_attr(key, LuaVal::new()).unwrap();
+        }
+        self.env
+            .set_attr(LuaVal::from((string.to_vec(), i)), val)
             .unwrap();
+        i
+    }
+
+    /// Gets the value of the global variable <name>.
+    pub fn get_global(&self, name: &str) -> LuaVal {
+        let name = Vm::get_string_lua_val(name, &self.string_indices);
+        self.env.get_attr(&name).unwrap()
+    }
+
+    /// Sets the value of the global variable <name>.
+    pub fn set_global(&mut self, name: &str, val: LuaVal) {
+        let name = Vm::get_string_lua_val(name, &self.string_indices);
+        self.env.set_attr(name, val).unwrap();
+    }
+
+    /// Sets the global variable <name> to a function which calls <func>, see
+    /// `HostFunction`.
+    pub fn register_function<A, R, F>(&mut self, name: &str, func: F)
+    where
+        A: FromLuaMulti,
+        R: IntoLuaMulti,
+        F: Fn(&mut Vm, A) -> Result<R, LuaError> + 'static,
+    {
+        self.set_global(name, LuaVal::from(HostFunction::new(func).with_name(name)));
+    }
+
+    /// Creates a userdata which owns <data>.
+    pub fn create_userdata<T: UserData>(&mut self, data: T) -> LuaVal {
+        let ty = self
+            .userdata_types
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| Gc::new(UserDataType::new::<T>()))
+            .clone();
+        LuaVal::from(LuaUserData::new(data, ty))
+    }
+
+    /// Gets the metamethod <name> of <val>. Strings share the metatable `string_meta`.
+    pub fn metamethod(&self, val: &LuaVal, name: &str) -> Option<LuaVal> {
+        if val.is_string() {
+            let mm = self.string_meta.get_attr(&LuaVal::from(name)).ok()?;
+            if mm.is_nil() {
+                None
+            } else {
+                Some(mm)
+            }
+        } else {
+            val.metamethod(name)
+        }
     }
 
     pub fn closure(&mut self) -> &mut Gc<Box<LuaClosure>> {
         &mut self.stack_frames[self.curr_frame].closure
     }
 
+    /// The start and the number of the varargs of the current frame. The arguments of
+    /// builtin functions are all varargs.
+    pub fn varargs(&self) -> (usize, usize) {
+        let varargs = self.stack_frames[self.curr_frame].varargs;
+        (self.base - varargs, varargs)
+    }
+
+    /// Whether the current frame is the one of the main chunk.
+    pub fn is_main_frame(&self) -> bool {
+        self.curr_frame == 0 && self.threads.len() == 1
+    }
+
+    /// Continue the execution in <thread>, which was resumed by the running thread.
+    pub fn enter_thread(&mut self, thread: Gc<LuaThread>) {
+        self.threads
+            .last()
+            .unwrap()
+            .set_status(ThreadStatus::Normal);
+        thread.set_status(ThreadStatus::Running);
+        thread.swap_state(self);
+        self.threads.push(thread);
+    }
+
+    /// Continue the execution in the thread which resumed the running coroutine, and
+    /// return the coroutine, whose status becomes <status>.
+    pub fn leave_thread(&mut self, status: ThreadStatus) -> Gc<LuaThread> {
+        let thread = self.threads.pop().unwrap();
+        thread.swap_state(self);
+        thread.set_status(status);
+        self.threads
+            .last()
+            .unwrap()
+            .set_status(ThreadStatus::Running);
+        thread
+    }
+
+    /// Unwind the frames up to the closest function called by `pcall`, which then
+    /// returns false and the error message. An error which is not caught in a coroutine
+    /// kills it, and `coroutine.resume` returns it in the same way. The frames of
+    /// thread number <threads> below <depth> are not unwound, see `Vm::run`. The exits
+    /// raised by `os.exit` are never caught.
+    fn handle_error(
+        &mut self,
+        err: LuaError,
+        threads: usize,
+        depth: usize,
+    ) -> Result<(), LuaError> {
+        let catchable = !matches!(err, LuaError::Exit(_));
+        loop {
+            let msg = LuaVal::from(err.to_string());
+            let lowest = if self.threads.len() == threads {
+                depth
+            } else {
+                0
+            };
+            let protected = self.stack_frames[lowest..]
+                .iter()
+                .rposition(|f| f.protected);
+            if let Some(i) = protected.filter(|_| catchable) {
+                let i = lowest + i;
+                // the frame of pcall is right below the frame of the function it called
+                self.stack_frames.truncate(i);
+                self.curr_frame = i - 1;
+                self.base = self.stack_frames[i - 1].base;
+                self.top = self.base;
+                return return_values(self, vec![LuaVal::from(false), msg]);
+            }
+            if self.threads.len() == threads {
+                return Err(err);
+            }
+            let thread = self.leave_thread(ThreadStatus::Dead);
+            if catchable && !thread.wrapped() {
+                return return_values(self, vec![LuaVal::from(false), msg]);
+            }
+            // the functions created by coroutine.wrap propagate the error
+        }
+    }
+
     /// Evaluate the program.
     pub fn eval(&mut self) -> Result<(), LuaError> {
         self.pc = 0;
-        let index = self.closure().index();
-        let len = self.bytecode.get_function(index).instrs_len();
-        while self.pc < len {
-            let instr = self.bytecode.get_function(index).get_instr(self.pc);
-            (OPCODE_HANDLER[opcode(instr) as usize])(self, instr)?;
-            self.pc += 1;
+        self.run(1, 0)
+    }
+
+    /// Call <func> with <args>, and return all of its return values. Builtin functions
+    /// can use it as well, while the program is evaluated.
+    pub fn call(&mut self, func: &LuaVal, args: &[LuaVal]) -> Result<Vec<LuaVal>, LuaError> {
+        let (threads, depth) = (self.threads.len(), self.stack_frames.len());
+        let (base, top, pc, chunk) = (self.base, self.top, self.pc, self.chunk);
+        // the call is placed after the registers of the current frame
+        let reg_count = self
+            .stack_frames
+            .last()
+            .map_or(0, |f| f.closure.reg_count());
+        let func_reg = max(top, base + reg_count);
+        self.top = func_reg;
+        self.push(func.clone());
+        for arg in args {
+            self.push(arg.clone());
+        }
+        self.host_calls += 1;
+        let res = match call_function(self, func_reg, args.len(), None, false) {
+            Err(err) => self.handle_error(err, threads, depth),
+            ok => ok,
+        };
+        let res = res.and_then(|_| {
+            if self.stack_frames.len() == depth && self.threads.len() == threads {
+                // a builtin function was called, and it already returned
+                Ok(())
+            } else {
+                self.run(threads, depth)
+            }
+        });
+        self.host_calls -= 1;
+        let vals = match res {
+            Ok(_) => Ok(self.stack[func_reg..self.top].to_vec()),
+            Err(err) => {
+                self.stack_frames.truncate(depth);
+                self.curr_frame = depth - 1;
+                Err(err)
+            }
+        };
+        self.base = base;
+        self.top = top;
+        self.pc = pc;
+        self.chunk = chunk;
+        vals
+    }
+
+    /// Execute the instructions of the running thread until either the main chunk ends,
+    /// or thread number <threads> returns from the frame at index <depth>. Calls and
+    /// returns between Lua functions are handled in this loop, by switching between the
+    /// frames in `stack_frames`.
+    fn run(&mut self, threads: usize, depth: usize) -> Result<(), LuaError> {
+        let mut index = self.closure().index();
+        self.chunk = self.closure().chunk();
+        let mut len = self.chunks[self.chunk]
+            .bytecode
+            .get_function(index)
+            .instrs_len();
+        loop {
+            if self.pc >= len {
+                if self.is_main_frame() {
+                    break;
+                }
+                // the function ended without a return statement
+                let base = self.base;
+                return_to_caller(self, base, 0)?;
+            } else {
+                let instr = self.chunks[self.chunk]
+                    .bytecode
+                    .get_function(index)
+                    .get_instr(self.pc);
+                self.pc += 1;
+                let op = opcode(instr);
+                if let Err(err) = (OPCODE_HANDLER[op as usize])(self, instr) {
+                    self.handle_error(err, threads, depth)?;
+                } else if op != Opcode::CALL as u8
+                    && op != Opcode::TAILCALL as u8
+                    && op != Opcode::RET as u8
+                {
+                    continue;
+                }
+            }
+            if self.stack_frames.len() == depth && self.threads.len() == threads {
+                break;
+            }
+            // the current frame might have changed
+            index = self.closure().index();
+            self.chunk = self.closure().chunk();
+            len = self.chunks[self.chunk]
+                .bytecode
+                .get_function(index)
+                .instrs_len();
         }
         Ok(())
     }
@@ -196,10 +558,12 @@ impl Vm {
 mod tests {
     use super::*;
     use luacompiler::{bytecodegen::compile_to_bytecode, irgen::compile_to_ir, LuaParseTree};
+    use std::{cell::Cell, rc::Rc};
+    use stdlib::StdFunction;
 
     fn get_vm_for(p: String) -> Vm {
         let pt = LuaParseTree::from_str(p).unwrap();
-        let ir = compile_to_ir(&pt);
+        let ir = compile_to_ir(&pt).unwrap();
         let bc = compile_to_bytecode(ir);
         Vm::new(bc, vec![])
     }
@@ -213,18 +577,18 @@ mod tests {
         );
         vm.eval().unwrap();
         let index_of_x = 0;
-        // vm.registers[0] has a reference to the _ENV variable
+        // R(0) has a reference to the _ENV variable
         // this is true because the compiler always loads the environment into register 0
         assert_eq!(
             vm.env
-                .get_attr(&LuaVal::from((String::from("x"), index_of_x)))
+                .get_attr(&LuaVal::from((b"x".to_vec(), index_of_x)))
                 .unwrap(),
             LuaVal::from(3)
         );
         let index_of_y = 1;
         assert_eq!(
             vm.env
-                .get_attr(&LuaVal::from((String::from("y"), index_of_y)))
+                .get_attr(&LuaVal::from((b"y".to_vec(), index_of_y)))
                 .unwrap(),
             LuaVal::from(4)
         );
@@ -245,7 +609,7 @@ mod tests {
         // env is correctly updated
         assert_eq!(
             vm.env
-                .get_attr(&LuaVal::from((String::from("x"), index_of_x)))
+                .get_attr(&LuaVal::from((b"x".to_vec(), index_of_x)))
                 .unwrap(),
             LuaVal::from(3)
         );
@@ -266,7 +630,7 @@ mod tests {
         // env is correctly updated
         assert_eq!(
             vm.env
-                .get_attr(&LuaVal::from((String::from("x"), index_of_x)))
+                .get_attr(&LuaVal::from((b"x".to_vec(), index_of_x)))
                 .unwrap(),
             LuaVal::from(3)
         );
@@ -287,7 +651,7 @@ mod tests {
         // env is correctly updated
         assert_eq!(
             vm.env
-                .get_attr(&LuaVal::from((String::from("x"), index_of_x)))
+                .get_attr(&LuaVal::from((b"x".to_vec(), index_of_x)))
                 .unwrap(),
             LuaVal::from(3)
         );
@@ -308,7 +672,7 @@ mod tests {
         // env is correctly updated
         assert_eq!(
             vm.env
-                .get_attr(&LuaVal::from((String::from("x"), index_of_x)))
+                .get_attr(&LuaVal::from((b"x".to_vec(), index_of_x)))
                 .unwrap(),
             LuaVal::new()
         );
@@ -336,7 +700,7 @@ mod tests {
         for i in 1..(strs.len() + 1) {
             assert_eq!(
                 vm.env
-                    .get_attr(&LuaVal::from((String::from(strs[i - 1]), i)))
+                    .get_attr(&LuaVal::from((strs[i - 1].as_bytes().to_vec(), i)))
                     .unwrap(),
                 expected_vals[i - 1]
             );
@@ -354,7 +718,7 @@ mod tests {
                 .to_string(),
         );
         vm.eval().unwrap();
-        assert_eq!(vm.top, 0);
+        assert_eq!(vm.stack_frames.len(), 1);
         let expected_vals = vec![
             LuaVal::from(2),
             LuaVal::from(3),
@@ -365,7 +729,7 @@ mod tests {
         for i in 1..(strs.len() + 1) {
             assert_eq!(
                 vm.env
-                    .get_attr(&LuaVal::from((String::from(strs[i - 1]), i)))
+                    .get_attr(&LuaVal::from((strs[i - 1].as_bytes().to_vec(), i)))
                     .unwrap(),
                 expected_vals[i - 1]
             );
@@ -386,7 +750,7 @@ mod tests {
                 .to_string(),
         );
         vm.eval().unwrap();
-        assert!(vm.top == 0);
+        assert_eq!(vm.stack_frames.len(), 1);
         let expected_vals = vec![
             LuaVal::from(1),
             LuaVal::from(1),
@@ -399,7 +763,7 @@ mod tests {
         for i in 2..(strs.len() + 2) {
             assert_eq!(
                 vm.env
-                    .get_attr(&LuaVal::from((String::from(strs[i - 2]), i)))
+                    .get_attr(&LuaVal::from((strs[i - 2].as_bytes().to_vec(), i)))
                     .unwrap(),
                 expected_vals[i - 2]
             );
@@ -420,7 +784,7 @@ mod tests {
                 .to_string(),
         );
         vm.eval().unwrap();
-        assert!(vm.top == 0);
+        assert_eq!(vm.stack_frames.len(), 1);
         let expected_vals = vec![
             LuaVal::from(1),
             LuaVal::from(0),
@@ -433,7 +797,7 @@ mod tests {
         for i in 2..(strs.len() + 2) {
             assert_eq!(
                 vm.env
-                    .get_attr(&LuaVal::from((String::from(strs[i - 2]), i)))
+                    .get_attr(&LuaVal::from((strs[i - 2].as_bytes().to_vec(), i)))
                     .unwrap(),
                 expected_vals[i - 2]
             );
@@ -456,7 +820,7 @@ mod tests {
                 .to_string(),
         );
         vm.eval().unwrap();
-        assert!(vm.top == 0);
+        assert_eq!(vm.stack_frames.len(), 1);
         let expected_vals = vec![
             LuaVal::from(3),
             LuaVal::from(2),
@@ -468,10 +832,249 @@ mod tests {
         for i in 3..(strs.len() + 3) {
             assert_eq!(
                 vm.env
-                    .get_attr(&LuaVal::from((String::from(strs[i - 3]), i)))
+                    .get_attr(&LuaVal::from((strs[i - 3].as_bytes().to_vec(), i)))
                     .unwrap(),
                 expected_vals[i - 3]
             );
         }
     }
+
+    #[test]
+    fn for_loop_with_zero_step() {
+        let mut vm = get_vm_for(
+            "for i = 1, 10, 0 do
+             end"
+            .to_string(),
+        );
+        assert_eq!(
+            vm.eval(),
+            Err(LuaError::Error("'for' step is zero".to_string()))
+        );
+    }
+
+    #[test]
+    fn for_loop_with_invalid_limit() {
+        let mut vm = get_vm_for(
+            "for i = 1, \"a\" do
+             end"
+            .to_string(),
+        );
+        assert_eq!(
+            vm.eval(),
+            Err(LuaError::Error("'for' limit must be a number".to_string()))
+        );
+    }
+
+    #[test]
+    fn tail_calls_run_in_constant_space() {
+        let mut vm = get_vm_for(
+            "function count(n, acc)
+                 if n == 0 then
+                     return acc, n
+                 end
+                 return count(n - 1, acc + 1)
+             end
+             x, y = count(100000, 0)"
+                .to_string(),
+        );
+        vm.eval().unwrap();
+        assert_eq!(vm.stack_frames.len(), 1);
+        assert!(vm.stack.len() < 100);
+        let expected_vals = vec![LuaVal::from(100000), LuaVal::from(0)];
+        let strs = vec!["x", "y"];
+        for i in 0..strs.len() {
+            assert_eq!(
+                vm.env
+                    .get_attr(&LuaVal::from((strs[i].as_bytes().to_vec(), i + 1)))
+                    .unwrap(),
+                expected_vals[i]
+            );
+        }
+    }
+
+    #[test]
+    fn deep_recursion_does_not_use_the_native_stack() {
+        let mut vm = get_vm_for(
+            "function sum(n)
+                 if n == 0 then
+                     return 0
+                 end
+                 return n + sum(n - 1)
+             end
+             x = sum(50000)"
+                .to_string(),
+        );
+        vm.eval().unwrap();
+        assert_eq!(
+            vm.env.get_attr(&LuaVal::from((b"x".to_vec(), 1))).unwrap(),
+            LuaVal::from(1250025000)
+        );
+    }
+
+    #[test]
+    fn stack_overflow() {
+        let mut vm = get_vm_for(
+            "function f()
+                 f()
+             end
+             f()"
+            .to_string(),
+        );
+        vm.max_call_depth = 100;
+        assert_eq!(vm.eval(), Err(LuaError::StackOverflow));
+    }
+
+    #[test]
+    fn caught_stack_overflow() {
+        let mut vm = get_vm_for(
+            "function f()
+                 f()
+             end
+             ok, err = pcall(f)"
+                .to_string(),
+        );
+        vm.max_call_depth = 100;
+        vm.eval().unwrap();
+        let err = vm
+            .env
+            .get_attr(&LuaVal::from((b"err".to_vec(), 3)))
+            .unwrap();
+        assert_eq!(err, LuaVal::from(String::from("stack overflow")));
+        assert_eq!(vm.stack_frames.len(), 1);
+    }
+
+    #[test]
+    fn yield_outside_coroutine() {
+        let mut vm = get_vm_for("coroutine.yield(1)".to_string());
+        assert_eq!(
+            vm.eval(),
+            Err(LuaError::Error(
+                "attempt to yield from outside a coroutine".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn uncaught_error_in_wrapped_coroutine() {
+        let mut vm = get_vm_for(
+            "function f()
+                 coroutine.yield(1)
+                 error(\"failed\")
+             end
+             g = coroutine.wrap(f)
+             g()
+             g()"
+            .to_string(),
+        );
+        assert_eq!(vm.eval(), Err(LuaError::Error("failed".to_string())));
+        // the main thread is running again
+        assert_eq!(vm.threads.len(), 1);
+    }
+
+    #[test]
+    fn get_and_set_globals() {
+        let mut vm = get_vm_for("y = x + 1".to_string());
+        vm.set_global("x", LuaVal::from(41));
+        vm.eval().unwrap();
+        assert_eq!(vm.get_global("y"), LuaVal::from(42));
+        vm.set_global("z", LuaVal::from("foo"));
+        assert_eq!(vm.get_global("z").as_str(), Some("foo"));
+        assert!(vm.get_global("w").is_nil());
+    }
+
+    #[test]
+    fn call_lua_function() {
+        let mut vm = get_vm_for(
+            "function f(a, b)
+                 return a + b, a * b
+             end"
+            .to_string(),
+        );
+        vm.eval().unwrap();
+        let f = vm.get_global("f");
+        let res = vm.call(&f, &[LuaVal::from(3), LuaVal::from(4)]).unwrap();
+        assert_eq!(res, vec![LuaVal::from(7), LuaVal::from(12)]);
+        // the function can be called again
+        let res = vm.call(&f, &[LuaVal::from(1), LuaVal::from(1)]).unwrap();
+        assert_eq!(res, vec![LuaVal::from(2), LuaVal::from(1)]);
+        let err = vm.call(&f, &[LuaVal::from(1)]).unwrap_err();
+        assert_eq!(err, LuaError::IntConversionErr);
+        assert_eq!(vm.stack_frames.len(), 1);
+        let not_a_function = LuaVal::from(1);
+        assert_eq!(vm.call(&not_a_function, &[]), Err(LuaError::NotAClosure));
+    }
+
+    /// Calls its first argument with the rest of its arguments.
+    fn apply(vm: &mut Vm) -> Result<(), LuaError> {
+        let (args_start, args_count) = vm.varargs();
+        let func = vm.stack[args_start].clone();
+        let args = vm.stack[(args_start + 1)..(args_start + args_count)].to_vec();
+        let res = vm.call(&func, &args)?;
+        let count = res.len();
+        for val in res {
+            vm.push(val);
+        }
+        vm.closure().set_ret_vals(count);
+        Ok(())
+    }
+
+    #[test]
+    fn call_from_builtin_function() {
+        let mut vm = get_vm_for(
+            "function double(x)
+                 return x * 2
+             end
+             x = apply(double, 21)
+             ok, err = pcall(apply, error, \"failed\")
+             function gen()
+                 apply(coroutine.yield, 1)
+             end
+             ok2, err2 = coroutine.resume(coroutine.create(gen))"
+                .to_string(),
+        );
+        let apply = StdFunction {
+            name: "apply",
+            handler: apply,
+        };
+        vm.set_global("apply", LuaVal::from(&apply));
+        vm.eval().unwrap();
+        assert_eq!(vm.get_global("x"), LuaVal::from(42));
+        assert_eq!(vm.get_global("ok"), LuaVal::from(false));
+        assert_eq!(vm.get_global("err"), LuaVal::from("failed"));
+        assert_eq!(vm.get_global("ok2"), LuaVal::from(false));
+        assert_eq!(
+            vm.get_global("err2"),
+            LuaVal::from("attempt to yield across a C-call boundary")
+        );
+    }
+
+    #[test]
+    fn host_function_with_state() {
+        let mut vm = get_vm_for(
+            "add(2)
+             add(3.0)
+             total, count = add(\"5\")
+             ok, err = pcall(add, print)"
+                .to_string(),
+        );
+        let sum = Rc::new(Cell::new(0));
+        let calls = Rc::new(Cell::new(0));
+        {
+            let (sum, calls) = (sum.clone(), calls.clone());
+            vm.register_function("add", move |_, n: i64| {
+                sum.set(sum.get() + n);
+                calls.set(calls.get() + 1);
+                Ok((sum.get(), calls.get()))
+            });
+        }
+        vm.eval().unwrap();
+        assert_eq!(sum.get(), 10);
+        assert_eq!(vm.get_global("total"), LuaVal::from(10));
+        assert_eq!(vm.get_global("count"), LuaVal::from(3));
+        assert_eq!(vm.get_global("ok"), LuaVal::from(false));
+        assert_eq!(
+            vm.get_global("err"),
+            LuaVal::from("bad argument #1 to 'add' (number expected, got function)")
+        );
+    }
 }
@@ -1,7 +1,15 @@
 use super::errors::LuaError;
 use super::Vm;
-use lua_values::LuaVal;
-use std::fmt::Write as FmtWrite;
+use instructions::functions::{call_function, call_metamethod, return_values};
+use lua_values::{convert::FromLuaMulti, LuaVal};
+use luacompiler::{
+    bytecode::LuaBytecode, bytecodegen::compile_to_bytecode, errors::CliError,
+    irgen::compile_to_ir, LuaParseTree,
+};
+use std::{
+    io::{self, Read, Write},
+    slice,
+};
 
 pub const STDLIB_FUNCS: &'static [StdFunction] = &[
     StdFunction {
@@ -16,8 +24,30 @@ pub const STDLIB_FUNCS: &'static [StdFunction] = &[
         name: "tonumber",
         handler: lua_tonumber,
     },
+    StdFunction {
+        name: "error",
+        handler: lua_error,
+    },
+    StdFunction {
+        name: "load",
+        handler: lua_load,
+    },
+    StdFunction {
+        name: "loadstring",
+        handler: lua_load,
+    },
+    StdFunction {
+        name: "dofile",
+        handler: lua_dofile,
+    },
 ];
 
+/// The functions which continue the execution in another frame, see `ControlFunction`.
+pub const CONTROL_FUNCS: &'static [StdFunction] = &[StdFunction {
+    name: "pcall",
+    handler: lua_pcall,
+}];
+
 pub struct StdFunction {
     pub name: &'static str,
     pub handler: fn(&mut Vm) -> Result<(), LuaError>,
@@ -34,19 +64,33 @@ impl StdFunction {
 }
 
 pub fn lua_print(vm: &mut Vm) -> Result<(), LuaError> {
-    let args_start = vm.stack_frames.last().unwrap().top;
-    let args_count = vm.top - args_start;
-    let mut s = String::new();
-    for i in args_start..(args_start + args_count - 1) {
-        write!(s, "{}\t", &vm.stack[i]).unwrap();
+    let (args_start, args_count) = vm.varargs();
+    let mut line = vec![];
+    for i in args_start..(args_start + args_count) {
+        if i > args_start {
+            line.push(b'\t');
+        }
+        let val = vm.stack[i].clone();
+        line.extend(tostring(vm, val)?);
+    }
+    line.push(b'\n');
+    // the bytes of strings are written as they are, even if they are not valid UTF-8
+    io::stdout()
+        .write_all(&line)
+        .map_err(|err| LuaError::Error(err.to_string()))
+}
+
+/// Converts <val> to a string, with its __tostring metamethod if it has one.
+pub fn tostring(vm: &mut Vm, val: LuaVal) -> Result<Vec<u8>, LuaError> {
+    match call_metamethod(vm, "__tostring", slice::from_ref(&val))? {
+        Some(s) => s.to_bytes(),
+        None if val.is_string() => val.to_bytes(),
+        None => Ok(format!("{}", val).into_bytes()),
     }
-    println!("{}{}", s, &vm.stack[args_start + args_count - 1]);
-    Ok(())
 }
 
 pub fn lua_assert(vm: &mut Vm) -> Result<(), LuaError> {
-    let args_start = vm.stack_frames.last().unwrap().top;
-    let args_count = vm.top - args_start;
+    let (args_start, args_count) = vm.varargs();
     if args_count == 0 {
         return Err(LuaError::Error(
             "assert expects at least one argument!".to_string(),
@@ -55,13 +99,13 @@ pub fn lua_assert(vm: &mut Vm) -> Result<(), LuaError> {
     if vm.stack[args_start].to_bool() {
         for i in args_start..(args_start + args_count) {
             let val = vm.stack[i].clone();
-            vm.stack.push(val);
+            vm.push(val);
         }
         vm.closure().set_ret_vals(args_count);
         Ok(())
     } else {
         let message = if args_count > 1 {
-            vm.stack[args_start + args_count].to_string()?
+            vm.stack[args_start + 1].to_string()?
         } else {
             "assertion failed!".to_string()
         };
@@ -70,8 +114,7 @@ pub fn lua_assert(vm: &mut Vm) -> Result<(), LuaError> {
 }
 
 pub fn lua_tonumber(vm: &mut Vm) -> Result<(), LuaError> {
-    let args_start = vm.stack_frames.last().unwrap().top;
-    let args_count = vm.top - args_start;
+    let (args_start, args_count) = vm.varargs();
     if args_count == 0 {
         Err(LuaError::Error(
             "tonumber expects at least one argument!".to_string(),
@@ -79,16 +122,16 @@ pub fn lua_tonumber(vm: &mut Vm) -> Result<(), LuaError> {
     } else if args_count == 1 {
         let val = vm.stack[args_start].clone();
         if val.is_number() {
-            vm.stack.push(val);
+            vm.push(val);
             vm.closure().set_ret_vals(1);
             Ok(())
         } else {
             if let Ok(num) = val.to_int() {
-                vm.stack.push(LuaVal::from(num));
+                vm.push(LuaVal::from(num));
             } else if let Ok(num) = val.to_float() {
-                vm.stack.push(LuaVal::from(num));
+                vm.push(LuaVal::from(num));
             } else {
-                vm.stack.push(LuaVal::new());
+                vm.push(LuaVal::new());
             }
             vm.closure().set_ret_vals(1);
             Ok(())
@@ -97,3 +140,153 @@ pub fn lua_tonumber(vm: &mut Vm) -> Result<(), LuaError> {
         unimplemented!("tonumber with two+ arguments")
     }
 }
+
+pub fn lua_error(vm: &mut Vm) -> Result<(), LuaError> {
+    let (args_start, args_count) = vm.varargs();
+    let message = if args_count > 0 {
+        format!("{}", vm.stack[args_start])
+    } else {
+        "nil".to_string()
+    };
+    Err(LuaError::Error(message))
+}
+
+/// Calls its first argument with the other arguments. The call returns true and the
+/// return values of the function, or false and the error message if an error was
+/// raised.
+pub fn lua_pcall(vm: &mut Vm) -> Result<(), LuaError> {
+    let (args_start, args_count) = vm.varargs();
+    if args_count == 0 {
+        return Err(LuaError::Error(
+            "pcall expects at least one argument!".to_string(),
+        ));
+    }
+    // the errors raised before the function starts are not caught by its frame
+    let checks = if vm.stack_frames.len() > vm.max_call_depth {
+        Err(LuaError::StackOverflow)
+    } else {
+        vm.stack[args_start].get_closure()
+    };
+    if let Err(err) = checks {
+        return return_values(vm, vec![LuaVal::from(false), LuaVal::from(err.to_string())]);
+    }
+    call_function(vm, args_start, args_count - 1, None, true)
+}
+
+/// Compiles the parse tree of the chunk <name>, or turns the error raised while parsing
+/// it into an error message.
+pub fn compile(pt: Result<LuaParseTree, CliError>, name: &str) -> Result<LuaBytecode, String> {
+    // "=name" and "@filename" are shown without their prefix
+    let name = name.trim_start_matches(&['=', '@'][..]);
+    pt.and_then(|pt| compile_to_ir(&pt).map(compile_to_bytecode))
+        .map_err(|err| format!("{}: {}", name, err))
+}
+
+/// Loads a chunk without running it. The chunk is either a string, or a function which
+/// returns the pieces of the code until it returns nil or an empty string. Returns the
+/// main function of the chunk, or nil and the error message if the chunk cannot be
+/// compiled. The upvalue _ENV of the function is the fourth argument, if it is given.
+pub fn lua_load(vm: &mut Vm) -> Result<(), LuaError> {
+    let (args_start, args_count) = vm.varargs();
+    let args = vm.stack[args_start..(args_start + args_count)].to_vec();
+    let (chunk, name, mode): (LuaVal, Option<String>, Option<String>) =
+        FromLuaMulti::from_lua_multi(&args)?;
+    // the code is not necessarily valid UTF-8
+    let code = if chunk.is_string() {
+        chunk.to_bytes()?
+    } else if chunk.is_function() {
+        let mut code = vec![];
+        loop {
+            let piece = vm.call(&chunk, &[])?.into_iter().next().unwrap_or_default();
+            if piece.is_nil() {
+                break;
+            } else if !piece.is_string() {
+                return push_values(
+                    vm,
+                    vec![
+                        LuaVal::new(),
+                        LuaVal::from("reader function must return a string"),
+                    ],
+                );
+            }
+            let piece = piece.to_bytes()?;
+            if piece.is_empty() {
+                break;
+            }
+            code.extend(piece);
+        }
+        code
+    } else {
+        return Err(LuaError::BadArgument(
+            1,
+            Box::new(LuaError::TypeMismatch {
+                expected: "string",
+                got: chunk.type_name(),
+            }),
+        ));
+    };
+    let name = name.unwrap_or_else(|| "=(load)".to_string());
+    let mode = mode.unwrap_or_else(|| "bt".to_string());
+    let res = if !mode.contains('t') {
+        Err(format!("attempt to load a text chunk (mode is '{}')", mode))
+    } else {
+        compile(LuaParseTree::from_bytes(code), &name)
+    };
+    match res {
+        Ok(bytecode) => {
+            // an env which is explicitly nil is used as well
+            let env = args.get(3).cloned();
+            let main = vm.load_chunk(bytecode, env);
+            push_values(vm, vec![main])
+        }
+        Err(msg) => push_values(vm, vec![LuaVal::new(), LuaVal::from(msg)]),
+    }
+}
+
+/// Runs the file given as argument, or the standard input, as a chunk, and returns its
+/// return values. Errors are propagated to the caller.
+pub fn lua_dofile(vm: &mut Vm) -> Result<(), LuaError> {
+    let file: Option<String> = args(vm)?;
+    let res = match file {
+        Some(ref file) => compile(LuaParseTree::new(file), &format!("@{}", file)),
+        None => {
+            let mut code = vec![];
+            match io::stdin().read_to_end(&mut code) {
+                Ok(_) => compile(LuaParseTree::from_bytes(code), "=stdin"),
+                Err(err) => Err(format!("stdin: {}", err)),
+            }
+        }
+    };
+    let main = vm.load_chunk(res.map_err(LuaError::Error)?, None);
+    let rets = vm.call(&main, &[])?;
+    push_values(vm, rets)
+}
+
+/// Pushes <vals> as the return values of a builtin function.
+pub fn push_values(vm: &mut Vm, vals: Vec<LuaVal>) -> Result<(), LuaError> {
+    let count = vals.len();
+    for val in vals {
+        vm.push(val);
+    }
+    vm.closure().set_ret_vals(count);
+    Ok(())
+}
+
+/// Converts the arguments of the running builtin.
+pub fn args<A: FromLuaMulti>(vm: &Vm) -> Result<A, LuaError> {
+    let (args_start, args_count) = vm.varargs();
+    A::from_lua_multi(&vm.stack[args_start..(args_start + args_count)])
+}
+
+/// Converts the position <pos> in a string of length <len> to a position counted from
+/// the start: negative positions are counted from the end of the string, where -1 is
+/// its last byte. Positions before the start are 0.
+pub fn abs_pos(pos: i64, len: usize) -> i64 {
+    if pos >= 0 {
+        pos
+    } else if pos.unsigned_abs() > len as u64 {
+        0
+    } else {
+        len as i64 + pos + 1
+    }
+}
@@ -1,46 +1,284 @@
-extern crate clap;
 extern crate luacompiler;
 extern crate luavm;
+extern crate rustyline;
 
-use clap::{App, Arg};
-use luacompiler::{bytecodegen::compile_to_bytecode, irgen::compile_to_ir, LuaParseTree};
-use luavm::Vm;
+mod repl;
+
+use luacompiler::{
+    bytecode::LuaBytecode, bytecodegen::compile_to_bytecode, errors::CliError,
+    irgen::compile_to_ir, LuaParseTree,
+};
+use luavm::{LuaError, LuaVal, Vm};
+use std::{
+    env,
+    io::{self, IsTerminal, Read},
+    process,
+};
+
+const VERSION: &str = "Lua 5.3 (luavm 0.1)";
+
+const USAGE: &str = "usage: lua [options] [script [args]]
+Available options are:
+  -e stat     execute string 'stat'
+  -i          enter interactive mode after executing 'script'
+  -l name     require library 'name' into global 'name'
+  -v          show version information
+  -E          ignore environment variables
+  --bytecode  print the bytecode of 'script'
+  --          stop handling options
+  -           stop handling options and execute stdin";
+
+/// The -e and -l options, which are handled in the order they were given.
+enum Action {
+    Exec(String),
+    Require(String),
+}
+
+/// The options given on the command line, see `USAGE`.
+#[derive(Default)]
+struct Options {
+    actions: Vec<Action>,
+    interactive: bool,
+    version: bool,
+    ignore_env: bool,
+    bytecode: bool,
+    /// The index of the script in the arguments, if one was given.
+    script: Option<usize>,
+}
+
+/// The reason why the interpreter stops before the end of its work.
+enum Stop {
+    /// An error, which is reported before exiting with a failure.
+    Error(String),
+    /// A call to `os.exit` with a status.
+    Exit(i32),
+}
+
+impl From<String> for Stop {
+    fn from(msg: String) -> Self {
+        Stop::Error(msg)
+    }
+}
+
+impl From<LuaError> for Stop {
+    fn from(err: LuaError) -> Self {
+        match err {
+            LuaError::Exit(status) => Stop::Exit(status),
+            err => Stop::Error(err.to_string()),
+        }
+    }
+}
+
+/// Parses the options in <args>, which end at the script.
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut opts = Options::default();
+    let mut i = 1;
+    while i < args.len() {
+        let arg = args[i].as_str();
+        match arg {
+            "--" => {
+                if i + 1 < args.len() {
+                    opts.script = Some(i + 1);
+                }
+                break;
+            }
+            "-" => {
+                opts.script = Some(i);
+                break;
+            }
+            "-i" => {
+                opts.interactive = true;
+                opts.version = true;
+            }
+            "-v" => opts.version = true,
+            "-E" => opts.ignore_env = true,
+            "--bytecode" => opts.bytecode = true,
+            _ if arg.starts_with("-e") || arg.starts_with("-l") => {
+                // the value either follows the option, or is the next argument
+                let value = if arg.len() > 2 {
+                    arg[2..].to_string()
+                } else if i + 1 < args.len() {
+                    i += 1;
+                    args[i].clone()
+                } else {
+                    return Err(format!("'{}' needs argument", arg));
+                };
+                opts.actions.push(if arg.starts_with("-e") {
+                    Action::Exec(value)
+                } else {
+                    Action::Require(value)
+                });
+            }
+            _ if arg.starts_with('-') => return Err(format!("unrecognized option '{}'", arg)),
+            _ => {
+                opts.script = Some(i);
+                break;
+            }
+        }
+        i += 1;
+    }
+    Ok(opts)
+}
+
+/// Creates the `arg` table: the script is at index 0, its arguments at the positive
+/// indices, and the interpreter and its options at the negative ones. Without a script,
+/// the interpreter is at index 0.
+fn arg_table(args: &[String], script: usize) -> LuaVal {
+    let table = LuaVal::new_table();
+    for (i, arg) in args.iter().enumerate() {
+        table
+            .set_attr(
+                LuaVal::from(i as i64 - script as i64),
+                LuaVal::from(arg.as_str()),
+            )
+            .unwrap();
+    }
+    table
+}
+
+/// Compiles <pt>, or returns the error reported by the compiler.
+fn compile(pt: &LuaParseTree) -> Result<LuaBytecode, String> {
+    compile_to_ir(pt)
+        .map(compile_to_bytecode)
+        .map_err(|err| err.to_string())
+}
+
+/// Compiles the chunk <name>, and runs it with <args>.
+fn run_chunk(
+    vm: &mut Vm,
+    pt: Result<LuaParseTree, CliError>,
+    name: &str,
+    args: &[LuaVal],
+    print_bytecode: bool,
+) -> Result<(), Stop> {
+    let bc = pt
+        .map_err(|err| err.to_string())
+        .and_then(|pt| compile(&pt))
+        .map_err(|msg| format!("{}: {}", name, msg))?;
+    if print_bytecode {
+        println!("{}", &bc);
+    }
+    let main = vm.load_chunk(bc, None);
+    vm.call(&main, args)?;
+    Ok(())
+}
+
+/// Reads all of the standard input.
+fn read_stdin() -> Result<Vec<u8>, String> {
+    let mut code = vec![];
+    io::stdin()
+        .read_to_end(&mut code)
+        .map_err(|err| format!("stdin: {}", err))?;
+    Ok(code)
+}
+
+/// Runs the code given by LUA_INIT_5_3 or LUA_INIT, which is either a chunk, or the
+/// name of a file prefixed by "@".
+fn run_lua_init(vm: &mut Vm) -> Result<(), Stop> {
+    let (name, init) = match env::var("LUA_INIT_5_3") {
+        Ok(init) => ("LUA_INIT_5_3", init),
+        Err(_) => match env::var("LUA_INIT") {
+            Ok(init) => ("LUA_INIT", init),
+            Err(_) => return Ok(()),
+        },
+    };
+    match init.strip_prefix('@') {
+        Some(file) => run_chunk(vm, LuaParseTree::new(file), file, &[], false),
+        None => run_chunk(vm, LuaParseTree::from_str(init.clone()), name, &[], false),
+    }
+}
+
+/// Handles everything but the interactive mode, in the same order as `lua`.
+fn run(vm: &mut Vm, args: &[String], opts: &Options) -> Result<(), Stop> {
+    if !opts.ignore_env {
+        run_lua_init(vm)?;
+    }
+    for action in &opts.actions {
+        match action {
+            Action::Exec(code) => run_chunk(
+                vm,
+                LuaParseTree::from_str(code.clone()),
+                "(command line)",
+                &[],
+                false,
+            )?,
+            Action::Require(name) => {
+                let require = vm.get_global("require");
+                let module = vm.call(&require, &[LuaVal::from(name.as_str())])?;
+                vm.set_global(name, module.into_iter().next().unwrap_or_default());
+            }
+        }
+    }
+    if let Some(script) = opts.script {
+        // the arguments of the script are also its varargs
+        let script_args: Vec<LuaVal> = args[(script + 1)..]
+            .iter()
+            .map(|arg| LuaVal::from(arg.as_str()))
+            .collect();
+        let file = args[script].as_str();
+        let pt = if file == "-" {
+            read_stdin().map(LuaParseTree::from_bytes)?
+        } else {
+            LuaParseTree::new(file)
+        };
+        let name = if file == "-" { "stdin" } else { file };
+        run_chunk(vm, pt, name, &script_args, opts.bytecode)?;
+    }
+    Ok(())
+}
+
+/// Ends the process after <stop>. The interpreter is dropped first, so that the files
+/// which are still open are closed.
+fn exit(vm: Vm, stop: Stop) -> ! {
+    drop(vm);
+    match stop {
+        Stop::Error(msg) => {
+            eprintln!("lua: {}", msg);
+            process::exit(1);
+        }
+        Stop::Exit(status) => process::exit(status),
+    }
+}
 
 fn main() {
-    let matches = App::new("Lua interpreter")
-        .version("0.1")
-        .author("Robert Bartlensky")
-        .about("Interpret Lua files")
-        .arg(
-            Arg::with_name("bytecode")
-                .long("bytecode")
-                .help("Print the bytecode produced by the compiler."),
-        )
-        .arg(
-            Arg::with_name("INPUT")
-                .help("File to interpret")
-                .required(true)
-                .index(1)
-                .min_values(1)
-                .max_values(u64::max_value()),
-        )
-        .get_matches();
-    // we can safely unwrap because INPUT is not an optional argument
-    let mut script_args = matches.values_of("INPUT").unwrap();
-    let file = script_args.nth(0).unwrap();
-    let parse_tree = LuaParseTree::new(&file);
-    match parse_tree {
-        Ok(pt) => {
-            let bc = compile_to_bytecode(compile_to_ir(&pt));
-            if matches.is_present("bytecode") {
-                println!("{}", &bc);
+    let args: Vec<String> = env::args().collect();
+    let opts = match parse_args(&args) {
+        Ok(opts) => opts,
+        Err(msg) => {
+            eprintln!("lua: {}\n{}", msg, USAGE);
+            process::exit(1);
+        }
+    };
+    if opts.version {
+        println!("{}", VERSION);
+    }
+    // the chunks are run after an empty main chunk
+    let pt = LuaParseTree::from_str(String::new()).unwrap();
+    let mut vm = Vm::new(compile_to_bytecode(compile_to_ir(&pt).unwrap()), vec![]);
+    let arg = arg_table(&args, opts.script.unwrap_or(0));
+    vm.set_global("arg", arg);
+    if let Err(stop) = run(&mut vm, &args, &opts) {
+        exit(vm, stop);
+    }
+    let status = if opts.interactive {
+        repl::run(&mut vm)
+    } else if opts.script.is_none() && opts.actions.is_empty() && !opts.version {
+        if io::stdin().is_terminal() {
+            println!("{}", VERSION);
+            repl::run(&mut vm)
+        } else {
+            let res = read_stdin().map_err(Stop::from).and_then(|code| {
+                run_chunk(&mut vm, LuaParseTree::from_bytes(code), "stdin", &[], false)
+            });
+            if let Err(stop) = res {
+                exit(vm, stop);
             }
-            let mut all_args: Vec<&str> = vec![file];
-            let script_args: Vec<&str> = script_args.map(|v| v).collect();
-            all_args.extend(script_args);
-            let mut vm = Vm::new(bc, all_args);
-            vm.eval().unwrap();
+            None
         }
-        Err(err) => println!("{:#?}", err),
+    } else {
+        None
+    };
+    if let Some(status) = status {
+        exit(vm, Stop::Exit(status));
     }
 }
@@ -0,0 +1,71 @@
+use compile;
+use luacompiler::{bytecode::LuaBytecode, LuaParseTree};
+use luavm::{LuaError, Vm};
+use rustyline::{error::ReadlineError, Editor};
+
+/// Reads chunks from the standard input, and runs them in <vm> until the end of the
+/// input. The values of the chunks which are expressions are printed. Returns the
+/// status given to `os.exit` if it was called.
+pub fn run(vm: &mut Vm) -> Option<i32> {
+    let mut editor = Editor::<()>::new();
+    while let Some(chunk) = read_chunk(&mut editor) {
+        let res = match chunk {
+            Ok(bc) => match vm.exec_chunk(bc) {
+                Err(LuaError::Exit(status)) => return Some(status),
+                res => res.map_err(|err| err.to_string()),
+            },
+            Err(msg) => Err(msg),
+        };
+        match res {
+            Ok(ref vals) if vals.is_empty() => {}
+            Ok(vals) => {
+                let print = vm.get_global("print");
+                if let Err(err) = vm.call(&print, &vals) {
+                    eprintln!("{}", err);
+                }
+            }
+            Err(msg) => eprintln!("{}", msg),
+        }
+    }
+    None
+}
+
+/// Reads lines until they form a complete chunk, and compiles it. A line which starts
+/// with "=", or which is an expression, is read as a return statement. Returns `None`
+/// at the end of the input.
+fn read_chunk(editor: &mut Editor<()>) -> Option<Result<LuaBytecode, String>> {
+    let mut code = String::new();
+    loop {
+        let prompt = if code.is_empty() { "> " } else { ">> " };
+        let line = match editor.readline(prompt) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => {
+                // the incomplete chunk is discarded
+                code.clear();
+                continue;
+            }
+            Err(ReadlineError::Eof) => return None,
+            Err(err) => {
+                eprintln!("{}", err);
+                return None;
+            }
+        };
+        editor.add_history_entry(line.as_str());
+        if code.is_empty() && line.starts_with('=') {
+            code = format!("return {}", &line[1..]);
+        } else {
+            if !code.is_empty() {
+                code.push('\n');
+            }
+            code.push_str(&line);
+        }
+        if let Ok(pt) = LuaParseTree::from_str(format!("return {}", code)) {
+            return Some(compile(&pt).map_err(|msg| format!("stdin: {}", msg)));
+        }
+        match LuaParseTree::from_str(code.clone()) {
+            Ok(pt) => return Some(compile(&pt).map_err(|msg| format!("stdin: {}", msg))),
+            Err(ref err) if err.is_incomplete(&code) => continue,
+            Err(err) => return Some(Err(format!("stdin: {}", err))),
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use std::{
+    env, fs,
+    io::Write,
+    process::{self, Command, Output, Stdio},
+};
+
+fn luavm(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_luavm"))
+        .args(args)
+        .env_remove("LUA_INIT")
+        .env_remove("LUA_INIT_5_3")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn stdout(out: &Output) -> String {
+    String::from_utf8(out.stdout.clone()).unwrap()
+}
+
+fn stderr(out: &Output) -> String {
+    String::from_utf8(out.stderr.clone()).unwrap()
+}
+
+#[test]
+fn options_and_args() {
+    let out = luavm(
+        &["-e", "x = 2", "-eprint(x)", "-", "a", "b"],
+        "print(...) print(arg[0 - 1], arg[0], arg[2])",
+    );
+    assert!(out.status.success());
+    assert_eq!(stdout(&out), "2\na\tb\n-eprint(x)\t-\tb\n");
+    let out = luavm(&["-v"], "");
+    assert!(out.status.success());
+    assert!(stdout(&out).starts_with("Lua 5.3"));
+    // without a script, the standard input is run if it is not a terminal
+    let out = luavm(&[], "print(\"piped\")");
+    assert_eq!(stdout(&out), "piped\n");
+    let out = luavm(&["--", "-"], "print(1)");
+    assert_eq!(stdout(&out), "1\n");
+}
+
+#[test]
+fn modules_and_init() {
+    let out = luavm(&["-l", "counter", "-e", "print(counter(1), loads)"], "");
+    assert_eq!(stderr(&out), "lua: module 'counter' not found:\n\tno field package.preload['counter']\n\tno file './counter.lua'\n\tno file './counter/init.lua'\n\tno file './counter.luabc'\n");
+    let out = Command::new(env!("CARGO_BIN_EXE_luavm"))
+        .args(["-l", "counter", "-e", "print(counter(1), loads)"])
+        .env("LUA_INIT", "package.path = \"tests/lua_modules/?.lua\"")
+        .output()
+        .unwrap();
+    assert_eq!(stdout(&out), "2\t1\n");
+    let out = Command::new(env!("CARGO_BIN_EXE_luavm"))
+        .args(["-E", "-e", "print(1)"])
+        .env("LUA_INIT", "error(\"init\")")
+        .output()
+        .unwrap();
+    assert_eq!(stdout(&out), "1\n");
+}
+
+#[test]
+fn errors() {
+    let out = luavm(&["-e", "error(\"failed\")"], "");
+    assert_eq!(out.status.code(), Some(1));
+    assert_eq!(stderr(&out), "lua: failed\n");
+    let out = luavm(&["-"], "x = = 1");
+    assert_eq!(out.status.code(), Some(1));
+    assert_eq!(stderr(&out), "lua: stdin: syntax error at position 4\n");
+    let out = luavm(&["-"], "x = 'a\\q'");
+    assert_eq!(out.status.code(), Some(1));
+    assert_eq!(
+        stderr(&out),
+        "lua: stdin: invalid escape sequence '\\q' at position 4\n"
+    );
+    let out = luavm(&["-x"], "");
+    assert_eq!(out.status.code(), Some(1));
+    assert!(stderr(&out).starts_with("lua: unrecognized option '-x'\nusage: "));
+}
+
+#[test]
+fn io_library() {
+    // unlike print, io.write doesn't add separators or a newline
+    let out = luavm(
+        &["-e", "io.write(\"a\", 1, \"b\") io.stdout:write(\"c\\n\")"],
+        "",
+    );
+    assert_eq!(stdout(&out), "a1bc\n");
+    let out = luavm(
+        &[
+            "-e",
+            "print(io.read(\"n\", \"l\", \"L\", \"a\")) print(io.read())",
+        ],
+        " 12 rest\nmore\nend",
+    );
+    assert_eq!(stdout(&out), "12\t rest\tmore\n\tend\nnil\n");
+    let path = env::temp_dir().join(format!("luavm_io_{}.txt", process::id()));
+    let script = "local path = arg[1]
+        local f = io.open(path, \"w\")
+        print(f:write(\"first\\n\", 2, \"\\n\") == f)
+        f:close()
+        print(f)
+        io.output(path)
+        io.write(\"replaced\\n\")
+        io.close()
+        f = io.open(path, \"a+\")
+        f:write(\"appended\")
+        f:seek(\"set\")
+        print(f:read(\"a\"))
+        f:close()";
+    let out = luavm(&["-", path.to_str().unwrap()], script);
+    assert_eq!(stderr(&out), "");
+    assert_eq!(stdout(&out), "true\nfile (closed)\nreplaced\nappended\n");
+    assert_eq!(fs::read_to_string(&path).unwrap(), "replaced\nappended");
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn os_exit() {
+    // the exit unwinds through pcall and coroutines, and stops the later options
+    let out = luavm(
+        &[
+            "-e",
+            "io.write(\"before\") print(pcall(os.exit, 3))",
+            "-e",
+            "print(\"after\")",
+        ],
+        "",
+    );
+    assert_eq!(out.status.code(), Some(3));
+    assert_eq!(stdout(&out), "before");
+    assert_eq!(stderr(&out), "");
+    let out = luavm(
+        &["-"],
+        "function f() os.exit(1 == 2) end pcall(coroutine.wrap(f))",
+    );
+    assert_eq!(out.status.code(), Some(1));
+    assert_eq!(stderr(&out), "");
+    let out = luavm(&["-e", "os.exit(1 == 1)", "-e", "error(1)"], "");
+    assert!(out.status.success());
+    let out = luavm(&[], "os.exit(7.0)");
+    assert_eq!(out.status.code(), Some(7));
+    // a status which doesn't fit in an int is a failure, not truncated to 0
+    let out = luavm(&[], "os.exit(4294967296)");
+    assert_eq!(out.status.code(), Some(1));
+}
@@ -0,0 +1,273 @@
+extern crate gc;
+#[macro_use]
+extern crate gc_derive;
+extern crate luacompiler;
+extern crate luavm;
+
+use luacompiler::{bytecodegen::compile_to_bytecode, irgen::compile_to_ir, LuaParseTree};
+use luavm::{FromLuaMulti, IntoLua, IntoLuaMulti, LuaError, LuaVal, UserData, UserDataMethods, Vm};
+use std::{cell::RefCell, collections::HashMap, env, fs, process, rc::Rc};
+
+fn vm_for(source: &str) -> Vm {
+    let pt = LuaParseTree::from_str(source.to_string()).unwrap();
+    let bc = compile_to_bytecode(compile_to_ir(&pt).unwrap());
+    Vm::new(bc, vec![])
+}
+
+#[test]
+fn globals_and_calls() {
+    let mut vm = vm_for(
+        "function area(w, h)
+             return w * h, \"m2\"
+         end
+         function fail()
+             error(\"no\")
+         end",
+    );
+    vm.eval().unwrap();
+    let area = vm.get_global("area");
+    assert_eq!(area.type_name(), "function");
+    let res = vm
+        .call(&area, &[LuaVal::from(2.5), LuaVal::from(2)])
+        .unwrap();
+    assert_eq!(res[0].as_float(), Some(5.0));
+    assert_eq!(res[1].as_str(), Some("m2"));
+    let fail = vm.get_global("fail");
+    assert_eq!(vm.call(&fail, &[]), Err(LuaError::Error("no".to_string())));
+}
+
+#[test]
+fn tables() {
+    let mut vm = vm_for("n = config.width * 2");
+    let config = LuaVal::new_table();
+    config
+        .set_attr(LuaVal::from("width"), LuaVal::from(21))
+        .unwrap();
+    vm.set_global("config", config.clone());
+    vm.eval().unwrap();
+    assert_eq!(vm.get_global("n").as_int(), Some(42));
+    assert!(vm.get_global("config").is_table());
+    assert_eq!(
+        config.get_attr(&LuaVal::from("width")).unwrap().as_int(),
+        Some(21)
+    );
+    assert_eq!(LuaVal::from(true).as_bool(), Some(true));
+    assert_eq!(LuaVal::new().type_name(), "nil");
+}
+
+#[test]
+fn conversions() {
+    let mut vm = vm_for(
+        "function scale(t, k)
+             return t.x * k, t.y * k
+         end",
+    );
+    vm.eval().unwrap();
+    let mut point = HashMap::new();
+    point.insert("x", 1.5);
+    point.insert("y", 2.0);
+    let scale = vm.get_global("scale");
+    let res = vm.call(&scale, &(point, 2).into_lua_multi()).unwrap();
+    let (x, y): (f64, f64) = FromLuaMulti::from_lua_multi(&res).unwrap();
+    assert_eq!((x, y), (3.0, 4.0));
+    assert_eq!(
+        <(i64,)>::from_lua_multi(&["a".into_lua()])
+            .unwrap_err()
+            .to_string(),
+        "bad argument #1 (number expected, got string)"
+    );
+}
+
+#[test]
+fn host_functions() {
+    let mut vm = vm_for(
+        "put(\"a\", 1)
+         put(\"b\", 2)
+         old = put(\"a\", 3)
+         n = size()",
+    );
+    let db = Rc::new(RefCell::new(HashMap::new()));
+    let db2 = db.clone();
+    vm.register_function("put", move |_, (k, v): (String, i64)| {
+        Ok(db2.borrow_mut().insert(k, v))
+    });
+    let db3 = db.clone();
+    vm.register_function("size", move |_, ()| Ok(db3.borrow().len()));
+    vm.eval().unwrap();
+    assert_eq!(vm.get_global("old").as_int(), Some(1));
+    assert_eq!(vm.get_global("n").as_int(), Some(2));
+    assert_eq!(db.borrow()["a"], 3);
+}
+
+#[derive(Trace, Finalize, Clone, PartialEq, Debug)]
+struct Point {
+    x: f64,
+    y: f64,
+}
+
+impl UserData for Point {
+    fn add_methods(methods: &mut UserDataMethods<Self>) {
+        methods.add_field_getter("x", |p: &Point| Ok(p.x));
+        methods.add_field_setter("x", |p: &mut Point, x: f64| {
+            p.x = x;
+            Ok(())
+        });
+        methods.add_method("len", |_, p: &Point, ()| Ok(p.x.hypot(p.y)));
+        methods.add_method_mut("scale", |_, p: &mut Point, k: f64| {
+            p.x *= k;
+            p.y *= k;
+            Ok(())
+        });
+        methods.add_meta_function("__add", |vm, (a, b): (LuaVal, LuaVal)| {
+            let a = a.with_userdata(|p: &Point| p.clone())?;
+            let b = b.with_userdata(|p: &Point| p.clone())?;
+            Ok(vm.create_userdata(Point {
+                x: a.x + b.x,
+                y: a.y + b.y,
+            }))
+        });
+        methods.add_meta_function("__eq", |_, (a, b): (LuaVal, LuaVal)| {
+            Ok(
+                a.with_userdata(|p: &Point| p.clone())?
+                    == b.with_userdata(|p: &Point| p.clone())?,
+            )
+        });
+        methods.add_meta_method("__tostring", |_, p: &Point, ()| {
+            Ok(format!("({}, {})", p.x, p.y))
+        });
+        methods.add_meta_method("__call", |_, p: &Point, k: f64| Ok(p.y * k));
+        methods.add_meta_method("__index", |_, _: &Point, name: String| {
+            Ok(format!("no field {}", name))
+        });
+    }
+}
+
+#[test]
+fn userdata() {
+    let mut vm = vm_for(
+        "p.x = p.x + 2
+         len = p.len(p)
+         p.scale(p, 2)
+         q = p + origin
+         same = q == p
+         different = q == origin
+         identical = p == p
+         y3 = p(3)
+         missing = p.z
+         ok, err = pcall(p.len, 1)
+         ok2, err2 = pcall(p.scale, p, \"big\")
+         kind = type(p)
+         formatted = string.format(\"p = %s\", p)",
+    );
+    let p = vm.create_userdata(Point { x: 1.0, y: 4.0 });
+    let origin = vm.create_userdata(Point { x: 0.0, y: 0.0 });
+    vm.set_global("p", p.clone());
+    vm.set_global("origin", origin);
+    vm.register_function("type", |_, v: LuaVal| Ok(v.type_name()));
+    vm.eval().unwrap();
+    assert_eq!(vm.get_global("len").as_float(), Some(5.0));
+    assert_eq!(
+        p.with_userdata(|p: &Point| p.clone()),
+        Ok(Point { x: 6.0, y: 8.0 })
+    );
+    assert_eq!(
+        vm.get_global("q").with_userdata(|p: &Point| p.clone()),
+        Ok(Point { x: 6.0, y: 8.0 })
+    );
+    assert_eq!(vm.get_global("same").as_bool(), Some(true));
+    assert_eq!(vm.get_global("different").as_bool(), Some(false));
+    assert_eq!(vm.get_global("identical").as_bool(), Some(true));
+    assert_eq!(vm.get_global("y3").as_float(), Some(24.0));
+    assert_eq!(vm.get_global("missing").as_str(), Some("no field z"));
+    assert_eq!(
+        vm.get_global("err").as_str(),
+        Some("bad argument #1 to 'len' (embedding::Point expected, got number)")
+    );
+    assert_eq!(
+        vm.get_global("err2").as_str(),
+        Some("bad argument #2 to 'scale' (number expected, got string)")
+    );
+    assert_eq!(vm.get_global("kind").as_str(), Some("userdata"));
+    assert_eq!(vm.get_global("formatted").as_str(), Some("p = (6, 8)"));
+    assert!(p == vm.get_global("p"));
+    assert!(p != vm.get_global("q"));
+    assert_eq!(
+        p.with_userdata(|s: &String| s.len()),
+        Err(LuaError::TypeMismatch {
+            expected: "alloc::string::String",
+            got: "embedding::Point"
+        })
+    );
+    let tostring = vm.get_global("p").metamethod("__tostring").unwrap();
+    assert_eq!(
+        vm.call(&tostring, &[p]).unwrap()[0].as_str(),
+        Some("(6, 8)")
+    );
+}
+
+#[test]
+fn chunks() {
+    let mut vm = vm_for("x = 1");
+    vm.eval().unwrap();
+    // "late" is not a string of the first chunk
+    vm.set_global("late", LuaVal::from(5));
+    let bc = |source: &str| {
+        let pt = LuaParseTree::from_str(source.to_string()).unwrap();
+        compile_to_bytecode(compile_to_ir(&pt).unwrap())
+    };
+    let res = vm.exec_chunk(bc("y = x + late return y")).unwrap();
+    assert_eq!(res, vec![LuaVal::from(6)]);
+    assert_eq!(vm.get_global("y").as_int(), Some(6));
+    vm.exec_chunk(bc("late = late + 1")).unwrap();
+    assert_eq!(vm.get_global("late").as_int(), Some(6));
+    assert_eq!(
+        vm.exec_chunk(bc("error(\"failed\")")),
+        Err(LuaError::Error("failed".to_string()))
+    );
+    // the chunks loaded with an env use it instead of the globals
+    let env = LuaVal::new_table();
+    vm.set_global("env", env.clone());
+    vm.exec_chunk(bc(
+        "load(\"x = 2 function f() z = x end\", \"c\", \"t\", env)()",
+    ))
+    .unwrap();
+    vm.call(&env.get_attr(&LuaVal::from("f")).unwrap(), &[])
+        .unwrap();
+    assert_eq!(env.get_attr(&LuaVal::from("z")).unwrap().as_int(), Some(2));
+    assert_eq!(vm.get_global("x").as_int(), Some(1));
+    assert!(vm.get_global("z").is_nil());
+}
+
+#[test]
+fn modules() {
+    let dir = env::temp_dir().join(format!("luavm_modules_{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let pt = LuaParseTree::from_str("compiled = 1 return 7".to_string()).unwrap();
+    compile_to_bytecode(compile_to_ir(&pt).unwrap())
+        .serialize_to_file(dir.join("compiled.luabc").to_str().unwrap())
+        .unwrap();
+    fs::write(dir.join("broken.luabc"), b"not bytecode").unwrap();
+    let mut vm = vm_for("");
+    let path = format!("{}/?.luabc", dir.to_str().unwrap());
+    let package = vm.get_global("package");
+    package
+        .set_attr(LuaVal::from("path"), LuaVal::from(path.as_str()))
+        .unwrap();
+    let require = vm.get_global("require");
+    let res = vm.call(&require, &[LuaVal::from("compiled")]).unwrap();
+    assert_eq!(res, vec![LuaVal::from(7)]);
+    assert_eq!(vm.get_global("compiled").as_int(), Some(1));
+    let err = vm.call(&require, &[LuaVal::from("broken")]).unwrap_err();
+    assert!(err.to_string().starts_with(&format!(
+        "error loading module 'broken' from file '{}/broken.luabc':",
+        dir.to_str().unwrap()
+    )));
+    assert_eq!(
+        vm.call(&require, &[LuaVal::from("a.b")]),
+        Err(LuaError::Error(format!(
+            "module 'a.b' not found:\n\tno field package.preload['a.b']\n\tno file '{}/a/b.luabc'",
+            dir.to_str().unwrap()
+        )))
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,8 @@
+loads = (loads or 0) + 1
+module_name, module_file = ...
+
+function add_one(n)
+    return n + 1
+end
+
+return add_one
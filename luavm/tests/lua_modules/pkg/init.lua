@@ -0,0 +1 @@
+pkg_loaded = 1
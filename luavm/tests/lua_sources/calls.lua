@@ -0,0 +1,45 @@
+function id(...)
+   return ...
+end
+
+function sum(a, b, c)
+   local s = 0
+   if a then
+      s = s + a
+   end
+   if b then
+      s = s + b
+   end
+   if c then
+      s = s + c
+   end
+   return s
+end
+
+-- the registers of a loop must survive the calls in its body
+local total = 0
+local i = 0
+while i < 10 do
+   total = total + sum(i, 1)
+   i = i + 1
+end
+assert(total == 55)
+
+-- all return values of the last argument are passed on
+assert(sum(id(1, 2, 3)) == 6)
+assert(sum(1, id(2, 3)) == 6)
+assert(sum(id(1, 2), 3) == 4)
+assert(sum(1, id(id(2, 3))) == 6)
+
+-- missing arguments and return values are nil
+assert(sum(id(1)) == 1)
+local x, y, z = id(1)
+assert(sum(x, y, z) == 1)
+
+function three()
+   return 1, 2, 3
+end
+
+local a, b, c, d = three()
+assert(a + b + c == 6)
+assert(sum(d, d, d) == 0)
@@ -0,0 +1,2 @@
+loaded = (loaded or 0) + 1
+return loaded, "chunk"
@@ -0,0 +1,33 @@
+local a = 1
+local b = 2
+local n = 0
+if a < b then n = n + 1 end
+if b > a then n = n + 1 end
+if a <= 1 then n = n + 1 end
+if b >= 3 then n = n + 100 end
+if a == 1 then n = n + 1 end
+if a ~= b then n = n + 1 end
+assert(n == 5)
+local c = a < b
+if c then n = n + 1 end
+assert(c)
+assert(n == 6)
+local i = 0
+while i ~= 10 do
+   i = i + 1
+end
+assert(i == 10)
+-- the operands of and/or in conditions jump to the branches
+n = 0
+if a > 2 or b > 1 then n = n + 1 else n = n + 100 end
+if a < 2 and b < 2 then n = n + 100 else n = n + 1 end
+if a < 2 and b > 1 then n = n + 1 end
+if x or a == 1 then n = n + 1 end
+if (a > 1 or b > 1) and (a > 1 and b > 1 or a == 1) then n = n + 1 end
+if a > 1 or b > 2 or c then n = n + 1 end
+assert(n == 6)
+i = 0
+while i < 3 and b > 0 or i == 4 do
+   i = i + 1
+end
+assert(i == 3)
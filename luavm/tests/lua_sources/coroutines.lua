@@ -0,0 +1,127 @@
+-- values are passed both ways through resume and yield
+function accumulate(a)
+   local total = a
+   while total < 100 do
+      local b = coroutine.yield(total)
+      total = total + b
+   end
+   return total, "done"
+end
+
+local co = coroutine.create(accumulate)
+assert(coroutine.status(co) == "suspended")
+local ok, v = coroutine.resume(co, 10)
+assert(ok)
+assert(v == 10)
+ok, v = coroutine.resume(co, 50)
+assert(v == 60)
+local ok2, w, s = coroutine.resume(co, 50)
+assert(ok2)
+assert(w == 110)
+assert(s == "done")
+assert(coroutine.status(co) == "dead")
+ok, v = coroutine.resume(co)
+if ok then
+   error("resumed a dead coroutine")
+end
+assert(v == "cannot resume dead coroutine")
+
+-- generators
+function range(n)
+   local i = 1
+   while i <= n do
+      coroutine.yield(i)
+      i = i + 1
+   end
+end
+
+local next = coroutine.wrap(range)
+local sum = 0
+local i = next(4)
+while i do
+   sum = sum + i
+   i = next()
+end
+assert(sum == 10)
+
+-- yielding across pcall
+function protected()
+   local x = coroutine.yield(1)
+   error(x)
+end
+
+function body()
+   local ok, err = pcall(protected)
+   coroutine.yield(err)
+   return 3
+end
+
+co = coroutine.create(body)
+local _, a = coroutine.resume(co)
+assert(a == 1)
+local _, b = coroutine.resume(co, "oops")
+assert(b == "oops")
+local _, c = coroutine.resume(co)
+assert(c == 3)
+
+-- errors kill the coroutine and are returned by resume
+function failing()
+   coroutine.yield(1)
+   error("failed")
+end
+
+co = coroutine.create(failing)
+coroutine.resume(co)
+ok, v = coroutine.resume(co)
+if ok then
+   error("the coroutine didn't fail")
+end
+assert(v == "failed")
+assert(coroutine.status(co) == "dead")
+
+-- but they are propagated by wrap
+local wrapped = coroutine.wrap(failing)
+wrapped()
+ok, v = pcall(wrapped)
+if ok then
+   error("the wrapped coroutine didn't fail")
+end
+assert(v == "failed")
+
+-- status, running and isyieldable
+outer = 0
+function inner()
+   assert(coroutine.status(outer) == "normal")
+end
+
+function check_running()
+   local co, main = coroutine.running()
+   if main then
+      error("a coroutine is not the main thread")
+   end
+   assert(co == outer)
+   assert(coroutine.status(outer) == "running")
+   assert(coroutine.isyieldable())
+   coroutine.resume(coroutine.create(inner))
+end
+
+outer = coroutine.create(check_running)
+assert(coroutine.resume(outer))
+local _, main = coroutine.running()
+assert(main)
+if coroutine.isyieldable() then
+   error("the main thread is not yieldable")
+end
+
+-- coroutines are table keys, whichever copy of them is used
+keys = table.pack()
+function check_key()
+   local co = coroutine.running()
+   assert(keys[co] == 1)
+end
+
+local co = coroutine.create(check_key)
+keys[co] = 1
+local same = co
+assert(keys[same] == 1)
+assert(coroutine.resume(co))
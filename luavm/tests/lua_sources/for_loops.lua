@@ -0,0 +1,85 @@
+-- the bounds are evaluated only once
+local n = 3
+local a = 0
+for i=1,n do
+   n = n + 1
+   a = a + 1
+end
+assert(a == 3)
+-- float loops
+a = 0
+for i=0,1,0.25 do
+   a = a + i
+end
+assert(a == 2.5)
+a = 0
+for i=1.0,2 do
+   assert(math.type(i) == "float")
+   a = a + 1
+end
+assert(a == 2)
+-- loops which don't run
+a = 0
+for i=2,1 do
+   a = a + 1
+end
+for i=1,2,1-2 do
+   a = a + 1
+end
+assert(a == 0)
+-- changing the control variable doesn't affect the iterations
+a = 0
+for i=1,5 do
+   i = i * 10
+   a = a + i
+end
+assert(a == 150)
+-- nested loops
+a = 0
+for i=1,4 do
+   for j=i,4 do
+      a = a + 1
+   end
+end
+assert(a == 10)
+
+-- a float limit doesn't make a float loop, it's rounded towards the start instead, and
+-- saturates at the bounds of the integers
+function float_limits()
+   local a = 0
+   for i=1,2.5 do
+      assert(math.type(i) == "integer")
+      a = a + 1
+   end
+   assert(a == 2)
+   a = 0
+   for i=3,1.5,0-1 do
+      assert(math.type(i) == "integer")
+      a = a + i
+   end
+   assert(a == 5)
+   a = 0
+   for i=math.maxinteger-1,2^70 do
+      assert(math.type(i) == "integer")
+      a = a + 1
+   end
+   assert(a == 2)
+   a = 0
+   for i=math.mininteger+1,0-2^70,0-1 do
+      a = a + 1
+   end
+   assert(a == 2)
+   -- loops which don't run, as the limit is beyond the integers
+   a = 0
+   for i=1,0-2^70 do
+      a = a + 1
+   end
+   for i=1,2^70,0-1 do
+      a = a + 1
+   end
+   for i=1,0.0/0 do
+      a = a + 1
+   end
+   assert(a == 0)
+end
+float_limits()
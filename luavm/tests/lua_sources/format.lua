@@ -0,0 +1,63 @@
+function test_integers()
+   assert(string.format("%d", 42) == "42")
+   assert(string.format("%5d|%-5d|%05d", 42, 42, 0 - 42) == "   42|42   |-0042")
+   assert(string.format("%+d % d %.3d", 7, 7, 7) == "+7  7 007")
+   assert(string.format("%i", 3.0) == "3")
+   assert(string.format("%d", "10") == "10")
+   assert(string.format("%x %X %#x %o %#o", 255, 255, 255, 8, 8) == "ff FF 0xff 10 010")
+   assert(string.format("%x", 0 - 1) == "ffffffffffffffff")
+   assert(string.format("%u", 0 - 1) == "18446744073709551615")
+   assert(string.format("%c%c%c", 76, 117, 97) == "Lua")
+end
+test_integers()
+
+function test_floats()
+   assert(string.format("%f", 1.5) == "1.500000")
+   assert(string.format("%.2f|%8.3f|%-8.1f|%08.2f", 3.14159, 2.5, 2.5, 0 - 2.5) == "3.14|   2.500|2.5     |-0002.50")
+   assert(string.format("%.0f %.0f %#.0f", 0.5, 1.5, 2) == "0 2 2.")
+   assert(string.format("%e", 12345.678) == "1.234568e+04")
+   assert(string.format("%.2E", 0.000123) == "1.23E-04")
+   assert(string.format("%g %g %g", 100000, 1000000, 0.0001) == "100000 1e+06 0.0001")
+   assert(string.format("%g %.3g %#g", 2.5, 3.14159, 1) == "2.5 3.14 1.00000")
+   assert(string.format("%a %A", 1, 0.5) == "0x1p+0 0X1P-1")
+   assert(string.format("%.1a", 1.75) == "0x1.cp+0")
+   assert(string.format("%5.1f%%", 99.44) == " 99.4%")
+   assert(string.format("%f %f", tonumber("1e999"), 0 - tonumber("1e999")) == "inf -inf")
+end
+test_floats()
+
+function test_strings()
+   assert(string.format("%s", "x") == "x")
+   assert(string.format("%5s|%-5s|%.2s", "ab", "ab", "abc") == "   ab|ab   |ab")
+   assert(string.format("%s %s %s", 12, 1.5, print == print) == "12 1.5 true")
+   assert(string.format("%q", "x") == string.char(34, 120, 34))
+   -- the quotes, new lines and control characters are escaped
+   local q = string.format("%q", string.char(34, 10, 1, 50))
+   assert(q == string.char(34, 92, 34, 92, 10, 92, 48, 48, 49, 50, 34))
+   assert(string.format("%q %q", 1, 0.5) == "1 0x1p-1")
+   assert(string.format("%q", 0 - 9223372036854775807 - 1) == "0x8000000000000000")
+   assert(string.format("%q", tonumber("1e999")) == "1e9999")
+   assert(string.format("no directives") == "no directives")
+end
+test_strings()
+
+function check_error(msg, a, b)
+   local ok, err = pcall(string.format, a, b)
+   if ok then
+      error("no error")
+   end
+   assert(err == msg)
+end
+
+function test_errors()
+   check_error("bad argument #2 to 'format' (number has no integer representation)", "%d", 1.5)
+   check_error("bad argument #2 to 'format' (number has no integer representation)", "%d", 2 ^ 63)
+   check_error("bad argument #2 to 'format' (number expected, got string)", "%f", "x")
+   local ok, err = pcall(string.format, "%d")
+   assert(err == "bad argument #2 to 'format' (no value)")
+   check_error("invalid option '%y' to 'format'", "%y", 1)
+   check_error("invalid format (width or precision too long)", "%123d", 1)
+   check_error("invalid format (repeated flags)", "%------d", 1)
+   check_error("bad argument #2 to 'format' (value has no literal form)", "%q", print)
+end
+test_errors()
@@ -0,0 +1,136 @@
+-- the files are read relatively to the directory of the crate, like the modules of
+-- require.lua
+
+function assert_nil(x)
+   if x then
+      error("not nil")
+   end
+end
+
+function check_error(msg, f, a, b, c)
+   local ok, err = pcall(f, a, b, c)
+   if ok then
+      error("no error")
+   end
+   assert(err == msg)
+end
+
+path = "tests/lua_modules/counter.lua"
+
+function test_open_read()
+   local f = io.open(path)
+   assert(io.type(f) == "file")
+   assert(f:read() == "loads = (loads or 0) + 1")
+   assert(f:read("L") == "module_name, module_file = ...\n")
+   assert(f:read("l") == "")
+   assert(f:read(8) == "function")
+   local a, b = f:read("*l", "n")
+   assert(a == " add_one(n)")
+   assert_nil(b)
+   assert(f:read(0) == "")
+   local rest = f:read("a")
+   assert(f:read("a") == "")
+   assert_nil(f:read())
+   assert_nil(f:read(0))
+   assert_nil(f:read(1))
+   assert(f:seek("set", 4) == 4)
+   assert(f:read(1) == "s")
+   assert(f:seek() == 5)
+   assert_nil(f:seek("cur", 0 - 6))
+   assert(f:seek("cur", 0 - 5) == 0)
+   assert(f:read(5) == "loads")
+   assert(f:seek("end") == 114)
+   assert(f:close())
+   assert(io.type(f) == "closed file")
+   check_error("attempt to use a closed file", f.read, f)
+   check_error("attempt to use a closed file", f.close, f)
+end
+test_open_read()
+
+function test_numbers()
+   local f = io.open(path)
+   f:read(25)
+   -- the next line is "module_name, ..."
+   assert_nil(f:read("n"))
+   assert(f:read(6) == "module")
+   f:close()
+end
+test_numbers()
+
+function test_lines()
+   local it = io.lines(path)
+   assert(it() == "loads = (loads or 0) + 1")
+   assert(it() == "module_name, module_file = ...")
+   local n = 2
+   local line = it()
+   while line do
+      n = n + 1
+      line = it()
+   end
+   assert(n == 8)
+   check_error("file is already closed", it)
+   local f = io.open(path)
+   it = f:lines(5, "L")
+   local a, b = it()
+   assert(a == "loads")
+   assert(b == " = (loads or 0) + 1\n")
+   f:close()
+   check_error("file is already closed", it)
+   check_error("no/such/file: No such file or directory", io.lines, "no/such/file")
+end
+test_lines()
+
+function test_errors()
+
+function test_line_buffering()
+   local name = os.tmpname()
+   local f = io.open(name, "w")
+   assert(f:setvbuf("line"))
+   f:write("first\n")
+   -- the line can be read back without flushing the file
+   local g = io.open(name)
+   assert(g:read("a") == "first\n")
+   g:close()
+   f:close()
+   assert(os.remove(name))
+end
+test_line_buffering()
+   local f, msg, errno = io.open("no/such/file")
+   assert_nil(f)
+   assert(msg == "no/such/file: No such file or directory")
+   assert(errno == 2)
+   check_error("bad argument #2 to 'open' (invalid mode)", io.open, path, "rw")
+   check_error("bad argument #2 to 'open' (invalid mode)", io.open, path, "")
+   f = io.open(path, "rb")
+   check_error("bad argument #2 to 'read' (invalid format)", f.read, f, "x")
+   check_error("bad argument #2 to 'seek' (invalid option 'middle')", f.seek, f, "middle")
+   check_error("bad argument #2 to 'setvbuf' (invalid option 'big')", f.setvbuf, f, "big")
+   assert(f:setvbuf("line"))
+   local ok, msg = f:write("x")
+   assert_nil(ok)
+   f:close()
+   assert_nil(io.type(1))
+   local ok, err = pcall(io.type)
+   assert(err == "bad argument #1 to 'type' (value expected)")
+end
+test_errors()
+
+function test_standard_files()
+   assert(io.type(io.stdout) == "file")
+   assert(io.output() == io.stdout)
+   assert(io.input() == io.stdin)
+   assert(io.write() == io.stdout)
+   local ok, msg = io.stdout:close()
+   assert_nil(ok)
+   assert(msg == "cannot close standard file")
+   local f = io.input(path)
+   assert(io.input() == f)
+   assert(io.read() == "loads = (loads or 0) + 1")
+   f:close()
+   check_error("standard input file is closed", io.read)
+   io.input(io.stdin)
+   check_error("cannot open file 'no/such/file' (No such file or directory)", io.input, "no/such/file")
+   check_error("bad argument #1 to 'output' (FILE* expected, got function)", io.output, print)
+   assert(io.output() == io.stdout)
+end
+test_standard_files()
@@ -0,0 +1,64 @@
+f = load("x = 1 return x + 1")
+assert(f() == 2)
+assert(x == 1)
+y = 5
+g = loadstring("return y * 2, ...")
+a, b = g(3)
+assert(a == 10)
+assert(b == 3)
+
+-- the chunk can define functions which use the globals
+load("function triple(n) return n * 3 end")()
+assert(triple(y) == 15)
+
+h, msg = load("x = = 1", "=broken")
+if h then
+    error("the chunk should not compile")
+end
+assert(msg == "broken: syntax error at position 4")
+
+h, msg = load("x = 2", "text", "b")
+if h then
+    error("the chunk should not be loaded")
+end
+assert(msg == "attempt to load a text chunk (mode is 'b')")
+
+n = 0
+function reader()
+    n = n + 1
+    if n == 1 then
+        return "return "
+    end
+    if n == 2 then
+        return "42"
+    end
+end
+assert(load(reader)() == 42)
+
+ok, msg = pcall(load, 1)
+assert(msg == "bad argument #1 to 'load' (string expected, got number)")
+
+c, name = dofile("tests/lua_sources/chunk.lua")
+assert(c == 1)
+assert(name == "chunk")
+assert(dofile("tests/lua_sources/chunk.lua") == 2)
+ok, msg = pcall(dofile, "tests/lua_sources/missing.lua")
+if ok then
+    error("the file should not exist")
+end
+
+-- the errors of the compiler are reported like the syntax errors
+h, msg = load("return '\\q'", "=escape")
+if h then
+    error("the chunk should not compile")
+end
+assert(msg == "escape: invalid escape sequence '\\q' at position 7")
+
+-- the bytes of the chunk are kept as they are, even if they are not valid UTF-8
+f = load("return '\xFF\xC3\xA9', 1 -- \xFE")
+assert(f() == "\xFF\xC3\xA9")
+assert(string.len(f()) == 3)
+h, msg = load("x\xFF = 1")
+if h then
+    error("the chunk should not compile")
+end
@@ -0,0 +1,168 @@
+function assert_nil(x)
+   if x then
+      error("not nil")
+   end
+end
+
+function check_error(msg, f, a, b, c)
+   local ok, err = pcall(f, a, b, c)
+   if ok then
+      error("no error")
+   end
+   assert(err == msg)
+end
+
+function test_constants()
+   assert(math.maxinteger == 9223372036854775807)
+   assert(math.ult(math.maxinteger, math.mininteger))
+   assert(math.type(math.maxinteger) == "integer")
+   assert(math.huge > math.maxinteger)
+   assert(0 - math.huge < math.mininteger)
+   assert(math.pi > 3.14159)
+   assert(math.pi < 3.1416)
+end
+test_constants()
+
+function test_integer_results()
+   assert(math.abs(0 - 3) == 3)
+   assert(math.type(math.abs(0 - 3)) == "integer")
+   assert(math.abs(0 - 2.5) == 2.5)
+   assert(math.abs(math.mininteger) == math.mininteger)
+   assert(math.floor(3.7) == 3)
+   assert(math.type(math.floor(3.7)) == "integer")
+   assert(math.floor(0 - 3.5) == 0 - 4)
+   assert(math.ceil(3.2) == 4)
+   assert(math.type(math.ceil(3.2)) == "integer")
+   assert(math.type(math.floor(7)) == "integer")
+   assert(math.type(math.floor(math.maxinteger * 4.0)) == "float")
+   assert(math.floor(math.huge) == math.huge)
+   assert(math.type(math.floor("2.5")) == "integer")
+end
+test_integer_results()
+
+function test_float_functions()
+   assert(math.sqrt(16) == 4)
+   assert(math.type(math.sqrt(16)) == "float")
+   assert(math.sin(0) == 0)
+   assert(math.cos(0) == 1)
+   assert(math.tan(0) == 0)
+   assert(math.asin(1) == math.pi / 2)
+   assert(math.acos(1) == 0)
+   assert(math.atan(1) == math.pi / 4)
+   assert(math.atan(1, 0 - 1) == 3 * math.pi / 4)
+   assert(math.atan(0, 0 - 1) == math.pi)
+   assert(math.exp(0) == 1)
+   assert(math.log(1) == 0)
+   assert(math.log(8, 2) == 3)
+   assert(math.log(1000, 10) == 3)
+   assert(math.log(81, 3) > 3.9999)
+   assert(math.log(81, 3) < 4.0001)
+   check_error("bad argument #1 to 'sqrt' (number expected, got string)", math.sqrt, "x")
+end
+test_float_functions()
+
+function test_fmod_modf()
+   assert(math.fmod(7, 3) == 1)
+   assert(math.type(math.fmod(7, 3)) == "integer")
+   assert(math.fmod(0 - 7, 3) == 0 - 1)
+   assert(math.fmod(7, 0 - 3) == 1)
+   assert(math.fmod(math.mininteger, 0 - 1) == 0)
+   assert(math.fmod(5.5, 2) == 1.5)
+   assert(math.type(math.fmod(6.0, 2)) == "float")
+   check_error("bad argument #2 to 'fmod' (zero)", math.fmod, 1, 0)
+   local i, f = math.modf(3.75)
+   assert(i == 3)
+   assert(f == 0.75)
+   i, f = math.modf(0 - 3.75)
+   assert(i == 0 - 3)
+   assert(f == 0 - 0.75)
+   i, f = math.modf(5)
+   assert(i == 5)
+   assert(math.type(i) == "integer")
+   assert(math.type(f) == "float")
+   i, f = math.modf(math.huge)
+   assert(i == math.huge)
+   assert(f == 0)
+end
+test_fmod_modf()
+
+function test_conversions()
+   assert(math.tointeger(3.0) == 3)
+   assert(math.type(math.tointeger(3.0)) == "integer")
+   assert(math.tointeger(7) == 7)
+   assert_nil(math.tointeger(3.5))
+   assert_nil(math.tointeger(math.huge))
+   assert_nil(math.tointeger(2 ^ 63))
+   assert(math.tointeger(0 - 2 ^ 63) == math.mininteger)
+   assert_nil(math.tointeger(print))
+   assert(math.type(1) == "integer")
+   assert(math.type(1.0) == "float")
+   assert_nil(math.type("1"))
+   local ok, err = pcall(math.type)
+   assert(err == "bad argument #1 to 'type' (value expected)")
+   assert(math.ult(1, 2))
+   assert(math.ult(1, 0 - 1))
+   assert(math.ult(0 - 2, 0 - 1))
+   assert_nil(math.ult(2, 1))
+end
+test_conversions()
+
+function test_min_max()
+   assert(math.max(1, 5, 3) == 5)
+   assert(math.min(4, 2, 8) == 2)
+   assert(math.max(0 - 1) == 0 - 1)
+   assert(math.type(math.max(1, 2.0, 2)) == "float")
+   assert(math.type(math.min(1, 1.5)) == "integer")
+   assert(math.max(1.5, math.huge) == math.huge)
+   check_error("bad argument #2 to 'max' (number expected, got string)", math.max, 1, "x")
+   local ok, err = pcall(math.min)
+   assert(err == "bad argument #1 to 'min' (number expected, got no value)")
+end
+test_min_max()
+
+function test_random()
+   for i = 1, 100 do
+      local x = math.random()
+      assert(x >= 0)
+      assert(x < 1)
+      local n = math.random(6)
+      assert(n >= 1)
+      assert(n <= 6)
+      assert(math.type(n) == "integer")
+      n = math.random(0 - 3, 3)
+      assert(n >= 0 - 3)
+      assert(n <= 3)
+      assert(math.random(5, 5) == 5)
+   end
+   n = math.random(math.mininteger, math.maxinteger)
+   assert(math.type(n) == "integer")
+   -- check_error is not used, as the number of arguments matters
+   local ok, err = pcall(math.random, 3, 1)
+   assert(err == "bad argument #1 to 'random' (interval is empty)")
+   ok, err = pcall(math.random, 0)
+   assert(err == "bad argument #1 to 'random' (interval is empty)")
+   ok, err = pcall(math.random, 0 - 1)
+   assert(err == "bad argument #1 to 'random' (interval is empty)")
+   ok, err = pcall(math.random, 1, 2, 3)
+   assert(err == "wrong number of arguments")
+   ok, err = pcall(math.random, 1.5)
+   assert(err == "bad argument #1 to 'random' (number has no integer representation)")
+end
+test_random()
+
+function test_randomseed()
+   math.randomseed(42)
+   local min, max = math.mininteger, math.maxinteger
+   local a, b, c = math.random(1000), math.random(), math.random(min, max)
+   math.randomseed(42)
+   assert(math.random(1000) == a)
+   assert(math.random() == b)
+   assert(math.random(min, max) == c)
+   math.randomseed(42, 1)
+   local d = math.random(min, max)
+   math.randomseed(42.0, 1)
+   assert(math.random(min, max) == d)
+   assert(d ~= c)
+   math.randomseed()
+end
+test_randomseed()
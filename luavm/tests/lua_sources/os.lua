@@ -0,0 +1,128 @@
+-- the dates are read in UTC when they are printed, so that the results don't depend on
+-- the timezone
+
+function assert_nil(x)
+   if x then
+      error("not nil")
+   end
+end
+
+function check_error(msg, f, a, b, c)
+   local ok, err = pcall(f, a, b, c)
+   if ok then
+      error("no error")
+   end
+   assert(err == msg)
+end
+
+function test_date()
+   assert(os.date("!%Y-%m-%d %H:%M:%S", 0) == "1970-01-01 00:00:00")
+   assert(os.date("!%j %a %b %%", 86400 * 40) == "041 Tue Feb %")
+   assert(os.date("!%Ey %Od", 0) == "70 01")
+   local t = os.date("!*t", 86400 + 3661)
+   assert(t.year == 1970)
+   assert(t.month == 1)
+   assert(t.day == 2)
+   assert(t.hour == 1)
+   assert(t.min == 1)
+   assert(t.sec == 1)
+   assert(t.yday == 2)
+   assert(t.wday == 6)
+   assert(t.isdst == (1 == 2))
+   assert(os.date("!x", 0) == "x")
+   assert(os.date() == os.date("%c"))
+   check_error("bad argument #1 to 'date' (invalid conversion specifier '%Ez')", os.date, "%Ez")
+   check_error("bad argument #1 to 'date' (invalid conversion specifier '%')", os.date, "a%")
+   check_error("bad argument #2 to 'date' (number expected, got string)", os.date, "%c", "x")
+end
+test_date()
+
+function test_time()
+   assert(math.type(os.time()) == "integer")
+   local now = os.time()
+   local t = os.date("*t", now)
+   assert(os.time(t) == now)
+   local date = t
+   t = table.pack()
+   t.year = date.year
+   t.month = date.month
+   t.day = date.day
+   local noon = os.time(t)
+   assert(t.min == 0)
+   -- the fields are normalized
+   t.hour = 24 + 12
+   t.yday = 1000
+   assert(os.time(t) > noon)
+   assert(t.hour == 12)
+   assert(os.date("*t", noon + 86400).wday == t.wday)
+   t = table.pack()
+   t.year = 2001
+   t.month = 14
+   t.day = 0
+   os.time(t)
+   assert(t.year == 2002)
+   assert(t.month == 1)
+   assert(t.day == 31)
+   assert(t.hour == 12)
+   assert(t.min == 0)
+end
+test_time()
+
+function test_time_errors()
+   local t = table.pack()
+   t.year = 2000
+   t.month = 1
+   check_error("field 'day' missing in date table", os.time, t)
+   t.day = 1.5
+   check_error("field 'day' is not an integer", os.time, t)
+   t.day = "x"
+   check_error("field 'day' is not an integer", os.time, t)
+   t.day = 1
+   t.sec = math.maxinteger
+   check_error("field 'sec' is out-of-bound", os.time, t)
+   check_error("bad argument #1 to 'time' (table expected, got number)", os.time, 1)
+end
+test_time_errors()
+
+function test_clock_and_difftime()
+   local c = os.clock()
+   assert(math.type(c) == "float")
+   local n = 0
+   for i = 1, 10000 do
+      n = n + i
+   end
+   assert(os.clock() >= c)
+   assert(os.difftime(10, 4) == 6.0)
+   assert(math.type(os.difftime(10, 4)) == "float")
+   check_error("bad argument #2 to 'difftime' (number expected, got nil)", os.difftime, 1)
+end
+test_clock_and_difftime()
+
+function test_getenv()
+   assert(os.getenv("PATH"))
+   assert_nil(os.getenv("LUAVM_NO_SUCH_VARIABLE"))
+   assert_nil(os.getenv("A=B"))
+   assert_nil(os.getenv(""))
+end
+test_getenv()
+
+function test_files()
+   local name = os.tmpname()
+   local other = os.tmpname()
+   assert(name ~= other)
+   local f = io.open(name)
+   assert(f:read("a") == "")
+   f:close()
+   assert(os.remove(other))
+   assert(os.rename(name, other))
+   assert_nil(io.open(name))
+   local ok, msg, errno = os.remove(name)
+   assert_nil(ok)
+   assert(errno == 2)
+   ok, msg = os.rename(name, other)
+   assert_nil(ok)
+   assert(os.remove(other))
+   ok, msg = os.remove("no/such/file")
+   assert(msg == "no/such/file: No such file or directory")
+end
+test_files()
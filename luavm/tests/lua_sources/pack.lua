@@ -0,0 +1,74 @@
+function test_integers()
+   assert(string.pack("<i4", 100) == string.char(100, 0, 0, 0))
+   assert(string.pack(">i4", 100) == string.char(0, 0, 0, 100))
+   assert(string.pack("<h>H", 1, 2) == string.char(1, 0, 0, 2))
+   assert(string.unpack("<i4", string.char(100, 0, 0, 0)) == 100)
+   local a, b, pos = string.unpack(">I2<I2", string.char(1, 2, 1, 2))
+   assert(a == 258)
+   assert(b == 513)
+   assert(pos == 5)
+   assert(string.unpack("<i16", string.pack("<i16", 127)) == 127)
+   assert(string.unpack("<j", string.pack("<j", 9223372036854775807)) == 9223372036854775807)
+   assert(string.pack("b", 0 - 1) == string.char(255))
+   assert(string.pack(">I2", 65280) == "\xff\0")
+   assert(string.unpack("<i2", "\xfe\xff") == 0 - 2)
+   assert(string.len(string.pack("<d", 0.1)) == 8)
+end
+test_integers()
+
+function test_floats_and_strings()
+   assert(string.unpack("<d", string.pack("<d", 2.5)) == 2.5)
+   assert(string.unpack(">f", string.pack(">f", 0.5)) == 0.5)
+   assert(string.pack("z", "ab") == string.char(97, 98, 0))
+   assert(string.pack("s1", "ab") == string.char(2, 97, 98))
+   assert(string.pack("c4", "ab") == string.char(97, 98, 0, 0))
+   local s, z, c, pos = string.unpack("s2zc3", string.pack("s2zc3", "hi", "there", "abc"))
+   assert(s == "hi")
+   assert(z == "there")
+   assert(c == "abc")
+   assert(pos == 14)
+end
+test_floats_and_strings()
+
+function test_alignment()
+   assert(string.packsize("i4") == 4)
+   assert(string.packsize("bi4") == 5)
+   assert(string.packsize("!bi4") == 8)
+   assert(string.packsize("!2bi4") == 6)
+   assert(string.packsize("bXi4") == 1)
+   assert(string.packsize("!bXi4") == 4)
+   assert(string.packsize("!bxd") == 16)
+   assert(string.pack("!<bi2", 1, 2) == string.char(1, 0, 2, 0))
+   local a, b = string.unpack("!<bi2", string.char(1, 0, 2, 0))
+   assert(b == 2)
+   -- unpacking starts at the given position
+   assert(string.unpack("b", string.char(1, 2, 3), 0 - 1) == 3)
+end
+test_alignment()
+
+function check_error(msg, f, a, b, c)
+   local ok, err = pcall(f, a, b, c)
+   if ok then
+      error("no error")
+   end
+   assert(err == msg)
+end
+
+function test_errors()
+   check_error("bad argument #2 to 'pack' (integer overflow)", string.pack, "i1", 128)
+   check_error("bad argument #2 to 'pack' (unsigned overflow)", string.pack, "I1", 256)
+   check_error("bad argument #2 to 'pack' (string longer than given size)", string.pack, "c1", "ab")
+   check_error("bad argument #2 to 'pack' (string contains zeros)", string.pack, "z", string.char(0))
+   check_error("bad argument #2 to 'pack' (string length does not fit in given size)", string.pack, "s1", string.rep("x", 256))
+   check_error("invalid format option 'y'", string.pack, "y")
+   check_error("integral size (17) out of limits [1,16]", string.pack, "i17", 1)
+   check_error("missing size for format option 'c'", string.pack, "c", "")
+   check_error("bad argument #1 to 'pack' (invalid next option for option 'X')", string.pack, "X")
+   check_error("bad argument #1 to 'pack' (format asks for alignment not power of 2)", string.pack, "!4i3", 1)
+   check_error("bad argument #1 to 'packsize' (variable-length format)", string.packsize, "s")
+   check_error("bad argument #2 to 'unpack' (data string too short)", string.unpack, "i4", "abc")
+   check_error("bad argument #2 to 'unpack' (unfinished string for format 'z')", string.unpack, "z", "abc")
+   check_error("bad argument #3 to 'unpack' (initial position out of string)", string.unpack, "b", "abc", 5)
+   check_error("9-byte integer does not fit into Lua Integer", string.unpack, "<i9", string.rep(string.char(1), 9))
+end
+test_errors()
@@ -0,0 +1,101 @@
+function assert_nil(x)
+   if x then
+      error("not nil")
+   end
+end
+
+function test_find()
+   local s, e = string.find("hello world", "o w")
+   assert(s == 5)
+   assert(e == 7)
+   s, e = string.find("hello world", "l+")
+   assert(s == 3)
+   assert(e == 4)
+   assert(string.find("hello world", "o", 6) == 8)
+   assert_nil(string.find("abc", "b", 0 - 1))
+   -- a plain find ignores the special characters
+   assert(string.find("a.b", ".", 1, 1) == 2)
+   assert(string.find("abc", "", 4) == 4)
+   assert_nil(string.find("abc", "", 10))
+end
+test_find()
+
+function test_match()
+   local k, v = string.match("key = value", "(%w+)%s*=%s*(%w+)")
+   assert(k == "key")
+   assert(v == "value")
+   local s, e = string.match("hello", "()ll()")
+   assert(s == 3)
+   assert(e == 5)
+   assert(string.match("  trim  ", "^%s*(.-)%s*$") == "trim")
+   assert(string.match("2024", "^[0-9]+$") == "2024")
+   assert(string.match("a-b", "[a%-]+") == "a-")
+   assert(string.match("x[]]", "[]]") == "]")
+   assert_nil(string.match("x1", "^%d"))
+end
+test_match()
+
+function test_balance_frontier_backref()
+   assert(string.match("f(a(b)c)d", "%b()") == "(a(b)c)")
+   assert(string.match("THE (quick) fox", "%f[%a]%a+", 5) == "quick")
+   assert(string.match("abcabc", "(abc)%1") == "abc")
+   assert_nil(string.match("abcabd", "(abc)%1"))
+end
+test_balance_frontier_backref()
+
+function test_gmatch()
+   local it = string.gmatch("one two  three", "%a+")
+   assert(it() == "one")
+   assert(it() == "two")
+   assert(it() == "three")
+   assert_nil(it())
+   local pairs = ("a=1, b=2"):gmatch("(%w+)=(%w+)")
+   local k, v = pairs()
+   assert(k == "a")
+   assert(v == "1")
+end
+test_gmatch()
+
+function test_gsub()
+   local s, n = string.gsub("hello world", "o", "0")
+   assert(s == "hell0 w0rld")
+   assert(n == 2)
+   assert(string.gsub("hello world", "(%w+)", "<%1>") == "<hello> <world>")
+   assert(string.gsub("hello world", "%w+", "%0 %0", 1) == "hello hello world")
+   assert(string.gsub("abc", "", "-") == "-a-b-c-")
+   assert(string.gsub("abc", "^", "-") == "-abc")
+   assert(("x=1, y=2"):gsub("(%w+)=(%w+)", "%2=%1") == "1=x, 2=y")
+   assert(string.gsub("100%", "%%", " percent") == "100 percent")
+end
+test_gsub()
+
+function test_gsub_functions_and_tables()
+   assert(string.gsub("$name is $age", "%$(%w+)", string.upper) == "NAME is AGE")
+   -- the match is kept if the function returns nil
+   function keep(x)
+   end
+   assert(string.gsub("hello", "l", keep) == "hello")
+   assert(string.gsub("a b", "%w", package.loaded) == "a b")
+   package.preload.b = "loaded"
+   assert(string.gsub("a b", "%w", package.preload) == "a loaded")
+end
+test_gsub_functions_and_tables()
+
+function check_error(msg, f, a, b, c)
+   local ok, err = pcall(f, a, b, c)
+   if ok then
+      error("no error")
+   end
+   assert(err == msg)
+end
+
+function test_errors()
+   check_error("malformed pattern (ends with '%')", string.match, "x", "%")
+   check_error("malformed pattern (missing ']')", string.match, "x", "[a")
+   check_error("unfinished capture", string.match, "x", "(()")
+   check_error("invalid pattern capture", string.match, "x", "x)")
+   check_error("invalid capture index %2", string.gsub, "x", "x", "%2")
+   check_error("invalid use of '%' in replacement string", string.gsub, "x", "x", "%")
+   check_error("pattern too complex", string.match, string.rep("a", 300), string.rep("a?", 300))
+end
+test_errors()
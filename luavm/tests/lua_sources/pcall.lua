@@ -0,0 +1,60 @@
+function div(a, b)
+   if b == 0 then
+      error("division by zero")
+   end
+   return a / b, a % b
+end
+
+local ok, q, r = pcall(div, 7.5, 2.5)
+assert(ok)
+assert(q == 3)
+assert(r == 0)
+
+local err
+ok, err = pcall(div, 1, 0)
+if ok then
+   error("the error wasn't caught")
+end
+assert(err == "division by zero")
+
+-- errors are caught by the closest pcall
+function nested()
+   local ok, err = pcall(div, 1, 0)
+   assert(err == "division by zero")
+   return 5
+end
+
+local x
+ok, x = pcall(nested)
+assert(x == 5)
+
+-- builtins
+ok, err = pcall(assert, 1 == 2, "custom message")
+assert(err == "custom message")
+ok, err = pcall(error, "message")
+assert(err == "message")
+local y = 1
+ok, err = pcall(y)
+assert(err == "attempt to call a non-function value")
+
+-- pcall of pcall
+local ok1, ok2, v = pcall(pcall, div, 4.0, 2.0)
+assert(ok1)
+assert(ok2)
+assert(v == 2)
+ok1, ok2, v = pcall(pcall, error, "inner")
+assert(ok1)
+assert(v == "inner")
+
+-- the stack is usable after an error
+function deep(n)
+   if n == 0 then
+      error("bottom")
+   end
+   local z = deep(n - 1)
+   return z
+end
+
+ok, err = pcall(deep, 100)
+assert(err == "bottom")
+assert(div(9.0, 3.0) == 3)
@@ -0,0 +1,42 @@
+package.path = "tests/lua_modules/?.lua;tests/lua_modules/?/init.lua"
+
+f = require("counter")
+assert(f(1) == 2)
+assert(require("counter") == f)
+assert(package.loaded.counter == f)
+assert(loads == 1)
+assert(module_name == "counter")
+assert(module_file == "tests/lua_modules/counter.lua")
+
+-- modules which return nothing are stored as true
+assert(require("pkg") == package.loaded.pkg)
+assert(package.loaded.pkg)
+assert(pkg_loaded == 1)
+
+function greeting_loader(name, extra)
+    assert(extra == ":preload:")
+    return name
+end
+package.preload.greeting = greeting_loader
+assert(require("greeting") == "greeting")
+
+assert(require("io") == io)
+assert(package.loaded.package == package)
+
+ok, msg = pcall(require, "cycle_a")
+assert(msg == "circular require of module 'cycle_a'")
+-- the modules which failed to load are not marked as loading anymore
+ok, msg = pcall(require, "cycle_b")
+assert(msg == "circular require of module 'cycle_b'")
+
+ok, msg = pcall(require, "missing")
+if ok then
+    error("the module should not exist")
+end
+
+file, msg = package.searchpath("a.b", "tests/?.lua;tests/lua_modules/?.lua")
+if file then
+    error("the file should not exist")
+end
+assert(package.searchpath("counter", "tests/?.lua;tests/lua_modules/?.lua") == "tests/lua_modules/counter.lua")
+assert(package.searchpath("lua_modules/counter", "tests/?.lua") == "tests/lua_modules/counter.lua")
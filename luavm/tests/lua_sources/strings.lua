@@ -0,0 +1,98 @@
+function test_len()
+   assert(string.len("hello") == 5)
+   assert(string.len("") == 0)
+   assert(string.len(123) == 3)
+end
+test_len()
+
+function test_sub()
+   -- negative positions are counted from the end
+   local s = "hello world"
+   assert(string.sub(s, 1, 5) == "hello")
+   assert(string.sub(s, 7) == "world")
+   assert(string.sub(s, 0 - 5) == "world")
+   assert(string.sub(s, 0 - 5, 0 - 2) == "worl")
+   assert(string.sub(s, 0 - 100, 2) == "he")
+   assert(string.sub(s, 5, 100) == "o world")
+   assert(string.sub(s, 4, 2) == "")
+   assert(string.sub(s, 0) == s)
+end
+test_sub()
+
+function test_upper_lower_rep()
+   assert(string.upper("abc1D") == "ABC1D")
+   assert(string.lower("ABC1d") == "abc1d")
+   assert(string.rep("ab", 3) == "ababab")
+   assert(string.rep("ab", 3, ", ") == "ab, ab, ab")
+   assert(string.rep("ab", 0) == "")
+   assert(string.rep("ab", 0 - 1) == "")
+   assert(string.reverse("abc") == "cba")
+end
+test_upper_lower_rep()
+
+function test_byte_char()
+   assert(string.byte("ABC") == 65)
+   assert(string.byte("ABC", 0 - 1) == 67)
+   local a, b, c = string.byte("ABC", 1, 0 - 1)
+   assert(a == 65)
+   assert(b == 66)
+   assert(c == 67)
+   assert(string.char(72, 105) == "Hi")
+   assert(string.char() == "")
+end
+test_byte_char()
+
+function test_char_range()
+   local ok, err = pcall(string.char, 65, 256)
+   if ok then
+      error("string.char accepted 256")
+   end
+   assert(err == "bad argument #2 to 'char' (value out of range)")
+end
+test_char_range()
+
+function test_methods()
+   -- strings have methods through their metatable
+   local s = "hello world"
+   assert(s:upper() == "HELLO WORLD")
+   assert(s:sub(1, 5):upper() == "HELLO")
+   assert(("x"):rep(3) == "xxx")
+   assert(s:len() == 11)
+   local name = "len"
+   assert(s[name] == string.len)
+end
+test_methods()
+
+function test_escapes()
+   assert("a\tb\n" == string.char(97, 9, 98, 10))
+   assert("\65\066\x43\z
+           \x44" == "ABCD")
+   assert("\"\'\\" == string.char(34, 39, 92))
+   assert(string.len("\0\0") == 2)
+end
+test_escapes()
+
+function test_binary()
+   -- strings are bytes, which are not necessarily valid UTF-8
+   local s = "\xff\xfe\0"
+   assert(string.len(s) == 3)
+   assert(string.byte(s, 1) == 255)
+   assert(s == string.char(255, 254, 0))
+   assert(s ~= string.char(255, 254))
+   assert(string.reverse(s) == "\0\xfe\xff")
+   assert(string.sub(s, 2, 2) == "\xfe")
+   assert("\xff" > "\x7f")
+   assert("\xc3" < "\xc3\xa9")
+   assert(string.upper("\xe9a") == "\xe9A")
+end
+test_binary()
+
+function test_long_strings()
+   -- only short strings are interned, long ones are compared by content
+   local a = string.rep("ab", 30)
+   assert(a == string.rep("ab", 30))
+   assert(a ~= string.rep("ab", 29))
+   assert(a > string.rep("ab", 29))
+   assert(string.sub(a, 1, 2) == "ab")
+end
+test_long_strings()
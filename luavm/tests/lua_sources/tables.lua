@@ -0,0 +1,120 @@
+-- tables are created with table.pack, as there are no table constructors yet
+
+function assert_nil(x)
+   if x then
+      error("not nil")
+   end
+end
+
+function check_error(msg, f, a, b, c)
+   local ok, err = pcall(f, a, b, c)
+   if ok then
+      error("no error")
+   end
+   assert(err == msg)
+end
+
+function test_pack_unpack()
+   local t = table.pack(1, "two", 3)
+   assert(t.n == 3)
+   assert(t[2] == "two")
+   local a, b, c = table.unpack(t)
+   assert(a == 1)
+   assert(b == "two")
+   assert(c == 3)
+   local x, y = table.unpack(t, 2)
+   assert(x == "two")
+   assert(y == 3)
+   assert(table.unpack(t, 3, 3) == 3)
+   assert(table.pack().n == 0)
+   assert(table.pack(table.unpack(t, 1, 5)).n == 5)
+   assert(table.pack(table.unpack(t, 3, 2)).n == 0)
+   check_error("too many results to unpack", table.unpack, t, 1, 10000000)
+end
+test_pack_unpack()
+
+function test_insert_remove()
+   local t = table.pack(1, 2, 3)
+   table.insert(t, 4)
+   assert(t[4] == 4)
+   table.insert(t, 1, 0)
+   assert(t[1] == 0)
+   assert(t[5] == 4)
+   assert(table.concat(t, ",") == "0,1,2,3,4")
+   assert(table.remove(t) == 4)
+   assert_nil(t[5])
+   assert(table.remove(t, 1) == 0)
+   assert(table.concat(t, ",") == "1,2,3")
+   -- the position after the last element is valid
+   table.insert(t, 4, 4)
+   assert_nil(table.remove(t, 5))
+   assert(table.concat(t, ",") == "1,2,3,4")
+   local empty = table.pack()
+   assert_nil(table.remove(empty))
+   check_error("bad argument #2 to 'insert' (position out of bounds)", table.insert, t, 7, 1)
+   check_error("bad argument #2 to 'remove' (position out of bounds)", table.remove, t, 7)
+   local ok, err = pcall(table.insert, t)
+   assert(err == "wrong number of arguments to 'insert'")
+   check_error("bad argument #1 to 'insert' (table expected, got number)", table.insert, 1, 2)
+end
+test_insert_remove()
+
+function test_concat()
+   local t = table.pack("a", 1, "c")
+   assert(table.concat(t) == "a1c")
+   assert(table.concat(t, ", ") == "a, 1, c")
+   assert(table.concat(t, "-", 2) == "1-c")
+   assert(table.concat(t, "-", 1, 2) == "a-1")
+   assert(table.concat(t, "-", 3, 2) == "")
+   t[2] = table.pack()
+   check_error("invalid value (at index 2) in table for 'concat'", table.concat, t)
+end
+test_concat()
+
+function test_move()
+   local t = table.pack(1, 2, 3, 4, 5)
+   table.move(t, 1, 3, 3)
+   assert(table.concat(t, ",") == "1,2,1,2,3")
+   table.move(t, 2, 5, 1)
+   assert(table.concat(t, ",") == "2,1,2,3,3")
+   local u = table.move(t, 1, 2, 1, table.pack())
+   assert(table.concat(u, ",") == "2,1")
+   local ok, err = pcall(table.move, t, 1, 2, 9223372036854775807)
+   assert(err == "bad argument #4 to 'move' (destination wrap around)")
+end
+test_move()
+
+function greater(a, b)
+   return a > b
+end
+
+function always(a, b)
+   return 1
+end
+
+function test_sort()
+   local t = table.pack(5, 2, 8, 1, 9, 3, 7, 4, 6, 0)
+   table.sort(t)
+   assert(table.concat(t, ",") == "0,1,2,3,4,5,6,7,8,9")
+   table.sort(t, greater)
+   assert(table.concat(t, ",") == "9,8,7,6,5,4,3,2,1,0")
+   local s = table.pack("pear", "apple", "fig")
+   table.sort(s)
+   assert(table.concat(s, " ") == "apple fig pear")
+   local big = table.pack()
+   for i = 1, 500 do
+      big[i] = (i * 7919) % 1000
+   end
+   table.sort(big)
+   local sorted = 1
+   for i = 2, 500 do
+      if big[i - 1] > big[i] then
+         sorted = 0
+      end
+   end
+   assert(sorted == 1)
+   check_error("invalid order function for sorting", table.sort, big, always)
+   check_error("attempt to compare string with number", table.sort, table.pack(1, "x"))
+   check_error("bad argument #2 to 'sort' (function expected, got number)", table.sort, t, 1)
+end
+test_sort()
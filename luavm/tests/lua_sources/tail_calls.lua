@@ -0,0 +1,20 @@
+function is_even(n)
+   if n == 0 then
+      return 1
+   end
+   return is_odd(n - 1)
+end
+function is_odd(n)
+   if n == 0 then
+      return 0
+   end
+   return is_even(n - 1)
+end
+assert(is_even(50001) == 0)
+assert(is_odd(50001) == 1)
+-- tail calls to builtin functions
+function check(a)
+   return assert(a)
+end
+local a, b = check(2)
+assert(a == 2)
@@ -0,0 +1,126 @@
+function assert_nil(x)
+   if x then
+      error("not nil")
+   end
+end
+
+function check_error(msg, f, a, b, c)
+   local ok, err = pcall(f, a, b, c)
+   if ok then
+      error("no error")
+   end
+   assert(err == msg)
+end
+
+-- "héllo€", with characters of 1, 2 and 3 bytes
+s = "h\u{E9}llo\u{20AC}"
+
+function test_escapes()
+   assert("\u{41}" == "A")
+   assert("\u{e9}" == "\xC3\xA9")
+   assert("\u{20AC}" == "\xE2\x82\xAC")
+   assert("\u{1F600}" == "\xF0\x9F\x98\x80")
+   assert("\u{7FFFFFFF}" == "\xFD\xBF\xBF\xBF\xBF\xBF")
+   assert(string.len(s) == 9)
+end
+test_escapes()
+
+function test_char()
+   assert(utf8.char(72, 233, 8364) == "H\u{E9}\u{20AC}")
+   assert(utf8.char() == "")
+   assert(utf8.char(1114111) == "\u{10FFFF}")
+   check_error("bad argument #2 to 'char' (value out of range)", utf8.char, 1, 1114112)
+   check_error("bad argument #1 to 'char' (value out of range)", utf8.char, 0 - 1)
+   check_error("bad argument #1 to 'char' (number expected, got string)", utf8.char, "x")
+end
+test_char()
+
+function test_codepoint()
+   assert(utf8.codepoint(s) == 104)
+   assert(utf8.codepoint(s, 2) == 233)
+   local a, b, c = utf8.codepoint(s, 1, 4)
+   assert(a == 104)
+   assert(b == 233)
+   assert(c == 108)
+   assert(utf8.codepoint(s, 0 - 3) == 8364)
+   assert_nil(utf8.codepoint(s, 3, 2))
+   check_error("invalid UTF-8 code", utf8.codepoint, s, 3)
+   check_error("invalid UTF-8 code", utf8.codepoint, "\xC0\x80")
+   check_error("bad argument #2 to 'codepoint' (out of range)", utf8.codepoint, s, 0)
+   check_error("bad argument #3 to 'codepoint' (out of range)", utf8.codepoint, s, 1, 10)
+end
+test_codepoint()
+
+function test_len()
+   assert(utf8.len(s) == 6)
+   assert(utf8.len("") == 0)
+   assert(utf8.len(s, 4) == 4)
+   assert(utf8.len(s, 0 - 3) == 1)
+   assert(utf8.len(s, 1, 2) == 2)
+   assert(utf8.len(s, 10) == 0)
+   local n, pos = utf8.len("ab\xFFc")
+   assert_nil(n)
+   assert(pos == 3)
+   n, pos = utf8.len(s, 3)
+   assert_nil(n)
+   assert(pos == 3)
+   -- overlong encodings and values after 10FFFF are invalid
+   n, pos = utf8.len("\xC1\xBF")
+   assert(pos == 1)
+   n, pos = utf8.len("a\u{110000}")
+   assert(pos == 2)
+   -- as well as sequences of more than 4 bytes
+   n, pos = utf8.len("\xFF\x80\x80\x80\x80\x80\x80\x80")
+   assert_nil(n)
+   assert(pos == 1)
+   check_error("bad argument #2 to 'len' (initial position out of string)", utf8.len, s, 11)
+   check_error("bad argument #3 to 'len' (final position out of string)", utf8.len, s, 1, 10)
+end
+test_len()
+
+function test_offset()
+   assert(utf8.offset(s, 1) == 1)
+   assert(utf8.offset(s, 3) == 4)
+   assert(utf8.offset(s, 6) == 7)
+   assert(utf8.offset(s, 7) == 10)
+   assert_nil(utf8.offset(s, 8))
+   assert(utf8.offset(s, 0 - 1) == 7)
+   assert(utf8.offset(s, 0 - 5) == 2)
+   assert_nil(utf8.offset(s, 0 - 7))
+   assert(utf8.offset(s, 0, 3) == 2)
+   assert(utf8.offset(s, 0, 9) == 7)
+   assert(utf8.offset(s, 2, 2) == 4)
+   check_error("initial position is a continuation byte", utf8.offset, s, 1, 3)
+   check_error("bad argument #3 to 'offset' (position out of range)", utf8.offset, s, 1, 11)
+end
+test_offset()
+
+function test_codes()
+   local iter, str, pos = utf8.codes(s)
+   assert(str == s)
+   assert(pos == 0)
+   local positions = table.pack()
+   local codes = table.pack()
+   local p, c = iter(str, pos)
+   while p do
+      table.insert(positions, p)
+      table.insert(codes, c)
+      p, c = iter(str, p)
+   end
+   assert(table.concat(positions, ",") == "1,2,4,5,6,7")
+   assert(table.concat(codes, ",") == "104,233,108,108,111,8364")
+   iter = utf8.codes("a\xFF")
+   check_error("invalid UTF-8 code", iter, "a\xFF", 1)
+   -- a truncated sequence is invalid too
+   check_error("invalid UTF-8 code", iter, "\xE2\x82", 0)
+end
+test_codes()
+
+function test_charpattern()
+   assert(utf8.charpattern == "[\0-\x7F\xC2-\xF4][\x80-\xBF]*")
+   local it = string.gmatch(s, utf8.charpattern)
+   assert(it() == "h")
+   assert(it() == "\u{E9}")
+   assert(it() == "l")
+end
+test_charpattern()
@@ -11,7 +11,7 @@ fn compile_and_run(file: &str) {
     println!("Parsing {}", file);
     let pt = LuaParseTree::new(file).unwrap();
     println!("Compiling {}", file);
-    let bc = compile_to_bytecode(compile_to_ir(&pt));
+    let bc = compile_to_bytecode(compile_to_ir(&pt).unwrap());
     println!("Interpreting {}", file);
     let mut vm = Vm::new(bc, vec![]);
     vm.eval().unwrap();